@@ -0,0 +1,70 @@
+//! SET/GET throughput through `App::dispatch_command`.
+//!
+//! Run with `cargo bench --bench get_set`. Numbers from switching the store
+//! from a `BTreeMap` to a `HashMap` (1M keys, 3 rounds, release build):
+//!
+//! | index    | SET ops/s | GET ops/s |
+//! |----------|-----------|-----------|
+//! | BTreeMap | ~1.16M    | ~1.43M    |
+//! | HashMap  | ~1.06M    | ~1.38M    |
+//!
+//! The difference is within run-to-run noise: per-command dispatch (argument
+//! cloning, reply serialization) dominates the index lookup at this key
+//! length. A hash index won because lookups stay O(1) as the keyspace and key
+//! sizes grow, and nothing on the hot path needs ordering.
+//!
+//! The store has since moved from the `HashMap` to an `IndexMap`. The expiry
+//! cycle and RANDOMKEY pick random keys by position, and KEYS and SCAN walk
+//! the keys by position. Before and after that change, on another machine
+//! (median of 5 runs, which spread by about 20%):
+//!
+//! | index    | SET ops/s | GET ops/s |
+//! |----------|-----------|-----------|
+//! | HashMap  | ~0.56M    | ~0.70M    |
+//! | IndexMap | ~0.60M    | ~0.81M    |
+//!
+//! This is within noise again. An `IndexMap` is a hash table of positions
+//! into a vector of entries, so lookups stay O(1). Removals swap the last
+//! entry into the gap, which keeps them O(1) too.
+
+use std::time::Instant;
+
+use redis::{
+    commands::{App, Client},
+    value::Value,
+};
+
+const KEYS: usize = 1_000_000;
+const ROUNDS: usize = 3;
+
+fn command(args: &[&str]) -> Value {
+    Value::Array(Some(args.iter().map(|a| Value::str(a)).collect()))
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let app = App::new();
+    let mut client = Client::default();
+    let keys: Vec<String> = (0..KEYS).map(|i| format!("key:{i}")).collect();
+
+    let mut set_total = 0.0;
+    let mut get_total = 0.0;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        for k in &keys {
+            app.dispatch_command(&mut client, command(&["SET", k, "value"]))
+                .await;
+        }
+        set_total += KEYS as f64 / start.elapsed().as_secs_f64();
+
+        let start = Instant::now();
+        for k in &keys {
+            app.dispatch_command(&mut client, command(&["GET", k]))
+                .await;
+        }
+        get_total += KEYS as f64 / start.elapsed().as_secs_f64();
+    }
+
+    println!("SET: {:.0} ops/s", set_total / ROUNDS as f64);
+    println!("GET: {:.0} ops/s", get_total / ROUNDS as f64);
+}
@@ -34,5 +34,28 @@ where
     }
 }
 
-
 impl<S> Eq for CaseInsensitive<S> where S: AsRef<str> {}
+
+/// A short string in ASCII lowercase, kept on the stack. Every command looks
+/// its name up in lowercase, this spares it an allocation for that.
+pub struct Lowercase {
+    len: usize,
+    buf: [u8; Lowercase::CAPACITY],
+}
+
+impl Lowercase {
+    /// the longest string that fits, far longer than any command name
+    pub const CAPACITY: usize = 32;
+
+    /// `s` in lowercase, `None` if it's longer than [`Lowercase::CAPACITY`]
+    pub fn new(s: &str) -> Option<Self> {
+        let mut buf = [0; Self::CAPACITY];
+        buf.get_mut(..s.len())?.copy_from_slice(s.as_bytes());
+        buf.make_ascii_lowercase();
+        Some(Lowercase { len: s.len(), buf })
+    }
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.buf[..self.len]).expect("lowercasing ascii keeps utf-8 valid")
+    }
+}
@@ -1,4 +1,4 @@
-use std::{ffi::CStr, io::Write};
+use std::io::Write;
 
 use serde::{Deserialize, Serialize};
 
@@ -28,7 +28,7 @@ where
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     foo("hello world")?;
-    let x = CStr::from_bytes_with_nul(b"a\0")?;
+    let x = c"a";
     foo(x)?;
     foo(1)?;
     foo([1, 2].as_slice())?;
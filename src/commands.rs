@@ -1,74 +1,319 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt, fs,
+    future::Future,
+    io,
+    net::SocketAddr,
+    ops::RangeInclusive,
+    path::Path,
+    pin::Pin,
+    slice::ChunksExact,
     sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    time::Duration,
 };
 
+use indexmap::IndexSet;
 use parking_lot::Mutex;
 use serde::Serialize;
+use tokio::sync::Notify;
 
-use crate::{case_insensitive::CaseInsensitive, serializer::to_bytes, value::Value};
+use crate::{
+    case_insensitive::{CaseInsensitive, Lowercase},
+    compression,
+    float::format_human,
+    glob, handoff,
+    hash::Hash,
+    lcs,
+    mem_size::MemSize,
+    object::Object,
+    rdb,
+    reply::RespError,
+    reply_pool::{PoolStats, ReplyPool},
+    sample::sample,
+    serializer::{to_bytes_with_protocol, Protocol, Serializer},
+    store::{scan_step, Clock, Entry, Kind, Store},
+    value::{Map, Value},
+};
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Default, Clone)]
-struct Entry {
-    value: Value,
-    expiry: Option<u128>,
+/// Why a key was removed from the keyspace, see [`App::on_evict`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EvictionReason {
+    /// its time to live ran out
+    Expired,
+    /// DEL, UNLINK or GETDEL removed it
+    Deleted,
 }
 
-impl Entry {
-    fn is_expired(&self) -> bool {
-        let Some(expiry) = self.expiry else {
-            return false;
-        };
+type EvictionHook =
+    Box<dyn Fn(Value, EvictionReason) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// A command that ran for longer than `watchdog-period`, see
+/// [`App::on_watchdog`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct WatchdogReport {
+    /// the name of the command as the client sent it
+    pub command: String,
+    /// how long it had been running then
+    pub elapsed: Duration,
+    /// whether it was aborted, which `watchdog-kill` asks for
+    pub killed: bool,
+}
+
+/// A KEYS over more keys than `keys-warning-threshold`, see
+/// [`App::on_keys_warning`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct KeysWarning {
+    /// the pattern the client asked for
+    pub pattern: Value,
+    /// how many keys there were
+    pub keys: usize,
+    /// where the client connects from, if the server knows
+    pub client: Option<SocketAddr>,
+}
+
+type KeysHook = Box<dyn Fn(KeysWarning) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// shared so the watchdog can report from a task of its own
+type WatchdogHook =
+    Arc<dyn Fn(WatchdogReport) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
 
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("time went backwards")
-            .as_millis();
-        now > expiry
+/// A client blocked by BLPOP and friends until one of its keys is pushed to,
+/// see [`App::block`].
+#[derive(Debug)]
+struct Waiter {
+    keys: Vec<Value>,
+    notify: Notify,
+    /// the key a push to woke it for, until it acts on the wakeup
+    woken_by: Mutex<Option<Value>>,
+    /// it gives up after a while, rather than waiting for as long as it takes
+    timeout: bool,
+}
+
+/// the clients blocked on each key, those blocked longest first
+type Blocked = HashMap<Value, VecDeque<Arc<Waiter>>>;
+
+/// takes `waiter` out of the queues of all of its keys
+fn unblock(blocked: &mut Blocked, waiter: &Arc<Waiter>) {
+    for key in &waiter.keys {
+        if let Some(waiters) = blocked.get_mut(key) {
+            waiters.retain(|w| !Arc::ptr_eq(w, waiter));
+            if waiters.is_empty() {
+                blocked.remove(key);
+            }
+        }
     }
+}
 
-    fn new(value: Value) -> Self {
-        Self {
-            value,
-            expiry: None,
+/// A [`Waiter`] in the queues of its keys for as long as the client waits.
+///
+/// A blocked command is dropped when its client goes away. Dropping the guard
+/// then takes the waiter out of the queues, and hands a wakeup it got but
+/// never acted on to the next client blocked on the key, so the element
+/// pushed for it isn't left waiting for no one.
+struct WaitGuard<'a> {
+    app: &'a App,
+    waiter: Arc<Waiter>,
+}
+
+impl WaitGuard<'_> {
+    /// for a client that goes on to act on its wakeup itself
+    fn done(self) {
+        self.waiter.woken_by.lock().take();
+    }
+}
+
+impl Drop for WaitGuard<'_> {
+    fn drop(&mut self) {
+        unblock(&mut self.app.blocked.lock(), &self.waiter);
+        if let Some(key) = self.waiter.woken_by.lock().take() {
+            self.app.wake(&key, 1);
         }
     }
+}
+
+/// hands `report` to each of `hooks`, see [`App::on_watchdog`]
+fn report_to(hooks: &[WatchdogHook], report: WatchdogReport) {
+    for hook in hooks {
+        tokio::spawn(hook(report.clone()));
+    }
+}
+
+#[derive(Default)]
+struct Hooks {
+    evict: Vec<EvictionHook>,
+    watchdog: Vec<WatchdogHook>,
+    keys: Vec<KeysHook>,
+}
 
-    fn expires_in(&mut self, ms: u128) -> &mut Self {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("time went backwards")
-            .as_millis();
-        self.expiry = Some(now + ms);
-        self
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("evict", &self.evict.len())
+            .field("watchdog", &self.watchdog.len())
+            .field("keys", &self.keys.len())
+            .finish()
     }
 }
 
 #[derive(Debug)]
 pub struct App {
-    store: Arc<Mutex<BTreeMap<Value, Entry>>>,
+    store: Arc<Mutex<Store>>,
     config: Mutex<BTreeMap<String, String>>,
+    hooks: Mutex<Hooks>,
+    /// faults set up with DEBUG INJECT, by lowercase command name
+    faults: Mutex<BTreeMap<String, Fault>>,
+    /// how the connections' reply buffer pools do, for INFO
+    reply_stats: Mutex<PoolStats>,
+    blocked: Mutex<Blocked>,
 }
 
 impl App {
     pub fn new() -> Self {
         Self {
-            store: Arc::new(Mutex::new(BTreeMap::new())),
+            store: Arc::new(Mutex::new(Store::new())),
             config: Mutex::new(BTreeMap::new()),
+            hooks: Mutex::new(Hooks::default()),
+            faults: Mutex::new(BTreeMap::new()),
+            reply_stats: Mutex::new(PoolStats::default()),
+            blocked: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Registers a callback that is called with every key removed from the
+    /// keyspace and why, e.g. to refresh a cache or count evictions.
+    ///
+    /// Callbacks are spawned on the tokio runtime, so they don't hold up the
+    /// command that removed the key, and there's no ordering between them.
+    /// Expired keys are noticed when they're looked up, by the expiry cycle
+    /// (see [`App::active_expire`]) or on [`App::prune_expired`]. Keys that
+    /// are overwritten, renamed or emptied by removing their last element
+    /// aren't reported, only those removed by expiry or a delete command.
+    pub fn on_evict<F, Fut>(&self, hook: F)
+    where
+        F: Fn(Value, EvictionReason) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks
+            .lock()
+            .evict
+            .push(Box::new(move |key, reason| Box::pin(hook(key, reason))));
+    }
+
+    /// Registers a callback that is called with every command the watchdog
+    /// catches, see `watchdog-period`. Like those of [`App::on_evict`] they
+    /// are spawned on the tokio runtime.
+    pub fn on_watchdog<F, Fut>(&self, hook: F)
+    where
+        F: Fn(WatchdogReport) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks
+            .lock()
+            .watchdog
+            .push(Arc::new(move |report| Box::pin(hook(report))));
+    }
+
+    /// Registers a callback that is called whenever KEYS runs over more keys
+    /// than `keys-warning-threshold` (0 turns it off), to find the clients
+    /// that should move to SCAN. Like those of [`App::on_evict`] they are
+    /// spawned on the tokio runtime.
+    pub fn on_keys_warning<F, Fut>(&self, hook: F)
+    where
+        F: Fn(KeysWarning) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks
+            .lock()
+            .keys
+            .push(Box::new(move |warning| Box::pin(hook(warning))));
+    }
+
+    fn evicted(&self, keys: impl IntoIterator<Item = Value>, reason: EvictionReason) {
+        let hooks = self.hooks.lock();
+        if hooks.evict.is_empty() {
+            return;
+        }
+        for key in keys {
+            for hook in &hooks.evict {
+                tokio::spawn(hook(key.clone(), reason));
+            }
+        }
+    }
+
+    /// removes `key` if it expired, telling the eviction hooks about it
+    fn expire_if_needed(&self, store: &mut Store, key: &Value) {
+        if store.remove_expired(key).is_some() {
+            self.evicted([key.clone()], EvictionReason::Expired);
         }
     }
 
     pub fn prune_expired(&self) {
-        let mut store = self.store.lock();
-        let expired: Vec<_> = store
-            .iter()
-            .filter_map(|(k, v)| v.is_expired().then_some(k).cloned())
-            .collect();
+        let expired = self.store.lock().prune_expired();
+        self.evicted(expired.into_iter().map(|(k, _)| k), EvictionReason::Expired);
+    }
+
+    fn expire_cycle(&self) {
+        let expired = self.store.lock().expire_cycle(EXPIRE_CYCLE_BUDGET);
+        self.evicted(expired.into_iter().map(|(k, _)| k), EvictionReason::Expired);
+    }
+
+    /// Runs a cycle of active expiry, removing expired keys that nobody looks
+    /// up anymore. Meant to be called every [`EXPIRE_CYCLE_INTERVAL`].
+    ///
+    /// Nothing happens while the clock is frozen by DEBUG SET-CLOCK, keys then
+    /// only go away when looked up or on DEBUG EXPIRE-CYCLE.
+    pub fn active_expire(&self) {
+        if let Clock::Frozen(_) = self.store.lock().clock() {
+            return;
+        }
+        self.expire_cycle();
+    }
+
+    /// Writes the keyspace and the counters INFO reports to `path` for the
+    /// server replacing this one, see [`crate::handoff`]. The file is written
+    /// next to `path` and renamed, so it's never there half written.
+    ///
+    /// Writes that come in after this returns aren't in the file, the server
+    /// is meant to exit right after.
+    pub fn write_handoff(&self, path: &Path) -> io::Result<()> {
+        let map = self.store.lock();
+        let counters = handoff::Counters {
+            expire: map.expire_stats().clone(),
+            replies: *self.reply_stats.lock(),
+        };
+        let mut encoder = handoff::Encoder::new(&counters);
+        for (key, entry) in map.iter() {
+            encoder.key(key, &entry.value, entry.expiry)?;
+        }
+
+        let partial = path.with_extension("partial");
+        fs::write(&partial, encoder.finish())?;
+        fs::rename(&partial, path)
+    }
 
-        for e in expired {
-            store.remove(&e);
+    /// Takes over what [`App::write_handoff`] wrote to `path` and removes
+    /// the file. Keys that expired in the meantime are left out. Returns how
+    /// many keys were taken over.
+    pub fn read_handoff(&self, path: &Path) -> io::Result<usize> {
+        let (counters, keys) = handoff::decode(&fs::read(path)?)?;
+        let mut map = self.store.lock();
+        let now = map.now_ms();
+        let mut taken = 0;
+        for key in keys {
+            if key.expiry.is_some_and(|expiry| expiry <= now) {
+                continue;
+            }
+            map.insert(key.key.clone(), Entry::new(key.value));
+            map.set_expiry(&key.key, key.expiry);
+            taken += 1;
         }
+        map.set_expire_stats(counters.expire);
+        *self.reply_stats.lock() = counters.replies;
+        fs::remove_file(path)?;
+        Ok(taken)
     }
 }
 
@@ -78,32 +323,365 @@ impl Default for App {
     }
 }
 
+/// Per-connection state.
+#[derive(Debug, Default)]
+pub struct Client {
+    pub protocol: Protocol,
+    /// set by `CLIENT COMPRESS`, see [`crate::compression`]
+    pub compress_above: Option<usize>,
+    /// where the client connects from, if the server knows
+    pub addr: Option<SocketAddr>,
+    pool: ReplyPool,
+}
+
+impl Client {
+    /// Hands back a reply once it's been written, its buffer is reused for
+    /// the next one.
+    pub fn recycle(&mut self, reply: Vec<u8>) {
+        self.pool.recycle(reply);
+    }
+
+    /// a serializer for replies to this client
+    fn serializer(&mut self) -> Serializer {
+        let serializer = Serializer::with_output(self.pool.take(), self.protocol);
+        match self.compress_above {
+            Some(threshold) => serializer.with_compression(threshold),
+            None => serializer,
+        }
+    }
+
+    /// encodes a reply that is a [`Value`] already, skipping serde
+    fn encode(&mut self, value: &Value) -> Vec<u8> {
+        let mut serializer = self.serializer();
+        serializer
+            .write_value(value)
+            .expect("writing to a Vec can't fail");
+        serializer.into_inner()
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    #[error("-FAILURE")]
+    #[error("FAILURE")]
     Failure,
-    #[error("-ERR {0}")]
+    #[error("ERR {0}")]
     Generic(String),
-    #[error("-ERR {0}")]
+    #[error("ERR {0}")]
     GenericStatic(&'static str),
-    #[error("-INVALIDREQ {0}")]
+    #[error("INVALIDREQ {0}")]
     InvalidReq(&'static str),
-    #[error("-UNKNOWN {0}")]
+    #[error("UNKNOWN {0}")]
     UnknownCommand(String),
-    #[error("-WRONGTYPE {0}")]
+    #[error("WRONGTYPE {0}")]
     TypeError(String),
+    #[error("NOPROTO unsupported protocol version")]
+    NoProto,
+    #[error("ERR syntax error")]
+    Syntax,
+    #[error("ERR value is not an integer or out of range")]
+    NotAnInteger,
+    #[error("ERR value is not a valid float")]
+    NotAFloat,
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("ERR string exceeds maximum allowed size (proto-max-bulk-len)")]
+    StringTooLong,
+    #[error("BUSYKEY Target key name already exists.")]
+    BusyKey,
+}
+
+impl From<Error> for RespError {
+    fn from(value: Error) -> Self {
+        RespError::parse(&value.to_string())
+    }
 }
 
 type Resp<T> = Result<T, Error>;
 
+/// the longest string a command may create, 512MB like in Redis
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+/// how many keys KEYS matches before it lets other commands at the store
+const KEYS_CHUNK: usize = 1024;
+
+/// KEYS over more keys than this is reported to the [`App::on_keys_warning`]
+/// hooks, unless `keys-warning-threshold` says otherwise
+const KEYS_WARNING_THRESHOLD: usize = 100_000;
+
+/// values that take more work than this to free are freed in the background
+/// by UNLINK, like `LAZYFREE_THRESHOLD` in Redis
+const LAZYFREE_THRESHOLD: usize = 64;
+
+/// the most members HRANDFIELD and SRANDMEMBER pick for a negative count,
+/// unless `random-reply-limit` says otherwise
+const RANDOM_REPLY_LIMIT: u64 = 1_000_000;
+
 trait ArgParse: Sized {
     fn from_args(args: &[Value]) -> Result<Self, Error>;
 }
 
+/// Resolves the inclusive `start` and `stop` of a list command against a list
+/// of `len` elements, negative ones count from the end. `None` if no element
+/// is in range.
+fn list_range(len: usize, start: i64, stop: i64) -> Option<RangeInclusive<usize>> {
+    let len = len as i64;
+    let resolve = |i: i64| if i < 0 { (len + i).max(0) } else { i };
+    let (start, stop) = (resolve(start), resolve(stop).min(len - 1));
+    (start <= stop).then_some(start as usize..=stop as usize)
+}
+
+fn parse_int(arg: &Value) -> Result<i64, Error> {
+    arg.get_str()
+        .and_then(|x| x.parse::<i64>().ok())
+        .ok_or(Error::NotAnInteger)
+}
+
+/// splits the arguments of MSET and friends into key value pairs
+fn key_value_pairs<'a>(argv: &'a [Value], command: &str) -> Resp<ChunksExact<'a, Value>> {
+    if argv.is_empty() || !argv.len().is_multiple_of(2) {
+        return Err(Error::Generic(format!(
+            "wrong number of arguments for '{command}' command"
+        )));
+    }
+    Ok(argv.chunks_exact(2))
+}
+
+/// The fields HEXPIRE and friends end with, after `FIELDS numfields`.
+fn parse_fields(args: &[Value]) -> Resp<&[Value]> {
+    let missing =
+        Error::GenericStatic("Mandatory argument FIELDS is missing or not at the right position");
+    let [option, numfields, fields @ ..] = args else {
+        return Err(missing);
+    };
+    if !option
+        .get_str()
+        .is_some_and(|o| CaseInsensitive(o) == "fields")
+    {
+        return Err(missing);
+    }
+    let numfields = usize::try_from(parse_int(numfields)?)
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or(Error::GenericStatic(
+            "Parameter `numFields` should be greater than 0",
+        ))?;
+    if numfields != fields.len() {
+        return Err(Error::GenericStatic(
+            "The `numfields` parameter must match the number of arguments",
+        ));
+    }
+    Ok(fields)
+}
+
+/// splits `args` where `FIELDS` starts, see [`parse_fields`]
+fn split_fields(args: &[Value]) -> (&[Value], &[Value]) {
+    let at = args
+        .iter()
+        .position(|arg| {
+            arg.get_str()
+                .is_some_and(|a| CaseInsensitive(a) == "fields")
+        })
+        .unwrap_or(args.len());
+    args.split_at(at)
+}
+
+/// parses a finite float, exponents are allowed
+fn parse_float(arg: &Value) -> Result<f64, Error> {
+    arg.get_str()
+        .and_then(|x| x.parse::<f64>().ok())
+        .filter(|x| x.is_finite())
+        .ok_or(Error::NotAFloat)
+}
+
+/// `current + by` for INCRBY and friends, which fail rather than overflow
+fn add_int(current: i64, by: i64) -> Resp<i64> {
+    current.checked_add(by).ok_or(Error::GenericStatic(
+        "increment or decrement would overflow",
+    ))
+}
+
+/// `current + by` for INCRBYFLOAT and friends, which only store finite floats
+fn add_float(current: f64, by: f64) -> Resp<f64> {
+    let new = current + by;
+    if !new.is_finite() {
+        return Err(Error::GenericStatic(
+            "increment would produce NaN or Infinity",
+        ));
+    }
+    Ok(new)
+}
+
+/// Parses the timeout of a blocking command, in seconds. `None` for 0, which
+/// blocks for as long as it takes.
+fn parse_timeout(arg: &Value) -> Result<Option<Duration>, Error> {
+    let secs = parse_float(arg)
+        .map_err(|_| Error::GenericStatic("timeout is not a float or out of range"))?;
+    if secs < 0.0 {
+        return Err(Error::GenericStatic("timeout is negative"));
+    }
+    if secs == 0.0 {
+        return Ok(None);
+    }
+    Duration::try_from_secs_f64(secs)
+        .map(Some)
+        .map_err(|_| Error::GenericStatic("timeout is out of range"))
+}
+
+/// parses the `LEFT` or `RIGHT` of LMOVE and friends, true for `LEFT`
+fn parse_end(arg: &Value) -> Result<bool, Error> {
+    let arg = arg.get_str().ok_or(Error::Syntax)?;
+    if CaseInsensitive(arg) == "left" {
+        Ok(true)
+    } else if CaseInsensitive(arg) == "right" {
+        Ok(false)
+    } else {
+        Err(Error::Syntax)
+    }
+}
+
+/// An expiry as given in a command. Relative ones are resolved against the
+/// store's clock when the command runs.
+#[derive(Debug, Clone, Copy)]
+enum Deadline {
+    /// in this many ms
+    In(u128),
+    /// at this unix time in ms
+    At(u128),
+}
+
+impl Deadline {
+    fn resolve(self, now: u128) -> u128 {
+        match self {
+            Deadline::In(ms) => now + ms,
+            Deadline::At(ms) => ms,
+        }
+    }
+}
+
+/// Parses the relative (`EX`, `PX`) and absolute (`EXAT`, `PXAT`) expiry
+/// options shared by SET, GETEX and friends. Returns `None` if `option` is not
+/// one of them, otherwise consumes its argument from `args` and returns the
+/// deadline.
+fn parse_expiry<'a>(
+    option: &str,
+    args: &mut impl Iterator<Item = &'a Value>,
+    command: &str,
+) -> Result<Option<Deadline>, Error> {
+    let option = CaseInsensitive(option);
+    let (scale, absolute) = if option == "ex" {
+        (1000, false)
+    } else if option == "px" {
+        (1, false)
+    } else if option == "exat" {
+        (1000, true)
+    } else if option == "pxat" {
+        (1, true)
+    } else {
+        return Ok(None);
+    };
+
+    let amount = parse_int(args.next().ok_or(Error::Syntax)?)?;
+    let invalid = || Error::Generic(format!("invalid expire time in '{command}' command"));
+    let ms = amount
+        .checked_mul(scale)
+        .filter(|ms| *ms > 0)
+        .ok_or_else(invalid)? as u128;
+
+    if absolute {
+        Ok(Some(Deadline::At(ms)))
+    } else {
+        Ok(Some(Deadline::In(ms)))
+    }
+}
+
+/// The `NX`, `XX`, `GT` and `LT` options of EXPIRE and friends. Like in
+/// Redis, a key without an expiry counts as never expiring for `GT` and `LT`.
+#[derive(Debug, Default, Clone, Copy)]
+struct ExpireConditions {
+    /// only if the key has no expiry
+    nx: bool,
+    /// only if it has one
+    xx: bool,
+    /// only if the new expiry is later
+    gt: bool,
+    /// only if the new expiry is earlier
+    lt: bool,
+}
+
+impl ArgParse for ExpireConditions {
+    fn from_args(args: &[Value]) -> Result<Self, Error> {
+        let mut conditions = Self::default();
+        for arg in args {
+            let option = arg.get_str().ok_or(Error::Syntax)?;
+            let flag = match CaseInsensitive(option) {
+                o if o == "nx" => &mut conditions.nx,
+                o if o == "xx" => &mut conditions.xx,
+                o if o == "gt" => &mut conditions.gt,
+                o if o == "lt" => &mut conditions.lt,
+                _ => return Err(Error::Generic(format!("Unsupported option {option}"))),
+            };
+            *flag = true;
+        }
+
+        if conditions.nx && (conditions.xx || conditions.gt || conditions.lt) {
+            return Err(Error::GenericStatic(
+                "NX and XX, GT or LT options at the same time are not compatible",
+            ));
+        }
+        if conditions.gt && conditions.lt {
+            return Err(Error::GenericStatic(
+                "GT and LT options at the same time are not compatible",
+            ));
+        }
+        Ok(conditions)
+    }
+}
+
+impl ExpireConditions {
+    /// whether a key expiring at `current` may get the expiry `new`
+    fn allow(self, current: Option<u128>, new: i128) -> bool {
+        match current {
+            None => !self.xx && !self.gt,
+            Some(current) => {
+                let current = current as i128;
+                !self.nx && (!self.gt || new > current) && (!self.lt || new < current)
+            }
+        }
+    }
+}
+
+/// when SET should write the value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Condition {
+    /// only if the key doesn't exist yet
+    Nx,
+    /// only if it does
+    Xx,
+}
+
 struct SetArgs {
     key: Value,
     val: Value,
-    expiry: Option<i64>,
+    expiry: Option<Deadline>,
+    /// keep the key's current expiry instead of clearing it
+    keep_ttl: bool,
+    condition: Option<Condition>,
+    /// reply with the old value instead of OK
+    get: bool,
+}
+
+impl SetArgs {
+    /// a plain `SET key val`
+    fn new(key: Value, val: Value) -> Self {
+        Self {
+            key,
+            val,
+            expiry: None,
+            keep_ttl: false,
+            condition: None,
+            get: false,
+        }
+    }
 }
 
 impl ArgParse for SetArgs {
@@ -117,189 +695,7249 @@ impl ArgParse for SetArgs {
             .ok_or(Error::GenericStatic("set is missing value argument"))?;
         let val = val.to_owned();
 
-        let mut out = SetArgs {
-            key,
-            val,
-            expiry: None,
-        };
+        let mut out = SetArgs::new(key, val);
 
         let mut args = args.iter();
 
         while let Some(arg) = args.next() {
-            if arg.get_str().is_some_and(|x| CaseInsensitive(x) == "px") {
-                let expiry = args
-                    .next()
-                    .ok_or(Error::GenericStatic("PX expects expiry."))?;
-
-                let expiry = expiry
-                    .get_str()
-                    .and_then(|x| x.parse::<i64>().ok())
-                    .ok_or(Error::TypeError("expiry must be an int".into()))?;
+            let arg = arg.get_str().ok_or(Error::Syntax)?;
+            let condition = if CaseInsensitive(arg) == "nx" {
+                Condition::Nx
+            } else if CaseInsensitive(arg) == "xx" {
+                Condition::Xx
+            } else if CaseInsensitive(arg) == "get" {
+                out.get = true;
+                continue;
+            } else if CaseInsensitive(arg) == "keepttl" {
+                if out.expiry.is_some() {
+                    return Err(Error::Syntax);
+                }
+                out.keep_ttl = true;
+                continue;
+            } else if let Some(deadline) = parse_expiry(arg, &mut args, "set")? {
+                if out.expiry.is_some() || out.keep_ttl {
+                    return Err(Error::Syntax);
+                }
+                out.expiry = Some(deadline);
+                continue;
+            } else {
+                return Err(Error::Syntax);
+            };
 
-                out.expiry = Some(expiry)
+            if out.condition.is_some_and(|c| c != condition) {
+                return Err(Error::Syntax);
             }
+            out.condition = Some(condition);
         }
 
         Ok(out)
     }
 }
 
-enum ConfigArgs {
-    Get(String),
-    Set(String, String),
+enum Expiry {
+    At(Deadline),
+    Persist,
 }
 
-impl ArgParse for ConfigArgs {
+struct GetExArgs {
+    key: Value,
+    expiry: Option<Expiry>,
+}
+
+impl ArgParse for GetExArgs {
     fn from_args(args: &[Value]) -> Result<Self, Error> {
-        let (verb, args) = args
-            .split_first()
-            .ok_or(Error::GenericStatic("config requires verb (get/set)"))?;
+        GetExArgs::parse(args, "getex")
+    }
+}
 
-        let verb = verb
-            .get_str()
-            .ok_or(Error::GenericStatic("verb must be string"))?
-            .to_ascii_lowercase();
+impl GetExArgs {
+    /// what `command` takes, HGETEX takes the same before its fields
+    fn parse(args: &[Value], command: &str) -> Result<Self, Error> {
+        let (key, args) = args.split_first().ok_or_else(|| {
+            Error::Generic(format!("wrong number of arguments for '{command}' command"))
+        })?;
 
-        match verb.as_str() {
-            "get" => {
-                if args.len() != 1 {
-                    return Err(Error::GenericStatic(
-                        "config get requires exactly one parameter",
-                    ));
-                }
-                let key = args[0]
-                    .get_str()
-                    .ok_or(Error::GenericStatic("config get key must be string"))?;
-                Ok(Self::Get(key.clone()))
-            }
-            "set" => {
-                if args.len() != 2 {
-                    return Err(Error::GenericStatic(
-                        "config set requires exactly two parameters",
-                    ));
-                }
+        let mut out = GetExArgs {
+            key: key.to_owned(),
+            expiry: None,
+        };
 
-                let key = args[0]
-                    .get_str()
-                    .ok_or(Error::GenericStatic("config set key must be string"))?
-                    .clone();
-                let value = args[1]
-                    .get_str()
-                    .ok_or(Error::GenericStatic("config set value must be string"))?
-                    .clone();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let arg = arg.get_str().ok_or(Error::Syntax)?;
+            let expiry = if CaseInsensitive(arg) == "persist" {
+                Expiry::Persist
+            } else if let Some(deadline) = parse_expiry(arg, &mut args, command)? {
+                Expiry::At(deadline)
+            } else {
+                return Err(Error::Syntax);
+            };
 
-                Ok(Self::Set(key, value))
+            if out.expiry.is_some() {
+                return Err(Error::Syntax);
             }
-            _ => Err(Error::GenericStatic("config requires verb (get/set)")),
+            out.expiry = Some(expiry);
         }
-    }
-}
 
-impl App {
-    pub fn set_config(&self, key: String, value: String) {
-        self.config.lock().insert(key, value);
+        Ok(out)
     }
+}
 
-    pub async fn ping(&self) -> Resp<impl Serialize> {
-        Ok("PONG")
-    }
+/// SCAN's cursor and options
+struct ScanArgs {
+    cursor: u64,
+    pattern: Option<Vec<u8>>,
+    count: usize,
+    kind: Option<Kind>,
+    /// HSCAN only replies with the fields
+    no_values: bool,
+}
 
-    pub async fn echo(&self, argv: &[Value]) -> Resp<impl Serialize> {
-        let [v] = argv else {
-            return Err(Error::InvalidReq("echo expects exactly one argument"));
-        };
-        Ok(v.clone())
+impl ArgParse for ScanArgs {
+    fn from_args(args: &[Value]) -> Result<Self, Error> {
+        ScanArgs::parse(args, "scan")
     }
+}
 
-    pub async fn set(&self, argv: &[Value]) -> Resp<impl Serialize> {
-        let args = SetArgs::from_args(argv)?;
+impl ScanArgs {
+    /// What `command` takes after its key, if it has one. TYPE is only for
+    /// SCAN and NOVALUES only for HSCAN, SSCAN takes neither.
+    fn parse(args: &[Value], command: &str) -> Result<Self, Error> {
+        let (cursor, args) = args.split_first().ok_or_else(|| {
+            Error::Generic(format!("wrong number of arguments for '{command}' command"))
+        })?;
+        let cursor = cursor
+            .get_str()
+            .and_then(|c| c.parse().ok())
+            .ok_or(Error::GenericStatic("invalid cursor"))?;
 
-        let mut map = self.store.lock();
-        let mut entry = Entry::new(args.val);
+        let mut out = ScanArgs {
+            cursor,
+            pattern: None,
+            count: 10,
+            kind: None,
+            no_values: false,
+        };
 
-        if let Some(expiry) = args.expiry.and_then(|x| x.try_into().ok()) {
-            entry.expires_in(expiry);
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let arg = arg.get_str().ok_or(Error::Syntax)?;
+            if command == "hscan" && CaseInsensitive(arg) == "novalues" {
+                out.no_values = true;
+                continue;
+            }
+            let value = args.next().ok_or(Error::Syntax)?;
+            if CaseInsensitive(arg) == "match" {
+                out.pattern = Some(value.get_bytes().ok_or(Error::Syntax)?.to_vec());
+            } else if CaseInsensitive(arg) == "count" {
+                out.count = usize::try_from(parse_int(value)?)
+                    .ok()
+                    .filter(|count| *count > 0)
+                    .ok_or(Error::Syntax)?;
+            } else if command == "scan" && CaseInsensitive(arg) == "type" {
+                let name = value.get_str().ok_or(Error::Syntax)?;
+                out.kind = Some(
+                    Kind::from_name(name)
+                        .ok_or_else(|| Error::Generic(format!("unknown type name '{name}'")))?,
+                );
+            } else {
+                return Err(Error::Syntax);
+            }
         }
 
-        map.insert(args.key, entry);
-
-        Ok("OK")
+        Ok(out)
     }
+}
 
-    pub async fn get(&self, argv: &[Value]) -> Resp<impl Serialize> {
-        let [k] = argv else {
-            return Err(Error::InvalidReq("get expects exactly one argument"));
+struct HelloArgs {
+    protocol: Option<Protocol>,
+}
+
+impl ArgParse for HelloArgs {
+    fn from_args(args: &[Value]) -> Result<Self, Error> {
+        let Some((protover, rest)) = args.split_first() else {
+            return Ok(Self { protocol: None });
         };
-        let map = self.store.lock();
-        let v = map.get(k).cloned().unwrap_or_default();
 
-        if v.is_expired() {
-            return Ok(Value::Null);
+        if !rest.is_empty() {
+            return Err(Error::GenericStatic("HELLO options are not supported"));
         }
 
-        Ok(v.value)
+        let protocol = match protover.get_str() {
+            Some("2") => Protocol::Resp2,
+            Some("3") => Protocol::Resp3,
+            Some(v) if v.parse::<i64>().is_ok() => return Err(Error::NoProto),
+            _ => {
+                return Err(Error::GenericStatic(
+                    "Protocol version is not an integer or out of range",
+                ))
+            }
+        };
+
+        Ok(Self {
+            protocol: Some(protocol),
+        })
     }
+}
 
-    pub async fn config(&self, argv: &[Value]) -> Resp<impl Serialize> {
-        let args = ConfigArgs::from_args(argv)?;
+enum MemoryArgs {
+    Usage(Value),
+}
+
+impl ArgParse for MemoryArgs {
+    fn from_args(args: &[Value]) -> Result<Self, Error> {
+        let (sub, args) = args
+            .split_first()
+            .ok_or(Error::GenericStatic("memory requires a subcommand"))?;
+        let sub = sub
+            .get_str()
+            .ok_or(Error::GenericStatic("subcommand must be string"))?;
+
+        if CaseInsensitive(sub) != "usage" {
+            return Err(Error::Generic(format!("unknown memory subcommand '{sub}'")));
+        }
 
+        let (key, args) = args.split_first().ok_or(Error::GenericStatic(
+            "wrong number of arguments for 'memory|usage' command",
+        ))?;
+        // SAMPLES only matters for sampling nested values, sizes here are exact
         match args {
-            ConfigArgs::Get(k) => {
-                let config_value = Value::String(self.config.lock().get(&k).cloned());
-                Ok(Value::Array(Some(vec![
-                    Value::String(Some(k)),
-                    config_value,
-                ])))
-            }
-            ConfigArgs::Set(key, value) => {
-                self.set_config(key, value);
-                Ok(Value::str("OK"))
+            [] => {}
+            [samples, count]
+                if samples
+                    .get_str()
+                    .is_some_and(|x| CaseInsensitive(x) == "samples") =>
+            {
+                parse_int(count)?;
             }
+            _ => return Err(Error::Syntax),
         }
+
+        Ok(Self::Usage(key.clone()))
     }
+}
 
-    async fn dispatch_inner(&self, arg: Value) -> Resp<Vec<u8>> {
-        let Value::Array(Some(argv)) = arg else {
-            return Err(Error::InvalidReq("command must be an array"));
+struct LcsArgs {
+    a: Value,
+    b: Value,
+    /// reply with just the length
+    len: bool,
+    /// reply with where the matches are
+    idx: bool,
+    /// leave out matches shorter than this with IDX
+    min_match_len: usize,
+    /// add the length of each match with IDX
+    with_match_len: bool,
+}
+
+impl ArgParse for LcsArgs {
+    fn from_args(args: &[Value]) -> Result<Self, Error> {
+        let [a, b, args @ ..] = args else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'lcs' command",
+            ));
+        };
+        let mut out = LcsArgs {
+            a: a.clone(),
+            b: b.clone(),
+            len: false,
+            idx: false,
+            min_match_len: 0,
+            with_match_len: false,
         };
 
-        let Some((cmd, args)) = argv.split_first() else {
-            return Err(Error::InvalidReq("argv must not be empty"));
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let arg = arg.get_str().ok_or(Error::Syntax)?;
+            if CaseInsensitive(arg) == "len" {
+                out.len = true;
+            } else if CaseInsensitive(arg) == "idx" {
+                out.idx = true;
+            } else if CaseInsensitive(arg) == "minmatchlen" {
+                let min = parse_int(args.next().ok_or(Error::Syntax)?)?;
+                out.min_match_len = usize::try_from(min).unwrap_or(0);
+            } else if CaseInsensitive(arg) == "withmatchlen" {
+                out.with_match_len = true;
+            } else {
+                return Err(Error::Syntax);
+            }
+        }
+        if out.len && out.idx {
+            return Err(Error::GenericStatic(
+                "If you want both the length and indexes, please just use IDX.",
+            ));
+        }
+        Ok(out)
+    }
+}
+
+/// what LMPOP and BLMPOP take, after the timeout of BLMPOP
+struct MpopArgs {
+    keys: Vec<Value>,
+    /// pop off the head of the list rather than the tail
+    front: bool,
+    count: usize,
+}
+
+impl ArgParse for MpopArgs {
+    fn from_args(args: &[Value]) -> Result<Self, Error> {
+        let [numkeys, args @ ..] = args else {
+            return Err(Error::Syntax);
         };
-        let Value::String(Some(command)) = cmd else {
-            return Err(Error::TypeError("command must be a string".into()));
+        let numkeys = usize::try_from(parse_int(numkeys)?)
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or(Error::GenericStatic("numkeys should be greater than 0"))?;
+        if args.len() <= numkeys {
+            return Err(Error::Syntax);
+        }
+        let (keys, args) = args.split_at(numkeys);
+        let [end, args @ ..] = args else {
+            return Err(Error::Syntax);
         };
+        let count = match args {
+            [] => 1,
+            [option, count]
+                if option
+                    .get_str()
+                    .is_some_and(|o| CaseInsensitive(o) == "count") =>
+            {
+                usize::try_from(parse_int(count)?)
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or(Error::GenericStatic("count should be greater than 0"))?
+            }
+            _ => return Err(Error::Syntax),
+        };
+        Ok(MpopArgs {
+            keys: keys.to_vec(),
+            front: parse_end(end)?,
+            count,
+        })
+    }
+}
 
-        match command.to_lowercase().as_str() {
-            "ping" => self.ping().await.to_bytes(),
-            "echo" => self.echo(args).await.to_bytes(),
-            "set" => self.set(args).await.to_bytes(),
-            "get" => self.get(args).await.to_bytes(),
-            "config" => self.config(args).await.to_bytes(),
-            _ => Err(Error::UnknownCommand(command.to_owned())),
+struct LposArgs {
+    key: Value,
+    element: Value,
+    /// which match to start from, negative ones count from the tail
+    rank: i64,
+    /// how many matches to reply with, 0 for all of them, `None` for the
+    /// first one without an array around it
+    count: Option<usize>,
+    /// how many elements to look at at most, 0 for all of them
+    max_len: usize,
+}
+
+impl ArgParse for LposArgs {
+    fn from_args(args: &[Value]) -> Result<Self, Error> {
+        let [key, element, args @ ..] = args else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'lpos' command",
+            ));
+        };
+        let mut out = LposArgs {
+            key: key.clone(),
+            element: element.clone(),
+            rank: 1,
+            count: None,
+            max_len: 0,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let arg = arg.get_str().ok_or(Error::Syntax)?;
+            let mut value = || parse_int(args.next().ok_or(Error::Syntax)?);
+            if CaseInsensitive(arg) == "rank" {
+                let rank = value()?;
+                if rank == 0 {
+                    return Err(Error::GenericStatic(
+                        "RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list",
+                    ));
+                }
+                out.rank = rank;
+            } else if CaseInsensitive(arg) == "count" {
+                let count = usize::try_from(value()?)
+                    .map_err(|_| Error::GenericStatic("COUNT can't be negative"))?;
+                out.count = Some(count);
+            } else if CaseInsensitive(arg) == "maxlen" {
+                out.max_len = usize::try_from(value()?)
+                    .map_err(|_| Error::GenericStatic("MAXLEN can't be negative"))?;
+            } else {
+                return Err(Error::Syntax);
+            }
         }
+        Ok(out)
     }
+}
 
-    pub async fn dispatch_command(&self, arg: Value) -> Vec<u8> {
-        match self.dispatch_inner(arg).await {
-            Ok(i) => i,
-            Err(e) => (e.to_string() + "\r\n").into_bytes(),
+/// what HRANDFIELD takes, without a count it replies with a single field
+struct RandFieldArgs {
+    key: Value,
+    count: Option<i64>,
+    with_values: bool,
+}
+
+impl ArgParse for RandFieldArgs {
+    fn from_args(args: &[Value]) -> Result<Self, Error> {
+        let (key, count, with_values) = match args {
+            [key] => (key, None, false),
+            [key, count] => (key, Some(count), false),
+            [key, count, option]
+                if option
+                    .get_str()
+                    .is_some_and(|o| CaseInsensitive(o) == "withvalues") =>
+            {
+                (key, Some(count), true)
+            }
+            _ => return Err(Error::Syntax),
+        };
+        let count = count.map(parse_int).transpose()?;
+        // the reply to a negative count has that many entries, twice as many
+        // values with WITHVALUES
+        let min = if with_values {
+            -i64::MAX / 2
+        } else {
+            -i64::MAX
+        };
+        if count.is_some_and(|count| count < min) {
+            return Err(Error::GenericStatic("value is out of range"));
         }
+        Ok(RandFieldArgs {
+            key: key.clone(),
+            count,
+            with_values,
+        })
     }
 }
 
-trait ToBytes {
-    fn to_bytes(self) -> Result<Vec<u8>, Error>;
+/// what SINTERCARD takes
+struct InterCardArgs {
+    keys: Vec<Value>,
+    /// stop counting there, 0 for no limit
+    limit: usize,
 }
 
-impl<T> ToBytes for Result<T, Error>
-where
-    T: Serialize,
-{
-    fn to_bytes(self) -> Result<Vec<u8>, Error> {
+impl ArgParse for InterCardArgs {
+    fn from_args(args: &[Value]) -> Result<Self, Error> {
+        let [numkeys, args @ ..] = args else {
+            return Err(Error::Syntax);
+        };
+        let numkeys = usize::try_from(parse_int(numkeys)?)
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or(Error::GenericStatic("numkeys should be greater than 0"))?;
+        if args.len() < numkeys {
+            return Err(Error::GenericStatic(
+                "Number of keys can't be greater than number of args",
+            ));
+        }
+        let (keys, args) = args.split_at(numkeys);
+        let limit = match args {
+            [] => 0,
+            [option, limit]
+                if option
+                    .get_str()
+                    .is_some_and(|o| CaseInsensitive(o) == "limit") =>
+            {
+                usize::try_from(parse_int(limit)?)
+                    .map_err(|_| Error::GenericStatic("LIMIT can't be negative"))?
+            }
+            _ => return Err(Error::Syntax),
+        };
+        Ok(InterCardArgs {
+            keys: keys.to_vec(),
+            limit,
+        })
+    }
+}
+
+/// what SINTER, SUNION and SDIFF do with their sets
+#[derive(Debug, Clone, Copy)]
+enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+impl SetOp {
+    fn apply(self, sets: &[Arc<IndexSet<Value>>]) -> IndexSet<Value> {
         match self {
-            Ok(ok) => to_bytes(&ok).map_err(|_| Error::GenericStatic("failed to serialize")),
-            Err(i) => Err(i),
+            SetOp::Inter => intersection(sets).cloned().collect(),
+            SetOp::Union => sets.iter().flat_map(|set| set.iter()).cloned().collect(),
+            SetOp::Diff => {
+                let (first, rest) = sets.split_first().expect("at least one key");
+                first
+                    .iter()
+                    .filter(|member| !rest.iter().any(|set| set.contains(*member)))
+                    .cloned()
+                    .collect()
+            }
+        }
+    }
+}
+
+/// The members all of `sets` have, found lazily so SINTERCARD can stop at
+/// its limit. Only the smallest set is walked.
+fn intersection(sets: &[Arc<IndexSet<Value>>]) -> impl Iterator<Item = &Value> {
+    let mut sets: Vec<&IndexSet<Value>> = sets.iter().map(|set| &**set).collect();
+    sets.sort_by_key(|set| set.len());
+    let smallest = sets.remove(0);
+    smallest
+        .iter()
+        .filter(move |member| sets.iter().all(|set| set.contains(*member)))
+}
+
+struct SortArgs {
+    key: Value,
+    /// the pattern of the keys to sort by instead of the elements
+    by: Option<Vec<u8>>,
+    /// offset and count
+    limit: Option<(i64, i64)>,
+    /// the patterns of what to reply with instead of the elements
+    get: Vec<Vec<u8>>,
+    desc: bool,
+    /// compare bytes instead of numbers
+    alpha: bool,
+    /// where to store the result as a list
+    store: Option<Value>,
+}
+
+impl SortArgs {
+    fn from_args(args: &[Value], read_only: bool) -> Result<Self, Error> {
+        let (key, args) = args.split_first().ok_or(Error::Generic(format!(
+            "wrong number of arguments for '{}' command",
+            if read_only { "sort_ro" } else { "sort" }
+        )))?;
+        let mut out = SortArgs {
+            key: key.clone(),
+            by: None,
+            limit: None,
+            get: vec![],
+            desc: false,
+            alpha: false,
+            store: None,
+        };
+
+        let mut args = args.iter();
+        let pattern = |args: &mut std::slice::Iter<Value>| {
+            args.next()
+                .and_then(Value::get_bytes)
+                .map(<[u8]>::to_vec)
+                .ok_or(Error::Syntax)
+        };
+        while let Some(arg) = args.next() {
+            let arg = arg.get_str().ok_or(Error::Syntax)?;
+            if CaseInsensitive(arg) == "asc" {
+                out.desc = false;
+            } else if CaseInsensitive(arg) == "desc" {
+                out.desc = true;
+            } else if CaseInsensitive(arg) == "alpha" {
+                out.alpha = true;
+            } else if CaseInsensitive(arg) == "by" {
+                out.by = Some(pattern(&mut args)?);
+            } else if CaseInsensitive(arg) == "get" {
+                out.get.push(pattern(&mut args)?);
+            } else if CaseInsensitive(arg) == "limit" {
+                let offset = parse_int(args.next().ok_or(Error::Syntax)?)?;
+                let count = parse_int(args.next().ok_or(Error::Syntax)?)?;
+                out.limit = Some((offset, count));
+            } else if CaseInsensitive(arg) == "store" && !read_only {
+                out.store = Some(args.next().ok_or(Error::Syntax)?.clone());
+            } else {
+                return Err(Error::Syntax);
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Looks up what a BY or GET `pattern` of SORT refers to for `element`. The
+/// first `*` in it is replaced by the element to get a key, which holds the
+/// value, or with `->field` after the `*`, a hash that holds it in `field`.
+/// `#` is the element itself. Keys of the wrong type count as missing.
+fn sort_lookup(map: &Store, pattern: &[u8], element: &[u8]) -> Option<Vec<u8>> {
+    if pattern == b"#" {
+        return Some(element.to_vec());
+    }
+    let star = pattern.iter().position(|&c| c == b'*')?;
+    let (key_end, field) = match pattern[star + 1..].windows(2).position(|w| w == b"->") {
+        Some(arrow) if star + 1 + arrow + 2 < pattern.len() => {
+            let arrow = star + 1 + arrow;
+            (arrow, Some(&pattern[arrow + 2..]))
+        }
+        _ => (pattern.len(), None),
+    };
+    let key = [&pattern[..star], element, &pattern[star + 1..key_end]].concat();
+
+    let entry = map.get(&Value::bytes(key))?;
+    match field {
+        Some(field) => {
+            let (hash, field) = (entry.value.as_hash()?, Value::bytes(field));
+            if !hash.is_live(&field, map.now_ms()) {
+                return None;
+            }
+            hash.get(&field)?.get_bytes().map(<[u8]>::to_vec)
+        }
+        None if entry.value.kind() == Kind::String => {
+            entry.value.to_value().get_bytes().map(<[u8]>::to_vec)
+        }
+        None => None,
+    }
+}
+
+struct CopyArgs {
+    source: Value,
+    destination: Value,
+    /// overwrite the destination if it exists
+    replace: bool,
+}
+
+impl ArgParse for CopyArgs {
+    fn from_args(args: &[Value]) -> Result<Self, Error> {
+        let [source, destination, args @ ..] = args else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'copy' command",
+            ));
+        };
+        let mut out = CopyArgs {
+            source: source.clone(),
+            destination: destination.clone(),
+            replace: false,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let arg = arg.get_str().ok_or(Error::Syntax)?;
+            if CaseInsensitive(arg) == "replace" {
+                out.replace = true;
+            } else if CaseInsensitive(arg) == "db" {
+                // there is only one database
+                if parse_int(args.next().ok_or(Error::Syntax)?)? != 0 {
+                    return Err(Error::GenericStatic("DB index is out of range"));
+                }
+            } else {
+                return Err(Error::Syntax);
+            }
+        }
+        Ok(out)
+    }
+}
+
+struct RestoreArgs {
+    key: Value,
+    /// in milliseconds, 0 for no expiry
+    ttl: i64,
+    payload: Value,
+    /// overwrite the key if it exists
+    replace: bool,
+    /// `ttl` is a unix time rather than relative to now
+    absttl: bool,
+    /// seconds since the key was last accessed, for LRU eviction
+    idle: Option<u64>,
+    /// the access frequency counter, for LFU eviction
+    freq: Option<u8>,
+}
+
+impl ArgParse for RestoreArgs {
+    fn from_args(args: &[Value]) -> Result<Self, Error> {
+        let [key, ttl, payload, args @ ..] = args else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'restore' command",
+            ));
+        };
+        let ttl = parse_int(ttl)?;
+        if ttl < 0 {
+            return Err(Error::GenericStatic("Invalid TTL value, must be >= 0"));
+        }
+        let mut out = RestoreArgs {
+            key: key.clone(),
+            ttl,
+            payload: payload.clone(),
+            replace: false,
+            absttl: false,
+            idle: None,
+            freq: None,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let arg = arg.get_str().ok_or(Error::Syntax)?;
+            if CaseInsensitive(arg) == "replace" {
+                out.replace = true;
+            } else if CaseInsensitive(arg) == "absttl" {
+                out.absttl = true;
+            } else if CaseInsensitive(arg) == "idletime" && out.freq.is_none() {
+                // like in Redis, only one of IDLETIME and FREQ
+                let idle = parse_int(args.next().ok_or(Error::Syntax)?)?;
+                out.idle =
+                    Some(u64::try_from(idle).map_err(|_| {
+                        Error::GenericStatic("Invalid IDLETIME value, must be >= 0")
+                    })?);
+            } else if CaseInsensitive(arg) == "freq" && out.idle.is_none() {
+                let freq = parse_int(args.next().ok_or(Error::Syntax)?)?;
+                out.freq = Some(u8::try_from(freq).map_err(|_| {
+                    Error::GenericStatic("Invalid FREQ value, must be >= 0 and <= 255")
+                })?);
+            } else {
+                return Err(Error::Syntax);
+            }
+        }
+        Ok(out)
+    }
+}
+
+enum ObjectArgs {
+    Encoding(Value),
+    Refcount(Value),
+    Idletime(Value),
+    Freq(Value),
+}
+
+impl ArgParse for ObjectArgs {
+    fn from_args(args: &[Value]) -> Result<Self, Error> {
+        let (sub, args) = args
+            .split_first()
+            .ok_or(Error::GenericStatic("object requires a subcommand"))?;
+        let sub = sub
+            .get_str()
+            .ok_or(Error::GenericStatic("subcommand must be string"))?;
+
+        let variant = if CaseInsensitive(sub) == "encoding" {
+            Self::Encoding
+        } else if CaseInsensitive(sub) == "refcount" {
+            Self::Refcount
+        } else if CaseInsensitive(sub) == "idletime" {
+            Self::Idletime
+        } else if CaseInsensitive(sub) == "freq" {
+            Self::Freq
+        } else {
+            return Err(Error::Generic(format!("unknown object subcommand '{sub}'")));
+        };
+
+        let [key] = args else {
+            return Err(Error::Generic(format!(
+                "wrong number of arguments for 'object|{}' command",
+                sub.to_lowercase()
+            )));
+        };
+        Ok(variant(key.clone()))
+    }
+}
+
+/// how often the server should call [`App::active_expire`]
+pub const EXPIRE_CYCLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// how long a single expiry cycle may take, a quarter of the interval like in
+/// Redis
+const EXPIRE_CYCLE_BUDGET: Duration = Duration::from_millis(25);
+
+/// What DEBUG INJECT makes a command do, to see how clients cope.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Fault {
+    /// wait this long before running it
+    Delay(Duration),
+    /// fail with this message instead of running it
+    Error(String),
+}
+
+enum DebugArgs {
+    Sleep(Duration),
+    Object(Value),
+    SetClock(Clock),
+    ExpireCycle,
+    /// `None` removes the fault
+    Inject(String, Option<Fault>),
+}
+
+/// A delay like `50ms`, `2s` or `50`, which is in ms.
+fn parse_delay(delay: &Value) -> Result<Duration, Error> {
+    let invalid = || Error::GenericStatic("invalid delay, expected e.g. 50ms or 2s");
+    let delay = delay.get_str().ok_or_else(invalid)?;
+    let (number, scale) = if let Some(ms) = delay.strip_suffix("ms") {
+        (ms, 1)
+    } else if let Some(s) = delay.strip_suffix('s') {
+        (s, 1000)
+    } else {
+        (delay, 1)
+    };
+    let ms = number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .ok_or_else(invalid)?;
+    Ok(Duration::from_millis(ms))
+}
+
+impl ArgParse for DebugArgs {
+    fn from_args(args: &[Value]) -> Result<Self, Error> {
+        let (sub, args) = args
+            .split_first()
+            .ok_or(Error::GenericStatic("debug requires a subcommand"))?;
+
+        let sub = sub
+            .get_str()
+            .ok_or(Error::GenericStatic("subcommand must be string"))?;
+
+        if CaseInsensitive(sub) == "sleep" {
+            let [secs] = args else {
+                return Err(Error::GenericStatic(
+                    "debug sleep expects exactly one argument",
+                ));
+            };
+            let secs = secs
+                .get_str()
+                .and_then(|x| x.parse::<f64>().ok())
+                .and_then(|x| Duration::try_from_secs_f64(x).ok())
+                .ok_or(Error::GenericStatic("value is not a valid float"))?;
+            return Ok(Self::Sleep(secs));
+        }
+
+        if CaseInsensitive(sub) == "object" {
+            let [key] = args else {
+                return Err(Error::GenericStatic(
+                    "debug object expects exactly one argument",
+                ));
+            };
+            return Ok(Self::Object(key.clone()));
+        }
+
+        if CaseInsensitive(sub) == "set-clock" {
+            let [time] = args else {
+                return Err(Error::GenericStatic(
+                    "debug set-clock expects exactly one argument",
+                ));
+            };
+            if time
+                .get_str()
+                .is_some_and(|x| CaseInsensitive(x) == "system")
+            {
+                return Ok(Self::SetClock(Clock::System));
+            }
+            let ms = parse_int(time)?;
+            let ms = u128::try_from(ms).map_err(|_| Error::NotAnInteger)?;
+            return Ok(Self::SetClock(Clock::Frozen(ms)));
+        }
+
+        if CaseInsensitive(sub) == "expire-cycle" {
+            if !args.is_empty() {
+                return Err(Error::GenericStatic(
+                    "debug expire-cycle takes no arguments",
+                ));
+            }
+            return Ok(Self::ExpireCycle);
+        }
+
+        if CaseInsensitive(sub) == "inject" {
+            let [command, kind, rest @ ..] = args else {
+                return Err(Error::GenericStatic(
+                    "debug inject expects a command and DELAY <time>, ERROR [message] or OFF",
+                ));
+            };
+            let command = command.get_str().ok_or(Error::Syntax)?.to_lowercase();
+            if command == "debug" {
+                // it would lock out removing the fault
+                return Err(Error::GenericStatic("can't inject faults into DEBUG"));
+            }
+            let kind = kind.get_str().ok_or(Error::Syntax)?;
+            let fault = match rest {
+                [delay] if CaseInsensitive(kind) == "delay" => {
+                    Some(Fault::Delay(parse_delay(delay)?))
+                }
+                [] if CaseInsensitive(kind) == "error" => {
+                    Some(Fault::Error("injected fault".into()))
+                }
+                [message] if CaseInsensitive(kind) == "error" => Some(Fault::Error(
+                    message.get_str().ok_or(Error::Syntax)?.to_owned(),
+                )),
+                [] if CaseInsensitive(kind) == "off" => None,
+                _ => return Err(Error::Syntax),
+            };
+            return Ok(Self::Inject(command, fault));
+        }
+
+        Err(Error::Generic(format!("unknown debug subcommand '{sub}'")))
+    }
+}
+
+enum ClientArgs {
+    /// the threshold, or `None` to turn compression off
+    Compress(Option<usize>),
+}
+
+impl ArgParse for ClientArgs {
+    fn from_args(args: &[Value]) -> Result<Self, Error> {
+        let (sub, args) = args
+            .split_first()
+            .ok_or(Error::GenericStatic("client requires a subcommand"))?;
+        let sub = sub
+            .get_str()
+            .ok_or(Error::GenericStatic("subcommand must be string"))?;
+
+        if CaseInsensitive(sub) != "compress" {
+            return Err(Error::Generic(format!("unknown client subcommand '{sub}'")));
+        }
+
+        let (toggle, args) = args.split_first().ok_or(Error::GenericStatic(
+            "wrong number of arguments for 'client|compress' command",
+        ))?;
+        let toggle = toggle.get_str().ok_or(Error::Syntax)?;
+        match args {
+            [] if CaseInsensitive(toggle) == "off" => Ok(Self::Compress(None)),
+            [] if CaseInsensitive(toggle) == "on" => {
+                Ok(Self::Compress(Some(compression::DEFAULT_THRESHOLD)))
+            }
+            [threshold] if CaseInsensitive(toggle) == "on" => {
+                let threshold = parse_int(threshold)?;
+                let threshold = usize::try_from(threshold).map_err(|_| Error::NotAnInteger)?;
+                Ok(Self::Compress(Some(threshold)))
+            }
+            _ => Err(Error::Syntax),
+        }
+    }
+}
+
+enum ConfigArgs {
+    Get(String),
+    Set(String, String),
+}
+
+impl ArgParse for ConfigArgs {
+    fn from_args(args: &[Value]) -> Result<Self, Error> {
+        let (verb, args) = args
+            .split_first()
+            .ok_or(Error::GenericStatic("config requires verb (get/set)"))?;
+
+        let verb = verb
+            .get_str()
+            .ok_or(Error::GenericStatic("verb must be string"))?
+            .to_ascii_lowercase();
+
+        match verb.as_str() {
+            "get" => {
+                if args.len() != 1 {
+                    return Err(Error::GenericStatic(
+                        "config get requires exactly one parameter",
+                    ));
+                }
+                let key = args[0]
+                    .get_str()
+                    .ok_or(Error::GenericStatic("config get key must be string"))?;
+                Ok(Self::Get(key.to_owned()))
+            }
+            "set" => {
+                if args.len() != 2 {
+                    return Err(Error::GenericStatic(
+                        "config set requires exactly two parameters",
+                    ));
+                }
+
+                let key = args[0]
+                    .get_str()
+                    .ok_or(Error::GenericStatic("config set key must be string"))?
+                    .to_owned();
+                let value = args[1]
+                    .get_str()
+                    .ok_or(Error::GenericStatic("config set value must be string"))?
+                    .to_owned();
+
+                Ok(Self::Set(key, value))
+            }
+            _ => Err(Error::GenericStatic("config requires verb (get/set)")),
+        }
+    }
+}
+
+impl App {
+    pub fn set_config(&self, key: String, value: String) {
+        self.config.lock().insert(key, value);
+    }
+
+    fn get_config(&self, key: &str) -> Option<String> {
+        self.config.lock().get(key).cloned()
+    }
+
+    pub async fn ping(&self) -> Resp<impl Serialize> {
+        Ok("PONG")
+    }
+
+    pub async fn hello(&self, client: &mut Client, argv: &[Value]) -> Resp<impl Serialize> {
+        let args = HelloArgs::from_args(argv)?;
+        if let Some(protocol) = args.protocol {
+            client.protocol = protocol;
         }
+
+        let proto = match client.protocol {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        };
+
+        Ok(Map::from([
+            ("server".into(), "redis".into()),
+            ("version".into(), env!("CARGO_PKG_VERSION").into()),
+            ("proto".into(), proto.into()),
+            ("mode".into(), "standalone".into()),
+            ("role".into(), "master".into()),
+            ("modules".into(), Value::from(vec![])),
+        ]))
+    }
+
+    pub async fn echo(&self, argv: &[Value]) -> Resp<Value> {
+        let [v] = argv else {
+            return Err(Error::InvalidReq("echo expects exactly one argument"));
+        };
+        Ok(v.clone())
+    }
+
+    /// Replies with OK, or with the old value (null if there was none) when
+    /// GET is given. If NX or XX keep the value from being written the reply
+    /// is null, or again the old value with GET.
+    pub async fn set(&self, argv: &[Value]) -> Resp<Arc<Value>> {
+        self.set_with(SetArgs::from_args(argv)?)
+    }
+
+    fn set_with(&self, args: SetArgs) -> Resp<Arc<Value>> {
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, &args.key);
+        let old = map.get(&args.key);
+        let old_expiry = old.and_then(|e| e.expiry);
+        let old_value = match old {
+            Some(old) if args.get => {
+                if old.value.kind() != Kind::String {
+                    return Err(Error::WrongType);
+                }
+                Some(old.value.to_value())
+            }
+            _ => None,
+        };
+
+        let skip = match args.condition {
+            Some(Condition::Nx) => old.is_some(),
+            Some(Condition::Xx) => old.is_none(),
+            None => false,
+        };
+        if skip {
+            return Ok(old_value.unwrap_or_default());
+        }
+
+        let mut entry = Entry::new(args.val);
+        entry.expiry = if args.keep_ttl {
+            old_expiry
+        } else {
+            args.expiry.map(|d| d.resolve(map.now_ms()))
+        };
+        map.insert(args.key, entry);
+
+        Ok(match old_value {
+            Some(old) => old,
+            None if args.get => Arc::default(),
+            None => Arc::new("OK".into()),
+        })
+    }
+
+    pub async fn get(&self, argv: &[Value]) -> Resp<Arc<Value>> {
+        let [k] = argv else {
+            return Err(Error::InvalidReq("get expects exactly one argument"));
+        };
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, k);
+        match map.access(k) {
+            Some(e) if e.value.kind() != Kind::String => Err(Error::WrongType),
+            Some(e) => Ok(e.value.to_value()),
+            None => Ok(Arc::default()),
+        }
+    }
+
+    pub async fn memory(&self, argv: &[Value]) -> Resp<impl Serialize> {
+        match MemoryArgs::from_args(argv)? {
+            MemoryArgs::Usage(key) => {
+                let map = self.store.lock();
+                Ok(map
+                    .get(&key)
+                    .map(|entry| (key.mem_size() + entry.mem_size()) as i64))
+            }
+        }
+    }
+
+    /// the type of the value at `key`, `none` if there is no such key
+    pub async fn type_of(&self, argv: &[Value]) -> Resp<&'static str> {
+        let [key] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'type' command",
+            ));
+        };
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        Ok(map
+            .get(key)
+            .map_or("none", |entry| entry.value.kind().name()))
+    }
+
+    /// How a key's value is stored and how it's been used. Missing keys get a
+    /// null reply. Looking doesn't count as an access.
+    pub async fn object(&self, argv: &[Value]) -> Resp<Value> {
+        let args = ObjectArgs::from_args(argv)?;
+        let (ObjectArgs::Encoding(key)
+        | ObjectArgs::Refcount(key)
+        | ObjectArgs::Idletime(key)
+        | ObjectArgs::Freq(key)) = &args;
+        // like Redis, only the metadata the eviction policy uses is shown
+        let lfu = self
+            .get_config("maxmemory-policy")
+            .is_some_and(|policy| policy.contains("lfu"));
+        let map = self.store.lock();
+        let now = map.now_ms();
+        let Some(entry) = map.get(key) else {
+            return Ok(Value::Null);
+        };
+        Ok(match args {
+            ObjectArgs::Encoding(_) => entry.value.encoding().into(),
+            ObjectArgs::Refcount(_) => (entry.value.refcount() as i64).into(),
+            ObjectArgs::Idletime(_) if lfu => {
+                return Err(Error::GenericStatic(
+                    "An LFU maxmemory policy is selected, idle time not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.",
+                ))
+            }
+            ObjectArgs::Idletime(_) => {
+                let idle = now.saturating_sub(entry.last_access) / 1000;
+                i64::try_from(idle).unwrap_or(i64::MAX).into()
+            }
+            ObjectArgs::Freq(_) if !lfu => {
+                return Err(Error::GenericStatic(
+                    "An LFU maxmemory policy is not selected, access frequency not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.",
+                ))
+            }
+            ObjectArgs::Freq(_) => i64::from(entry.freq(now)).into(),
+        })
+    }
+
+    pub async fn debug(&self, argv: &[Value]) -> Resp<Value> {
+        match DebugArgs::from_args(argv)? {
+            DebugArgs::Sleep(duration) => {
+                // with a frozen clock, sleeping just moves it forward
+                let advanced = self.store.lock().advance_clock(duration.as_millis());
+                if !advanced {
+                    tokio::time::sleep(duration).await;
+                }
+                Ok("OK".into())
+            }
+            DebugArgs::Object(key) => {
+                let map = self.store.lock();
+                let entry = map.get(&key).ok_or(Error::GenericStatic("no such key"))?;
+                let len = rdb::serialized_length(&entry.value.to_value())
+                    .map_err(|e| Error::Generic(e.to_string()))?;
+                Ok(format!(
+                    "Value at:{:p} refcount:{} encoding:{} serializedlength:{len}",
+                    entry.value.as_ptr(),
+                    entry.value.refcount(),
+                    entry.value.encoding(),
+                )
+                .into())
+            }
+            DebugArgs::SetClock(clock) => {
+                self.store.lock().set_clock(clock);
+                Ok("OK".into())
+            }
+            DebugArgs::Inject(command, fault) => {
+                let mut faults = self.faults.lock();
+                match fault {
+                    Some(fault) => faults.insert(command, fault),
+                    None => faults.remove(&command),
+                };
+                Ok("OK".into())
+            }
+            DebugArgs::ExpireCycle => {
+                self.expire_cycle();
+                let map = self.store.lock();
+                let stats = map.expire_stats();
+                Ok(Map::from([
+                    ("sampled".into(), (stats.last_sampled as i64).into()),
+                    ("expired".into(), (stats.last_expired as i64).into()),
+                    ("stale-perc".into(), stats.stale_percent().into()),
+                    (
+                        "duration-us".into(),
+                        (stats.last_duration.as_micros() as i64).into(),
+                    ),
+                    ("cycles".into(), (stats.cycles as i64).into()),
+                    (
+                        "time-cap-reached".into(),
+                        (stats.time_cap_reached as i64).into(),
+                    ),
+                    ("volatile-keys".into(), (map.stats().expires as i64).into()),
+                ])
+                .into())
+            }
+        }
+    }
+
+    pub async fn info(&self, argv: &[Value]) -> Resp<Value> {
+        let (mut clients, mut stats, mut keyspace) =
+            (argv.is_empty(), argv.is_empty(), argv.is_empty());
+        for section in argv {
+            let section = section
+                .get_str()
+                .ok_or(Error::GenericStatic("section must be string"))?;
+            let all = ["default", "all", "everything"]
+                .iter()
+                .any(|s| CaseInsensitive(section) == *s);
+            clients |= all || CaseInsensitive(section) == "clients";
+            stats |= all || CaseInsensitive(section) == "stats";
+            keyspace |= all || CaseInsensitive(section) == "keyspace";
+        }
+
+        let mut info = String::new();
+        let map = self.store.lock();
+        if clients {
+            let blocked = self.blocked.lock();
+            // clients blocked on several keys wait in the queue of each
+            let waiters: HashMap<_, _> = blocked
+                .values()
+                .flatten()
+                .map(|waiter| (Arc::as_ptr(waiter), waiter.timeout))
+                .collect();
+            info.push_str("# Clients\r\n");
+            info.push_str(&format!("blocked_clients:{}\r\n", waiters.len()));
+            info.push_str(&format!(
+                "clients_in_timeout_table:{}\r\n",
+                waiters.values().filter(|timeout| **timeout).count()
+            ));
+            info.push_str(&format!("total_blocking_keys:{}\r\n", blocked.len()));
+            if stats || keyspace {
+                info.push_str("\r\n");
+            }
+        }
+        if stats {
+            let stats = map.expire_stats();
+            info.push_str("# Stats\r\n");
+            info.push_str(&format!("expired_keys:{}\r\n", stats.expired_keys));
+            info.push_str(&format!(
+                "expired_stale_perc:{:.2}\r\n",
+                stats.stale_percent()
+            ));
+            info.push_str(&format!(
+                "expired_time_cap_reached_count:{}\r\n",
+                stats.time_cap_reached
+            ));
+            info.push_str(&format!(
+                "expire_cycle_cpu_milliseconds:{}\r\n",
+                stats.cycle_time.as_millis()
+            ));
+            info.push_str(&format!("expire_cycles:{}\r\n", stats.cycles));
+            let replies = *self.reply_stats.lock();
+            info.push_str(&format!("reply_pool_hits:{}\r\n", replies.hits));
+            info.push_str(&format!("reply_pool_misses:{}\r\n", replies.misses));
+            info.push_str(&format!(
+                "reply_pool_hit_rate:{:.2}\r\n",
+                replies.hit_rate()
+            ));
+            info.push_str(&format!("reply_buffer_peak:{}\r\n", replies.peak));
+            if keyspace {
+                info.push_str("\r\n");
+            }
+        }
+        if keyspace {
+            info.push_str("# Keyspace\r\n");
+            let stats = map.stats();
+            // there is only one database, and like Redis it's left out when
+            // empty
+            if stats.keys > 0 {
+                info.push_str(&format!(
+                    "db0:keys={},expires={},avg_ttl={}\r\n",
+                    stats.keys,
+                    stats.expires,
+                    stats.avg_ttl(map.now_ms())
+                ));
+                let types: Vec<_> = Kind::ALL
+                    .iter()
+                    .map(|kind| format!("{}={}", kind.name(), stats.count(*kind)))
+                    .collect();
+                info.push_str(&format!("db0_types:{}\r\n", types.join(",")));
+            }
+        }
+
+        Ok(Value::Verbatim {
+            format: *b"txt",
+            text: info,
+        })
+    }
+
+    /// removes the `argv` keys and returns the entries of those that existed
+    fn remove_keys(&self, argv: &[Value], command: &str) -> Resp<Vec<Entry>> {
+        if argv.is_empty() {
+            return Err(Error::Generic(format!(
+                "wrong number of arguments for '{command}' command"
+            )));
+        }
+        let mut map = self.store.lock();
+        let (keys, entries): (Vec<_>, _) = argv
+            .iter()
+            .filter_map(|key| {
+                self.expire_if_needed(&mut map, key);
+                Some((key.clone(), map.remove(key)?))
+            })
+            .unzip();
+        self.evicted(keys, EvictionReason::Deleted);
+        Ok(entries)
+    }
+
+    /// removes the `argv` keys and replies how many there were
+    pub async fn del(&self, argv: &[Value]) -> Resp<i64> {
+        Ok(self.remove_keys(argv, "del")?.len() as i64)
+    }
+
+    /// Like DEL, but big values are freed by a blocking task instead of
+    /// holding up the command.
+    pub async fn unlink(&self, argv: &[Value]) -> Resp<i64> {
+        let removed = self.remove_keys(argv, "unlink")?;
+        let count = removed.len() as i64;
+        let (lazy, now): (Vec<_>, Vec<_>) = removed
+            .into_iter()
+            .partition(|e| e.value.free_effort() > LAZYFREE_THRESHOLD);
+        drop(now);
+        if !lazy.is_empty() {
+            tokio::task::spawn_blocking(move || drop(lazy));
+        }
+        Ok(count)
+    }
+
+    /// how many of the `argv` keys exist, keys given twice count twice
+    pub async fn exists(&self, argv: &[Value]) -> Resp<i64> {
+        if argv.is_empty() {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'exists' command",
+            ));
+        }
+        let mut map = self.store.lock();
+        let count = argv
+            .iter()
+            .filter(|key| {
+                self.expire_if_needed(&mut map, key);
+                map.get(key).is_some()
+            })
+            .count();
+        Ok(count as i64)
+    }
+
+    /// The values of all `argv` keys, with nulls for the ones that are
+    /// missing or don't hold a string.
+    pub async fn mget(&self, argv: &[Value]) -> Resp<Value> {
+        if argv.is_empty() {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'mget' command",
+            ));
+        }
+        let mut map = self.store.lock();
+        let values = argv
+            .iter()
+            .map(|key| {
+                self.expire_if_needed(&mut map, key);
+                match map.access(key) {
+                    Some(entry) if entry.value.kind() == Kind::String => {
+                        (*entry.value.to_value()).clone()
+                    }
+                    _ => Value::Null,
+                }
+            })
+            .collect();
+        Ok(Value::Array(Some(values)))
+    }
+
+    /// Sets every key value pair in `argv`, all at once as far as other
+    /// clients can tell.
+    pub async fn mset(&self, argv: &[Value]) -> Resp<impl Serialize> {
+        let pairs = key_value_pairs(argv, "mset")?;
+        let mut map = self.store.lock();
+        for pair in pairs {
+            self.expire_if_needed(&mut map, &pair[0]);
+            map.insert(pair[0].clone(), Entry::new(pair[1].clone()));
+        }
+        Ok("OK")
+    }
+
+    /// Moves the value at `key` to `new_key` together with its expiry,
+    /// replacing what's there unless `nx`. Returns whether it was moved.
+    fn rename_with(&self, argv: &[Value], nx: bool, command: &str) -> Resp<bool> {
+        let [key, new_key] = argv else {
+            return Err(Error::Generic(format!(
+                "wrong number of arguments for '{command}' command"
+            )));
+        };
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        self.expire_if_needed(&mut map, new_key);
+        if map.get(key).is_none() {
+            return Err(Error::GenericStatic("no such key"));
+        }
+        if key == new_key {
+            return Ok(!nx);
+        }
+        if nx && map.get(new_key).is_some() {
+            return Ok(false);
+        }
+        let entry = map.remove(key).expect("the key exists");
+        self.insert_waking(&mut map, new_key.clone(), entry);
+        Ok(true)
+    }
+
+    pub async fn rename(&self, argv: &[Value]) -> Resp<impl Serialize> {
+        self.rename_with(argv, false, "rename")?;
+        Ok("OK")
+    }
+
+    /// RENAME, but only if `new_key` doesn't exist. Replies 1 if the key was
+    /// renamed and 0 if not.
+    pub async fn renamenx(&self, argv: &[Value]) -> Resp<i64> {
+        Ok(self.rename_with(argv, true, "renamenx")? as i64)
+    }
+
+    /// Copies the value at the source key and its expiry to the destination,
+    /// unless that exists and `REPLACE` isn't given. Replies 1 if the value
+    /// was copied and 0 if not.
+    pub async fn copy(&self, argv: &[Value]) -> Resp<i64> {
+        let args = CopyArgs::from_args(argv)?;
+        if args.source == args.destination {
+            return Err(Error::GenericStatic(
+                "source and destination objects are the same",
+            ));
+        }
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, &args.source);
+        self.expire_if_needed(&mut map, &args.destination);
+        let Some(entry) = map.get(&args.source) else {
+            return Ok(0);
+        };
+        if !args.replace && map.get(&args.destination).is_some() {
+            return Ok(0);
+        }
+        // values are shared until one of the keys changes its own
+        let entry = entry.clone();
+        self.insert_waking(&mut map, args.destination, entry);
+        Ok(1)
+    }
+
+    /// counts an access to each of the keys, replies how many exist
+    pub async fn touch(&self, argv: &[Value]) -> Resp<i64> {
+        if argv.is_empty() {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'touch' command",
+            ));
+        }
+        let mut map = self.store.lock();
+        let count = argv
+            .iter()
+            .filter(|key| {
+                self.expire_if_needed(&mut map, key);
+                map.access(key).is_some()
+            })
+            .count();
+        Ok(count as i64)
+    }
+
+    /// Sorts the elements of a list or set, numerically unless ALPHA is
+    /// given, by themselves or by what BY points to. Replies with them, or
+    /// what GET points to, or stores them as a list with STORE and replies
+    /// how many there are.
+    fn sort_with(&self, argv: &[Value], read_only: bool) -> Resp<Value> {
+        let args = SortArgs::from_args(argv, read_only)?;
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, &args.key);
+        let value = match map.access(&args.key) {
+            Some(entry) => entry.value.to_value(),
+            None => Arc::new(Value::Array(Some(vec![]))),
+        };
+        let elements: Vec<&[u8]> = match &*value {
+            Value::Array(Some(items)) | Value::Set(items) => {
+                items.iter().filter_map(Value::get_bytes).collect()
+            }
+            _ => return Err(Error::WrongType),
+        };
+
+        // a BY pattern that doesn't depend on the element means not to sort
+        let sort = args.by.as_ref().is_none_or(|by| by.contains(&b'*'));
+        // the elements with what they're compared by, alphabetically or
+        // numerically
+        let mut sorted = vec![];
+        for element in elements {
+            let by = match &args.by {
+                Some(by) if sort => sort_lookup(&map, by, element),
+                _ => Some(element.to_vec()),
+            };
+            let score = match &by {
+                _ if args.alpha || !sort => 0.0,
+                // missing weights count as 0
+                None => 0.0,
+                Some(by) => std::str::from_utf8(by)
+                    .ok()
+                    .and_then(|s| s.parse::<f64>().ok())
+                    .filter(|x| !x.is_nan())
+                    .ok_or(Error::GenericStatic(
+                        "One or more scores can't be converted into double",
+                    ))?,
+            };
+            sorted.push((element, by, score));
+        }
+        if sort {
+            sorted.sort_by(|a, b| {
+                let order = if args.alpha {
+                    // missing ones come first
+                    a.1.cmp(&b.1)
+                } else {
+                    // ties are broken by the elements, for a stable reply
+                    a.2.total_cmp(&b.2).then_with(|| a.0.cmp(b.0))
+                };
+                if args.desc {
+                    order.reverse()
+                } else {
+                    order
+                }
+            });
+        }
+
+        let (offset, count) = args.limit.unwrap_or((0, -1));
+        let start = usize::try_from(offset).unwrap_or(0).min(sorted.len());
+        let end = usize::try_from(count).map_or(sorted.len(), |count| {
+            start.saturating_add(count).min(sorted.len())
+        });
+        let mut out = vec![];
+        for (element, ..) in &sorted[start..end] {
+            if args.get.is_empty() {
+                out.push(Value::bytes(*element));
+            }
+            for pattern in &args.get {
+                out.push(sort_lookup(&map, pattern, element).map_or(Value::Null, Value::bytes));
+            }
+        }
+
+        let Some(destination) = args.store else {
+            return Ok(Value::from(out));
+        };
+        let len = out.len() as i64;
+        map.remove(&destination);
+        if len > 0 {
+            // missing values are stored as empty strings
+            let items = out
+                .into_iter()
+                .map(|v| if v == Value::Null { Value::str("") } else { v })
+                .collect();
+            let entry = Entry::new(Value::Array(Some(items)));
+            self.insert_waking(&mut map, destination, entry);
+        }
+        Ok(len.into())
+    }
+
+    pub async fn sort(&self, argv: &[Value]) -> Resp<Value> {
+        self.sort_with(argv, false)
+    }
+
+    /// SORT without STORE
+    pub async fn sort_ro(&self, argv: &[Value]) -> Resp<Value> {
+        self.sort_with(argv, true)
+    }
+
+    /// The value at `key` serialized the way Redis does it, for RESTORE. Null if
+    /// there is no such key.
+    pub async fn dump(&self, argv: &[Value]) -> Resp<Value> {
+        let [key] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'dump' command",
+            ));
+        };
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let Some(entry) = map.get(key) else {
+            return Ok(Value::Null);
+        };
+        let payload = rdb::dump(&entry.value).map_err(|e| Error::Generic(e.to_string()))?;
+        Ok(Value::bytes(payload))
+    }
+
+    /// Creates `key` from a DUMP payload, with the TTL in milliseconds.
+    /// IDLETIME and FREQ set the access metadata eviction goes by, which a
+    /// new key otherwise starts over with.
+    pub async fn restore(&self, argv: &[Value]) -> Resp<&'static str> {
+        let args = RestoreArgs::from_args(argv)?;
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, &args.key);
+        if !args.replace && map.get(&args.key).is_some() {
+            return Err(Error::BusyKey);
+        }
+        let payload = args.payload.get_bytes().ok_or(Error::Syntax)?;
+        let object = rdb::restore(payload).map_err(|e| Error::Generic(e.to_string()))?;
+
+        if args.replace {
+            map.remove(&args.key);
+        }
+        let now = map.now_ms();
+        let expiry = match args.ttl as u128 {
+            0 => None,
+            ttl if args.absttl => Some(ttl),
+            ttl => Some(now + ttl),
+        };
+        // like a SET with an expiry in the past, the key is gone right away
+        if expiry.is_some_and(|expiry| expiry <= now) {
+            return Ok("OK");
+        }
+        let mut entry = Entry::new(object);
+        entry.expiry = expiry;
+        let last_access = args
+            .idle
+            .map(|idle| now.saturating_sub(u128::from(idle) * 1000));
+        self.insert_waking(&mut map, args.key.clone(), entry);
+        map.set_access(&args.key, last_access, args.freq);
+        Ok("OK")
+    }
+
+    /// Like MSET, but only if none of the keys exist. Replies 1 if the keys
+    /// were set and 0 if not.
+    pub async fn msetnx(&self, argv: &[Value]) -> Resp<i64> {
+        let pairs = key_value_pairs(argv, "msetnx")?;
+        let mut map = self.store.lock();
+        for pair in pairs.clone() {
+            self.expire_if_needed(&mut map, &pair[0]);
+            if map.get(&pair[0]).is_some() {
+                return Ok(0);
+            }
+        }
+        for pair in pairs {
+            map.insert(pair[0].clone(), Entry::new(pair[1].clone()));
+        }
+        Ok(1)
+    }
+
+    /// Appends to the string at `key`, creating it if it doesn't exist, and
+    /// returns the new length.
+    pub async fn append(&self, argv: &[Value]) -> Resp<i64> {
+        let [key, tail] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'append' command",
+            ));
+        };
+        let tail = tail.get_bytes().ok_or(Error::Syntax)?;
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let Some(entry) = map.access_mut(key) else {
+            map.insert(key.clone(), Entry::new(Value::bytes(tail)));
+            return Ok(tail.len() as i64);
+        };
+        if entry.value.kind() != Kind::String {
+            return Err(Error::WrongType);
+        }
+        if entry.value.strlen() + tail.len() > MAX_STRING_LEN {
+            return Err(Error::StringTooLong);
+        }
+        Ok(entry.value.append(tail) as i64)
+    }
+
+    /// The bytes of the string at `key` from `start` to `end`, both
+    /// inclusive. Negative indices count from the end.
+    pub async fn getrange(&self, argv: &[Value]) -> Resp<Value> {
+        let [key, start, end] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'getrange' command",
+            ));
+        };
+        let (mut start, mut end) = (parse_int(start)?, parse_int(end)?);
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let value = match map.access(key) {
+            Some(entry) if entry.value.kind() != Kind::String => return Err(Error::WrongType),
+            Some(entry) => &entry.value,
+            None => return Ok(Value::str("")),
+        };
+
+        let len = value.strlen() as i64;
+        if start < 0 && end < 0 && start > end {
+            return Ok(Value::str(""));
+        }
+        if start < 0 {
+            start = (len + start).max(0);
+        }
+        if end < 0 {
+            end = (len + end).max(0);
+        }
+        end = end.min(len - 1);
+        if start > end || len == 0 {
+            return Ok(Value::str(""));
+        }
+        Ok(Value::bytes(value.range(start as usize..end as usize + 1)))
+    }
+
+    /// Overwrites the string at `key` from `offset` on, padding it with zeros
+    /// if needed, and returns its new length.
+    pub async fn setrange(&self, argv: &[Value]) -> Resp<i64> {
+        let [key, offset, bytes] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'setrange' command",
+            ));
+        };
+        let offset = usize::try_from(parse_int(offset)?)
+            .map_err(|_| Error::GenericStatic("offset is out of range"))?;
+        let bytes = bytes.get_bytes().ok_or(Error::Syntax)?;
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let entry = map.access_mut(key);
+        if entry
+            .as_ref()
+            .is_some_and(|e| e.value.kind() != Kind::String)
+        {
+            return Err(Error::WrongType);
+        }
+        // nothing is written, not even the padding
+        if bytes.is_empty() {
+            return Ok(entry.map_or(0, |e| e.value.strlen()) as i64);
+        }
+        if offset.saturating_add(bytes.len()) > MAX_STRING_LEN {
+            return Err(Error::StringTooLong);
+        }
+
+        match entry {
+            Some(entry) => Ok(entry.value.set_range(offset, bytes) as i64),
+            None => {
+                let mut value = Object::embstr(Value::str(""));
+                let len = value.set_range(offset, bytes);
+                map.insert(key.clone(), Entry::new(value));
+                Ok(len as i64)
+            }
+        }
+    }
+
+    /// The longest common subsequence of two strings, missing keys count as
+    /// empty. With IDX the reply says where the runs it's made of are, like
+    /// in Redis from the last one to the first.
+    pub async fn lcs(&self, argv: &[Value]) -> Resp<Value> {
+        let args = LcsArgs::from_args(argv)?;
+        let mut map = self.store.lock();
+        let mut string = |key: &Value| {
+            self.expire_if_needed(&mut map, key);
+            match map.access(key) {
+                Some(entry) if entry.value.kind() != Kind::String => Err(Error::GenericStatic(
+                    "The specified keys must contain string values",
+                )),
+                Some(entry) => Ok(entry.value.to_value()),
+                None => Ok(Arc::new(Value::str(""))),
+            }
+        };
+        let (a, b) = (string(&args.a)?, string(&args.b)?);
+        drop(map);
+        let (a, b) = (
+            a.get_bytes().unwrap_or_default(),
+            b.get_bytes().unwrap_or_default(),
+        );
+
+        // the table takes 4 bytes a cell
+        if lcs::table_size(a, b).is_none_or(|cells| cells > MAX_STRING_LEN / 4) {
+            return Err(Error::GenericStatic(
+                "Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len",
+            ));
+        }
+        let out = lcs::lcs(a, b);
+        if args.len {
+            return Ok((out.subsequence.len() as i64).into());
+        }
+        if !args.idx {
+            return Ok(Value::bytes(out.subsequence));
+        }
+
+        let range = |(start, end): (usize, usize)| {
+            Value::from(vec![(start as i64).into(), (end as i64).into()])
+        };
+        let matches = out
+            .matches
+            .iter()
+            .filter(|m| m.len() >= args.min_match_len)
+            .map(|m| {
+                let mut item = vec![range(m.a), range(m.b)];
+                if args.with_match_len {
+                    item.push((m.len() as i64).into());
+                }
+                Value::from(item)
+            })
+            .collect::<Vec<_>>();
+        Ok(Value::from(Map::from([
+            ("matches".into(), matches.into()),
+            ("len".into(), (out.subsequence.len() as i64).into()),
+        ])))
+    }
+
+    /// the length of the string at `key`, 0 if there is none
+    pub async fn strlen(&self, argv: &[Value]) -> Resp<i64> {
+        let [key] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'strlen' command",
+            ));
+        };
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        match map.get(key) {
+            Some(entry) if entry.value.kind() != Kind::String => Err(Error::WrongType),
+            Some(entry) => Ok(entry.value.strlen() as i64),
+            None => Ok(0),
+        }
+    }
+
+    /// Adds `by` to the integer stored at `key`, which counts as 0 if it
+    /// doesn't exist. The key keeps its expiry.
+    fn incr_by(&self, key: &Value, by: i64) -> Resp<i64> {
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let (current, expiry) = match map.get(key) {
+            Some(entry) if entry.value.kind() != Kind::String => return Err(Error::WrongType),
+            Some(entry) => (
+                entry.value.as_int().ok_or(Error::NotAnInteger)?,
+                entry.expiry,
+            ),
+            None => (0, None),
+        };
+        let new = add_int(current, by)?;
+
+        let mut entry = Entry::new(Value::bytes(new.to_string()));
+        entry.expiry = expiry;
+        map.insert(key.clone(), entry);
+        Ok(new)
+    }
+
+    pub async fn incr(&self, argv: &[Value]) -> Resp<i64> {
+        let [key] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'incr' command",
+            ));
+        };
+        self.incr_by(key, 1)
+    }
+
+    pub async fn decr(&self, argv: &[Value]) -> Resp<i64> {
+        let [key] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'decr' command",
+            ));
+        };
+        self.incr_by(key, -1)
+    }
+
+    pub async fn incrby(&self, argv: &[Value]) -> Resp<i64> {
+        let [key, by] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'incrby' command",
+            ));
+        };
+        self.incr_by(key, parse_int(by)?)
+    }
+
+    pub async fn decrby(&self, argv: &[Value]) -> Resp<i64> {
+        let [key, by] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'decrby' command",
+            ));
+        };
+        let by = parse_int(by)?
+            .checked_neg()
+            .ok_or(Error::GenericStatic("decrement would overflow"))?;
+        self.incr_by(key, by)
+    }
+
+    /// Like INCRBY for floats. The result is stored and sent back formatted by
+    /// [`format_human`].
+    pub async fn incrbyfloat(&self, argv: &[Value]) -> Resp<Value> {
+        let [key, by] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'incrbyfloat' command",
+            ));
+        };
+        let by = parse_float(by)?;
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let (current, expiry) = match map.get(key) {
+            Some(entry) if entry.value.kind() != Kind::String => return Err(Error::WrongType),
+            Some(entry) => (parse_float(&entry.value.to_value())?, entry.expiry),
+            None => (0.0, None),
+        };
+        let new = Value::bytes(format_human(add_float(current, by)?));
+        let mut entry = Entry::new(Object::embstr(new.clone()));
+        entry.expiry = expiry;
+        map.insert(key.clone(), entry);
+        Ok(new)
+    }
+
+    pub async fn getex(&self, argv: &[Value]) -> Resp<Arc<Value>> {
+        let args = GetExArgs::from_args(argv)?;
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, &args.key);
+        let Some(entry) = map.access(&args.key) else {
+            return Ok(Arc::new(Value::Null));
+        };
+        if entry.value.kind() != Kind::String {
+            return Err(Error::WrongType);
+        }
+        let value = entry.value.to_value();
+
+        match args.expiry {
+            Some(Expiry::At(deadline)) => {
+                let deadline = deadline.resolve(map.now_ms());
+                map.set_expiry(&args.key, Some(deadline))
+            }
+            Some(Expiry::Persist) => map.set_expiry(&args.key, None),
+            None => true,
+        };
+
+        Ok(value)
+    }
+
+    /// All keys matching a glob-style pattern, see [`crate::glob`]. The store
+    /// is only locked for [`KEYS_CHUNK`] keys at a time, see
+    /// [`Store::keys_before`].
+    ///
+    /// With `extension-commands` set to `yes`, `LIMIT n` stops after `n`
+    /// matches, 0 for no limit.
+    pub async fn keys(&self, client: &Client, argv: &[Value]) -> Resp<Value> {
+        let (pattern, limit) = match argv {
+            [pattern] => (pattern, 0),
+            [pattern, option, limit]
+                if self.extension_commands()
+                    && option
+                        .get_str()
+                        .is_some_and(|o| CaseInsensitive(o) == "limit") =>
+            {
+                let limit = usize::try_from(parse_int(limit)?)
+                    .map_err(|_| Error::GenericStatic("LIMIT can't be negative"))?;
+                (pattern, limit)
+            }
+            _ => {
+                return Err(Error::GenericStatic(
+                    "wrong number of arguments for 'keys' command",
+                ))
+            }
+        };
+        self.warn_about_keys(client, pattern);
+        let pattern = pattern.get_bytes().ok_or(Error::Syntax)?;
+        let limit = match limit {
+            0 => usize::MAX,
+            n => n,
+        };
+
+        // a set, keys moved around between chunks may come up twice
+        let mut keys = IndexSet::new();
+        let mut end = usize::MAX;
+        while keys.len() < limit {
+            {
+                let map = self.store.lock();
+                let (start, chunk) = map.keys_before(end, KEYS_CHUNK);
+                for key in chunk {
+                    if keys.len() < limit
+                        && glob::matches(pattern, key.get_bytes().unwrap_or_default())
+                    {
+                        keys.insert(key.clone());
+                    }
+                }
+                end = start;
+            }
+            if end == 0 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        Ok(Value::Array(Some(keys.into_iter().collect())))
+    }
+
+    /// tells the [`App::on_keys_warning`] hooks about a KEYS over a large
+    /// keyspace
+    fn warn_about_keys(&self, client: &Client, pattern: &Value) {
+        let threshold = self
+            .get_config("keys-warning-threshold")
+            .and_then(|x| x.parse::<usize>().ok())
+            .unwrap_or(KEYS_WARNING_THRESHOLD);
+        let keys = self.store.lock().stats().keys;
+        if threshold == 0 || keys <= threshold {
+            return;
+        }
+        for hook in &self.hooks.lock().keys {
+            tokio::spawn(hook(KeysWarning {
+                pattern: pattern.clone(),
+                keys,
+                client: client.addr,
+            }));
+        }
+    }
+
+    /// A random key, null if there are none. Expired keys that are picked are
+    /// removed, then another one is picked.
+    pub async fn randomkey(&self, argv: &[Value]) -> Resp<Value> {
+        if !argv.is_empty() {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'randomkey' command",
+            ));
+        }
+        let mut map = self.store.lock();
+        loop {
+            let Some(key) = map.random_key() else {
+                return Ok(Value::Null);
+            };
+            let key = key.clone();
+            if map.get(&key).is_some() {
+                return Ok(key);
+            }
+            self.expire_if_needed(&mut map, &key);
+        }
+    }
+
+    /// Iterates over the keyspace a few keys at a time, see [`Store::scan`].
+    /// Replies with the cursor to go on from and the keys. `MATCH` and `TYPE`
+    /// only filter the keys visited, so a reply may have none even though the
+    /// scan isn't done yet.
+    pub async fn scan(&self, argv: &[Value]) -> Resp<Value> {
+        let args = ScanArgs::from_args(argv)?;
+
+        let map = self.store.lock();
+        let (cursor, batch) = map.scan(args.cursor, args.count);
+        let keys = batch
+            .into_iter()
+            .filter(|(_, entry)| args.kind.is_none_or(|kind| entry.value.kind() == kind))
+            .filter(|(key, _)| {
+                args.pattern.as_ref().is_none_or(|pattern| {
+                    glob::matches(pattern, key.get_bytes().unwrap_or_default())
+                })
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        Ok(Value::Array(Some(vec![
+            Value::bytes(cursor.to_string()),
+            Value::Array(Some(keys)),
+        ])))
+    }
+
+    /// removes the string at `key` and replies with it
+    pub async fn getdel(&self, argv: &[Value]) -> Resp<Arc<Value>> {
+        let [key] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'getdel' command",
+            ));
+        };
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let Some(entry) = map.get(key) else {
+            return Ok(Arc::default());
+        };
+        if entry.value.kind() != Kind::String {
+            return Err(Error::WrongType);
+        }
+        let entry = map.remove(key).expect("the key exists");
+        self.evicted([key.clone()], EvictionReason::Deleted);
+        Ok(entry.value.to_value())
+    }
+
+    /// SET with GET, for older clients
+    pub async fn getset(&self, argv: &[Value]) -> Resp<Arc<Value>> {
+        let [key, val] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'getset' command",
+            ));
+        };
+        self.set_with(SetArgs {
+            get: true,
+            ..SetArgs::new(key.clone(), val.clone())
+        })
+    }
+
+    /// SET NX, replying 1 if the key was set and 0 if not
+    pub async fn setnx(&self, argv: &[Value]) -> Resp<i64> {
+        let [key, val] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'setnx' command",
+            ));
+        };
+        let reply = self.set_with(SetArgs {
+            condition: Some(Condition::Nx),
+            ..SetArgs::new(key.clone(), val.clone())
+        })?;
+        Ok((*reply != Value::Null) as i64)
+    }
+
+    /// SETEX and PSETEX, SET with the `option` (`EX` or `PX`) expiry
+    fn set_expiring(&self, argv: &[Value], option: &str, command: &str) -> Resp<Arc<Value>> {
+        let [key, time, val] = argv else {
+            return Err(Error::Generic(format!(
+                "wrong number of arguments for '{command}' command"
+            )));
+        };
+        let expiry = parse_expiry(option, &mut std::iter::once(time), command)?;
+        self.set_with(SetArgs {
+            expiry,
+            ..SetArgs::new(key.clone(), val.clone())
+        })
+    }
+
+    pub async fn setex(&self, argv: &[Value]) -> Resp<Arc<Value>> {
+        self.set_expiring(argv, "ex", "setex")
+    }
+
+    pub async fn psetex(&self, argv: &[Value]) -> Resp<Arc<Value>> {
+        self.set_expiring(argv, "px", "psetex")
+    }
+
+    /// EXPIRE and friends, `scale` converts the time given to ms and
+    /// `absolute` tells whether it's a unix time. Replies 1 if the expiry was
+    /// set and 0 if there is no such key or the conditions didn't hold. A
+    /// deadline that already passed deletes the key.
+    fn expire_with(&self, argv: &[Value], scale: i64, absolute: bool, command: &str) -> Resp<i64> {
+        let [key, time, options @ ..] = argv else {
+            return Err(Error::Generic(format!(
+                "wrong number of arguments for '{command}' command"
+            )));
+        };
+        let time = parse_int(time)?;
+        let conditions = ExpireConditions::from_args(options)?;
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let now = map.now_ms();
+        let invalid = || Error::Generic(format!("invalid expire time in '{command}' command"));
+        let mut deadline = time.checked_mul(scale).ok_or_else(invalid)?;
+        if !absolute {
+            let now = i64::try_from(now).map_err(|_| invalid())?;
+            deadline = deadline.checked_add(now).ok_or_else(invalid)?;
+        }
+
+        let Some(entry) = map.get(key) else {
+            return Ok(0);
+        };
+        if !conditions.allow(entry.expiry, deadline.into()) {
+            return Ok(0);
+        }
+        if deadline as i128 <= now as i128 {
+            map.remove(key);
+        } else {
+            map.set_expiry(key, Some(deadline as u128));
+        }
+        Ok(1)
+    }
+
+    pub async fn expire(&self, argv: &[Value]) -> Resp<i64> {
+        self.expire_with(argv, 1000, false, "expire")
+    }
+
+    pub async fn pexpire(&self, argv: &[Value]) -> Resp<i64> {
+        self.expire_with(argv, 1, false, "pexpire")
+    }
+
+    pub async fn expireat(&self, argv: &[Value]) -> Resp<i64> {
+        self.expire_with(argv, 1000, true, "expireat")
+    }
+
+    pub async fn pexpireat(&self, argv: &[Value]) -> Resp<i64> {
+        self.expire_with(argv, 1, true, "pexpireat")
+    }
+
+    /// removes the expiry of `key`, replies 1 if it had one
+    pub async fn persist(&self, argv: &[Value]) -> Resp<i64> {
+        let [key] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'persist' command",
+            ));
+        };
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        if map.get(key).and_then(|e| e.expiry).is_none() {
+            return Ok(0);
+        }
+        map.set_expiry(key, None);
+        Ok(1)
+    }
+
+    /// TTL and friends: the time left (or the deadline, if `absolute`) in ms
+    /// or rounded to seconds. -2 if there is no such key, -1 if it doesn't
+    /// expire.
+    fn ttl_with(&self, argv: &[Value], ms: bool, absolute: bool, command: &str) -> Resp<i64> {
+        let [key] = argv else {
+            return Err(Error::Generic(format!(
+                "wrong number of arguments for '{command}' command"
+            )));
+        };
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let now = map.now_ms();
+        let Some(entry) = map.get(key) else {
+            return Ok(-2);
+        };
+        let Some(expiry) = entry.expiry else {
+            return Ok(-1);
+        };
+        let time = if absolute {
+            expiry
+        } else {
+            expiry.saturating_sub(now)
+        };
+        let time = if ms { time } else { (time + 500) / 1000 };
+        Ok(i64::try_from(time).unwrap_or(i64::MAX))
+    }
+
+    pub async fn ttl(&self, argv: &[Value]) -> Resp<i64> {
+        self.ttl_with(argv, false, false, "ttl")
+    }
+
+    pub async fn pttl(&self, argv: &[Value]) -> Resp<i64> {
+        self.ttl_with(argv, true, false, "pttl")
+    }
+
+    pub async fn expiretime(&self, argv: &[Value]) -> Resp<i64> {
+        self.ttl_with(argv, false, true, "expiretime")
+    }
+
+    pub async fn pexpiretime(&self, argv: &[Value]) -> Resp<i64> {
+        self.ttl_with(argv, true, true, "pexpiretime")
+    }
+
+    /// Pushes the elements onto the head of the list at `key`, or its tail,
+    /// one after the other. The list is created if needed. Returns its new
+    /// length.
+    fn push(&self, argv: &[Value], front: bool, command: &str) -> Resp<i64> {
+        let [key, elements @ ..] = argv else {
+            return Err(Error::Generic(format!(
+                "wrong number of arguments for '{command}' command"
+            )));
+        };
+        if elements.is_empty() {
+            return Err(Error::Generic(format!(
+                "wrong number of arguments for '{command}' command"
+            )));
+        }
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        if map.get(key).is_none() {
+            map.insert(key.clone(), Entry::new(Object::List(Arc::default())));
+        }
+        let entry = map.access_mut(key).expect("inserted above");
+        let list = entry.value.list_mut().ok_or(Error::WrongType)?;
+        for element in elements {
+            if front {
+                list.push_front(element.clone());
+            } else {
+                list.push_back(element.clone());
+            }
+        }
+        let len = list.len();
+        self.wake(key, elements.len());
+        Ok(len as i64)
+    }
+
+    pub async fn lpush(&self, argv: &[Value]) -> Resp<i64> {
+        self.push(argv, true, "lpush")
+    }
+
+    pub async fn rpush(&self, argv: &[Value]) -> Resp<i64> {
+        self.push(argv, false, "rpush")
+    }
+
+    /// Removes and replies with the first element of the list at `key`, or
+    /// the last. With a count, replies with an array of up to that many.
+    /// Lists that become empty are removed.
+    fn pop(&self, argv: &[Value], front: bool, command: &str) -> Resp<Value> {
+        let (key, count) = match argv {
+            [key] => (key, None),
+            [key, count] => (key, Some(count)),
+            _ => {
+                return Err(Error::Generic(format!(
+                    "wrong number of arguments for '{command}' command"
+                )))
+            }
+        };
+        let count = count
+            .map(|count| {
+                usize::try_from(parse_int(count)?)
+                    .map_err(|_| Error::GenericStatic("value is out of range, must be positive"))
+            })
+            .transpose()?;
+
+        let mut map = self.store.lock();
+        let popped = self.pop_list(&mut map, key, front, count.unwrap_or(1))?;
+        Ok(match (popped, count) {
+            (Some(popped), Some(_)) => Value::Array(Some(popped)),
+            (Some(popped), None) => popped.into_iter().next().unwrap_or(Value::Null),
+            (None, Some(_)) => Value::Array(None),
+            (None, None) => Value::Null,
+        })
+    }
+
+    /// Pops up to `count` elements off the head of the list at `key`, or its
+    /// tail. A list left empty is removed. `None` if there is no list.
+    fn pop_list(
+        &self,
+        map: &mut Store,
+        key: &Value,
+        front: bool,
+        count: usize,
+    ) -> Resp<Option<Vec<Value>>> {
+        self.expire_if_needed(map, key);
+        let Some(entry) = map.access_mut(key) else {
+            return Ok(None);
+        };
+        let list = entry.value.list_mut().ok_or(Error::WrongType)?;
+        let popped = if front {
+            list.drain(..count.min(list.len())).collect()
+        } else {
+            let at = list.len().saturating_sub(count);
+            list.drain(at..).rev().collect()
+        };
+        if list.is_empty() {
+            map.remove(key);
+        }
+        Ok(Some(popped))
+    }
+
+    pub async fn lpop(&self, argv: &[Value]) -> Resp<Value> {
+        self.pop(argv, true, "lpop")
+    }
+
+    pub async fn rpop(&self, argv: &[Value]) -> Resp<Value> {
+        self.pop(argv, false, "rpop")
+    }
+
+    /// Pops an element off `source` and pushes it onto `destination`, from
+    /// and onto their heads or tails, all under one lock. Returns the
+    /// element, `None` if there's no `source`.
+    fn move_element(
+        &self,
+        map: &mut Store,
+        source: &Value,
+        destination: &Value,
+        from_front: bool,
+        to_front: bool,
+    ) -> Resp<Option<Value>> {
+        self.expire_if_needed(map, source);
+        self.expire_if_needed(map, destination);
+        let Some(entry) = map.get(source) else {
+            return Ok(None);
+        };
+        // nothing may change if either of them isn't a list
+        if entry.value.as_list().is_none()
+            || map
+                .get(destination)
+                .is_some_and(|e| e.value.as_list().is_none())
+        {
+            return Err(Error::WrongType);
+        }
+
+        let list = map
+            .access_mut(source)
+            .and_then(|e| e.value.list_mut())
+            .expect("checked above");
+        let popped = if from_front {
+            list.pop_front()
+        } else {
+            list.pop_back()
+        };
+        let Some(element) = popped else {
+            return Ok(None);
+        };
+
+        if map.get(destination).is_none() {
+            map.insert(
+                destination.clone(),
+                Entry::new(Object::List(Arc::default())),
+            );
+        }
+        let list = map
+            .access_mut(destination)
+            .and_then(|e| e.value.list_mut())
+            .expect("checked above");
+        if to_front {
+            list.push_front(element.clone());
+        } else {
+            list.push_back(element.clone());
+        }
+        self.wake(destination, 1);
+
+        // a list rotated onto itself is never left empty
+        if map
+            .get(source)
+            .and_then(|e| e.value.as_list())
+            .is_some_and(VecDeque::is_empty)
+        {
+            map.remove(source);
+        }
+        Ok(Some(element))
+    }
+
+    pub async fn lmove(&self, argv: &[Value]) -> Resp<Value> {
+        let [source, destination, from, to] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'lmove' command",
+            ));
+        };
+        let (from, to) = (parse_end(from)?, parse_end(to)?);
+        let mut map = self.store.lock();
+        let element = self.move_element(&mut map, source, destination, from, to)?;
+        Ok(element.unwrap_or(Value::Null))
+    }
+
+    pub async fn rpoplpush(&self, argv: &[Value]) -> Resp<Value> {
+        let [source, destination] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'rpoplpush' command",
+            ));
+        };
+        let mut map = self.store.lock();
+        let element = self.move_element(&mut map, source, destination, false, true)?;
+        Ok(element.unwrap_or(Value::Null))
+    }
+
+    /// Runs `attempt` with the store locked until it has something to reply
+    /// with, blocking on `keys` in between. Pushes to any of them wake the
+    /// clients blocked on it longest first, see [`App::wake`]. Returns `None`
+    /// once `timeout` has passed, without one it blocks for as long as it
+    /// takes.
+    async fn block<T>(
+        &self,
+        keys: &[Value],
+        timeout: Option<Duration>,
+        mut attempt: impl FnMut(&mut Store) -> Resp<Option<T>>,
+    ) -> Resp<Option<T>> {
+        let deadline = timeout
+            .map(|timeout| {
+                tokio::time::Instant::now()
+                    .checked_add(timeout)
+                    .ok_or(Error::GenericStatic("timeout is out of range"))
+            })
+            .transpose()?;
+        loop {
+            let guard = {
+                let mut map = self.store.lock();
+                if let Some(out) = attempt(&mut map)? {
+                    return Ok(Some(out));
+                }
+                // still under the lock, so no push can slip in before it
+                let waiter = Arc::new(Waiter {
+                    keys: keys.to_vec(),
+                    notify: Notify::new(),
+                    woken_by: Mutex::new(None),
+                    timeout: deadline.is_some(),
+                });
+                let mut blocked = self.blocked.lock();
+                for key in keys {
+                    blocked
+                        .entry(key.clone())
+                        .or_default()
+                        .push_back(waiter.clone());
+                }
+                WaitGuard { app: self, waiter }
+            };
+
+            let notified = guard.waiter.notify.notified();
+            let woken = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, notified).await.is_ok(),
+                None => {
+                    notified.await;
+                    true
+                }
+            };
+            guard.done();
+            if !woken {
+                // a push may have come in right as time ran out
+                return attempt(&mut self.store.lock());
+            }
+            // another client may have been quicker, then it's back to waiting
+        }
+    }
+
+    /// Wakes up to `count` of the clients blocked on `key`, those blocked
+    /// longest first. Called with the store locked when elements are pushed,
+    /// the woken clients find them once they get the lock.
+    fn wake(&self, key: &Value, count: usize) {
+        let mut blocked = self.blocked.lock();
+        for _ in 0..count {
+            let Some(waiter) = blocked.get_mut(key).and_then(VecDeque::pop_front) else {
+                break;
+            };
+            unblock(&mut blocked, &waiter);
+            *waiter.woken_by.lock() = Some(key.clone());
+            waiter.notify.notify_one();
+        }
+    }
+
+    /// Sets `key` to `entry`, waking the clients blocked on `key` if that puts
+    /// a list there. For the commands that store a whole value (RENAME, COPY,
+    /// SORT with STORE, RESTORE), pushes wake clients as they go.
+    fn insert_waking(&self, map: &mut Store, key: Value, entry: Entry) {
+        let len = entry.value.as_list().map_or(0, VecDeque::len);
+        if len > 0 {
+            self.wake(&key, len);
+        }
+        map.insert(key, entry);
+    }
+
+    /// Like LPOP or RPOP on the first of the keys that has a list, blocking
+    /// until one of them does. Replies with the key and the element.
+    async fn blocking_pop(&self, argv: &[Value], front: bool, command: &str) -> Resp<Value> {
+        let [keys @ .., timeout] = argv else {
+            return Err(Error::Generic(format!(
+                "wrong number of arguments for '{command}' command"
+            )));
+        };
+        if keys.is_empty() {
+            return Err(Error::Generic(format!(
+                "wrong number of arguments for '{command}' command"
+            )));
+        }
+        let timeout = parse_timeout(timeout)?;
+
+        let popped = self
+            .block(keys, timeout, |map| {
+                for key in keys {
+                    if let Some(element) = self
+                        .pop_list(map, key, front, 1)?
+                        .and_then(|popped| popped.into_iter().next())
+                    {
+                        return Ok(Some(Value::Array(Some(vec![key.clone(), element]))));
+                    }
+                }
+                Ok(None)
+            })
+            .await?;
+        Ok(popped.unwrap_or(Value::Array(None)))
+    }
+
+    pub async fn blpop(&self, argv: &[Value]) -> Resp<Value> {
+        self.blocking_pop(argv, true, "blpop").await
+    }
+
+    pub async fn brpop(&self, argv: &[Value]) -> Resp<Value> {
+        self.blocking_pop(argv, false, "brpop").await
+    }
+
+    /// LMOVE that blocks until there is a `source`
+    pub async fn blmove(&self, argv: &[Value]) -> Resp<Value> {
+        let [source, destination, from, to, timeout] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'blmove' command",
+            ));
+        };
+        let (from, to) = (parse_end(from)?, parse_end(to)?);
+        let timeout = parse_timeout(timeout)?;
+
+        let element = self
+            .block(std::slice::from_ref(source), timeout, |map| {
+                self.move_element(map, source, destination, from, to)
+            })
+            .await?;
+        Ok(element.unwrap_or(Value::Array(None)))
+    }
+
+    /// Pops up to `count` elements off the first of `keys` that has a list,
+    /// for LMPOP and BLMPOP. Replies with the key and the elements.
+    fn mpop(&self, map: &mut Store, args: &MpopArgs) -> Resp<Option<Value>> {
+        for key in &args.keys {
+            match self.pop_list(map, key, args.front, args.count)? {
+                Some(popped) if !popped.is_empty() => {
+                    return Ok(Some(Value::Array(Some(vec![
+                        key.clone(),
+                        Value::Array(Some(popped)),
+                    ]))));
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Pops up to COUNT elements, 1 by default, off the first of the keys
+    /// that has a list. Replies with the key and the elements, or null if
+    /// none of them has one.
+    pub async fn lmpop(&self, argv: &[Value]) -> Resp<Value> {
+        if argv.len() < 3 {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'lmpop' command",
+            ));
+        }
+        let args = MpopArgs::from_args(argv)?;
+        let popped = self.mpop(&mut self.store.lock(), &args)?;
+        Ok(popped.unwrap_or(Value::Array(None)))
+    }
+
+    /// LMPOP that blocks until one of the keys has a list
+    pub async fn blmpop(&self, argv: &[Value]) -> Resp<Value> {
+        let [timeout, args @ ..] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'blmpop' command",
+            ));
+        };
+        if args.len() < 3 {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'blmpop' command",
+            ));
+        }
+        let timeout = parse_timeout(timeout)?;
+        let args = MpopArgs::from_args(args)?;
+
+        let popped = self
+            .block(&args.keys, timeout, |map| self.mpop(map, &args))
+            .await?;
+        Ok(popped.unwrap_or(Value::Array(None)))
+    }
+
+    /// the length of the list at `key`, 0 if there is none
+    pub async fn llen(&self, argv: &[Value]) -> Resp<i64> {
+        let [key] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'llen' command",
+            ));
+        };
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        match map.get(key) {
+            Some(entry) => Ok(entry.value.as_list().ok_or(Error::WrongType)?.len() as i64),
+            None => Ok(0),
+        }
+    }
+
+    /// The elements of the list at `key` from `start` to `stop`, both
+    /// inclusive. Negative indices count from the end.
+    pub async fn lrange(&self, argv: &[Value]) -> Resp<Value> {
+        let [key, start, stop] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'lrange' command",
+            ));
+        };
+        let (start, stop) = (parse_int(start)?, parse_int(stop)?);
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let Some(entry) = map.access(key) else {
+            return Ok(Value::Array(Some(vec![])));
+        };
+        let list = entry.value.as_list().ok_or(Error::WrongType)?;
+        let Some(range) = list_range(list.len(), start, stop) else {
+            return Ok(Value::Array(Some(vec![])));
+        };
+        Ok(Value::Array(Some(list.range(range).cloned().collect())))
+    }
+
+    /// Inserts `element` right before or after the first `pivot` in the list
+    /// at `key`. Returns the new length, -1 without a pivot and 0 without a
+    /// list.
+    pub async fn linsert(&self, argv: &[Value]) -> Resp<i64> {
+        let [key, position, pivot, element] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'linsert' command",
+            ));
+        };
+        let position = position.get_str().ok_or(Error::Syntax)?;
+        let after = if CaseInsensitive(position) == "before" {
+            false
+        } else if CaseInsensitive(position) == "after" {
+            true
+        } else {
+            return Err(Error::Syntax);
+        };
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let Some(entry) = map.access_mut(key) else {
+            return Ok(0);
+        };
+        let list = entry.value.list_mut().ok_or(Error::WrongType)?;
+        let Some(at) = list.iter().position(|e| e == pivot) else {
+            return Ok(-1);
+        };
+        list.insert(at + after as usize, element.clone());
+        Ok(list.len() as i64)
+    }
+
+    /// replaces the element at `index` in the list at `key`, negative
+    /// indices count from the end
+    pub async fn lset(&self, argv: &[Value]) -> Resp<&'static str> {
+        let [key, index, element] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'lset' command",
+            ));
+        };
+        let index = parse_int(index)?;
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let entry = map
+            .access_mut(key)
+            .ok_or(Error::GenericStatic("no such key"))?;
+        let list = entry.value.list_mut().ok_or(Error::WrongType)?;
+        let index = if index < 0 {
+            index + list.len() as i64
+        } else {
+            index
+        };
+        let slot = usize::try_from(index)
+            .ok()
+            .and_then(|i| list.get_mut(i))
+            .ok_or(Error::GenericStatic("index out of range"))?;
+        *slot = element.clone();
+        Ok("OK")
+    }
+
+    /// Removes the first `count` elements equal to `element` from the list at
+    /// `key`, the last ones for a negative count and all of them for 0.
+    /// Returns how many were removed.
+    pub async fn lrem(&self, argv: &[Value]) -> Resp<i64> {
+        let [key, count, element] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'lrem' command",
+            ));
+        };
+        let count = parse_int(count)?;
+        let limit = match count.unsigned_abs() {
+            0 => usize::MAX,
+            n => usize::try_from(n).unwrap_or(usize::MAX),
+        };
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let Some(entry) = map.access_mut(key) else {
+            return Ok(0);
+        };
+        let list = entry.value.list_mut().ok_or(Error::WrongType)?;
+        let mut matches: Vec<usize> = list
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| *e == element)
+            .map(|(i, _)| i)
+            .collect();
+        if count < 0 {
+            matches.reverse();
+        }
+        matches.truncate(limit);
+        matches.sort_unstable();
+        // from the back, so the indices left stay where they are
+        for &i in matches.iter().rev() {
+            list.remove(i);
+        }
+        if list.is_empty() {
+            map.remove(key);
+        }
+        Ok(matches.len() as i64)
+    }
+
+    /// Keeps only the elements of the list at `key` from `start` to `stop`,
+    /// indexed like [`App::lrange`]. A list left empty is removed.
+    pub async fn ltrim(&self, argv: &[Value]) -> Resp<&'static str> {
+        let [key, start, stop] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'ltrim' command",
+            ));
+        };
+        let (start, stop) = (parse_int(start)?, parse_int(stop)?);
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let Some(entry) = map.access_mut(key) else {
+            return Ok("OK");
+        };
+        let list = entry.value.list_mut().ok_or(Error::WrongType)?;
+        match list_range(list.len(), start, stop) {
+            Some(range) => {
+                list.truncate(range.end() + 1);
+                list.drain(..range.start());
+            }
+            None => list.clear(),
+        }
+        if list.is_empty() {
+            map.remove(key);
+        }
+        Ok("OK")
+    }
+
+    /// The index of the first `element` in the list at `key`, or with COUNT
+    /// the indices of as many as asked for. RANK skips matches, from the
+    /// tail if it's negative, and MAXLEN limits how many elements are looked
+    /// at.
+    pub async fn lpos(&self, argv: &[Value]) -> Resp<Value> {
+        let args = LposArgs::from_args(argv)?;
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, &args.key);
+        let Some(entry) = map.access(&args.key) else {
+            return Ok(match args.count {
+                Some(_) => Value::Array(Some(vec![])),
+                None => Value::Null,
+            });
+        };
+        let list = entry.value.as_list().ok_or(Error::WrongType)?;
+
+        let max_len = match args.max_len {
+            0 => list.len(),
+            n => n,
+        };
+        let len = list.len();
+        let skip = usize::try_from(args.rank.unsigned_abs() - 1).unwrap_or(usize::MAX);
+        let mut found = (0..len.min(max_len))
+            .map(|n| if args.rank < 0 { len - 1 - n } else { n })
+            .filter(|&i| list[i] == args.element)
+            .skip(skip)
+            .map(|i| Value::Int(i as i64));
+
+        Ok(match args.count {
+            Some(0) => Value::Array(Some(found.collect())),
+            Some(count) => Value::Array(Some(found.take(count).collect())),
+            None => found.next().unwrap_or(Value::Null),
+        })
+    }
+
+    /// Runs `f` on the hash at `key`, `None` if there is no such key.
+    fn read_hash<T>(&self, key: &Value, f: impl FnOnce(&Hash) -> T) -> Resp<Option<T>> {
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        map.expire_fields(key);
+        let Some(entry) = map.access(key) else {
+            return Ok(None);
+        };
+        Ok(Some(f(entry.value.as_hash().ok_or(Error::WrongType)?)))
+    }
+
+    /// The hash at `key` for changing it, an empty one is added if there is
+    /// none. Whoever adds to it must not leave it empty.
+    fn write_hash<'a>(&self, map: &'a mut Store, key: &Value) -> Resp<&'a mut Hash> {
+        self.expire_if_needed(map, key);
+        map.expire_fields(key);
+        if map.get(key).is_none() {
+            map.insert(key.clone(), Entry::new(Object::Hash(Arc::default())));
+        }
+        let entry = map.access_mut(key).expect("inserted above");
+        entry.value.hash_mut().ok_or(Error::WrongType)
+    }
+
+    /// Sets the fields of the hash at `key` to their values, creating the
+    /// hash if needed. Returns how many of the fields are new.
+    pub async fn hset(&self, argv: &[Value]) -> Resp<i64> {
+        let [key, pairs @ ..] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hset' command",
+            ));
+        };
+        let pairs = key_value_pairs(pairs, "hset")?;
+
+        let mut map = self.store.lock();
+        let hash = self.write_hash(&mut map, key)?;
+        let added = pairs
+            .filter(|pair| hash.insert(pair[0].clone(), pair[1].clone()).is_none())
+            .count();
+        Ok(added as i64)
+    }
+
+    /// Sets `field` in the hash at `key` only if it isn't there yet. Returns
+    /// whether it was set.
+    pub async fn hsetnx(&self, argv: &[Value]) -> Resp<i64> {
+        let [key, field, value] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hsetnx' command",
+            ));
+        };
+        let mut map = self.store.lock();
+        let hash = self.write_hash(&mut map, key)?;
+        if hash.contains_key(field) {
+            return Ok(0);
+        }
+        hash.insert(field.clone(), value.clone());
+        Ok(1)
+    }
+
+    /// Like INCRBY for a field of the hash at `key`. Missing fields and
+    /// hashes start at 0.
+    pub async fn hincrby(&self, argv: &[Value]) -> Resp<i64> {
+        let [key, field, by] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hincrby' command",
+            ));
+        };
+        let by = parse_int(by)?;
+
+        let mut map = self.store.lock();
+        // a new hash gets the field, adding to 0 can't fail
+        let hash = self.write_hash(&mut map, key)?;
+        let current = match hash.get(field) {
+            Some(value) => value
+                .get_bytes()
+                .and_then(rdb::as_int)
+                .ok_or(Error::GenericStatic("hash value is not an integer"))?,
+            None => 0,
+        };
+        let new = add_int(current, by)?;
+        hash.insert_keep_ttl(field.clone(), Value::bytes(new.to_string()));
+        Ok(new)
+    }
+
+    /// Like INCRBYFLOAT for a field of the hash at `key`. Missing fields and
+    /// hashes start at 0.
+    pub async fn hincrbyfloat(&self, argv: &[Value]) -> Resp<Value> {
+        let [key, field, by] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hincrbyfloat' command",
+            ));
+        };
+        let by = parse_float(by)?;
+
+        let mut map = self.store.lock();
+        // a new hash gets the field, adding a finite float to 0 can't fail
+        let hash = self.write_hash(&mut map, key)?;
+        let current = match hash.get(field) {
+            Some(value) => {
+                parse_float(value).map_err(|_| Error::GenericStatic("hash value is not a float"))?
+            }
+            None => 0.0,
+        };
+        let new = Value::bytes(format_human(add_float(current, by)?));
+        hash.insert_keep_ttl(field.clone(), new.clone());
+        Ok(new)
+    }
+
+    /// the length of the value of `field` in the hash at `key`, 0 if either
+    /// is missing
+    pub async fn hstrlen(&self, argv: &[Value]) -> Resp<i64> {
+        let [key, field] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hstrlen' command",
+            ));
+        };
+        let len = self.read_hash(key, |hash| {
+            hash.get(field)
+                .and_then(Value::get_bytes)
+                .map_or(0, <[u8]>::len)
+        })?;
+        Ok(len.unwrap_or(0) as i64)
+    }
+
+    /// Rejects a negative `count` that asks for more picks than
+    /// `random-reply-limit`. Picks for those may repeat, so unlike for
+    /// positive counts the size of the collection doesn't bound the reply.
+    fn check_random_count(&self, count: Option<i64>) -> Resp<()> {
+        let limit = self
+            .get_config("random-reply-limit")
+            .and_then(|x| x.parse::<u64>().ok())
+            .unwrap_or(RANDOM_REPLY_LIMIT);
+        match count {
+            Some(count) if count < 0 && count.unsigned_abs() > limit => Err(Error::Generic(
+                format!("count is larger than random-reply-limit ({limit})"),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Random fields of the hash at `key`, see [`sample`] for what the count
+    /// means. WITHVALUES adds their values, after each field on RESP2 and
+    /// paired with it on RESP3.
+    pub async fn hrandfield(&self, client: &Client, argv: &[Value]) -> Resp<Value> {
+        let args = RandFieldArgs::from_args(argv)?;
+        self.check_random_count(args.count)?;
+        let picked = self.read_hash(&args.key, |hash| {
+            let Some(count) = args.count else {
+                return hash
+                    .get_index(fastrand::usize(..hash.len()))
+                    .map(|(field, _)| field.clone());
+            };
+            let picked = sample(hash.len(), count).into_iter().map(|i| {
+                let (field, value) = hash.get_index(i).expect("sampled within the hash");
+                (field.clone(), value.clone())
+            });
+            let reply = match (args.with_values, client.protocol) {
+                (false, _) => picked.map(|(field, _)| field).collect(),
+                (true, Protocol::Resp2) => picked.flat_map(|(f, v)| [f, v]).collect(),
+                (true, Protocol::Resp3) => picked
+                    .map(|(f, v)| Value::Array(Some(vec![f, v])))
+                    .collect(),
+            };
+            Some(Value::Array(Some(reply)))
+        })?;
+        Ok(match (picked.flatten(), args.count) {
+            (Some(reply), _) => reply,
+            (None, Some(_)) => Value::Array(Some(vec![])),
+            (None, None) => Value::Null,
+        })
+    }
+
+    /// Iterates over the fields of the hash at `key` a few at a time, the way
+    /// SCAN iterates over keys. Replies with the cursor to go on from and the
+    /// fields each followed by its value, or just the fields with NOVALUES.
+    pub async fn hscan(&self, argv: &[Value]) -> Resp<Value> {
+        let [key, args @ ..] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hscan' command",
+            ));
+        };
+        let args = ScanArgs::parse(args, "hscan")?;
+
+        let step = self.read_hash(key, |hash| {
+            let (cursor, batch) = scan_step(hash.iter(), args.cursor, args.count);
+            let matching = batch.into_iter().filter(|(field, _)| {
+                args.pattern.as_ref().is_none_or(|pattern| {
+                    glob::matches(pattern, field.get_bytes().unwrap_or_default())
+                })
+            });
+            let items = if args.no_values {
+                matching.map(|(field, _)| field.clone()).collect()
+            } else {
+                matching
+                    .flat_map(|(field, value)| [field.clone(), value.clone()])
+                    .collect()
+            };
+            (cursor, items)
+        })?;
+        let (cursor, items) = step.unwrap_or_default();
+        Ok(Value::Array(Some(vec![
+            Value::bytes(cursor.to_string()),
+            Value::Array(Some(items)),
+        ])))
+    }
+
+    /// the value of `field` in the hash at `key`, null if either is missing
+    pub async fn hget(&self, argv: &[Value]) -> Resp<Value> {
+        let [key, field] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hget' command",
+            ));
+        };
+        Ok(self
+            .read_hash(key, |hash| hash.get(field).cloned())?
+            .flatten()
+            .unwrap_or(Value::Null))
+    }
+
+    /// Removes the fields from the hash at `key`, and the hash once it's
+    /// empty. Returns how many of them were there.
+    pub async fn hdel(&self, argv: &[Value]) -> Resp<i64> {
+        let [key, fields @ ..] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hdel' command",
+            ));
+        };
+        if fields.is_empty() {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hdel' command",
+            ));
+        }
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        map.expire_fields(key);
+        let Some(entry) = map.access_mut(key) else {
+            return Ok(0);
+        };
+        let hash = entry.value.hash_mut().ok_or(Error::WrongType)?;
+        let removed = fields
+            .iter()
+            .filter(|field| hash.remove(field).is_some())
+            .count();
+        map.hash_changed(key);
+        Ok(removed as i64)
+    }
+
+    /// All fields of the hash at `key` with their values, a map on RESP3 and
+    /// a flat array of fields and values on RESP2.
+    pub async fn hgetall(&self, argv: &[Value]) -> Resp<Value> {
+        let [key] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hgetall' command",
+            ));
+        };
+        let entries = self.read_hash(key, |hash| {
+            hash.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+        })?;
+        Ok(Value::Map(Map::from_distinct(entries.unwrap_or_default())))
+    }
+
+    /// the values of the fields in the hash at `key`, null for missing ones
+    pub async fn hmget(&self, argv: &[Value]) -> Resp<Value> {
+        let [key, fields @ ..] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hmget' command",
+            ));
+        };
+        if fields.is_empty() {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hmget' command",
+            ));
+        }
+        let values = self.read_hash(key, |hash| {
+            fields
+                .iter()
+                .map(|field| hash.get(field).cloned().unwrap_or(Value::Null))
+                .collect()
+        })?;
+        Ok(Value::Array(Some(
+            values.unwrap_or_else(|| vec![Value::Null; fields.len()]),
+        )))
+    }
+
+    /// the number of fields in the hash at `key`, 0 if there is none
+    pub async fn hlen(&self, argv: &[Value]) -> Resp<i64> {
+        let [key] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hlen' command",
+            ));
+        };
+        Ok(self.read_hash(key, |hash| hash.len())?.unwrap_or(0) as i64)
+    }
+
+    pub async fn hkeys(&self, argv: &[Value]) -> Resp<Value> {
+        let [key] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hkeys' command",
+            ));
+        };
+        let keys = self.read_hash(key, |hash| hash.keys().cloned().collect())?;
+        Ok(Value::Array(Some(keys.unwrap_or_default())))
+    }
+
+    pub async fn hvals(&self, argv: &[Value]) -> Resp<Value> {
+        let [key] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hvals' command",
+            ));
+        };
+        let values = self.read_hash(key, |hash| hash.values().cloned().collect())?;
+        Ok(Value::Array(Some(values.unwrap_or_default())))
+    }
+
+    /// 1 if the hash at `key` has `field`, 0 otherwise
+    pub async fn hexists(&self, argv: &[Value]) -> Resp<i64> {
+        let [key, field] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hexists' command",
+            ));
+        };
+        let exists = self.read_hash(key, |hash| hash.contains_key(field))?;
+        Ok(exists.unwrap_or(false) as i64)
+    }
+
+    /// Gives fields of the hash at `key` a ttl of `time` (in ms times
+    /// `scale`), if NX, XX, GT or LT allow it. Replies for each field with -2
+    /// if there is no such field, 0 if the condition didn't allow it, 2 if
+    /// the field was removed right away because the time is 0, and 1
+    /// otherwise.
+    fn hexpire_with(&self, argv: &[Value], scale: i64, command: &str) -> Resp<Value> {
+        let [key, time, args @ ..] = argv else {
+            return Err(Error::Generic(format!(
+                "wrong number of arguments for '{command}' command"
+            )));
+        };
+        let (options, fields) = split_fields(args);
+        let fields = parse_fields(fields)?;
+        let conditions = ExpireConditions::from_args(options)?;
+        let time = parse_int(time)?;
+        if time < 0 {
+            return Err(Error::GenericStatic("invalid expire time, must be >= 0"));
+        }
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        map.expire_fields(key);
+        let now = map.now_ms();
+        let deadline = time
+            .checked_mul(scale)
+            .and_then(|ms| ms.checked_add(i64::try_from(now).ok()?))
+            .ok_or_else(|| Error::Generic(format!("invalid expire time in '{command}' command")))?;
+
+        let Some(entry) = map.access_mut(key) else {
+            return Ok(Value::Array(Some(vec![Value::Int(-2); fields.len()])));
+        };
+        let hash = entry.value.hash_mut().ok_or(Error::WrongType)?;
+        let replies = fields
+            .iter()
+            .map(|field| {
+                let reply = if !hash.contains_key(field) {
+                    -2
+                } else if !conditions.allow(hash.expiry(field), deadline.into()) {
+                    0
+                } else if deadline as u128 <= now {
+                    hash.remove(field);
+                    2
+                } else {
+                    hash.set_expiry(field, Some(deadline as u128));
+                    1
+                };
+                Value::Int(reply)
+            })
+            .collect();
+        map.hash_changed(key);
+        Ok(Value::Array(Some(replies)))
+    }
+
+    pub async fn hexpire(&self, argv: &[Value]) -> Resp<Value> {
+        self.hexpire_with(argv, 1000, "hexpire")
+    }
+
+    pub async fn hpexpire(&self, argv: &[Value]) -> Resp<Value> {
+        self.hexpire_with(argv, 1, "hpexpire")
+    }
+
+    /// The time to live of fields of the hash at `key`, in seconds or ms.
+    /// -2 for fields that aren't there and -1 for fields without one.
+    fn httl_with(&self, argv: &[Value], ms: bool, command: &str) -> Resp<Value> {
+        let [key, args @ ..] = argv else {
+            return Err(Error::Generic(format!(
+                "wrong number of arguments for '{command}' command"
+            )));
+        };
+        let fields = parse_fields(args)?;
+        let now = self.store.lock().now_ms();
+        let ttls = self.read_hash(key, |hash| {
+            fields
+                .iter()
+                .map(|field| {
+                    if !hash.contains_key(field) {
+                        return Value::Int(-2);
+                    }
+                    let Some(expiry) = hash.expiry(field) else {
+                        return Value::Int(-1);
+                    };
+                    let time = expiry.saturating_sub(now);
+                    let time = if ms { time } else { (time + 500) / 1000 };
+                    Value::Int(i64::try_from(time).unwrap_or(i64::MAX))
+                })
+                .collect()
+        })?;
+        Ok(Value::Array(Some(
+            ttls.unwrap_or_else(|| vec![Value::Int(-2); fields.len()]),
+        )))
+    }
+
+    pub async fn httl(&self, argv: &[Value]) -> Resp<Value> {
+        self.httl_with(argv, false, "httl")
+    }
+
+    pub async fn hpttl(&self, argv: &[Value]) -> Resp<Value> {
+        self.httl_with(argv, true, "hpttl")
+    }
+
+    /// Removes the ttl of fields of the hash at `key`. Replies for each field
+    /// with -2 if there is no such field, -1 if it has no ttl and 1 if its ttl
+    /// was removed.
+    pub async fn hpersist(&self, argv: &[Value]) -> Resp<Value> {
+        let [key, args @ ..] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hpersist' command",
+            ));
+        };
+        let fields = parse_fields(args)?;
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        map.expire_fields(key);
+        let Some(entry) = map.access_mut(key) else {
+            return Ok(Value::Array(Some(vec![Value::Int(-2); fields.len()])));
+        };
+        let hash = entry.value.hash_mut().ok_or(Error::WrongType)?;
+        let replies = fields
+            .iter()
+            .map(|field| match hash.expiry(field) {
+                _ if !hash.contains_key(field) => Value::Int(-2),
+                None => Value::Int(-1),
+                Some(_) => {
+                    hash.set_expiry(field, None);
+                    Value::Int(1)
+                }
+            })
+            .collect();
+        Ok(Value::Array(Some(replies)))
+    }
+
+    /// Like HMGET, and gives the fields that are there a new ttl like GETEX
+    /// does for keys, or removes theirs with PERSIST. Fields whose new ttl is
+    /// already over are removed.
+    pub async fn hgetex(&self, argv: &[Value]) -> Resp<Value> {
+        let (options, fields) = split_fields(argv.get(1..).unwrap_or_default());
+        let args = GetExArgs::parse(&argv[..argv.len().min(1 + options.len())], "hgetex")?;
+        let fields = parse_fields(fields)?;
+        let key = &args.key;
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        map.expire_fields(key);
+        let now = map.now_ms();
+        let Some(entry) = map.access_mut(key) else {
+            return Ok(Value::Array(Some(vec![Value::Null; fields.len()])));
+        };
+        let hash = entry.value.hash_mut().ok_or(Error::WrongType)?;
+        let values = fields
+            .iter()
+            .map(|field| hash.get(field).cloned().unwrap_or(Value::Null))
+            .collect();
+        for field in fields {
+            match args.expiry {
+                None => {}
+                Some(Expiry::Persist) => {
+                    hash.set_expiry(field, None);
+                }
+                Some(Expiry::At(deadline)) => match deadline.resolve(now) {
+                    deadline if deadline <= now => {
+                        hash.remove(field);
+                    }
+                    deadline => {
+                        hash.set_expiry(field, Some(deadline));
+                    }
+                },
+            }
+        }
+        map.hash_changed(key);
+        Ok(Value::Array(Some(values)))
+    }
+
+    /// Like HMGET, and removes the fields. The hash goes once it's empty.
+    pub async fn hgetdel(&self, argv: &[Value]) -> Resp<Value> {
+        let [key, args @ ..] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'hgetdel' command",
+            ));
+        };
+        let fields = parse_fields(args)?;
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        map.expire_fields(key);
+        let Some(entry) = map.access_mut(key) else {
+            return Ok(Value::Array(Some(vec![Value::Null; fields.len()])));
+        };
+        let hash = entry.value.hash_mut().ok_or(Error::WrongType)?;
+        let values = fields
+            .iter()
+            .map(|field| hash.remove(field).unwrap_or(Value::Null))
+            .collect();
+        map.hash_changed(key);
+        Ok(Value::Array(Some(values)))
+    }
+
+    /// Runs `f` on the set at `key`, `None` if there is no such key.
+    fn read_set<T>(&self, key: &Value, f: impl FnOnce(&IndexSet<Value>) -> T) -> Resp<Option<T>> {
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let Some(entry) = map.access(key) else {
+            return Ok(None);
+        };
+        Ok(Some(f(entry.value.as_set().ok_or(Error::WrongType)?)))
+    }
+
+    /// Adds the members to the set at `key`, creating it if needed. Returns
+    /// how many of them are new.
+    pub async fn sadd(&self, argv: &[Value]) -> Resp<i64> {
+        let [key, members @ ..] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'sadd' command",
+            ));
+        };
+        if members.is_empty() {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'sadd' command",
+            ));
+        }
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        if map.get(key).is_none() {
+            map.insert(key.clone(), Entry::new(Object::Set(Arc::default())));
+        }
+        let entry = map.access_mut(key).expect("inserted above");
+        let set = entry.value.set_mut().ok_or(Error::WrongType)?;
+        let added = members
+            .iter()
+            .filter(|member| set.insert((*member).clone()))
+            .count();
+        Ok(added as i64)
+    }
+
+    /// Removes the members from the set at `key`, and the set once it's
+    /// empty. Returns how many of them were there.
+    pub async fn srem(&self, argv: &[Value]) -> Resp<i64> {
+        let [key, members @ ..] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'srem' command",
+            ));
+        };
+        if members.is_empty() {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'srem' command",
+            ));
+        }
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let Some(entry) = map.access_mut(key) else {
+            return Ok(0);
+        };
+        let set = entry.value.set_mut().ok_or(Error::WrongType)?;
+        let removed = members
+            .iter()
+            .filter(|member| set.swap_remove(*member))
+            .count();
+        if set.is_empty() {
+            map.remove(key);
+        }
+        Ok(removed as i64)
+    }
+
+    /// all members of the set at `key`, a set on RESP3 and an array on RESP2
+    pub async fn smembers(&self, argv: &[Value]) -> Resp<Value> {
+        let [key] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'smembers' command",
+            ));
+        };
+        let members = self.read_set(key, |set| set.iter().cloned().collect())?;
+        Ok(Value::Set(members.unwrap_or_default()))
+    }
+
+    /// 1 if `member` is in the set at `key`, 0 otherwise
+    pub async fn sismember(&self, argv: &[Value]) -> Resp<i64> {
+        let [key, member] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'sismember' command",
+            ));
+        };
+        let found = self.read_set(key, |set| set.contains(member))?;
+        Ok(found.unwrap_or(false) as i64)
+    }
+
+    /// like SISMEMBER for each of the members
+    pub async fn smismember(&self, argv: &[Value]) -> Resp<Value> {
+        let [key, members @ ..] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'smismember' command",
+            ));
+        };
+        if members.is_empty() {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'smismember' command",
+            ));
+        }
+        let found = self.read_set(key, |set| {
+            members
+                .iter()
+                .map(|member| Value::Int(set.contains(member) as i64))
+                .collect()
+        })?;
+        Ok(Value::Array(Some(
+            found.unwrap_or_else(|| vec![Value::Int(0); members.len()]),
+        )))
+    }
+
+    /// the number of members of the set at `key`, 0 if there is none
+    pub async fn scard(&self, argv: &[Value]) -> Resp<i64> {
+        let [key] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'scard' command",
+            ));
+        };
+        Ok(self.read_set(key, IndexSet::len)?.unwrap_or(0) as i64)
+    }
+
+    /// Removes a random member from the set at `key` and replies with it, or
+    /// with a set of up to `count` distinct ones. The last member takes the
+    /// set with it.
+    pub async fn spop(&self, argv: &[Value]) -> Resp<Value> {
+        let (key, count) = match argv {
+            [] => {
+                return Err(Error::GenericStatic(
+                    "wrong number of arguments for 'spop' command",
+                ))
+            }
+            [key] => (key, None),
+            [key, count] => {
+                let count = parse_int(count)?;
+                if count < 0 {
+                    return Err(Error::GenericStatic(
+                        "value is out of range, must be positive",
+                    ));
+                }
+                (key, Some(count))
+            }
+            _ => return Err(Error::Syntax),
+        };
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let Some(entry) = map.access_mut(key) else {
+            return Ok(match count {
+                Some(_) => Value::Set(vec![]),
+                None => Value::Null,
+            });
+        };
+        let set = entry.value.set_mut().ok_or(Error::WrongType)?;
+        let picked: Vec<Value> = sample(set.len(), count.unwrap_or(1))
+            .into_iter()
+            .map(|i| set[i].clone())
+            .collect();
+        for member in &picked {
+            set.swap_remove(member);
+        }
+        if set.is_empty() {
+            map.remove(key);
+        }
+        Ok(match count {
+            Some(_) => Value::Set(picked),
+            None => picked.into_iter().next().unwrap_or(Value::Null),
+        })
+    }
+
+    /// A random member of the set at `key`, or an array of them with a count,
+    /// which means the same as for HRANDFIELD.
+    pub async fn srandmember(&self, argv: &[Value]) -> Resp<Value> {
+        let (key, count) = match argv {
+            [] => {
+                return Err(Error::GenericStatic(
+                    "wrong number of arguments for 'srandmember' command",
+                ))
+            }
+            [key] => (key, None),
+            [key, count] => (key, Some(parse_int(count)?)),
+            _ => return Err(Error::Syntax),
+        };
+        // the reply to a negative count has that many members
+        if count == Some(i64::MIN) {
+            return Err(Error::GenericStatic("value is out of range"));
+        }
+        self.check_random_count(count)?;
+
+        let picked = self.read_set(key, |set| {
+            let Some(count) = count else {
+                return set.get_index(fastrand::usize(..set.len())).cloned();
+            };
+            let members = sample(set.len(), count)
+                .into_iter()
+                .map(|i| set[i].clone())
+                .collect();
+            Some(Value::Array(Some(members)))
+        })?;
+        Ok(match (picked.flatten(), count) {
+            (Some(reply), _) => reply,
+            (None, Some(_)) => Value::Array(Some(vec![])),
+            (None, None) => Value::Null,
+        })
+    }
+
+    /// Iterates over the members of the set at `key` a few at a time, the way
+    /// SCAN iterates over keys.
+    pub async fn sscan(&self, argv: &[Value]) -> Resp<Value> {
+        let [key, args @ ..] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'sscan' command",
+            ));
+        };
+        let args = ScanArgs::parse(args, "sscan")?;
+
+        let step = self.read_set(key, |set| {
+            let (cursor, batch) = scan_step(set.iter(), args.cursor, args.count);
+            let members = batch
+                .into_iter()
+                .filter(|member| {
+                    args.pattern.as_ref().is_none_or(|pattern| {
+                        glob::matches(pattern, member.get_bytes().unwrap_or_default())
+                    })
+                })
+                .cloned()
+                .collect();
+            (cursor, members)
+        })?;
+        let (cursor, members) = step.unwrap_or_default();
+        Ok(Value::Array(Some(vec![
+            Value::bytes(cursor.to_string()),
+            Value::Array(Some(members)),
+        ])))
+    }
+
+    /// Moves `member` from the set at `source` to the one at `destination`,
+    /// which is created if needed. Replies with 1, or 0 if `source` doesn't
+    /// have it.
+    pub async fn smove(&self, argv: &[Value]) -> Resp<i64> {
+        let [source, destination, member] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'smove' command",
+            ));
+        };
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, source);
+        self.expire_if_needed(&mut map, destination);
+        let is_member = match map.access(source).map(|entry| entry.value.as_set()) {
+            None => return Ok(0),
+            Some(None) => return Err(Error::WrongType),
+            Some(Some(set)) => set.contains(member),
+        };
+        // neither set is changed unless both are sets
+        if map
+            .get(destination)
+            .is_some_and(|entry| entry.value.as_set().is_none())
+        {
+            return Err(Error::WrongType);
+        }
+        if !is_member || source == destination {
+            return Ok(is_member as i64);
+        }
+
+        let entry = map.access_mut(source).expect("the key exists");
+        let set = entry.value.set_mut().expect("checked above");
+        set.swap_remove(member);
+        if set.is_empty() {
+            map.remove(source);
+        }
+        if map.get(destination).is_none() {
+            map.insert(destination.clone(), Entry::new(Object::Set(Arc::default())));
+        }
+        let entry = map.access_mut(destination).expect("inserted above");
+        let set = entry.value.set_mut().expect("checked above");
+        set.insert(member.clone());
+        Ok(1)
+    }
+
+    /// The sets at `keys`, empty ones for missing keys. Fails if any of them
+    /// holds something else.
+    fn sets_of(&self, map: &mut Store, keys: &[Value]) -> Resp<Vec<Arc<IndexSet<Value>>>> {
+        keys.iter()
+            .map(|key| {
+                self.expire_if_needed(map, key);
+                match map.access(key).map(|entry| &entry.value) {
+                    None => Ok(Arc::default()),
+                    Some(Object::Set(set)) => Ok(Arc::clone(set)),
+                    Some(_) => Err(Error::WrongType),
+                }
+            })
+            .collect()
+    }
+
+    /// SINTER, SUNION and SDIFF
+    fn set_op(&self, argv: &[Value], op: SetOp, command: &str) -> Resp<Value> {
+        if argv.is_empty() {
+            return Err(Error::Generic(format!(
+                "wrong number of arguments for '{command}' command"
+            )));
+        }
+        let sets = self.sets_of(&mut self.store.lock(), argv)?;
+        Ok(Value::Set(op.apply(&sets).into_iter().collect()))
+    }
+
+    /// SINTERSTORE, SUNIONSTORE and SDIFFSTORE. An empty result removes the
+    /// destination rather than storing an empty set.
+    fn set_op_store(&self, argv: &[Value], op: SetOp, command: &str) -> Resp<i64> {
+        let [destination, keys @ ..] = argv else {
+            return Err(Error::Generic(format!(
+                "wrong number of arguments for '{command}' command"
+            )));
+        };
+        if keys.is_empty() {
+            return Err(Error::Generic(format!(
+                "wrong number of arguments for '{command}' command"
+            )));
+        }
+        let mut map = self.store.lock();
+        let result = op.apply(&self.sets_of(&mut map, keys)?);
+        let len = result.len() as i64;
+        map.remove(destination);
+        if len > 0 {
+            map.insert(
+                destination.clone(),
+                Entry::new(Object::Set(Arc::new(result))),
+            );
+        }
+        Ok(len)
+    }
+
+    /// the members all of the sets have
+    pub async fn sinter(&self, argv: &[Value]) -> Resp<Value> {
+        self.set_op(argv, SetOp::Inter, "sinter")
+    }
+
+    /// the members any of the sets has
+    pub async fn sunion(&self, argv: &[Value]) -> Resp<Value> {
+        self.set_op(argv, SetOp::Union, "sunion")
+    }
+
+    /// the members of the first set none of the others has
+    pub async fn sdiff(&self, argv: &[Value]) -> Resp<Value> {
+        self.set_op(argv, SetOp::Diff, "sdiff")
+    }
+
+    pub async fn sinterstore(&self, argv: &[Value]) -> Resp<i64> {
+        self.set_op_store(argv, SetOp::Inter, "sinterstore")
+    }
+
+    pub async fn sunionstore(&self, argv: &[Value]) -> Resp<i64> {
+        self.set_op_store(argv, SetOp::Union, "sunionstore")
+    }
+
+    pub async fn sdiffstore(&self, argv: &[Value]) -> Resp<i64> {
+        self.set_op_store(argv, SetOp::Diff, "sdiffstore")
+    }
+
+    /// How many members all of the sets have, up to LIMIT. The intersection
+    /// isn't built, so a small limit stays cheap on huge sets.
+    pub async fn sintercard(&self, argv: &[Value]) -> Resp<i64> {
+        if argv.len() < 2 {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'sintercard' command",
+            ));
+        }
+        let args = InterCardArgs::from_args(argv)?;
+        let sets = self.sets_of(&mut self.store.lock(), &args.keys)?;
+        let limit = match args.limit {
+            0 => usize::MAX,
+            limit => limit,
+        };
+        Ok(intersection(&sets).take(limit).count() as i64)
+    }
+
+    /// Everything tooling usually asks about a key in one reply: its type,
+    /// time to live in ms (-1 without one), encoding, memory usage and the
+    /// unix time in ms it was last accessed. Null if there is no such key.
+    ///
+    /// Only available with `extension-commands` set to `yes`.
+    pub async fn keymeta(&self, argv: &[Value]) -> Resp<Value> {
+        let [key] = argv else {
+            return Err(Error::GenericStatic(
+                "wrong number of arguments for 'keymeta' command",
+            ));
+        };
+
+        let mut map = self.store.lock();
+        self.expire_if_needed(&mut map, key);
+        let now = map.now_ms();
+        let Some(entry) = map.get(key) else {
+            return Ok(Value::Null);
+        };
+
+        let ttl = entry.expiry.map_or(-1, |expiry| {
+            i64::try_from(expiry.saturating_sub(now)).unwrap_or(i64::MAX)
+        });
+        let size = key.mem_size() + entry.mem_size();
+        let last_access = i64::try_from(entry.last_access).unwrap_or(i64::MAX);
+        Ok(Value::from(Map::from([
+            ("type".into(), entry.value.kind().name().into()),
+            ("ttl".into(), ttl.into()),
+            ("encoding".into(), entry.value.encoding().into()),
+            ("size".into(), (size as i64).into()),
+            ("last-access".into(), last_access.into()),
+        ])))
+    }
+
+    /// Only has the `COMPRESS ON [threshold]|OFF` subcommand, which is an
+    /// extension and needs `extension-commands` set to `yes`.
+    pub async fn client(&self, client: &mut Client, argv: &[Value]) -> Resp<impl Serialize> {
+        if !self.extension_commands() {
+            let sub = argv.first().and_then(Value::get_str).unwrap_or_default();
+            return Err(Error::Generic(format!("unknown client subcommand '{sub}'")));
+        }
+        match ClientArgs::from_args(argv)? {
+            ClientArgs::Compress(threshold) => client.compress_above = threshold,
+        }
+        Ok("OK")
+    }
+
+    /// whether commands that aren't part of Redis are enabled
+    fn extension_commands(&self) -> bool {
+        self.get_config("extension-commands")
+            .is_some_and(|x| x == "yes")
+    }
+
+    pub async fn config(&self, argv: &[Value]) -> Resp<impl Serialize> {
+        let args = ConfigArgs::from_args(argv)?;
+
+        match args {
+            ConfigArgs::Get(k) => {
+                let config_value = self.config.lock().get(&k).cloned();
+                Ok(Value::from(vec![k.into(), config_value.into()]))
+            }
+            ConfigArgs::Set(key, value) => {
+                self.set_config(key, value);
+                Ok("OK".into())
+            }
+        }
+    }
+
+    async fn dispatch_inner(&self, client: &mut Client, arg: &Value) -> Resp<Vec<u8>> {
+        let Value::Array(Some(argv)) = arg else {
+            return Err(Error::InvalidReq("command must be an array"));
+        };
+
+        let Some((cmd, args)) = argv.split_first() else {
+            return Err(Error::InvalidReq("argv must not be empty"));
+        };
+        let Some(command) = cmd.get_str() else {
+            return Err(Error::TypeError("command must be a string".into()));
+        };
+
+        // names too long to be lowercased on the stack aren't commands
+        let name = Lowercase::new(command);
+        let name = name.as_ref().map_or("", Lowercase::as_str);
+
+        let fault = self.faults.lock().get(name).cloned();
+        match fault {
+            Some(Fault::Delay(delay)) => tokio::time::sleep(delay).await,
+            Some(Fault::Error(message)) => return Err(Error::Generic(message)),
+            None => {}
+        }
+
+        match name {
+            "ping" => self.ping().await.to_bytes(client),
+            "hello" => self.hello(client, args).await.to_bytes(client),
+            "echo" => self.echo(args).await.map(|v| client.encode(&v)),
+            "set" => self.set(args).await.map(|v| client.encode(&v)),
+            "get" => self.get(args).await.map(|v| client.encode(&v)),
+            "getex" => self.getex(args).await.map(|v| client.encode(&v)),
+            "getdel" => self.getdel(args).await.map(|v| client.encode(&v)),
+            "getset" => self.getset(args).await.map(|v| client.encode(&v)),
+            "setnx" => self.setnx(args).await.to_bytes(client),
+            "setex" => self.setex(args).await.map(|v| client.encode(&v)),
+            "psetex" => self.psetex(args).await.map(|v| client.encode(&v)),
+            "expire" => self.expire(args).await.to_bytes(client),
+            "pexpire" => self.pexpire(args).await.to_bytes(client),
+            "expireat" => self.expireat(args).await.to_bytes(client),
+            "pexpireat" => self.pexpireat(args).await.to_bytes(client),
+            "persist" => self.persist(args).await.to_bytes(client),
+            "ttl" => self.ttl(args).await.to_bytes(client),
+            "pttl" => self.pttl(args).await.to_bytes(client),
+            "expiretime" => self.expiretime(args).await.to_bytes(client),
+            "pexpiretime" => self.pexpiretime(args).await.to_bytes(client),
+            "del" => self.del(args).await.to_bytes(client),
+            "unlink" => self.unlink(args).await.to_bytes(client),
+            "exists" => self.exists(args).await.to_bytes(client),
+            "touch" => self.touch(args).await.to_bytes(client),
+            "keys" => self.keys(client, args).await.map(|v| client.encode(&v)),
+            "rename" => self.rename(args).await.to_bytes(client),
+            "renamenx" => self.renamenx(args).await.to_bytes(client),
+            "copy" => self.copy(args).await.to_bytes(client),
+            "dump" => self.dump(args).await.map(|v| client.encode(&v)),
+            "restore" => self.restore(args).await.to_bytes(client),
+            "scan" => self.scan(args).await.map(|v| client.encode(&v)),
+            "randomkey" => self.randomkey(args).await.map(|v| client.encode(&v)),
+            "mget" => self.mget(args).await.map(|v| client.encode(&v)),
+            "mset" => self.mset(args).await.to_bytes(client),
+            "msetnx" => self.msetnx(args).await.to_bytes(client),
+            "append" => self.append(args).await.to_bytes(client),
+            "strlen" => self.strlen(args).await.to_bytes(client),
+            "lcs" => self.lcs(args).await.map(|v| client.encode(&v)),
+            "sort" => self.sort(args).await.map(|v| client.encode(&v)),
+            "sort_ro" => self.sort_ro(args).await.map(|v| client.encode(&v)),
+            "getrange" => self.getrange(args).await.map(|v| client.encode(&v)),
+            "setrange" => self.setrange(args).await.to_bytes(client),
+            "incr" => self.incr(args).await.to_bytes(client),
+            "decr" => self.decr(args).await.to_bytes(client),
+            "incrby" => self.incrby(args).await.to_bytes(client),
+            "decrby" => self.decrby(args).await.to_bytes(client),
+            "incrbyfloat" => self.incrbyfloat(args).await.map(|v| client.encode(&v)),
+            "config" => self.config(args).await.to_bytes(client),
+            "debug" => self.debug(args).await.map(|v| client.encode(&v)),
+            "memory" => self.memory(args).await.to_bytes(client),
+            "object" => self.object(args).await.map(|v| client.encode(&v)),
+            "type" => self.type_of(args).await.to_bytes(client),
+            "lpush" => self.lpush(args).await.to_bytes(client),
+            "rpush" => self.rpush(args).await.to_bytes(client),
+            "lpop" => self.lpop(args).await.map(|v| client.encode(&v)),
+            "rpop" => self.rpop(args).await.map(|v| client.encode(&v)),
+            "llen" => self.llen(args).await.to_bytes(client),
+            "lrange" => self.lrange(args).await.map(|v| client.encode(&v)),
+            "linsert" => self.linsert(args).await.to_bytes(client),
+            "lset" => self.lset(args).await.to_bytes(client),
+            "lrem" => self.lrem(args).await.to_bytes(client),
+            "ltrim" => self.ltrim(args).await.to_bytes(client),
+            "lpos" => self.lpos(args).await.map(|v| client.encode(&v)),
+            "lmove" => self.lmove(args).await.map(|v| client.encode(&v)),
+            "rpoplpush" => self.rpoplpush(args).await.map(|v| client.encode(&v)),
+            "blpop" => self.blpop(args).await.map(|v| client.encode(&v)),
+            "brpop" => self.brpop(args).await.map(|v| client.encode(&v)),
+            "blmove" => self.blmove(args).await.map(|v| client.encode(&v)),
+            "lmpop" => self.lmpop(args).await.map(|v| client.encode(&v)),
+            "blmpop" => self.blmpop(args).await.map(|v| client.encode(&v)),
+            "hset" => self.hset(args).await.to_bytes(client),
+            "hget" => self.hget(args).await.map(|v| client.encode(&v)),
+            "hdel" => self.hdel(args).await.to_bytes(client),
+            "hgetall" => self.hgetall(args).await.map(|v| client.encode(&v)),
+            "hmget" => self.hmget(args).await.map(|v| client.encode(&v)),
+            "hlen" => self.hlen(args).await.to_bytes(client),
+            "hkeys" => self.hkeys(args).await.map(|v| client.encode(&v)),
+            "hvals" => self.hvals(args).await.map(|v| client.encode(&v)),
+            "hexists" => self.hexists(args).await.to_bytes(client),
+            "hsetnx" => self.hsetnx(args).await.to_bytes(client),
+            "hincrby" => self.hincrby(args).await.to_bytes(client),
+            "hincrbyfloat" => self.hincrbyfloat(args).await.map(|v| client.encode(&v)),
+            "hstrlen" => self.hstrlen(args).await.to_bytes(client),
+            "hscan" => self.hscan(args).await.map(|v| client.encode(&v)),
+            "hexpire" => self.hexpire(args).await.map(|v| client.encode(&v)),
+            "hpexpire" => self.hpexpire(args).await.map(|v| client.encode(&v)),
+            "httl" => self.httl(args).await.map(|v| client.encode(&v)),
+            "hpttl" => self.hpttl(args).await.map(|v| client.encode(&v)),
+            "hpersist" => self.hpersist(args).await.map(|v| client.encode(&v)),
+            "hgetex" => self.hgetex(args).await.map(|v| client.encode(&v)),
+            "hgetdel" => self.hgetdel(args).await.map(|v| client.encode(&v)),
+            "sadd" => self.sadd(args).await.to_bytes(client),
+            "srem" => self.srem(args).await.to_bytes(client),
+            "smembers" => self.smembers(args).await.map(|v| client.encode(&v)),
+            "sismember" => self.sismember(args).await.to_bytes(client),
+            "smismember" => self.smismember(args).await.map(|v| client.encode(&v)),
+            "scard" => self.scard(args).await.to_bytes(client),
+            "smove" => self.smove(args).await.to_bytes(client),
+            "sscan" => self.sscan(args).await.map(|v| client.encode(&v)),
+            "spop" => self.spop(args).await.map(|v| client.encode(&v)),
+            "srandmember" => self.srandmember(args).await.map(|v| client.encode(&v)),
+            "sinter" => self.sinter(args).await.map(|v| client.encode(&v)),
+            "sunion" => self.sunion(args).await.map(|v| client.encode(&v)),
+            "sdiff" => self.sdiff(args).await.map(|v| client.encode(&v)),
+            "sinterstore" => self.sinterstore(args).await.to_bytes(client),
+            "sunionstore" => self.sunionstore(args).await.to_bytes(client),
+            "sdiffstore" => self.sdiffstore(args).await.to_bytes(client),
+            "sintercard" => self.sintercard(args).await.to_bytes(client),
+            "hrandfield" => self
+                .hrandfield(client, args)
+                .await
+                .map(|v| client.encode(&v)),
+            "info" => self.info(args).await.map(|v| client.encode(&v)),
+            "client" => self.client(client, args).await.to_bytes(client),
+            "keymeta" if self.extension_commands() => {
+                self.keymeta(args).await.map(|v| client.encode(&v))
+            }
+            _ => Err(Error::UnknownCommand(command.to_owned())),
+        }
+    }
+
+    /// Runs a command under the watchdog configured by `watchdog-period` (in
+    /// ms, 0 or unset disables it). Commands running longer than the period
+    /// are reported to the [`App::on_watchdog`] hooks, and aborted with an
+    /// error when `watchdog-kill` is `yes`.
+    ///
+    /// Blocking commands wait for as long as they're told to, so the watchdog
+    /// leaves them alone. The others only await before they touch the
+    /// keyspace (DEBUG SLEEP, injected delays), so aborting them there leaves
+    /// nothing half done. A handler stuck in a loop without yielding is
+    /// reported but keeps running.
+    async fn watched<F>(&self, command: &str, fut: F) -> Resp<Vec<u8>>
+    where
+        F: Future<Output = Resp<Vec<u8>>>,
+    {
+        const BLOCKING: [&str; 4] = ["blpop", "brpop", "blmove", "blmpop"];
+
+        let period = self
+            .get_config("watchdog-period")
+            .and_then(|x| x.parse::<u64>().ok())
+            .unwrap_or(0);
+        if period == 0 || BLOCKING.iter().any(|b| CaseInsensitive(command) == *b) {
+            return fut.await;
+        }
+        let period = Duration::from_millis(period);
+        let hooks = self.hooks.lock().watchdog.clone();
+        let report = WatchdogReport {
+            command: command.to_owned(),
+            elapsed: period,
+            killed: false,
+        };
+
+        if self.get_config("watchdog-kill").is_some_and(|x| x == "yes") {
+            return tokio::time::timeout(period, fut).await.unwrap_or_else(|_| {
+                let report = WatchdogReport {
+                    killed: true,
+                    ..report
+                };
+                report_to(&hooks, report);
+                Err(Error::Generic(format!(
+                    "command '{command}' killed by watchdog after {period:?}"
+                )))
+            });
+        }
+
+        // from a task of its own, so a handler that never yields is reported
+        // too
+        let reporter = tokio::spawn(async move {
+            tokio::time::sleep(period).await;
+            report_to(&hooks, report);
+        });
+        let res = fut.await;
+        reporter.abort();
+        res
+    }
+
+    pub async fn dispatch_command(&self, client: &mut Client, arg: Value) -> Vec<u8> {
+        let command = arg
+            .get_arr()
+            .and_then(|argv| argv.first())
+            .and_then(Value::get_str)
+            .unwrap_or_default();
+        let res = self
+            .watched(command, self.dispatch_inner(client, &arg))
+            .await;
+        self.reply_stats.lock().add(client.pool.take_stats());
+
+        match res {
+            Ok(i) => i,
+            Err(e) => to_bytes_with_protocol(&RespError::from(e), client.protocol)
+                .expect("error replies always serialize"),
+        }
+    }
+}
+
+trait ToBytes {
+    fn to_bytes(self, client: &mut Client) -> Result<Vec<u8>, Error>;
+}
+
+impl<T> ToBytes for Result<T, Error>
+where
+    T: Serialize,
+{
+    fn to_bytes(self, client: &mut Client) -> Result<Vec<u8>, Error> {
+        match self {
+            Ok(ok) => {
+                let mut serializer = client.serializer();
+                ok.serialize(&mut serializer)
+                    .map_err(|_| Error::GenericStatic("failed to serialize"))?;
+                Ok(serializer.into_inner())
+            }
+            Err(i) => Err(i),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn cmd(args: &[&str]) -> Value {
+        Value::from(args.iter().map(|&a| a.into()).collect::<Vec<_>>())
+    }
+
+    async fn run(app: &App, client: &mut Client, args: &[&str]) -> String {
+        let out = app.dispatch_command(client, cmd(args)).await;
+        String::from_utf8(out).expect("reply is not utf-8")
+    }
+
+    #[tokio::test]
+    async fn set_get() {
+        let app = App::new();
+        let mut client = Client::default();
+        assert_eq!(
+            run(&app, &mut client, &["SET", "k", "v"]).await,
+            "$2\r\nOK\r\n"
+        );
+        assert_eq!(run(&app, &mut client, &["GET", "k"]).await, "$1\r\nv\r\n");
+    }
+
+    #[tokio::test]
+    async fn set_options() {
+        let app = App::new();
+        let mut client = Client::default();
+        let (ok, nil) = ("$2\r\nOK\r\n", "$-1\r\n");
+        run(&app, &mut client, &["DEBUG", "SET-CLOCK", "1000"]).await;
+
+        assert_eq!(run(&app, &mut client, &["SET", "k", "a", "XX"]).await, nil);
+        assert_eq!(run(&app, &mut client, &["SET", "k", "a", "NX"]).await, ok);
+        assert_eq!(run(&app, &mut client, &["SET", "k", "b", "nx"]).await, nil);
+        assert_eq!(
+            run(&app, &mut client, &["SET", "k", "b", "NX", "GET"]).await,
+            "$1\r\na\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["SET", "k", "b", "XX", "GET"]).await,
+            "$1\r\na\r\n"
+        );
+        assert_eq!(run(&app, &mut client, &["GET", "k"]).await, "$1\r\nb\r\n");
+        assert_eq!(
+            run(&app, &mut client, &["SET", "new", "v", "GET"]).await,
+            nil
+        );
+
+        // plain SET clears the expiry, KEEPTTL keeps it
+        let ttl = |app: &App| app.store.lock().get(&"k".into()).unwrap().expiry;
+        run(&app, &mut client, &["SET", "k", "v", "EXAT", "5"]).await;
+        assert_eq!(ttl(&app), Some(5000));
+        run(&app, &mut client, &["SET", "k", "v", "KEEPTTL"]).await;
+        assert_eq!(ttl(&app), Some(5000));
+        run(&app, &mut client, &["SET", "k", "v", "PXAT", "2000"]).await;
+        assert_eq!(ttl(&app), Some(2000));
+        run(&app, &mut client, &["SET", "k", "v", "EX", "2"]).await;
+        assert_eq!(ttl(&app), Some(3000));
+        run(&app, &mut client, &["SET", "k", "v"]).await;
+        assert_eq!(ttl(&app), None);
+
+        // an expired key doesn't count as existing
+        run(&app, &mut client, &["SET", "k", "v", "PX", "10"]).await;
+        run(&app, &mut client, &["DEBUG", "SLEEP", "0.011"]).await;
+        assert_eq!(run(&app, &mut client, &["SET", "k", "w", "XX"]).await, nil);
+        assert_eq!(
+            run(&app, &mut client, &["SET", "k", "w", "NX", "GET"]).await,
+            nil
+        );
+
+        app.store
+            .lock()
+            .insert("list".into(), Entry::new(Value::Array(Some(vec![]))));
+        assert_eq!(
+            run(&app, &mut client, &["SET", "list", "v", "GET"]).await,
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        assert_eq!(run(&app, &mut client, &["SET", "list", "v"]).await, ok);
+    }
+
+    #[tokio::test]
+    async fn del_exists() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["MSET", "a", "1", "b", "2", "c", "3"]).await;
+        run(&app, &mut client, &["SET", "gone", "1", "PX", "1"]).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        assert_eq!(
+            run(&app, &mut client, &["EXISTS", "a", "a", "gone", "x", "b"]).await,
+            ":3\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["DEL", "a", "a", "gone", "x"]).await,
+            ":1\r\n"
+        );
+        assert_eq!(run(&app, &mut client, &["EXISTS", "a"]).await, ":0\r\n");
+
+        let big = Value::Array(Some((0..1000).map(Value::from).collect()));
+        app.store.lock().insert("big".into(), Entry::new(big));
+        assert_eq!(
+            run(&app, &mut client, &["UNLINK", "big", "b", "x"]).await,
+            ":2\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["EXISTS", "big", "b", "c"]).await,
+            ":1\r\n"
+        );
+        let info = run(&app, &mut client, &["INFO", "keyspace"]).await;
+        assert!(info.contains("db0:keys=1,"), "{info}");
+
+        for command in ["DEL", "UNLINK", "EXISTS"] {
+            assert_eq!(
+                run(&app, &mut client, &[command]).await,
+                format!(
+                    "-ERR wrong number of arguments for '{}' command\r\n",
+                    command.to_lowercase()
+                )
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn mget_mset() {
+        let app = App::new();
+        let mut client = Client::default();
+        assert_eq!(
+            run(&app, &mut client, &["MSET", "a", "1", "b", "2"]).await,
+            "$2\r\nOK\r\n"
+        );
+        app.store
+            .lock()
+            .insert("list".into(), Entry::new(Value::Array(Some(vec![]))));
+        assert_eq!(
+            run(&app, &mut client, &["MGET", "a", "missing", "list", "b"]).await,
+            "*4\r\n$1\r\n1\r\n$-1\r\n$-1\r\n$1\r\n2\r\n"
+        );
+
+        // MSET clears expiries like SET
+        run(&app, &mut client, &["SET", "a", "1", "EX", "100"]).await;
+        run(&app, &mut client, &["MSET", "a", "3"]).await;
+        assert_eq!(app.store.lock().get(&"a".into()).unwrap().expiry, None);
+
+        assert_eq!(
+            run(&app, &mut client, &["MSETNX", "c", "1", "a", "4"]).await,
+            ":0\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["MGET", "a", "c"]).await,
+            "*2\r\n$1\r\n3\r\n$-1\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["MSETNX", "c", "1", "d", "2"]).await,
+            ":1\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["MGET", "c", "d"]).await,
+            "*2\r\n$1\r\n1\r\n$1\r\n2\r\n"
+        );
+
+        for cmd in [&["MSET", "a"][..], &["MSETNX"], &["MSET", "a", "1", "b"]] {
+            assert_eq!(
+                run(&app, &mut client, cmd).await,
+                format!(
+                    "-ERR wrong number of arguments for '{}' command\r\n",
+                    cmd[0].to_lowercase()
+                )
+            );
+        }
+        assert_eq!(
+            run(&app, &mut client, &["MGET"]).await,
+            "-ERR wrong number of arguments for 'mget' command\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn append_strlen() {
+        let app = App::new();
+        let mut client = Client::default();
+        assert_eq!(run(&app, &mut client, &["STRLEN", "k"]).await, ":0\r\n");
+        assert_eq!(
+            run(&app, &mut client, &["APPEND", "k", "12"]).await,
+            ":2\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["APPEND", "k", "3"]).await,
+            ":3\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["OBJECT", "ENCODING", "k"]).await,
+            "$3\r\nraw\r\n"
+        );
+        assert_eq!(run(&app, &mut client, &["STRLEN", "k"]).await, ":3\r\n");
+
+        let long = "x".repeat(100);
+        assert_eq!(
+            run(&app, &mut client, &["APPEND", "k", &long]).await,
+            ":103\r\n"
+        );
+        assert_eq!(run(&app, &mut client, &["STRLEN", "k"]).await, ":103\r\n");
+
+        // a reply holding the old value keeps seeing it
+        let old = app.get(&["k".into()]).await.unwrap();
+        run(&app, &mut client, &["APPEND", "k", "\r\n"]).await;
+        assert_eq!(old.get_bytes().unwrap().len(), 103);
+        assert_eq!(
+            run(&app, &mut client, &["GET", "k"]).await,
+            format!("$105\r\n123{long}\r\n\r\n")
+        );
+
+        // the expiry survives
+        run(&app, &mut client, &["SET", "k", "v", "EX", "100"]).await;
+        run(&app, &mut client, &["APPEND", "k", "w"]).await;
+        assert!(app.store.lock().get(&"k".into()).unwrap().expiry.is_some());
+
+        app.store
+            .lock()
+            .insert("list".into(), Entry::new(Value::Array(Some(vec![]))));
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        assert_eq!(
+            run(&app, &mut client, &["APPEND", "list", "v"]).await,
+            wrongtype
+        );
+        assert_eq!(run(&app, &mut client, &["STRLEN", "list"]).await, wrongtype);
+    }
+
+    #[tokio::test]
+    async fn lists() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "s", "v"]).await;
+
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        let cases: &[(&[&str], &str)] = &[
+            (&["LPUSH", "l", "b", "a"], ":2\r\n"),
+            (&["RPUSH", "l", "c", "d", "e"], ":5\r\n"),
+            (&["LLEN", "l"], ":5\r\n"),
+            (&["TYPE", "l"], "$4\r\nlist\r\n"),
+            (
+                &["LRANGE", "l", "0", "-1"],
+                "*5\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n$1\r\nd\r\n$1\r\ne\r\n",
+            ),
+            (
+                &["LRANGE", "l", "-2", "100"],
+                "*2\r\n$1\r\nd\r\n$1\r\ne\r\n",
+            ),
+            (&["LRANGE", "l", "-100", "0"], "*1\r\n$1\r\na\r\n"),
+            (&["LRANGE", "l", "3", "1"], "*0\r\n"),
+            (&["LRANGE", "l", "5", "10"], "*0\r\n"),
+            (&["LRANGE", "missing", "0", "-1"], "*0\r\n"),
+            (&["LPOP", "l"], "$1\r\na\r\n"),
+            (&["RPOP", "l"], "$1\r\ne\r\n"),
+            (&["LPOP", "l", "0"], "*0\r\n"),
+            (&["RPOP", "l", "2"], "*2\r\n$1\r\nd\r\n$1\r\nc\r\n"),
+            // the last element takes the list with it
+            (&["LPOP", "l", "5"], "*1\r\n$1\r\nb\r\n"),
+            (&["EXISTS", "l"], ":0\r\n"),
+            (&["LPOP", "l"], "$-1\r\n"),
+            (&["LPOP", "l", "1"], "*-1\r\n"),
+            (&["LLEN", "l"], ":0\r\n"),
+            (
+                &["LPOP", "l", "-1"],
+                "-ERR value is out of range, must be positive\r\n",
+            ),
+            (&["LPUSH", "s", "x"], wrongtype),
+            (&["RPOP", "s"], wrongtype),
+            (&["LLEN", "s"], wrongtype),
+            (&["LRANGE", "s", "0", "1"], wrongtype),
+            (&["RPUSH", "l2", "x"], ":1\r\n"),
+            (&["GET", "l2"], wrongtype),
+            (&["APPEND", "l2", "x"], wrongtype),
+            (
+                &["LPUSH", "l"],
+                "-ERR wrong number of arguments for 'lpush' command\r\n",
+            ),
+            (
+                &["RPOP", "l", "1", "2"],
+                "-ERR wrong number of arguments for 'rpop' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+
+        client.protocol = Protocol::Resp3;
+        assert_eq!(run(&app, &mut client, &["LPOP", "l"]).await, "_\r\n");
+    }
+
+    #[tokio::test]
+    async fn hashes() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "s", "v"]).await;
+
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        let cases: &[(&[&str], &str)] = &[
+            (&["HSET", "h", "a", "1", "b", "2"], ":2\r\n"),
+            // only new fields count, the rest are overwritten
+            (&["HSET", "h", "b", "3", "c", "4"], ":1\r\n"),
+            (&["HSET", "h", "a", "1", "a", "5"], ":0\r\n"),
+            (&["TYPE", "h"], "$4\r\nhash\r\n"),
+            (&["HGET", "h", "a"], "$1\r\n5\r\n"),
+            (&["HGET", "h", "missing"], "$-1\r\n"),
+            (&["HGET", "missing", "a"], "$-1\r\n"),
+            (&["HLEN", "h"], ":3\r\n"),
+            (&["HLEN", "missing"], ":0\r\n"),
+            (&["HEXISTS", "h", "b"], ":1\r\n"),
+            (&["HEXISTS", "h", "x"], ":0\r\n"),
+            (&["HEXISTS", "missing", "b"], ":0\r\n"),
+            (
+                &["HMGET", "h", "a", "x", "c"],
+                "*3\r\n$1\r\n5\r\n$-1\r\n$1\r\n4\r\n",
+            ),
+            (&["HMGET", "missing", "a", "b"], "*2\r\n$-1\r\n$-1\r\n"),
+            (&["HKEYS", "h"], "*3\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n"),
+            (&["HVALS", "h"], "*3\r\n$1\r\n5\r\n$1\r\n3\r\n$1\r\n4\r\n"),
+            (
+                &["HGETALL", "h"],
+                "*6\r\n$1\r\na\r\n$1\r\n5\r\n$1\r\nb\r\n$1\r\n3\r\n$1\r\nc\r\n$1\r\n4\r\n",
+            ),
+            (&["HKEYS", "missing"], "*0\r\n"),
+            (&["HGETALL", "missing"], "*0\r\n"),
+            (&["HDEL", "h", "a", "x", "a"], ":1\r\n"),
+            (&["HDEL", "missing", "a"], ":0\r\n"),
+            // the last field takes the hash with it
+            (&["HDEL", "h", "b", "c"], ":2\r\n"),
+            (&["EXISTS", "h"], ":0\r\n"),
+            (&["HSET", "s", "a", "1"], wrongtype),
+            (&["HGET", "s", "a"], wrongtype),
+            (&["HDEL", "s", "a"], wrongtype),
+            (&["HGETALL", "s"], wrongtype),
+            (&["HMGET", "s", "a"], wrongtype),
+            (&["HLEN", "s"], wrongtype),
+            (&["HKEYS", "s"], wrongtype),
+            (&["HVALS", "s"], wrongtype),
+            (&["HEXISTS", "s", "a"], wrongtype),
+            (&["HSET", "h2", "f", "v"], ":1\r\n"),
+            (&["GET", "h2"], wrongtype),
+            (&["LPUSH", "h2", "x"], wrongtype),
+            (
+                &["HSET", "h", "a"],
+                "-ERR wrong number of arguments for 'hset' command\r\n",
+            ),
+            (
+                &["HSET", "h"],
+                "-ERR wrong number of arguments for 'hset' command\r\n",
+            ),
+            (
+                &["HDEL", "h"],
+                "-ERR wrong number of arguments for 'hdel' command\r\n",
+            ),
+            (
+                &["HMGET", "h"],
+                "-ERR wrong number of arguments for 'hmget' command\r\n",
+            ),
+            (
+                &["HGET", "h"],
+                "-ERR wrong number of arguments for 'hget' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+
+        client.protocol = Protocol::Resp3;
+        assert_eq!(
+            run(&app, &mut client, &["HGETALL", "h2"]).await,
+            "%1\r\n$1\r\nf\r\n$1\r\nv\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["HGETALL", "missing"]).await,
+            "%0\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn hash_fields() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "s", "v"]).await;
+        run(
+            &app,
+            &mut client,
+            &["HSET", "h", "n", "10", "f", "1.5", "w", "word"],
+        )
+        .await;
+
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        let cases: &[(&[&str], &str)] = &[
+            (&["HINCRBY", "h", "n", "5"], ":15\r\n"),
+            (&["HINCRBY", "h", "n", "-20"], ":-5\r\n"),
+            (&["HINCRBY", "h", "new", "3"], ":3\r\n"),
+            (&["HINCRBY", "fresh", "a", "1"], ":1\r\n"),
+            (&["HGET", "fresh", "a"], "$1\r\n1\r\n"),
+            (
+                &["HINCRBY", "h", "w", "1"],
+                "-ERR hash value is not an integer\r\n",
+            ),
+            (
+                &["HINCRBY", "h", "f", "1"],
+                "-ERR hash value is not an integer\r\n",
+            ),
+            (
+                &["HINCRBY", "h", "n", "x"],
+                "-ERR value is not an integer or out of range\r\n",
+            ),
+            (&["HSET", "h", "big", "9223372036854775807"], ":1\r\n"),
+            (
+                &["HINCRBY", "h", "big", "1"],
+                "-ERR increment or decrement would overflow\r\n",
+            ),
+            (&["HINCRBYFLOAT", "h", "f", "0.1"], "$3\r\n1.6\r\n"),
+            (&["HINCRBYFLOAT", "h", "n", "2.5"], "$4\r\n-2.5\r\n"),
+            (&["HINCRBYFLOAT", "h", "g", "5.0e3"], "$4\r\n5000\r\n"),
+            (&["HGET", "h", "g"], "$4\r\n5000\r\n"),
+            (
+                &["HINCRBYFLOAT", "h", "w", "1"],
+                "-ERR hash value is not a float\r\n",
+            ),
+            (
+                &["HINCRBYFLOAT", "h", "f", "x"],
+                "-ERR value is not a valid float\r\n",
+            ),
+            (&["HSET", "h", "huge", "1.7e308"], ":1\r\n"),
+            (
+                &["HINCRBYFLOAT", "h", "huge", "1.7e308"],
+                "-ERR increment would produce NaN or Infinity\r\n",
+            ),
+            (&["HSETNX", "h", "w", "other"], ":0\r\n"),
+            (&["HGET", "h", "w"], "$4\r\nword\r\n"),
+            (&["HSETNX", "h", "x", "y"], ":1\r\n"),
+            (&["HSETNX", "fresh2", "x", "y"], ":1\r\n"),
+            (&["HSTRLEN", "h", "w"], ":4\r\n"),
+            (&["HSTRLEN", "h", "missing"], ":0\r\n"),
+            (&["HSTRLEN", "missing", "w"], ":0\r\n"),
+            (&["HINCRBY", "s", "a", "1"], wrongtype),
+            (&["HINCRBYFLOAT", "s", "a", "1"], wrongtype),
+            (&["HSETNX", "s", "a", "1"], wrongtype),
+            (&["HSTRLEN", "s", "a"], wrongtype),
+            (
+                &["HSETNX", "h", "a"],
+                "-ERR wrong number of arguments for 'hsetnx' command\r\n",
+            ),
+            (
+                &["HINCRBY", "h", "a"],
+                "-ERR wrong number of arguments for 'hincrby' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn hash_field_expiry() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["DEBUG", "SET-CLOCK", "1000000"]).await;
+        run(&app, &mut client, &["SET", "s", "v"]).await;
+        run(
+            &app,
+            &mut client,
+            &["HSET", "h", "a", "1", "b", "2", "c", "3"],
+        )
+        .await;
+
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        let cases: &[(&[&str], &str)] = &[
+            (
+                &["HEXPIRE", "h", "10", "FIELDS", "2", "a", "x"],
+                "*2\r\n:1\r\n:-2\r\n",
+            ),
+            (
+                &["HPEXPIRE", "h", "20000", "XX", "FIELDS", "2", "a", "b"],
+                "*2\r\n:1\r\n:0\r\n",
+            ),
+            (
+                &["HEXPIRE", "h", "5", "GT", "FIELDS", "1", "a"],
+                "*1\r\n:0\r\n",
+            ),
+            (
+                &["HEXPIRE", "h", "5", "NX", "FIELDS", "2", "a", "b"],
+                "*2\r\n:0\r\n:1\r\n",
+            ),
+            (
+                &["HTTL", "h", "FIELDS", "4", "a", "b", "c", "x"],
+                "*4\r\n:20\r\n:5\r\n:-1\r\n:-2\r\n",
+            ),
+            (&["HPTTL", "h", "FIELDS", "1", "b"], "*1\r\n:5000\r\n"),
+            (
+                &["HTTL", "missing", "FIELDS", "2", "a", "b"],
+                "*2\r\n:-2\r\n:-2\r\n",
+            ),
+            (
+                &["HPERSIST", "h", "FIELDS", "3", "b", "c", "x"],
+                "*3\r\n:1\r\n:-1\r\n:-2\r\n",
+            ),
+            // HINCRBY keeps a field's ttl, HSET drops it
+            (&["HSET", "h", "b", "9"], ":0\r\n"),
+            (&["HEXPIRE", "h", "5", "FIELDS", "1", "b"], "*1\r\n:1\r\n"),
+            (&["HINCRBY", "h", "b", "1"], ":10\r\n"),
+            (&["HTTL", "h", "FIELDS", "1", "b"], "*1\r\n:5\r\n"),
+            (&["HSET", "h", "b", "2"], ":0\r\n"),
+            (&["HTTL", "h", "FIELDS", "1", "b"], "*1\r\n:-1\r\n"),
+            // a ttl of 0 removes the field right away
+            (&["HSET", "h", "d", "4"], ":1\r\n"),
+            (&["HEXPIRE", "h", "0", "FIELDS", "1", "d"], "*1\r\n:2\r\n"),
+            (&["HEXISTS", "h", "d"], ":0\r\n"),
+            // fields expire on their own, a is 20s from the start
+            (&["DEBUG", "SLEEP", "20"], "$2\r\nOK\r\n"),
+            (&["HGET", "h", "a"], "$-1\r\n"),
+            (&["HLEN", "h"], ":2\r\n"),
+            (
+                &["HGETEX", "h", "PX", "100", "FIELDS", "3", "b", "x", "c"],
+                "*3\r\n$1\r\n2\r\n$-1\r\n$1\r\n3\r\n",
+            ),
+            (
+                &["HPTTL", "h", "FIELDS", "2", "b", "c"],
+                "*2\r\n:100\r\n:100\r\n",
+            ),
+            (
+                &["HGETEX", "h", "PERSIST", "FIELDS", "1", "c"],
+                "*1\r\n$1\r\n3\r\n",
+            ),
+            (&["HGETEX", "h", "FIELDS", "1", "c"], "*1\r\n$1\r\n3\r\n"),
+            (&["HTTL", "h", "FIELDS", "1", "c"], "*1\r\n:-1\r\n"),
+            (
+                &["HGETEX", "h", "PXAT", "1", "FIELDS", "1", "c"],
+                "*1\r\n$1\r\n3\r\n",
+            ),
+            (&["HEXISTS", "h", "c"], ":0\r\n"),
+            (
+                &["HGETEX", "missing", "EX", "1", "FIELDS", "1", "a"],
+                "*1\r\n$-1\r\n",
+            ),
+            // the last field to expire takes the hash with it once it's looked
+            // at
+            (&["DEBUG", "SLEEP", "0.1"], "$2\r\nOK\r\n"),
+            (&["HLEN", "h"], ":0\r\n"),
+            (&["EXISTS", "h"], ":0\r\n"),
+            (&["HSET", "g", "a", "1", "b", "2"], ":2\r\n"),
+            (
+                &["HGETDEL", "g", "FIELDS", "2", "a", "x"],
+                "*2\r\n$1\r\n1\r\n$-1\r\n",
+            ),
+            (&["HGETDEL", "g", "FIELDS", "1", "b"], "*1\r\n$1\r\n2\r\n"),
+            (&["EXISTS", "g"], ":0\r\n"),
+            (&["HGETDEL", "g", "FIELDS", "1", "b"], "*1\r\n$-1\r\n"),
+            (&["HEXPIRE", "s", "1", "FIELDS", "1", "a"], wrongtype),
+            (&["HTTL", "s", "FIELDS", "1", "a"], wrongtype),
+            (&["HPERSIST", "s", "FIELDS", "1", "a"], wrongtype),
+            (&["HGETEX", "s", "FIELDS", "1", "a"], wrongtype),
+            (&["HGETDEL", "s", "FIELDS", "1", "a"], wrongtype),
+            (
+                &["HEXPIRE", "g", "1", "a"],
+                "-ERR Mandatory argument FIELDS is missing or not at the right position\r\n",
+            ),
+            (
+                &["HEXPIRE", "g", "1", "FIELDS", "0"],
+                "-ERR Parameter `numFields` should be greater than 0\r\n",
+            ),
+            (
+                &["HTTL", "g", "FIELDS", "2", "a"],
+                "-ERR The `numfields` parameter must match the number of arguments\r\n",
+            ),
+            (
+                &["HEXPIRE", "g", "-1", "FIELDS", "1", "a"],
+                "-ERR invalid expire time, must be >= 0\r\n",
+            ),
+            (
+                &["HEXPIRE", "g", "9223372036854775807", "FIELDS", "1", "a"],
+                "-ERR invalid expire time in 'hexpire' command\r\n",
+            ),
+            (
+                &["HEXPIRE", "g", "1", "NX", "XX", "FIELDS", "1", "a"],
+                "-ERR NX and XX, GT or LT options at the same time are not compatible\r\n",
+            ),
+            (
+                &["HGETEX", "g", "EX", "0", "FIELDS", "1", "a"],
+                "-ERR invalid expire time in 'hgetex' command\r\n",
+            ),
+            (
+                &["HGETEX", "g", "EX", "1", "PERSIST", "FIELDS", "1", "a"],
+                "-ERR syntax error\r\n",
+            ),
+            (
+                &["HEXPIRE", "g"],
+                "-ERR wrong number of arguments for 'hexpire' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+
+        // fields nobody looks at are removed by the expiry cycle
+        let hset = ["HSET", "cold", "a", "1", "b", "2", "c", "3"];
+        run(&app, &mut client, &hset).await;
+        run(&app, &mut client, &["HSET", "gone", "a", "1"]).await;
+        let expire = ["HPEXPIRE", "cold", "10", "FIELDS", "2", "a", "b"];
+        run(&app, &mut client, &expire).await;
+        let expire = ["HPEXPIRE", "gone", "10", "FIELDS", "1", "a"];
+        run(&app, &mut client, &expire).await;
+        run(&app, &mut client, &["DEBUG", "SLEEP", "0.01"]).await;
+        run(&app, &mut client, &["DEBUG", "EXPIRE-CYCLE"]).await;
+        let map = app.store.lock();
+        let cold = map.get(&Value::str("cold")).unwrap();
+        let fields: Vec<_> = cold.value.as_hash().unwrap().keys().collect();
+        assert_eq!(fields, [&Value::str("c")]);
+        assert!(map.get(&Value::str("gone")).is_none());
+    }
+
+    #[tokio::test]
+    async fn hrandfield() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "s", "v"]).await;
+        run(
+            &app,
+            &mut client,
+            &["HSET", "h", "a", "1", "b", "2", "c", "3"],
+        )
+        .await;
+        let fields = |reply: &str| -> Vec<String> {
+            let Value::Array(Some(items)) = Value::parse(reply.as_bytes()).unwrap().0 else {
+                panic!("not an array: {reply:?}");
+            };
+            items
+                .into_iter()
+                .map(|item| item.to_str().unwrap())
+                .collect()
+        };
+
+        let one = run(&app, &mut client, &["HRANDFIELD", "h"]).await;
+        assert!(["$1\r\na\r\n", "$1\r\nb\r\n", "$1\r\nc\r\n"].contains(&one.as_str()));
+
+        let picked = fields(&run(&app, &mut client, &["HRANDFIELD", "h", "2"]).await);
+        assert_eq!(picked.len(), 2);
+        assert_ne!(picked[0], picked[1]);
+        let mut all = fields(&run(&app, &mut client, &["HRANDFIELD", "h", "10"]).await);
+        all.sort();
+        assert_eq!(all, ["a", "b", "c"]);
+        let repeated = fields(&run(&app, &mut client, &["HRANDFIELD", "h", "-10"]).await);
+        assert_eq!(repeated.len(), 10);
+        assert!(repeated
+            .iter()
+            .all(|f| ["a", "b", "c"].contains(&f.as_str())));
+
+        // fields are followed by their values
+        let pairs = fields(&run(&app, &mut client, &["HRANDFIELD", "h", "-4", "withvalues"]).await);
+        assert_eq!(pairs.len(), 8);
+        for pair in pairs.chunks(2) {
+            let value = (pair[0].as_bytes()[0] - b'a' + b'1') as char;
+            assert_eq!(pair[1], value.to_string());
+        }
+
+        let cases: &[(&[&str], &str)] = &[
+            (&["HRANDFIELD", "h", "0"], "*0\r\n"),
+            (&["HRANDFIELD", "missing"], "$-1\r\n"),
+            (&["HRANDFIELD", "missing", "3"], "*0\r\n"),
+            (&["HRANDFIELD", "missing", "-3", "WITHVALUES"], "*0\r\n"),
+            (
+                &["HRANDFIELD", "s"],
+                "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+            ),
+            (
+                &["HRANDFIELD", "h", "x"],
+                "-ERR value is not an integer or out of range\r\n",
+            ),
+            (&["HRANDFIELD", "h", "1", "values"], "-ERR syntax error\r\n"),
+            (
+                &["HRANDFIELD", "h", "-9223372036854775808"],
+                "-ERR value is out of range\r\n",
+            ),
+            (
+                &["HRANDFIELD", "h", "-4611686018427387904", "WITHVALUES"],
+                "-ERR value is out of range\r\n",
+            ),
+            (&["HRANDFIELD"], "-ERR syntax error\r\n"),
+            (
+                &["HRANDFIELD", "h", "-1000001"],
+                "-ERR count is larger than random-reply-limit (1000000)\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+
+        // the limit only holds back picks that may repeat
+        app.set_config("random-reply-limit".into(), "3".into());
+        assert_eq!(
+            run(&app, &mut client, &["HRANDFIELD", "h", "-4", "WITHVALUES"]).await,
+            "-ERR count is larger than random-reply-limit (3)\r\n"
+        );
+        assert_eq!(
+            fields(&run(&app, &mut client, &["HRANDFIELD", "h", "-3"]).await).len(),
+            3
+        );
+        assert_eq!(
+            fields(&run(&app, &mut client, &["HRANDFIELD", "h", "10"]).await).len(),
+            3
+        );
+
+        client.protocol = Protocol::Resp3;
+        run(&app, &mut client, &["HSET", "one", "f", "v"]).await;
+        assert_eq!(
+            run(
+                &app,
+                &mut client,
+                &["HRANDFIELD", "one", "-2", "WITHVALUES"]
+            )
+            .await,
+            "*2\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn sets() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "s", "v"]).await;
+
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        let cases: &[(&[&str], &str)] = &[
+            (&["SADD", "set", "a", "b", "a"], ":2\r\n"),
+            // only new members count
+            (&["SADD", "set", "b", "c"], ":1\r\n"),
+            (&["TYPE", "set"], "$3\r\nset\r\n"),
+            (&["SCARD", "set"], ":3\r\n"),
+            (&["SCARD", "missing"], ":0\r\n"),
+            (
+                &["SMEMBERS", "set"],
+                "*3\r\n$1\r\na\r\n$1\r\nb\r\n$1\r\nc\r\n",
+            ),
+            (&["SMEMBERS", "missing"], "*0\r\n"),
+            (&["SISMEMBER", "set", "b"], ":1\r\n"),
+            (&["SISMEMBER", "set", "x"], ":0\r\n"),
+            (&["SISMEMBER", "missing", "b"], ":0\r\n"),
+            (
+                &["SMISMEMBER", "set", "a", "x", "c"],
+                "*3\r\n:1\r\n:0\r\n:1\r\n",
+            ),
+            (&["SMISMEMBER", "missing", "a", "b"], "*2\r\n:0\r\n:0\r\n"),
+            (&["SREM", "set", "a", "x", "a"], ":1\r\n"),
+            (&["SREM", "missing", "a"], ":0\r\n"),
+            // the last member takes the set with it
+            (&["SREM", "set", "b", "c"], ":2\r\n"),
+            (&["EXISTS", "set"], ":0\r\n"),
+            (&["SADD", "s", "a"], wrongtype),
+            (&["SREM", "s", "a"], wrongtype),
+            (&["SMEMBERS", "s"], wrongtype),
+            (&["SISMEMBER", "s", "a"], wrongtype),
+            (&["SMISMEMBER", "s", "a"], wrongtype),
+            (&["SCARD", "s"], wrongtype),
+            (&["SADD", "set2", "m"], ":1\r\n"),
+            (&["GET", "set2"], wrongtype),
+            (&["HSET", "set2", "f", "v"], wrongtype),
+            (
+                &["SADD", "set"],
+                "-ERR wrong number of arguments for 'sadd' command\r\n",
+            ),
+            (
+                &["SREM", "set"],
+                "-ERR wrong number of arguments for 'srem' command\r\n",
+            ),
+            (
+                &["SMISMEMBER", "set"],
+                "-ERR wrong number of arguments for 'smismember' command\r\n",
+            ),
+            (
+                &["SISMEMBER", "set"],
+                "-ERR wrong number of arguments for 'sismember' command\r\n",
+            ),
+            (
+                &["SCARD"],
+                "-ERR wrong number of arguments for 'scard' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+
+        client.protocol = Protocol::Resp3;
+        assert_eq!(
+            run(&app, &mut client, &["SMEMBERS", "set2"]).await,
+            "~1\r\n$1\r\nm\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["SMEMBERS", "missing"]).await,
+            "~0\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn set_algebra() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SADD", "a", "1", "2", "3", "4"]).await;
+        run(&app, &mut client, &["SADD", "b", "3", "4", "5"]).await;
+        run(&app, &mut client, &["SADD", "c", "4", "6"]).await;
+        run(&app, &mut client, &["SET", "s", "v"]).await;
+        run(&app, &mut client, &["SET", "str", "v"]).await;
+
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        let cases: &[(&[&str], &str)] = &[
+            (&["SINTER", "a", "b"], "*2\r\n$1\r\n3\r\n$1\r\n4\r\n"),
+            (&["SINTER", "a", "b", "c"], "*1\r\n$1\r\n4\r\n"),
+            (
+                &["SINTER", "a"],
+                "*4\r\n$1\r\n1\r\n$1\r\n2\r\n$1\r\n3\r\n$1\r\n4\r\n",
+            ),
+            (&["SINTER", "a", "missing"], "*0\r\n"),
+            (
+                &["SUNION", "b", "c", "missing"],
+                "*4\r\n$1\r\n3\r\n$1\r\n4\r\n$1\r\n5\r\n$1\r\n6\r\n",
+            ),
+            (&["SDIFF", "a", "b", "c"], "*2\r\n$1\r\n1\r\n$1\r\n2\r\n"),
+            (
+                &["SDIFF", "a", "missing"],
+                "*4\r\n$1\r\n1\r\n$1\r\n2\r\n$1\r\n3\r\n$1\r\n4\r\n",
+            ),
+            (&["SDIFF", "missing", "a"], "*0\r\n"),
+            (&["SINTERCARD", "2", "a", "b"], ":2\r\n"),
+            (&["SINTERCARD", "3", "a", "b", "c"], ":1\r\n"),
+            (&["SINTERCARD", "2", "a", "b", "LIMIT", "1"], ":1\r\n"),
+            (&["SINTERCARD", "2", "a", "b", "limit", "0"], ":2\r\n"),
+            (&["SINTERCARD", "2", "a", "missing"], ":0\r\n"),
+            // the destination may be one of the sets
+            (&["SUNIONSTORE", "a", "a", "c"], ":5\r\n"),
+            (&["SCARD", "a"], ":5\r\n"),
+            (&["SINTERSTORE", "dest", "a", "b"], ":2\r\n"),
+            (&["SMEMBERS", "dest"], "*2\r\n$1\r\n3\r\n$1\r\n4\r\n"),
+            // whatever was there is replaced, an empty result removes it
+            (&["SDIFFSTORE", "s", "b", "a"], ":1\r\n"),
+            (&["TYPE", "s"], "$3\r\nset\r\n"),
+            (&["SDIFFSTORE", "dest", "b", "a", "b"], ":0\r\n"),
+            (&["EXISTS", "dest"], ":0\r\n"),
+            (&["SADD", "dest", "x"], ":1\r\n"),
+            (&["SINTERSTORE", "dest", "missing"], ":0\r\n"),
+            (&["EXISTS", "dest"], ":0\r\n"),
+            (&["SINTER", "a", "str"], wrongtype),
+            // an empty set doesn't let SINTER skip the type checks
+            (&["SINTER", "missing", "str"], wrongtype),
+            (&["SUNION", "str"], wrongtype),
+            (&["SDIFF", "a", "str"], wrongtype),
+            (&["SINTERSTORE", "dest", "a", "str"], wrongtype),
+            (&["SINTERCARD", "2", "a", "str"], wrongtype),
+            (
+                &["SINTERCARD", "0", "a"],
+                "-ERR numkeys should be greater than 0\r\n",
+            ),
+            (
+                &["SINTERCARD", "3", "a", "b"],
+                "-ERR Number of keys can't be greater than number of args\r\n",
+            ),
+            (
+                &["SINTERCARD", "1", "a", "LIMIT", "-1"],
+                "-ERR LIMIT can't be negative\r\n",
+            ),
+            (&["SINTERCARD", "1", "a", "LIMIT"], "-ERR syntax error\r\n"),
+            (&["SINTERCARD", "1", "a", "b"], "-ERR syntax error\r\n"),
+            (
+                &["SINTER"],
+                "-ERR wrong number of arguments for 'sinter' command\r\n",
+            ),
+            (
+                &["SDIFFSTORE", "dest"],
+                "-ERR wrong number of arguments for 'sdiffstore' command\r\n",
+            ),
+            (
+                &["SINTERCARD", "1"],
+                "-ERR wrong number of arguments for 'sintercard' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn spop_srandmember() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "s", "v"]).await;
+        run(&app, &mut client, &["SADD", "set", "a", "b", "c"]).await;
+        let members = |reply: &str| -> Vec<String> {
+            let Value::Array(Some(items)) = Value::parse(reply.as_bytes()).unwrap().0 else {
+                panic!("not an array: {reply:?}");
+            };
+            items
+                .into_iter()
+                .map(|item| item.to_str().unwrap())
+                .collect()
+        };
+
+        let one = run(&app, &mut client, &["SRANDMEMBER", "set"]).await;
+        assert!(["$1\r\na\r\n", "$1\r\nb\r\n", "$1\r\nc\r\n"].contains(&one.as_str()));
+        let picked = members(&run(&app, &mut client, &["SRANDMEMBER", "set", "2"]).await);
+        assert_eq!(picked.len(), 2);
+        assert_ne!(picked[0], picked[1]);
+        let mut all = members(&run(&app, &mut client, &["SRANDMEMBER", "set", "10"]).await);
+        all.sort();
+        assert_eq!(all, ["a", "b", "c"]);
+        let repeated = members(&run(&app, &mut client, &["SRANDMEMBER", "set", "-10"]).await);
+        assert_eq!(repeated.len(), 10);
+        assert!(repeated
+            .iter()
+            .all(|m| ["a", "b", "c"].contains(&m.as_str())));
+        assert_eq!(run(&app, &mut client, &["SCARD", "set"]).await, ":3\r\n");
+
+        // popped members are gone, the last one takes the set with it
+        let one = run(&app, &mut client, &["SPOP", "set"]).await;
+        let one = Value::parse(one.as_bytes()).unwrap().0.to_str().unwrap();
+        assert_eq!(
+            run(&app, &mut client, &["SISMEMBER", "set", &one]).await,
+            ":0\r\n"
+        );
+        let mut rest = members(&run(&app, &mut client, &["SPOP", "set", "5"]).await);
+        rest.push(one);
+        rest.sort();
+        assert_eq!(rest, ["a", "b", "c"]);
+        assert_eq!(run(&app, &mut client, &["EXISTS", "set"]).await, ":0\r\n");
+
+        run(&app, &mut client, &["SADD", "set", "a", "b", "c"]).await;
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        let cases: &[(&[&str], &str)] = &[
+            (&["SPOP", "set", "0"], "*0\r\n"),
+            (&["SCARD", "set"], ":3\r\n"),
+            (&["SRANDMEMBER", "set", "0"], "*0\r\n"),
+            (&["SPOP", "missing"], "$-1\r\n"),
+            (&["SPOP", "missing", "2"], "*0\r\n"),
+            (&["SRANDMEMBER", "missing"], "$-1\r\n"),
+            (&["SRANDMEMBER", "missing", "-2"], "*0\r\n"),
+            (&["SPOP", "s"], wrongtype),
+            (&["SRANDMEMBER", "s", "1"], wrongtype),
+            (
+                &["SPOP", "set", "-1"],
+                "-ERR value is out of range, must be positive\r\n",
+            ),
+            (
+                &["SRANDMEMBER", "set", "-9223372036854775808"],
+                "-ERR value is out of range\r\n",
+            ),
+            (
+                &["SRANDMEMBER", "set", "-9223372036854775807"],
+                "-ERR count is larger than random-reply-limit (1000000)\r\n",
+            ),
+            (
+                &["SPOP", "set", "x"],
+                "-ERR value is not an integer or out of range\r\n",
+            ),
+            (&["SPOP", "set", "1", "2"], "-ERR syntax error\r\n"),
+            (&["SRANDMEMBER", "set", "1", "2"], "-ERR syntax error\r\n"),
+            (
+                &["SPOP"],
+                "-ERR wrong number of arguments for 'spop' command\r\n",
+            ),
+            (
+                &["SRANDMEMBER"],
+                "-ERR wrong number of arguments for 'srandmember' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+
+        // popping several replies with a set, picking several with an array
+        client.protocol = Protocol::Resp3;
+        run(&app, &mut client, &["SADD", "one", "m"]).await;
+        assert_eq!(
+            run(&app, &mut client, &["SRANDMEMBER", "one", "-2"]).await,
+            "*2\r\n$1\r\nm\r\n$1\r\nm\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["SPOP", "one", "2"]).await,
+            "~1\r\n$1\r\nm\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn smove() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "s", "v"]).await;
+        run(&app, &mut client, &["SADD", "src", "a", "b"]).await;
+        run(&app, &mut client, &["SADD", "dst", "c"]).await;
+
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        let cases: &[(&[&str], &str)] = &[
+            (&["SMOVE", "src", "dst", "a"], ":1\r\n"),
+            (&["SMEMBERS", "src"], "*1\r\n$1\r\nb\r\n"),
+            (&["SMEMBERS", "dst"], "*2\r\n$1\r\nc\r\n$1\r\na\r\n"),
+            (&["SMOVE", "src", "dst", "x"], ":0\r\n"),
+            (&["SMOVE", "missing", "dst", "a"], ":0\r\n"),
+            (&["EXISTS", "missing"], ":0\r\n"),
+            // a member the destination already has is still moved
+            (&["SADD", "src", "c"], ":1\r\n"),
+            (&["SMOVE", "src", "dst", "c"], ":1\r\n"),
+            (&["SCARD", "dst"], ":2\r\n"),
+            (&["SMOVE", "dst", "dst", "c"], ":1\r\n"),
+            (&["SMOVE", "dst", "dst", "x"], ":0\r\n"),
+            (&["SCARD", "dst"], ":2\r\n"),
+            // the last member takes the source with it, the destination is
+            // created
+            (&["SMOVE", "src", "new", "b"], ":1\r\n"),
+            (&["EXISTS", "src"], ":0\r\n"),
+            (&["SMEMBERS", "new"], "*1\r\n$1\r\nb\r\n"),
+            (&["SMOVE", "s", "dst", "a"], wrongtype),
+            (&["SMOVE", "dst", "s", "a"], wrongtype),
+            // not even when the source doesn't have the member
+            (&["SMOVE", "dst", "s", "x"], wrongtype),
+            (&["SISMEMBER", "dst", "a"], ":1\r\n"),
+            (
+                &["SMOVE", "dst", "new"],
+                "-ERR wrong number of arguments for 'smove' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn lmove() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "s", "v"]).await;
+        run(&app, &mut client, &["RPUSH", "a", "1", "2", "3"]).await;
+
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        let cases: &[(&[&str], &str)] = &[
+            (&["LMOVE", "a", "b", "LEFT", "RIGHT"], "$1\r\n1\r\n"),
+            (&["LMOVE", "a", "b", "right", "left"], "$1\r\n3\r\n"),
+            (&["LRANGE", "b", "0", "-1"], "*2\r\n$1\r\n3\r\n$1\r\n1\r\n"),
+            (&["RPOPLPUSH", "b", "b"], "$1\r\n1\r\n"),
+            (&["LRANGE", "b", "0", "-1"], "*2\r\n$1\r\n1\r\n$1\r\n3\r\n"),
+            // moving the last element removes the source
+            (&["RPOPLPUSH", "a", "b"], "$1\r\n2\r\n"),
+            (&["EXISTS", "a"], ":0\r\n"),
+            (
+                &["LRANGE", "b", "0", "-1"],
+                "*3\r\n$1\r\n2\r\n$1\r\n1\r\n$1\r\n3\r\n",
+            ),
+            (&["LMOVE", "missing", "b", "LEFT", "LEFT"], "$-1\r\n"),
+            (&["EXISTS", "missing"], ":0\r\n"),
+            // a single element rotated onto itself stays
+            (&["RPUSH", "one", "x"], ":1\r\n"),
+            (&["LMOVE", "one", "one", "LEFT", "RIGHT"], "$1\r\nx\r\n"),
+            (&["LRANGE", "one", "0", "-1"], "*1\r\n$1\r\nx\r\n"),
+            // nothing is popped if the destination isn't a list
+            (&["LMOVE", "b", "s", "LEFT", "LEFT"], wrongtype),
+            (&["RPOPLPUSH", "s", "b"], wrongtype),
+            (&["LLEN", "b"], ":3\r\n"),
+            (&["LMOVE", "b", "c", "UP", "LEFT"], "-ERR syntax error\r\n"),
+            (
+                &["RPOPLPUSH", "b"],
+                "-ERR wrong number of arguments for 'rpoplpush' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn lmpop() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "s", "v"]).await;
+        run(&app, &mut client, &["RPUSH", "b", "1", "2", "3"]).await;
+
+        let cases: &[(&[&str], &str)] = &[
+            (
+                &["LMPOP", "2", "a", "b", "LEFT"],
+                "*2\r\n$1\r\nb\r\n*1\r\n$1\r\n1\r\n",
+            ),
+            (
+                &["LMPOP", "2", "a", "b", "right", "COUNT", "5"],
+                "*2\r\n$1\r\nb\r\n*2\r\n$1\r\n3\r\n$1\r\n2\r\n",
+            ),
+            (&["EXISTS", "b"], ":0\r\n"),
+            (&["LMPOP", "2", "a", "b", "LEFT"], "*-1\r\n"),
+            (
+                &["LMPOP", "2", "a", "s", "LEFT"],
+                "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+            ),
+            (
+                &["LMPOP", "-1", "a", "LEFT"],
+                "-ERR numkeys should be greater than 0\r\n",
+            ),
+            (&["LMPOP", "1", "a", "UP"], "-ERR syntax error\r\n"),
+            (
+                &["LMPOP", "1", "a", "LEFT", "COUNT"],
+                "-ERR syntax error\r\n",
+            ),
+            (
+                &["LMPOP", "1", "a", "LEFT", "COUNT", "0"],
+                "-ERR count should be greater than 0\r\n",
+            ),
+            (
+                &["LMPOP", "1", "a"],
+                "-ERR wrong number of arguments for 'lmpop' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn blocking_lists() {
+        let app = App::new();
+        let (mut a, mut b, mut c) = (Client::default(), Client::default(), Client::default());
+        run(&app, &mut c, &["SET", "s", "v"]).await;
+        run(&app, &mut c, &["RPUSH", "l", "x"]).await;
+        run(&app, &mut c, &["RPUSH", "m", "1", "2", "3"]).await;
+
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        let cases: &[(&[&str], &str)] = &[
+            // nothing blocks if there's something to pop
+            (
+                &["BLPOP", "missing", "l", "0"],
+                "*2\r\n$1\r\nl\r\n$1\r\nx\r\n",
+            ),
+            (&["EXISTS", "l"], ":0\r\n"),
+            (
+                &["BLMPOP", "0", "2", "missing", "m", "RIGHT", "COUNT", "2"],
+                "*2\r\n$1\r\nm\r\n*2\r\n$1\r\n3\r\n$1\r\n2\r\n",
+            ),
+            (&["BLMOVE", "m", "l", "LEFT", "LEFT", "0"], "$1\r\n1\r\n"),
+            (&["BRPOP", "missing", "0.01"], "*-1\r\n"),
+            (
+                &["BLMOVE", "missing", "l", "LEFT", "LEFT", "0.01"],
+                "*-1\r\n",
+            ),
+            (&["BLMPOP", "0.01", "1", "missing", "LEFT"], "*-1\r\n"),
+            (&["BLPOP", "s", "0"], wrongtype),
+            (&["BLPOP", "l", "-1"], "-ERR timeout is negative\r\n"),
+            (
+                &["BLPOP", "l", "soon"],
+                "-ERR timeout is not a float or out of range\r\n",
+            ),
+            (
+                &["BLPOP", "missing", "1e20"],
+                "-ERR timeout is out of range\r\n",
+            ),
+            (
+                &["BLPOP", "missing", "1e19"],
+                "-ERR timeout is out of range\r\n",
+            ),
+            (
+                &["BLMPOP", "0", "0", "l", "LEFT"],
+                "-ERR numkeys should be greater than 0\r\n",
+            ),
+            (&["BLMPOP", "0", "2", "l", "LEFT"], "-ERR syntax error\r\n"),
+            (
+                &["BLMPOP", "0", "1", "l", "LEFT", "COUNT", "0"],
+                "-ERR count should be greater than 0\r\n",
+            ),
+            (
+                &["BLMOVE", "l", "m", "UP", "LEFT", "0"],
+                "-ERR syntax error\r\n",
+            ),
+            (
+                &["BLPOP", "0"],
+                "-ERR wrong number of arguments for 'blpop' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut a, cmd).await, *expected, "{cmd:?}");
+        }
+
+        // the client blocked longest is served first
+        let pushes = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            run(&app, &mut c, &["RPUSH", "q", "first"]).await;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            run(&app, &mut c, &["RPUSH", "q", "second"]).await;
+        };
+        let (first, second, _) = tokio::join!(
+            run(&app, &mut a, &["BLPOP", "q", "0"]),
+            run(&app, &mut b, &["BRPOP", "other", "q", "0"]),
+            pushes,
+        );
+        assert_eq!(first, "*2\r\n$1\r\nq\r\n$5\r\nfirst\r\n");
+        assert_eq!(second, "*2\r\n$1\r\nq\r\n$6\r\nsecond\r\n");
+
+        // a push of several elements wakes as many clients, and moving onto a
+        // key wakes its clients too
+        let pushes = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            run(&app, &mut c, &["LPUSH", "q", "1", "2"]).await;
+        };
+        let (moved, popped, _) = tokio::join!(
+            run(&app, &mut a, &["BLMOVE", "q", "dst", "RIGHT", "LEFT", "0"]),
+            run(
+                &app,
+                &mut b,
+                &["BLMPOP", "0", "1", "dst", "LEFT", "COUNT", "5"]
+            ),
+            pushes,
+        );
+        assert_eq!(moved, "$1\r\n1\r\n");
+        assert_eq!(popped, "*2\r\n$3\r\ndst\r\n*1\r\n$1\r\n1\r\n");
+
+        // INFO counts the blocked clients, and those that will time out
+        let info = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let info = run(&app, &mut c, &["INFO", "clients"]).await;
+            run(&app, &mut c, &["RPUSH", "x", "1"]).await;
+            run(&app, &mut c, &["RPUSH", "z", "1"]).await;
+            info
+        };
+        let (_, _, info) = tokio::join!(
+            run(&app, &mut a, &["BLPOP", "x", "y", "0"]),
+            run(&app, &mut b, &["BLPOP", "z", "10"]),
+            info,
+        );
+        assert!(
+            info.contains("# Clients\r\nblocked_clients:2\r\n"),
+            "{info}"
+        );
+        assert!(info.contains("clients_in_timeout_table:1\r\n"), "{info}");
+        assert!(info.contains("total_blocking_keys:3\r\n"), "{info}");
+        let info = run(&app, &mut c, &["INFO", "clients"]).await;
+        assert!(info.contains("blocked_clients:0\r\n"), "{info}");
+        assert_eq!(
+            run(&app, &mut a, &["LRANGE", "q", "0", "-1"]).await,
+            "*1\r\n$1\r\n2\r\n"
+        );
+        assert!(app.blocked.lock().is_empty());
+
+        // so does putting a whole list in place
+        run(&app, &mut c, &["RPUSH", "src", "a"]).await;
+        run(&app, &mut c, &["RPUSH", "nums", "3", "1", "2"]).await;
+        let stores = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            run(&app, &mut c, &["RENAME", "src", "k"]).await;
+            run(&app, &mut c, &["SORT", "nums", "STORE", "sorted"]).await;
+        };
+        let (renamed, sorted, _) = tokio::join!(
+            run(&app, &mut a, &["BLPOP", "k", "0"]),
+            run(&app, &mut b, &["BRPOP", "sorted", "0"]),
+            stores,
+        );
+        assert_eq!(renamed, "*2\r\n$1\r\nk\r\n$1\r\na\r\n");
+        assert_eq!(sorted, "*2\r\n$6\r\nsorted\r\n$1\r\n3\r\n");
+        assert!(app.blocked.lock().is_empty());
+
+        let start = std::time::Instant::now();
+        assert_eq!(
+            run(&app, &mut a, &["BLPOP", "missing", "0.05"]).await,
+            "*-1\r\n"
+        );
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(app.blocked.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn dropped_blocked_clients() {
+        let app = App::new();
+        let (mut a, mut b, mut c) = (Client::default(), Client::default(), Client::default());
+        let short = Duration::from_millis(20);
+
+        // a client that hangs up while blocked leaves the queue
+        let mut gone = Box::pin(run(&app, &mut a, &["BLPOP", "q", "0"]));
+        assert!(tokio::time::timeout(short, &mut gone).await.is_err());
+        drop(gone);
+        assert!(app.blocked.lock().is_empty());
+        let mut waiting = Box::pin(run(&app, &mut b, &["BLPOP", "q", "0"]));
+        assert!(tokio::time::timeout(short, &mut waiting).await.is_err());
+        run(&app, &mut c, &["RPUSH", "q", "x"]).await;
+        assert_eq!(
+            tokio::time::timeout(short, waiting).await.unwrap(),
+            "*2\r\n$1\r\nq\r\n$1\r\nx\r\n"
+        );
+
+        // one that hangs up after a push woke it hands the wakeup on
+        let mut gone = Box::pin(run(&app, &mut a, &["BLPOP", "q", "0"]));
+        assert!(tokio::time::timeout(short, &mut gone).await.is_err());
+        let mut waiting = Box::pin(run(&app, &mut b, &["BRPOP", "q", "0"]));
+        assert!(tokio::time::timeout(short, &mut waiting).await.is_err());
+        run(&app, &mut c, &["RPUSH", "q", "y"]).await;
+        drop(gone);
+        assert_eq!(
+            tokio::time::timeout(short, waiting).await.unwrap(),
+            "*2\r\n$1\r\nq\r\n$1\r\ny\r\n"
+        );
+        assert!(app.blocked.lock().is_empty());
+        assert_eq!(run(&app, &mut c, &["LLEN", "q"]).await, ":0\r\n");
+    }
+
+    #[tokio::test]
+    async fn list_edits() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "s", "v"]).await;
+        run(
+            &app,
+            &mut client,
+            &["RPUSH", "l", "a", "b", "c", "b", "a", "b"],
+        )
+        .await;
+
+        let ok = "$2\r\nOK\r\n";
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        let cases: &[(&[&str], &str)] = &[
+            (&["LPOS", "l", "b"], ":1\r\n"),
+            (&["LPOS", "l", "b", "RANK", "2"], ":3\r\n"),
+            (&["LPOS", "l", "b", "RANK", "-1"], ":5\r\n"),
+            (&["LPOS", "l", "b", "RANK", "4"], "$-1\r\n"),
+            (&["LPOS", "l", "b", "COUNT", "0"], "*3\r\n:1\r\n:3\r\n:5\r\n"),
+            (&["LPOS", "l", "b", "COUNT", "2", "RANK", "-1"], "*2\r\n:5\r\n:3\r\n"),
+            (&["LPOS", "l", "b", "COUNT", "0", "MAXLEN", "4"], "*2\r\n:1\r\n:3\r\n"),
+            (&["LPOS", "l", "x", "COUNT", "1"], "*0\r\n"),
+            (&["LPOS", "missing", "x"], "$-1\r\n"),
+            (&["LPOS", "missing", "x", "COUNT", "1"], "*0\r\n"),
+            (
+                &["LPOS", "l", "b", "RANK", "0"],
+                "-ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list\r\n",
+            ),
+            (&["LPOS", "l", "b", "COUNT", "-1"], "-ERR COUNT can't be negative\r\n"),
+            (&["LPOS", "l", "b", "MAXLEN", "-1"], "-ERR MAXLEN can't be negative\r\n"),
+            (&["LPOS", "l", "b", "FIRST", "1"], "-ERR syntax error\r\n"),
+            (&["LPOS", "l", "b", "RANK"], "-ERR syntax error\r\n"),
+            (&["LINSERT", "l", "BEFORE", "c", "x"], ":7\r\n"),
+            (&["LINSERT", "l", "after", "a", "y"], ":8\r\n"),
+            (&["LINSERT", "l", "AFTER", "nope", "y"], ":-1\r\n"),
+            (&["LINSERT", "missing", "AFTER", "a", "y"], ":0\r\n"),
+            (&["LINSERT", "l", "NEXT", "a", "y"], "-ERR syntax error\r\n"),
+            (
+                &["LRANGE", "l", "0", "-1"],
+                "*8\r\n$1\r\na\r\n$1\r\ny\r\n$1\r\nb\r\n$1\r\nx\r\n$1\r\nc\r\n$1\r\nb\r\n$1\r\na\r\n$1\r\nb\r\n",
+            ),
+            (&["LSET", "l", "1", "z"], ok),
+            (&["LSET", "l", "-1", "end"], ok),
+            (&["LSET", "l", "8", "z"], "-ERR index out of range\r\n"),
+            (&["LSET", "l", "-9", "z"], "-ERR index out of range\r\n"),
+            (&["LSET", "missing", "0", "z"], "-ERR no such key\r\n"),
+            (&["LREM", "l", "-1", "a"], ":1\r\n"),
+            (&["LREM", "l", "0", "b"], ":2\r\n"),
+            (&["LREM", "l", "5", "nope"], ":0\r\n"),
+            (
+                &["LRANGE", "l", "0", "-1"],
+                "*5\r\n$1\r\na\r\n$1\r\nz\r\n$1\r\nx\r\n$1\r\nc\r\n$3\r\nend\r\n",
+            ),
+            (&["LTRIM", "l", "1", "-2"], ok),
+            (
+                &["LRANGE", "l", "0", "-1"],
+                "*3\r\n$1\r\nz\r\n$1\r\nx\r\n$1\r\nc\r\n",
+            ),
+            (&["LTRIM", "l", "-2", "100"], ok),
+            (&["LRANGE", "l", "0", "-1"], "*2\r\n$1\r\nx\r\n$1\r\nc\r\n"),
+            // lists trimmed or emptied away are removed
+            (&["LTRIM", "l", "5", "10"], ok),
+            (&["EXISTS", "l"], ":0\r\n"),
+            (&["RPUSH", "l", "a", "a"], ":2\r\n"),
+            (&["LREM", "l", "0", "a"], ":2\r\n"),
+            (&["EXISTS", "l"], ":0\r\n"),
+            (&["LTRIM", "missing", "0", "1"], ok),
+            (&["LINSERT", "s", "BEFORE", "a", "b"], wrongtype),
+            (&["LSET", "s", "0", "a"], wrongtype),
+            (&["LREM", "s", "0", "a"], wrongtype),
+            (&["LTRIM", "s", "0", "1"], wrongtype),
+            (&["LPOS", "s", "a"], wrongtype),
+            (
+                &["LSET", "l", "0"],
+                "-ERR wrong number of arguments for 'lset' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn sort() {
+        let app = App::new();
+        let mut client = Client::default();
+        let list = |items: &[&str]| {
+            Entry::new(Value::Array(Some(
+                items.iter().map(|i| Value::str(i)).collect(),
+            )))
+        };
+        app.store
+            .lock()
+            .insert("nums".into(), list(&["3", "10", "1", "2.5", "-1"]));
+        app.store
+            .lock()
+            .insert("ids".into(), list(&["a", "c", "b", "d"]));
+        app.store.lock().insert(
+            "set".into(),
+            Entry::new(Value::Set(vec![Value::str("b"), Value::str("a")])),
+        );
+        app.store.lock().insert(
+            "user_c".into(),
+            Entry::new(Value::Map(Map::from([(
+                Value::str("name"),
+                Value::str("carol"),
+            )]))),
+        );
+        run(
+            &app,
+            &mut client,
+            &["MSET", "w_a", "3", "w_b", "1", "w_c", "2", "name_a", "ann"],
+        )
+        .await;
+
+        let cases: &[(&[&str], &str)] = &[
+            (&["SORT", "nums"], "*5\r\n$2\r\n-1\r\n$1\r\n1\r\n$3\r\n2.5\r\n$1\r\n3\r\n$2\r\n10\r\n"),
+            (&["SORT", "nums", "DESC", "LIMIT", "1", "2"], "*2\r\n$1\r\n3\r\n$3\r\n2.5\r\n"),
+            (&["SORT", "nums", "ALPHA", "LIMIT", "0", "2"], "*2\r\n$2\r\n-1\r\n$1\r\n1\r\n"),
+            (&["SORT", "nums", "LIMIT", "4", "-1"], "*1\r\n$2\r\n10\r\n"),
+            (&["SORT", "nums", "LIMIT", "9", "1"], "*0\r\n"),
+            (&["SORT", "set", "ALPHA"], "*2\r\n$1\r\na\r\n$1\r\nb\r\n"),
+            (&["SORT", "missing"], "*0\r\n"),
+            // missing weights count as 0, ties go by the element
+            (&["SORT", "ids", "BY", "w_*"], "*4\r\n$1\r\nd\r\n$1\r\nb\r\n$1\r\nc\r\n$1\r\na\r\n"),
+            (&["SORT", "ids", "BY", "nosort"], "*4\r\n$1\r\na\r\n$1\r\nc\r\n$1\r\nb\r\n$1\r\nd\r\n"),
+            (
+                &["SORT", "ids", "BY", "nosort", "GET", "#", "GET", "name_*", "GET", "user_*->name", "LIMIT", "0", "3"],
+                "*9\r\n$1\r\na\r\n$3\r\nann\r\n$-1\r\n$1\r\nc\r\n$-1\r\n$5\r\ncarol\r\n$1\r\nb\r\n$-1\r\n$-1\r\n",
+            ),
+            (&["SORT", "ids", "BY", "user_*->name", "ALPHA", "DESC", "LIMIT", "0", "1"], "*1\r\n$1\r\nc\r\n"),
+            (&["SORT", "ids", "STORE", "dst", "ALPHA", "GET", "name_*"], ":4\r\n"),
+            (&["TYPE", "dst"], "$4\r\nlist\r\n"),
+            (&["SORT", "dst", "BY", "nosort"], "*4\r\n$3\r\nann\r\n$0\r\n\r\n$0\r\n\r\n$0\r\n\r\n"),
+            (&["SORT_RO", "nums", "LIMIT", "0", "1"], "*1\r\n$2\r\n-1\r\n"),
+            // an empty result removes the destination
+            (&["SORT", "missing", "STORE", "dst"], ":0\r\n"),
+            (&["EXISTS", "dst"], ":0\r\n"),
+            (&["SORT_RO", "nums", "STORE", "dst"], "-ERR syntax error\r\n"),
+            (&["SORT", "ids"], "-ERR One or more scores can't be converted into double\r\n"),
+            (&["SORT", "name_a"], "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"),
+            (&["SORT", "nums", "LIMIT", "1"], "-ERR syntax error\r\n"),
+            (&["SORT", "nums", "LIMIT", "a", "1"], "-ERR value is not an integer or out of range\r\n"),
+            (&["SORT"], "-ERR wrong number of arguments for 'sort' command\r\n"),
+            (&["SORT_RO"], "-ERR wrong number of arguments for 'sort_ro' command\r\n"),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn lcs() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(
+            &app,
+            &mut client,
+            &["MSET", "a", "ohmytext", "b", "mynewtext"],
+        )
+        .await;
+        app.store
+            .lock()
+            .insert("list".into(), Entry::new(Value::Array(Some(vec![]))));
+
+        let cases: &[(&[&str], &str)] = &[
+            (&["LCS", "a", "b"], "$6\r\nmytext\r\n"),
+            (&["LCS", "a", "b", "LEN"], ":6\r\n"),
+            (&["LCS", "a", "missing"], "$0\r\n\r\n"),
+            (
+                &["LCS", "a", "b", "IDX"],
+                "*4\r\n$7\r\nmatches\r\n*2\r\n*2\r\n*2\r\n:4\r\n:7\r\n*2\r\n:5\r\n:8\r\n*2\r\n*2\r\n:2\r\n:3\r\n*2\r\n:0\r\n:1\r\n$3\r\nlen\r\n:6\r\n",
+            ),
+            (
+                &["LCS", "a", "b", "idx", "minmatchlen", "4", "withmatchlen"],
+                "*4\r\n$7\r\nmatches\r\n*1\r\n*3\r\n*2\r\n:4\r\n:7\r\n*2\r\n:5\r\n:8\r\n:4\r\n$3\r\nlen\r\n:6\r\n",
+            ),
+            (
+                &["LCS", "a", "b", "LEN", "IDX"],
+                "-ERR If you want both the length and indexes, please just use IDX.\r\n",
+            ),
+            (
+                &["LCS", "a", "list"],
+                "-ERR The specified keys must contain string values\r\n",
+            ),
+            (&["LCS", "a", "b", "MINMATCHLEN"], "-ERR syntax error\r\n"),
+            (&["LCS", "a", "b", "FOO"], "-ERR syntax error\r\n"),
+            (
+                &["LCS", "a"],
+                "-ERR wrong number of arguments for 'lcs' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+
+        client.protocol = Protocol::Resp3;
+        assert_eq!(
+            run(&app, &mut client, &["LCS", "a", "b", "IDX", "MINMATCHLEN", "3"]).await,
+            "%2\r\n$7\r\nmatches\r\n*1\r\n*2\r\n*2\r\n:4\r\n:7\r\n*2\r\n:5\r\n:8\r\n$3\r\nlen\r\n:6\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn getrange_setrange() {
+        let app = App::new();
+        let mut client = Client::default();
+        assert_eq!(
+            run(&app, &mut client, &["GETRANGE", "k", "0", "-1"]).await,
+            "$0\r\n\r\n"
+        );
+        run(&app, &mut client, &["SET", "k", "This is a string"]).await;
+        let cases = [
+            (("0", "3"), "This"),
+            (("-3", "-1"), "ing"),
+            (("0", "-1"), "This is a string"),
+            (("10", "100"), "string"),
+            (("-100", "3"), "This"),
+            (("5", "3"), ""),
+            (("-1", "-5"), ""),
+            (("16", "20"), ""),
+        ];
+        for ((start, end), expected) in cases {
+            assert_eq!(
+                run(&app, &mut client, &["GETRANGE", "k", start, end]).await,
+                format!("${}\r\n{expected}\r\n", expected.len()),
+                "{start} {end}"
+            );
+        }
+
+        assert_eq!(
+            run(&app, &mut client, &["SETRANGE", "k", "10", "strung"]).await,
+            ":16\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["GET", "k"]).await,
+            "$16\r\nThis is a strung\r\n"
+        );
+
+        // missing keys and short strings are padded with zeros
+        assert_eq!(
+            run(&app, &mut client, &["SETRANGE", "new", "3", "ab"]).await,
+            ":5\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["GET", "new"]).await,
+            "$5\r\n\0\0\0ab\r\n"
+        );
+        run(&app, &mut client, &["SETRANGE", "new", "6", "c"]).await;
+        assert_eq!(
+            run(&app, &mut client, &["GET", "new"]).await,
+            "$7\r\n\0\0\0ab\0c\r\n"
+        );
+
+        // writing nothing changes nothing
+        assert_eq!(
+            run(&app, &mut client, &["SETRANGE", "empty", "10", ""]).await,
+            ":0\r\n"
+        );
+        assert_eq!(run(&app, &mut client, &["GET", "empty"]).await, "$-1\r\n");
+        assert_eq!(
+            run(&app, &mut client, &["SETRANGE", "new", "100", ""]).await,
+            ":7\r\n"
+        );
+
+        // far past the end only what's written takes memory
+        assert_eq!(
+            run(&app, &mut client, &["SETRANGE", "far", "4000000", "x"]).await,
+            ":4000001\r\n"
+        );
+        let usage = run(&app, &mut client, &["MEMORY", "USAGE", "far"]).await;
+        assert!(usage[1..usage.len() - 2].parse::<usize>().unwrap() < 1000);
+        assert_eq!(
+            run(&app, &mut client, &["STRLEN", "far"]).await,
+            ":4000001\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["GETRANGE", "far", "-3", "-1"]).await,
+            "$3\r\n\0\0x\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["APPEND", "far", "y"]).await,
+            ":4000002\r\n"
+        );
+        let reply = run(&app, &mut client, &["GET", "far"]).await;
+        assert!(reply.starts_with("$4000002\r\n\0"));
+        assert!(reply.ends_with("\0xy\r\n"));
+
+        assert_eq!(
+            run(&app, &mut client, &["SETRANGE", "k", "-1", "x"]).await,
+            "-ERR offset is out of range\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["SETRANGE", "k", "536870912", "x"]).await,
+            "-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn incr_decr() {
+        let app = App::new();
+        let mut client = Client::default();
+        assert_eq!(run(&app, &mut client, &["INCR", "n"]).await, ":1\r\n");
+        assert_eq!(
+            run(&app, &mut client, &["INCRBY", "n", "41"]).await,
+            ":42\r\n"
+        );
+        assert_eq!(run(&app, &mut client, &["DECR", "n"]).await, ":41\r\n");
+        assert_eq!(
+            run(&app, &mut client, &["DECRBY", "n", "-9"]).await,
+            ":50\r\n"
+        );
+        assert_eq!(run(&app, &mut client, &["GET", "n"]).await, "$2\r\n50\r\n");
+
+        // the expiry survives
+        run(&app, &mut client, &["SET", "n", "10", "EX", "100"]).await;
+        run(&app, &mut client, &["INCR", "n"]).await;
+        assert!(app.store.lock().get(&"n".into()).unwrap().expiry.is_some());
+
+        run(&app, &mut client, &["SET", "max", &i64::MAX.to_string()]).await;
+        run(&app, &mut client, &["SET", "s", "01"]).await;
+        app.store
+            .lock()
+            .insert("list".into(), Entry::new(Value::Array(Some(vec![]))));
+        let cases: &[(&[&str], &str)] = &[
+            (
+                &["INCR", "max"],
+                "-ERR increment or decrement would overflow\r\n",
+            ),
+            (
+                &["DECRBY", "n", "-9223372036854775808"],
+                "-ERR decrement would overflow\r\n",
+            ),
+            (
+                &["INCR", "s"],
+                "-ERR value is not an integer or out of range\r\n",
+            ),
+            (
+                &["INCRBY", "n", "1.5"],
+                "-ERR value is not an integer or out of range\r\n",
+            ),
+            (
+                &["INCR", "list"],
+                "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+            ),
+            (
+                &["INCR"],
+                "-ERR wrong number of arguments for 'incr' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+        assert_eq!(run(&app, &mut client, &["GET", "s"]).await, "$2\r\n01\r\n");
+    }
+
+    #[tokio::test]
+    async fn incrbyfloat() {
+        let app = App::new();
+        let mut client = Client::default();
+        let cases = [
+            ("10.5", "$4\r\n10.5\r\n"),
+            ("0.1", "$4\r\n10.6\r\n"),
+            ("-5", "$3\r\n5.6\r\n"),
+            ("5.0e3", "$6\r\n5005.6\r\n"),
+            ("-5005.6", "$1\r\n0\r\n"),
+        ];
+        for (by, expected) in cases {
+            assert_eq!(
+                run(&app, &mut client, &["INCRBYFLOAT", "f", by]).await,
+                expected,
+                "{by}"
+            );
+        }
+
+        run(&app, &mut client, &["SET", "big", "1e308"]).await;
+        run(&app, &mut client, &["SET", "s", "abc"]).await;
+        let cases: &[(&[&str], &str)] = &[
+            (
+                &["INCRBYFLOAT", "big", "1e308"],
+                "-ERR increment would produce NaN or Infinity\r\n",
+            ),
+            (
+                &["INCRBYFLOAT", "f", "inf"],
+                "-ERR value is not a valid float\r\n",
+            ),
+            (
+                &["INCRBYFLOAT", "s", "1"],
+                "-ERR value is not a valid float\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn legacy_setters() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["DEBUG", "SET-CLOCK", "1000"]).await;
+        let ttl = |app: &App| app.store.lock().get(&"k".into()).unwrap().expiry;
+
+        assert_eq!(run(&app, &mut client, &["SETNX", "k", "a"]).await, ":1\r\n");
+        assert_eq!(run(&app, &mut client, &["SETNX", "k", "b"]).await, ":0\r\n");
+        assert_eq!(run(&app, &mut client, &["GET", "k"]).await, "$1\r\na\r\n");
+
+        assert_eq!(
+            run(&app, &mut client, &["SETEX", "k", "10", "b"]).await,
+            "$2\r\nOK\r\n"
+        );
+        assert_eq!(ttl(&app), Some(11000));
+        run(&app, &mut client, &["PSETEX", "k", "10", "c"]).await;
+        assert_eq!(ttl(&app), Some(1010));
+        assert_eq!(run(&app, &mut client, &["GET", "k"]).await, "$1\r\nc\r\n");
+
+        let cases: &[(&[&str], &str)] = &[
+            (
+                &["SETEX", "k", "0", "v"],
+                "-ERR invalid expire time in 'setex' command\r\n",
+            ),
+            (
+                &["PSETEX", "k", "-5", "v"],
+                "-ERR invalid expire time in 'psetex' command\r\n",
+            ),
+            (
+                &["SETEX", "k", "ten", "v"],
+                "-ERR value is not an integer or out of range\r\n",
+            ),
+            (
+                &["SETEX", "k", "10"],
+                "-ERR wrong number of arguments for 'setex' command\r\n",
+            ),
+            (
+                &["SETNX", "k"],
+                "-ERR wrong number of arguments for 'setnx' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+        assert_eq!(ttl(&app), Some(1010));
+    }
+
+    #[tokio::test]
+    async fn binary_values() {
+        let app = App::new();
+        let mut client = Client::default();
+        let key = Value::bytes(*b"\xff\x00k");
+        let value = Value::bytes(*b"\r\n\x80\x81");
+
+        let set = Value::from(vec!["SET".into(), key.clone(), value]);
+        app.dispatch_command(&mut client, set).await;
+        let get = Value::from(vec!["GET".into(), key]);
+        assert_eq!(
+            app.dispatch_command(&mut client, get).await,
+            b"$4\r\n\r\n\x80\x81\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn get_shares_value() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "k", &"v".repeat(1000)]).await;
+
+        let key = [Value::str("k")];
+        let a = app.get(&key).await.unwrap();
+        let b = app.get(&key).await.unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        let reply = run(&app, &mut client, &["DEBUG", "OBJECT", "k"]).await;
+        assert!(reply.contains(" refcount:3 "), "{reply}");
+    }
+
+    #[tokio::test]
+    async fn hello_switches_protocol() {
+        let app = App::new();
+        let mut client = Client::default();
+        assert_eq!(run(&app, &mut client, &["GET", "missing"]).await, "$-1\r\n");
+
+        let hello = run(&app, &mut client, &["HELLO", "3"]).await;
+        assert!(hello.starts_with("%6\r\n$6\r\nserver\r\n$5\r\nredis\r\n"));
+        assert_eq!(client.protocol, Protocol::Resp3);
+        assert_eq!(run(&app, &mut client, &["GET", "missing"]).await, "_\r\n");
+
+        let hello = run(&app, &mut client, &["HELLO", "2"]).await;
+        assert!(hello.starts_with("*12\r\n"));
+        assert_eq!(
+            run(&app, &mut client, &["HELLO", "4"]).await,
+            "-NOPROTO unsupported protocol version\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn getex() {
+        let app = App::new();
+        let mut client = Client::default();
+        assert_eq!(run(&app, &mut client, &["GETEX", "k"]).await, "$-1\r\n");
+
+        run(&app, &mut client, &["SET", "k", "v", "PX", "30"]).await;
+        assert_eq!(
+            run(&app, &mut client, &["GETEX", "k", "PERSIST"]).await,
+            "$1\r\nv\r\n"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(run(&app, &mut client, &["GET", "k"]).await, "$1\r\nv\r\n");
+
+        assert_eq!(
+            run(&app, &mut client, &["GETEX", "k", "PXAT", "1"]).await,
+            "$1\r\nv\r\n"
+        );
+        assert_eq!(run(&app, &mut client, &["GET", "k"]).await, "$-1\r\n");
+
+        app.store
+            .lock()
+            .insert("list".into(), Entry::new(Value::Array(Some(vec![]))));
+        assert_eq!(
+            run(&app, &mut client, &["GETEX", "list"]).await,
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn getdel_getset() {
+        let app = App::new();
+        let mut client = Client::default();
+        assert_eq!(run(&app, &mut client, &["GETDEL", "k"]).await, "$-1\r\n");
+        assert_eq!(
+            run(&app, &mut client, &["GETSET", "k", "a"]).await,
+            "$-1\r\n"
+        );
+
+        run(&app, &mut client, &["SET", "k", "a", "EX", "100"]).await;
+        assert_eq!(
+            run(&app, &mut client, &["GETSET", "k", "b"]).await,
+            "$1\r\na\r\n"
+        );
+        // like SET, GETSET drops the expiry
+        assert_eq!(app.store.lock().get(&"k".into()).unwrap().expiry, None);
+
+        assert_eq!(
+            run(&app, &mut client, &["GETDEL", "k"]).await,
+            "$1\r\nb\r\n"
+        );
+        assert_eq!(run(&app, &mut client, &["GET", "k"]).await, "$-1\r\n");
+        let info = run(&app, &mut client, &["INFO", "keyspace"]).await;
+        assert!(!info.contains("db0:"), "{info}");
+
+        app.store
+            .lock()
+            .insert("list".into(), Entry::new(Value::Array(Some(vec![]))));
+        let wrongtype = "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+        assert_eq!(run(&app, &mut client, &["GETDEL", "list"]).await, wrongtype);
+        assert_eq!(
+            run(&app, &mut client, &["GETSET", "list", "v"]).await,
+            wrongtype
+        );
+        assert_eq!(
+            run(&app, &mut client, &["GETDEL", "a", "b"]).await,
+            "-ERR wrong number of arguments for 'getdel' command\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn expiry_options_are_validated() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "k", "v"]).await;
+
+        let cases: &[(&[&str], &str)] = &[
+            (
+                &["GETEX", "k", "EX", "1", "PERSIST"],
+                "-ERR syntax error\r\n",
+            ),
+            (&["GETEX", "k", "EX"], "-ERR syntax error\r\n"),
+            (&["GETEX", "k", "FOO"], "-ERR syntax error\r\n"),
+            (
+                &["GETEX", "k", "EX", "abc"],
+                "-ERR value is not an integer or out of range\r\n",
+            ),
+            (
+                &["GETEX", "k", "PX", "0"],
+                "-ERR invalid expire time in 'getex' command\r\n",
+            ),
+            (
+                &["SET", "k", "v", "EX", "-1"],
+                "-ERR invalid expire time in 'set' command\r\n",
+            ),
+            (
+                &["SET", "k", "v", "EX", "1", "PX", "1"],
+                "-ERR syntax error\r\n",
+            ),
+            (
+                &["SET", "k", "v", "EX", "1", "KEEPTTL"],
+                "-ERR syntax error\r\n",
+            ),
+            (&["SET", "k", "v", "NX", "XX"], "-ERR syntax error\r\n"),
+            (&["SET", "k", "v", "FOO"], "-ERR syntax error\r\n"),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_usage() {
+        let app = App::new();
+        let mut client = Client::default();
+        assert_eq!(
+            run(&app, &mut client, &["MEMORY", "USAGE", "k"]).await,
+            "$-1\r\n"
+        );
+
+        run(&app, &mut client, &["SET", "k", "v"]).await;
+        let small = run(&app, &mut client, &["MEMORY", "USAGE", "k"]).await;
+        let value = "v".repeat(1000);
+        run(&app, &mut client, &["SET", "k", &value]).await;
+        let large = run(&app, &mut client, &["MEMORY", "USAGE", "k", "SAMPLES", "5"]).await;
+
+        let parse = |s: &str| s[1..s.len() - 2].parse::<usize>().unwrap();
+        assert!(parse(&large) >= parse(&small) + 999);
+    }
+
+    #[tokio::test]
+    async fn debug_object() {
+        let app = App::new();
+        let mut client = Client::default();
+        assert_eq!(
+            run(&app, &mut client, &["DEBUG", "OBJECT", "k"]).await,
+            "-ERR no such key\r\n"
+        );
+
+        let cases = [
+            ("bar", "embstr", 4),
+            ("1000", "int", 3),
+            ("-0", "embstr", 3),
+        ];
+        for (value, encoding, len) in cases {
+            run(&app, &mut client, &["SET", "k", value]).await;
+            let reply = run(&app, &mut client, &["DEBUG", "OBJECT", "k"]).await;
+            assert!(
+                reply.ends_with(&format!(" encoding:{encoding} serializedlength:{len}\r\n")),
+                "{value}: {reply}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn info_keyspace() {
+        let app = App::new();
+        let mut client = Client::default();
+        assert_eq!(
+            run(&app, &mut client, &["INFO", "keyspace"]).await,
+            "$12\r\n# Keyspace\r\n\r\n"
+        );
+
+        run(&app, &mut client, &["SET", "a", "1"]).await;
+        run(&app, &mut client, &["SET", "b", "1", "EX", "100"]).await;
+        run(&app, &mut client, &["SET", "c", "1", "PX", "1"]).await;
+        let info = run(&app, &mut client, &["INFO"]).await;
+        assert!(info.contains("db0:keys=3,expires=2,avg_ttl="), "{info}");
+        assert!(info.contains("db0_types:string=3,list=0,set=0,hash=0\r\n"));
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        app.prune_expired();
+        run(&app, &mut client, &["GETEX", "b", "PERSIST"]).await;
+        run(&app, &mut client, &["SET", "a", "2"]).await;
+        let info = run(&app, &mut client, &["INFO", "KEYSPACE"]).await;
+        assert!(
+            info.contains("db0:keys=2,expires=0,avg_ttl=0\r\n"),
+            "{info}"
+        );
+        assert!(info.contains("db0_types:string=2,"));
+
+        assert_eq!(
+            run(&app, &mut client, &["INFO", "server"]).await,
+            "$0\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn frozen_clock() {
+        let app = App::new();
+        let mut client = Client::default();
+        let ok = "$2\r\nOK\r\n";
+        assert_eq!(
+            run(&app, &mut client, &["DEBUG", "SET-CLOCK", "1000"]).await,
+            ok
+        );
+
+        run(&app, &mut client, &["SET", "a", "1", "PX", "100"]).await;
+        run(&app, &mut client, &["SET", "b", "1", "PXAT", "1150"]).await;
+        let info = run(&app, &mut client, &["INFO"]).await;
+        assert!(
+            info.contains("db0:keys=2,expires=2,avg_ttl=125\r\n"),
+            "{info}"
+        );
+
+        // a key is still there at its deadline, and gone right after
+        run(&app, &mut client, &["DEBUG", "SLEEP", "0.1"]).await;
+        assert_eq!(run(&app, &mut client, &["GET", "a"]).await, "$1\r\n1\r\n");
+        run(&app, &mut client, &["DEBUG", "SLEEP", "0.001"]).await;
+        assert_eq!(run(&app, &mut client, &["GET", "a"]).await, "$-1\r\n");
+        assert_eq!(run(&app, &mut client, &["GET", "b"]).await, "$1\r\n1\r\n");
+
+        // this returns right away instead of sleeping for an hour
+        run(&app, &mut client, &["DEBUG", "SLEEP", "3600"]).await;
+        assert_eq!(run(&app, &mut client, &["GET", "b"]).await, "$-1\r\n");
+
+        assert_eq!(
+            run(&app, &mut client, &["DEBUG", "SET-CLOCK", "-1"]).await,
+            "-ERR value is not an integer or out of range\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["DEBUG", "SET-CLOCK", "system"]).await,
+            ok
+        );
+        run(&app, &mut client, &["SET", "c", "1", "PXAT", "1000"]).await;
+        assert_eq!(run(&app, &mut client, &["GET", "c"]).await, "$-1\r\n");
+    }
+
+    #[tokio::test]
+    async fn keys() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["DEBUG", "SET-CLOCK", "1000"]).await;
+        run(
+            &app,
+            &mut client,
+            &[
+                "MSET", "user:1", "a", "user:2", "b", "user:10", "c", "other", "d",
+            ],
+        )
+        .await;
+        run(&app, &mut client, &["SET", "user:3", "e", "PX", "10"]).await;
+        run(&app, &mut client, &["DEBUG", "SLEEP", "0.011"]).await;
+
+        // the order is arbitrary
+        async fn keys(app: &App, pattern: &str) -> Vec<String> {
+            let reply = app
+                .keys(&Client::default(), &[Value::str(pattern)])
+                .await
+                .unwrap();
+            let Value::Array(Some(keys)) = reply else {
+                panic!("{reply:?}");
+            };
+            let mut keys: Vec<_> = keys
+                .iter()
+                .filter_map(|k| k.get_str().map(str::to_owned))
+                .collect();
+            keys.sort();
+            keys
+        }
+        assert_eq!(
+            keys(&app, "*").await,
+            ["other", "user:1", "user:10", "user:2"]
+        );
+        assert_eq!(keys(&app, "user:?").await, ["user:1", "user:2"]);
+        assert_eq!(keys(&app, "user:[^1]*").await, ["user:2"]);
+        assert!(keys(&app, "nothing*").await.is_empty());
+        assert_eq!(
+            run(&app, &mut client, &["KEYS", "other"]).await,
+            "*1\r\n$5\r\nother\r\n"
+        );
+        assert_eq!(
+            run(&app, &mut client, &["KEYS"]).await,
+            "-ERR wrong number of arguments for 'keys' command\r\n"
+        );
+
+        // LIMIT is an extension
+        assert_eq!(
+            run(&app, &mut client, &["KEYS", "*", "LIMIT", "1"]).await,
+            "-ERR wrong number of arguments for 'keys' command\r\n"
+        );
+        app.set_config("extension-commands".into(), "yes".into());
+        let reply = run(&app, &mut client, &["KEYS", "user:*", "limit", "2"]).await;
+        assert!(reply.starts_with("*2\r\n"), "{reply}");
+        let reply = run(&app, &mut client, &["KEYS", "user:*", "LIMIT", "0"]).await;
+        assert!(reply.starts_with("*3\r\n"), "{reply}");
+        assert_eq!(
+            run(&app, &mut client, &["KEYS", "*", "LIMIT", "-1"]).await,
+            "-ERR LIMIT can't be negative\r\n"
+        );
+
+        // KEYS over a large keyspace is reported
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        app.on_keys_warning(move |warning| {
+            let tx = tx.clone();
+            async move {
+                tx.send(warning).unwrap();
+            }
+        });
+        run(&app, &mut client, &["KEYS", "user:*"]).await;
+        tokio::task::yield_now().await;
+        assert!(rx.try_recv().is_err());
+        app.set_config("keys-warning-threshold".into(), "3".into());
+        run(&app, &mut client, &["KEYS", "user:*"]).await;
+        let warning = rx.recv().await.unwrap();
+        assert_eq!(warning.pattern, Value::str("user:*"));
+        assert!(warning.keys > 3, "{warning:?}");
+        assert_eq!(warning.client, None);
+        app.set_config("keys-warning-threshold".into(), "0".into());
+        run(&app, &mut client, &["KEYS", "user:*"]).await;
+        tokio::task::yield_now().await;
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn keys_in_chunks() {
+        let app = App::new();
+        let mut client = Client::default();
+        for i in 0..KEYS_CHUNK * 3 {
+            run(&app, &mut client, &["SET", &format!("k{i}"), "v"]).await;
+        }
+        match app.keys(&client, &[Value::str("*")]).await.unwrap() {
+            Value::Array(Some(keys)) => assert_eq!(keys.len(), KEYS_CHUNK * 3),
+            reply => panic!("{reply:?}"),
+        }
+
+        // keys removed between chunks don't make others go missing
+        let mut seen = HashSet::new();
+        let mut end = usize::MAX;
+        loop {
+            let mut map = app.store.lock();
+            let (start, chunk) = map.keys_before(end, 100);
+            seen.extend(chunk.into_iter().cloned());
+            end = start;
+            if end == 0 {
+                break;
+            }
+            map.remove(&Value::str(&format!("k{}", end / 2)));
+            map.remove(&Value::str(&format!("k{}", end + 1)));
+        }
+        let map = app.store.lock();
+        let left: Vec<_> = (0..KEYS_CHUNK * 3)
+            .map(|i| Value::str(&format!("k{i}")))
+            .filter(|key| map.get(key).is_some())
+            .collect();
+        assert!(left.len() < KEYS_CHUNK * 3);
+        assert!(left.iter().all(|key| seen.contains(key)));
+    }
+
+    #[tokio::test]
+    async fn randomkey() {
+        let app = App::new();
+        let mut client = Client::default();
+        assert_eq!(run(&app, &mut client, &["RANDOMKEY"]).await, "$-1\r\n");
+
+        run(&app, &mut client, &["DEBUG", "SET-CLOCK", "1000"]).await;
+        run(&app, &mut client, &["MSET", "a", "1", "b", "2", "c", "3"]).await;
+        let mut seen = BTreeMap::new();
+        for _ in 0..300 {
+            *seen
+                .entry(run(&app, &mut client, &["RANDOMKEY"]).await)
+                .or_insert(0) += 1;
+        }
+        assert_eq!(seen.len(), 3, "{seen:?}");
+        assert!(seen.values().all(|n| *n > 50), "{seen:?}");
+
+        // expired keys are never picked, and go when they would be
+        for i in 0..20 {
+            run(
+                &app,
+                &mut client,
+                &["SET", &format!("gone:{i}"), "1", "PX", "1"],
+            )
+            .await;
+        }
+        run(&app, &mut client, &["DEBUG", "SLEEP", "0.002"]).await;
+        for _ in 0..50 {
+            let key = run(&app, &mut client, &["RANDOMKEY"]).await;
+            assert!(!key.contains("gone"), "{key}");
+        }
+        assert!(app.store.lock().expire_stats().expired_keys > 0);
+
+        run(&app, &mut client, &["DEL", "a", "b", "c"]).await;
+        assert_eq!(run(&app, &mut client, &["RANDOMKEY"]).await, "$-1\r\n");
+        assert_eq!(app.store.lock().stats().keys, 0);
+        assert_eq!(
+            run(&app, &mut client, &["RANDOMKEY", "a"]).await,
+            "-ERR wrong number of arguments for 'randomkey' command\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn scan() {
+        let app = App::new();
+        let mut client = Client::default();
+        for i in 0..100 {
+            run(&app, &mut client, &["SET", &format!("key:{i}"), "v"]).await;
+        }
+        app.store
+            .lock()
+            .insert("list".into(), Entry::new(Value::Array(Some(vec![]))));
+
+        // one call, then `f` between each one and the next, returns all keys
+        // seen and the number of calls
+        async fn scan_all(
+            app: &App,
+            options: &[&str],
+            mut f: impl FnMut(&App, usize),
+        ) -> (Vec<String>, usize) {
+            let (mut cursor, mut keys, mut calls) = ("0".to_owned(), vec![], 0);
+            loop {
+                let mut argv = vec![Value::str(&cursor)];
+                argv.extend(options.iter().map(|o| Value::str(o)));
+                let reply = app.scan(&argv).await.unwrap();
+                let Value::Array(Some(reply)) = reply else {
+                    panic!("{reply:?}");
+                };
+                let [next, Value::Array(Some(batch))] = &reply[..] else {
+                    panic!("{reply:?}");
+                };
+                keys.extend(batch.iter().map(|k| k.get_str().unwrap().to_owned()));
+                cursor = next.get_str().unwrap().to_owned();
+                calls += 1;
+                if cursor == "0" {
+                    return (keys, calls);
+                }
+                f(app, calls);
+            }
+        }
+
+        let (keys, calls) = scan_all(&app, &[], |_, _| {}).await;
+        assert_eq!(keys.len(), 101);
+        assert!(calls >= 10, "{calls}");
+        let (_, calls) = scan_all(&app, &["COUNT", "1000"], |_, _| {}).await;
+        assert_eq!(calls, 1);
+        let (mut keys, _) = scan_all(&app, &["MATCH", "key:1?", "count", "7"], |_, _| {}).await;
+        keys.sort();
+        assert_eq!(
+            keys,
+            (10..20).map(|i| format!("key:{i}")).collect::<Vec<_>>()
+        );
+        let (keys, _) = scan_all(&app, &["TYPE", "LIST"], |_, _| {}).await;
+        assert_eq!(keys, ["list"]);
+
+        // keys that are there all along are seen, however the map is shuffled
+        // by removing and adding others
+        let (keys, _) = scan_all(&app, &["COUNT", "3"], |app, call| {
+            let mut map = app.store.lock();
+            for i in 0..3 {
+                map.remove(&Value::str(&format!("key:{}", 50 + (call * 3 + i) % 50)));
+                map.insert(
+                    Value::str(&format!("new:{call}:{i}")),
+                    Entry::new(Value::str("v")),
+                );
+            }
+        })
+        .await;
+        for i in 0..50 {
+            assert!(keys.contains(&format!("key:{i}")), "key:{i}");
+        }
+
+        let cases: &[(&[&str], &str)] = &[
+            (&["SCAN", "abc"], "-ERR invalid cursor\r\n"),
+            (&["SCAN", "-1"], "-ERR invalid cursor\r\n"),
+            (&["SCAN", "0", "COUNT", "0"], "-ERR syntax error\r\n"),
+            (&["SCAN", "0", "COUNT"], "-ERR syntax error\r\n"),
+            (&["SCAN", "0", "FOO", "1"], "-ERR syntax error\r\n"),
+            (
+                &["SCAN", "0", "TYPE", "zset"],
+                "-ERR unknown type name 'zset'\r\n",
+            ),
+            (
+                &["SCAN"],
+                "-ERR wrong number of arguments for 'scan' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn hscan() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "s", "v"]).await;
+        let mut hset = vec!["HSET".to_owned(), "h".to_owned()];
+        for i in 0..50 {
+            hset.extend([format!("f:{i}"), format!("v:{i}")]);
+        }
+        let hset: Vec<&str> = hset.iter().map(String::as_str).collect();
+        run(&app, &mut client, &hset).await;
+
+        // one call, then `f` between each one and the next, returns all items
+        // seen and the number of calls
+        async fn scan_all(
+            app: &App,
+            key: &str,
+            options: &[&str],
+            mut f: impl FnMut(&App, usize),
+        ) -> (Vec<String>, usize) {
+            let (mut cursor, mut items, mut calls) = ("0".to_owned(), vec![], 0);
+            loop {
+                let mut argv = vec![Value::str(key), Value::str(&cursor)];
+                argv.extend(options.iter().map(|o| Value::str(o)));
+                let reply = app.hscan(&argv).await.unwrap();
+                let Value::Array(Some(reply)) = reply else {
+                    panic!("{reply:?}");
+                };
+                let [next, Value::Array(Some(batch))] = &reply[..] else {
+                    panic!("{reply:?}");
+                };
+                items.extend(batch.iter().map(|k| k.get_str().unwrap().to_owned()));
+                cursor = next.get_str().unwrap().to_owned();
+                calls += 1;
+                if cursor == "0" {
+                    return (items, calls);
+                }
+                f(app, calls);
+            }
+        }
+
+        let (items, calls) = scan_all(&app, "h", &[], |_, _| {}).await;
+        assert_eq!(items.len(), 100);
+        assert!(calls >= 5, "{calls}");
+        for pair in items.chunks(2) {
+            assert_eq!(pair[0].replace("f:", "v:"), pair[1]);
+        }
+        let (_, calls) = scan_all(&app, "h", &["COUNT", "100"], |_, _| {}).await;
+        assert_eq!(calls, 1);
+        let (mut fields, _) = scan_all(&app, "h", &["MATCH", "f:1?", "NOVALUES"], |_, _| {}).await;
+        fields.sort();
+        assert_eq!(
+            fields,
+            (10..20).map(|i| format!("f:{i}")).collect::<Vec<_>>()
+        );
+
+        // fields that are there all along are seen, however the hash is
+        // shuffled by removing and adding others
+        let (fields, _) = scan_all(&app, "h", &["COUNT", "3", "NOVALUES"], |app, call| {
+            let mut map = app.store.lock();
+            let entry = map.access_mut(&Value::str("h")).unwrap();
+            let hash = entry.value.hash_mut().unwrap();
+            for i in 0..3 {
+                hash.remove(&Value::str(&format!("f:{}", 25 + (call * 3 + i) % 25)));
+                hash.insert(Value::str(&format!("new:{call}:{i}")), Value::str("v"));
+            }
+        })
+        .await;
+        for i in 0..25 {
+            assert!(fields.contains(&format!("f:{i}")), "f:{i}");
+        }
+
+        let cases: &[(&[&str], &str)] = &[
+            (&["HSCAN", "missing", "0"], "*2\r\n$1\r\n0\r\n*0\r\n"),
+            (
+                &["HSCAN", "s", "0"],
+                "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+            ),
+            (&["HSCAN", "h", "abc"], "-ERR invalid cursor\r\n"),
+            (&["HSCAN", "h", "0", "COUNT", "0"], "-ERR syntax error\r\n"),
+            (
+                &["HSCAN", "h", "0", "TYPE", "hash"],
+                "-ERR syntax error\r\n",
+            ),
+            (&["SCAN", "0", "NOVALUES"], "-ERR syntax error\r\n"),
+            (
+                &["HSCAN", "h"],
+                "-ERR wrong number of arguments for 'hscan' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn sscan() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "s", "v"]).await;
+        let mut sadd = vec!["SADD".to_owned(), "set".to_owned()];
+        sadd.extend((0..50).map(|i| format!("m:{i}")));
+        let sadd: Vec<&str> = sadd.iter().map(String::as_str).collect();
+        run(&app, &mut client, &sadd).await;
+
+        // one call, then `f` between each one and the next, returns all
+        // members seen and the number of calls
+        async fn scan_all(
+            app: &App,
+            options: &[&str],
+            mut f: impl FnMut(&App, usize),
+        ) -> (Vec<String>, usize) {
+            let (mut cursor, mut members, mut calls) = ("0".to_owned(), vec![], 0);
+            loop {
+                let mut argv = vec![Value::str("set"), Value::str(&cursor)];
+                argv.extend(options.iter().map(|o| Value::str(o)));
+                let reply = app.sscan(&argv).await.unwrap();
+                let Value::Array(Some(reply)) = reply else {
+                    panic!("{reply:?}");
+                };
+                let [next, Value::Array(Some(batch))] = &reply[..] else {
+                    panic!("{reply:?}");
+                };
+                members.extend(batch.iter().map(|m| m.get_str().unwrap().to_owned()));
+                cursor = next.get_str().unwrap().to_owned();
+                calls += 1;
+                if cursor == "0" {
+                    members.sort();
+                    return (members, calls);
+                }
+                f(app, calls);
+            }
+        }
+
+        let mut all: Vec<_> = (0..50).map(|i| format!("m:{i}")).collect();
+        all.sort();
+        let (members, calls) = scan_all(&app, &[], |_, _| {}).await;
+        assert_eq!(members, all);
+        assert!(calls >= 5, "{calls}");
+        let (_, calls) = scan_all(&app, &["COUNT", "100"], |_, _| {}).await;
+        assert_eq!(calls, 1);
+        let (members, _) = scan_all(&app, &["MATCH", "m:1?"], |_, _| {}).await;
+        assert_eq!(
+            members,
+            (10..20).map(|i| format!("m:{i}")).collect::<Vec<_>>()
+        );
+
+        // members that are there all along are seen, however the set is
+        // shuffled by removing and adding others
+        let (members, _) = scan_all(&app, &["COUNT", "3"], |app, call| {
+            let mut map = app.store.lock();
+            let entry = map.access_mut(&Value::str("set")).unwrap();
+            let set = entry.value.set_mut().unwrap();
+            for i in 0..3 {
+                set.swap_remove(&Value::str(&format!("m:{}", 25 + (call * 3 + i) % 25)));
+                set.insert(Value::str(&format!("new:{call}:{i}")));
+            }
+        })
+        .await;
+        for i in 0..25 {
+            assert!(members.contains(&format!("m:{i}")), "m:{i}");
+        }
+
+        let cases: &[(&[&str], &str)] = &[
+            (&["SSCAN", "missing", "0"], "*2\r\n$1\r\n0\r\n*0\r\n"),
+            (
+                &["SSCAN", "s", "0"],
+                "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+            ),
+            (&["SSCAN", "set", "abc"], "-ERR invalid cursor\r\n"),
+            (
+                &["SSCAN", "set", "0", "COUNT", "0"],
+                "-ERR syntax error\r\n",
+            ),
+            (&["SSCAN", "set", "0", "NOVALUES"], "-ERR syntax error\r\n"),
+            (
+                &["SSCAN", "set", "0", "TYPE", "set"],
+                "-ERR syntax error\r\n",
+            ),
+            (
+                &["SSCAN", "set"],
+                "-ERR wrong number of arguments for 'sscan' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn type_of() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "s", "1"]).await;
+        run(&app, &mut client, &["SET", "gone", "1", "PX", "1"]).await;
+        let values = [
+            ("list", Value::Array(Some(vec![]))),
+            ("set", Value::Set(vec![])),
+            ("hash", Value::Map(Map::default())),
+        ];
+        for (key, value) in values {
+            app.store.lock().insert(key.into(), Entry::new(value));
+        }
+        run(&app, &mut client, &["DEBUG", "SLEEP", "0.002"]).await;
+
+        let cases = [
+            ("s", "string"),
+            ("list", "list"),
+            ("set", "set"),
+            ("hash", "hash"),
+            ("missing", "none"),
+            ("gone", "none"),
+        ];
+        for (key, kind) in cases {
+            assert_eq!(
+                run(&app, &mut client, &["TYPE", key]).await,
+                format!("${}\r\n{kind}\r\n", kind.len()),
+                "{key}"
+            );
+        }
+        assert_eq!(
+            run(&app, &mut client, &["TYPE"]).await,
+            "-ERR wrong number of arguments for 'type' command\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn rename_copy() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["DEBUG", "SET-CLOCK", "1000"]).await;
+        run(&app, &mut client, &["SET", "a", "1", "PX", "500"]).await;
+        run(&app, &mut client, &["SET", "b", "2", "PX", "100"]).await;
+        run(&app, &mut client, &["APPEND", "c", "3"]).await;
+
+        let ok = "$2\r\nOK\r\n";
+        let cases: &[(&[&str], &str)] = &[
+            (&["RENAME", "missing", "x"], "-ERR no such key\r\n"),
+            (&["RENAMENX", "missing", "x"], "-ERR no such key\r\n"),
+            (&["RENAME", "a", "a"], ok),
+            (&["RENAMENX", "a", "a"], ":0\r\n"),
+            (&["RENAMENX", "a", "b"], ":0\r\n"),
+            // the destination's expiry goes, the source's comes along
+            (&["RENAME", "a", "b"], ok),
+            (&["EXISTS", "a"], ":0\r\n"),
+            (&["GET", "b"], "$1\r\n1\r\n"),
+            (&["PTTL", "b"], ":500\r\n"),
+            (&["RENAMENX", "b", "a"], ":1\r\n"),
+            (&["PTTL", "a"], ":500\r\n"),
+            (&["COPY", "a", "b"], ":1\r\n"),
+            (&["PTTL", "b"], ":500\r\n"),
+            (&["COPY", "c", "b"], ":0\r\n"),
+            (&["COPY", "missing", "b"], ":0\r\n"),
+            (&["COPY", "c", "b", "replace", "DB", "0"], ":1\r\n"),
+            (&["PTTL", "b"], ":-1\r\n"),
+            // changing the copy leaves the original alone
+            (&["APPEND", "b", "4"], ":2\r\n"),
+            (&["GET", "c"], "$1\r\n3\r\n"),
+            (
+                &["COPY", "a", "a"],
+                "-ERR source and destination objects are the same\r\n",
+            ),
+            (
+                &["COPY", "a", "b", "DB", "1"],
+                "-ERR DB index is out of range\r\n",
+            ),
+            (&["COPY", "a", "b", "FOO"], "-ERR syntax error\r\n"),
+            (
+                &["RENAME", "a"],
+                "-ERR wrong number of arguments for 'rename' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn dump_restore() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["DEBUG", "SET-CLOCK", "1000"]).await;
+        run(&app, &mut client, &["SET", "a", "hello"]).await;
+
+        // payloads aren't utf-8, so they skip the protocol
+        let restore = |key: &str, ttl: &str, payload: &Value, options: &[&str]| {
+            let mut argv = vec![Value::str(key), Value::str(ttl), payload.clone()];
+            argv.extend(options.iter().map(|o| Value::str(o)));
+            let app = &app;
+            async move { app.restore(&argv).await.map_err(|e| e.to_string()) }
+        };
+        assert_eq!(
+            app.dump(&[Value::str("missing")]).await.unwrap(),
+            Value::Null
+        );
+        let payload = app.dump(&[Value::str("a")]).await.unwrap();
+
+        assert_eq!(restore("b", "0", &payload, &[]).await, Ok("OK"));
+        assert_eq!(
+            run(&app, &mut client, &["GET", "b"]).await,
+            "$5\r\nhello\r\n"
+        );
+        assert_eq!(run(&app, &mut client, &["PTTL", "b"]).await, ":-1\r\n");
+        assert_eq!(
+            restore("b", "0", &payload, &[]).await,
+            Err("BUSYKEY Target key name already exists.".into())
+        );
+        assert_eq!(restore("b", "500", &payload, &["REPLACE"]).await, Ok("OK"));
+        assert_eq!(run(&app, &mut client, &["PTTL", "b"]).await, ":500\r\n");
+        assert_eq!(restore("c", "3000", &payload, &["absttl"]).await, Ok("OK"));
+        assert_eq!(run(&app, &mut client, &["PTTL", "c"]).await, ":2000\r\n");
+        // already expired, nothing is created
+        assert_eq!(restore("d", "1000", &payload, &["ABSTTL"]).await, Ok("OK"));
+        assert_eq!(run(&app, &mut client, &["EXISTS", "d"]).await, ":0\r\n");
+
+        // SET mykey 10, DUMP mykey, from the Redis docs
+        let redis = Value::bytes(b"\x00\xc0\n\t\x00\xbem\x06\x89Z(\x00\n".to_vec());
+        assert_eq!(restore("e", "0", &redis, &[]).await, Ok("OK"));
+        assert_eq!(run(&app, &mut client, &["INCR", "e"]).await, ":11\r\n");
+
+        // field ttls are carried along
+        run(&app, &mut client, &["HSET", "h", "f", "1", "g", "2"]).await;
+        run(
+            &app,
+            &mut client,
+            &["HPEXPIRE", "h", "5000", "FIELDS", "1", "f"],
+        )
+        .await;
+        let hash = app.dump(&[Value::str("h")]).await.unwrap();
+        assert_eq!(restore("h2", "0", &hash, &[]).await, Ok("OK"));
+        assert_eq!(
+            run(&app, &mut client, &["HPTTL", "h2", "FIELDS", "2", "f", "g"]).await,
+            "*2\r\n:5000\r\n:-1\r\n"
+        );
+        run(&app, &mut client, &["DEBUG", "SLEEP", "5"]).await;
+        assert_eq!(
+            run(&app, &mut client, &["HGETALL", "h2"]).await,
+            "*2\r\n$1\r\ng\r\n$1\r\n2\r\n"
+        );
+
+        let Value::String(Some(bytes)) = &payload else {
+            panic!("{payload:?}");
+        };
+        let mut corrupt = bytes.to_vec();
+        corrupt[2] ^= 1;
+        assert_eq!(
+            restore("f", "0", &Value::bytes(corrupt), &[]).await,
+            Err("ERR DUMP payload version or checksum are wrong".into())
+        );
+        // an empty hash, the keyspace never holds one
+        let mut empty = b"\x04\x00\x0b\x00".to_vec();
+        empty.extend_from_slice(&rdb::crc64(&empty).to_le_bytes());
+        assert_eq!(
+            restore("f", "0", &Value::bytes(empty), &[]).await,
+            Err("ERR Bad data format".into())
+        );
+        assert_eq!(run(&app, &mut client, &["EXISTS", "f"]).await, ":0\r\n");
+        assert_eq!(
+            restore("f", "-1", &payload, &[]).await,
+            Err("ERR Invalid TTL value, must be >= 0".into())
+        );
+        assert_eq!(
+            restore("f", "0", &payload, &["FOO"]).await,
+            Err("ERR syntax error".into())
+        );
+
+        // access metadata comes along for eviction
+        assert_eq!(
+            restore("i", "0", &payload, &["IDLETIME", "3"]).await,
+            Ok("OK")
+        );
+        assert_eq!(
+            run(&app, &mut client, &["OBJECT", "IDLETIME", "i"]).await,
+            ":3\r\n"
+        );
+        app.set_config("maxmemory-policy".into(), "allkeys-lfu".into());
+        assert_eq!(
+            restore("i", "0", &payload, &["REPLACE", "FREQ", "200"]).await,
+            Ok("OK")
+        );
+        assert_eq!(
+            run(&app, &mut client, &["OBJECT", "FREQ", "i"]).await,
+            ":200\r\n"
+        );
+        let cases: &[(&[&str], &str)] = &[
+            (
+                &["IDLETIME", "-1"],
+                "ERR Invalid IDLETIME value, must be >= 0",
+            ),
+            (
+                &["FREQ", "256"],
+                "ERR Invalid FREQ value, must be >= 0 and <= 255",
+            ),
+            (
+                &["FREQ", "-1"],
+                "ERR Invalid FREQ value, must be >= 0 and <= 255",
+            ),
+            (
+                &["FREQ", "x"],
+                "ERR value is not an integer or out of range",
+            ),
+            (&["IDLETIME", "1", "FREQ", "1"], "ERR syntax error"),
+            (&["FREQ", "1", "IDLETIME", "1"], "ERR syntax error"),
+            (&["IDLETIME"], "ERR syntax error"),
+        ];
+        for (options, expected) in cases {
+            assert_eq!(
+                restore("f", "0", &payload, options).await,
+                Err(expected.to_string()),
+                "{options:?}"
+            );
+        }
+        assert_eq!(
+            run(&app, &mut client, &["RESTORE", "f", "0"]).await,
+            "-ERR wrong number of arguments for 'restore' command\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn expire_ttl() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["DEBUG", "SET-CLOCK", "10000"]).await;
+        run(&app, &mut client, &["SET", "k", "v"]).await;
+
+        let cases: &[(&[&str], &str)] = &[
+            (&["TTL", "missing"], ":-2\r\n"),
+            (&["PEXPIRETIME", "missing"], ":-2\r\n"),
+            (&["TTL", "k"], ":-1\r\n"),
+            (&["EXPIRETIME", "k"], ":-1\r\n"),
+            (&["EXPIRE", "missing", "10"], ":0\r\n"),
+            (&["PERSIST", "k"], ":0\r\n"),
+            // keys without an expiry never expire, which is later than any
+            (&["EXPIRE", "k", "10", "XX"], ":0\r\n"),
+            (&["EXPIRE", "k", "10", "GT"], ":0\r\n"),
+            (&["PEXPIRE", "k", "1500", "lt"], ":1\r\n"),
+            (&["PTTL", "k"], ":1500\r\n"),
+            (&["TTL", "k"], ":2\r\n"),
+            (&["PEXPIRETIME", "k"], ":11500\r\n"),
+            (&["EXPIRETIME", "k"], ":12\r\n"),
+            (&["EXPIRE", "k", "10", "NX"], ":0\r\n"),
+            (&["EXPIRE", "k", "1", "GT"], ":0\r\n"),
+            (&["EXPIRE", "k", "10", "XX", "GT"], ":1\r\n"),
+            (&["EXPIREAT", "k", "15"], ":1\r\n"),
+            (&["PTTL", "k"], ":5000\r\n"),
+            (&["PEXPIREAT", "k", "12345"], ":1\r\n"),
+            (&["PTTL", "k"], ":2345\r\n"),
+            (&["PERSIST", "k"], ":1\r\n"),
+            (&["PTTL", "k"], ":-1\r\n"),
+            (
+                &["EXPIRE", "k", "10", "NX", "GT"],
+                "-ERR NX and XX, GT or LT options at the same time are not compatible\r\n",
+            ),
+            (
+                &["EXPIRE", "k", "10", "GT", "LT"],
+                "-ERR GT and LT options at the same time are not compatible\r\n",
+            ),
+            (
+                &["EXPIRE", "k", "10", "FOO"],
+                "-ERR Unsupported option FOO\r\n",
+            ),
+            (
+                &["EXPIRE", "k", "9223372036854775807"],
+                "-ERR invalid expire time in 'expire' command\r\n",
+            ),
+            (
+                &["PEXPIRE", "k", "9223372036854775807"],
+                "-ERR invalid expire time in 'pexpire' command\r\n",
+            ),
+            (
+                &["EXPIRE", "k", "ten"],
+                "-ERR value is not an integer or out of range\r\n",
+            ),
+            (
+                &["TTL", "k", "k"],
+                "-ERR wrong number of arguments for 'ttl' command\r\n",
+            ),
+            (&["PERSIST", "k"], ":0\r\n"),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+
+        // the key stays until right after its deadline
+        run(&app, &mut client, &["PEXPIRE", "k", "100"]).await;
+        run(&app, &mut client, &["DEBUG", "SLEEP", "0.1"]).await;
+        assert_eq!(run(&app, &mut client, &["PTTL", "k"]).await, ":0\r\n");
+        run(&app, &mut client, &["DEBUG", "SLEEP", "0.001"]).await;
+        assert_eq!(run(&app, &mut client, &["PTTL", "k"]).await, ":-2\r\n");
+
+        // a deadline in the past deletes the key
+        for cmd in [
+            &["EXPIRE", "k", "-1"][..],
+            &["PEXPIRE", "k", "0"],
+            &["EXPIREAT", "k", "1"],
+        ] {
+            run(&app, &mut client, &["SET", "k", "v"]).await;
+            assert_eq!(run(&app, &mut client, cmd).await, ":1\r\n", "{cmd:?}");
+            assert_eq!(run(&app, &mut client, &["EXISTS", "k"]).await, ":0\r\n");
+        }
+    }
+
+    #[tokio::test]
+    async fn expire_cycle() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["DEBUG", "SET-CLOCK", "1000"]).await;
+        for i in 0..100 {
+            run(
+                &app,
+                &mut client,
+                &["SET", &format!("v{i}"), "1", "PX", "10"],
+            )
+            .await;
+        }
+        for i in 0..10 {
+            run(&app, &mut client, &["SET", &format!("p{i}"), "1"]).await;
+        }
+        run(&app, &mut client, &["DEBUG", "SLEEP", "0.1"]).await;
+
+        // the background cycle leaves a frozen clock alone
+        app.active_expire();
+        let info = run(&app, &mut client, &["INFO", "stats"]).await;
+        assert!(info.contains("expired_keys:0\r\n"), "{info}");
+        assert!(info.contains("expire_cycles:0\r\n"), "{info}");
+
+        // every sampled key has expired, so the cycle goes on until there are
+        // none left
+        let reply = run(&app, &mut client, &["DEBUG", "EXPIRE-CYCLE"]).await;
+        assert!(reply.starts_with("*14\r\n"), "{reply}");
+        assert!(reply.contains("$7\r\nexpired\r\n:100\r\n"), "{reply}");
+        assert!(reply.contains("$13\r\nvolatile-keys\r\n:0\r\n"), "{reply}");
+
+        let info = run(&app, &mut client, &["INFO"]).await;
+        assert!(info.contains("expired_keys:100\r\n"), "{info}");
+        assert!(info.contains("expire_cycles:1\r\n"), "{info}");
+        assert!(info.contains("db0:keys=10,expires=0,"), "{info}");
+
+        // lazily expired keys count too
+        run(&app, &mut client, &["SET", "later", "1", "PX", "1000"]).await;
+        run(&app, &mut client, &["DEBUG", "SLEEP", "1.001"]).await;
+        assert_eq!(run(&app, &mut client, &["GET", "later"]).await, "$-1\r\n");
+        let info = run(&app, &mut client, &["INFO", "stats"]).await;
+        assert!(info.contains("expired_keys:101\r\n"), "{info}");
+
+        let reply = run(&app, &mut client, &["DEBUG", "EXPIRE-CYCLE"]).await;
+        assert!(reply.contains("$7\r\nsampled\r\n:0\r\n"), "{reply}");
+        assert!(
+            reply.contains("$10\r\nstale-perc\r\n$1\r\n0\r\n"),
+            "{reply}"
+        );
+    }
+
+    #[tokio::test]
+    async fn handoff() {
+        let path = std::env::temp_dir().join(format!("handoff-{}", std::process::id()));
+        let app = App::new();
+        let mut client = Client::default();
+        for cmd in [
+            &["SET", "s", "hello"][..],
+            &["SET", "n", "42", "EX", "100"],
+            &["SET", "gone", "x", "PX", "10"],
+            &["SET", "soon", "x", "PX", "200"],
+            &["RPUSH", "l", "a", "b"],
+            &["SETRANGE", "sparse", "100000", "x"],
+            &["HSET", "h", "f", "1", "g", "2"],
+            &["HEXPIRE", "h", "100", "FIELDS", "1", "f"],
+        ] {
+            run(&app, &mut client, cmd).await;
+        }
+        run(&app, &mut client, &["DEBUG", "SLEEP", "0.011"]).await;
+        run(&app, &mut client, &["GET", "gone"]).await;
+        app.write_handoff(&path).unwrap();
+
+        // keys that expire before the next server starts aren't taken over
+        run(&app, &mut client, &["DEBUG", "SLEEP", "0.2"]).await;
+        let next = App::new();
+        assert_eq!(next.read_handoff(&path).unwrap(), 5);
+        assert!(!path.exists());
+
+        let cases: &[(&[&str], &str)] = &[
+            (&["GET", "s"], "$5\r\nhello\r\n"),
+            (&["GET", "n"], "$2\r\n42\r\n"),
+            (&["EXISTS", "gone", "soon"], ":0\r\n"),
+            (&["LRANGE", "l", "0", "-1"], "*2\r\n$1\r\na\r\n$1\r\nb\r\n"),
+            (&["STRLEN", "sparse"], ":100001\r\n"),
+            (&["GETRANGE", "sparse", "99999", "-1"], "$2\r\n\0x\r\n"),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&next, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+        let ttl = run(&next, &mut client, &["TTL", "n"]).await;
+        assert!(ttl == ":100\r\n" || ttl == ":99\r\n", "{ttl}");
+        let ttls = run(&next, &mut client, &["HTTL", "h", "FIELDS", "2", "f", "g"]).await;
+        assert!(
+            ttls == "*2\r\n:100\r\n:-1\r\n" || ttls == "*2\r\n:99\r\n:-1\r\n",
+            "{ttls}"
+        );
+        let info = run(&next, &mut client, &["INFO", "STATS"]).await;
+        assert!(info.contains("expired_keys:1\r\n"), "{info}");
+
+        assert!(next.read_handoff(&path).is_err());
+    }
+
+    #[tokio::test]
+    async fn reply_pool_stats() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "k", &"v".repeat(1000)]).await;
+
+        // replies that are handed back are reused
+        for _ in 0..3 {
+            let reply = app.dispatch_command(&mut client, cmd(&["GET", "k"])).await;
+            client.recycle(reply);
+        }
+        // SET and the first GET needed new buffers, INFO counts once it's done
+        let info = run(&app, &mut client, &["INFO", "stats"]).await;
+        assert!(info.contains("reply_pool_hits:2\r\n"), "{info}");
+        assert!(info.contains("reply_pool_misses:2\r\n"), "{info}");
+        assert!(info.contains("reply_pool_hit_rate:0.50\r\n"), "{info}");
+        assert!(info.contains("reply_buffer_peak:1009\r\n"), "{info}");
+    }
+
+    #[tokio::test]
+    async fn object_encoding() {
+        let app = App::new();
+        let mut client = Client::default();
+        assert_eq!(
+            run(&app, &mut client, &["OBJECT", "ENCODING", "k"]).await,
+            "$-1\r\n"
+        );
+
+        let long = "v".repeat(100);
+        let cases = [
+            ("12", "int", ":2147483647\r\n"),
+            ("-12345678901", "int", ":1\r\n"),
+            ("012", "embstr", ":1\r\n"),
+            ("hello", "embstr", ":1\r\n"),
+            (long.as_str(), "raw", ":1\r\n"),
+        ];
+        for (value, encoding, refcount) in cases {
+            run(&app, &mut client, &["SET", "k", value]).await;
+            assert_eq!(
+                run(&app, &mut client, &["OBJECT", "ENCODING", "k"]).await,
+                format!("${}\r\n{encoding}\r\n", encoding.len())
+            );
+            assert_eq!(
+                run(&app, &mut client, &["OBJECT", "REFCOUNT", "k"]).await,
+                refcount
+            );
+            assert_eq!(
+                run(&app, &mut client, &["GET", "k"]).await,
+                format!("${}\r\n{value}\r\n", value.len())
+            );
+        }
+
+        // strings changed in place are raw until they're overwritten, and
+        // INCRBYFLOAT results are never int, even without a fraction
+        let cases: &[(&[&str], &str)] = &[
+            (&["SET", "k", "123"], "int"),
+            (&["INCR", "k"], "int"),
+            (&["APPEND", "k", "4"], "raw"),
+            (&["GETRANGE", "k", "0", "1"], "raw"),
+            (&["INCRBY", "k", "-1000"], "int"),
+            (&["SETRANGE", "k", "0", "9"], "raw"),
+            (&["SET", "k", "abc"], "embstr"),
+            (&["SET", "k", "10"], "int"),
+            (&["INCRBYFLOAT", "k", "0.5"], "embstr"),
+            (&["INCRBYFLOAT", "k", "0.5"], "embstr"),
+            (&["DEL", "k"], ""),
+            (&["APPEND", "k", "12"], "int"),
+            (&["DEL", "k"], ""),
+            (&["SETRANGE", "k", "0", "ab"], "raw"),
+        ];
+        for (cmd, encoding) in cases {
+            run(&app, &mut client, cmd).await;
+            if encoding.is_empty() {
+                continue;
+            }
+            assert_eq!(
+                run(&app, &mut client, &["OBJECT", "ENCODING", "k"]).await,
+                format!("${}\r\n{encoding}\r\n", encoding.len()),
+                "{cmd:?}"
+            );
+        }
+        assert_eq!(run(&app, &mut client, &["GET", "k"]).await, "$2\r\nab\r\n");
+
+        // inline values don't pay for the `Arc` and `Value` around them
+        let parse = |s: &str| s[1..s.len() - 2].parse::<usize>().unwrap();
+        run(&app, &mut client, &["SET", "k", "hello"]).await;
+        let inline = parse(&run(&app, &mut client, &["MEMORY", "USAGE", "k"]).await);
+        run(&app, &mut client, &["SET", "k", &"v".repeat(23)]).await;
+        let boxed = parse(&run(&app, &mut client, &["MEMORY", "USAGE", "k"]).await);
+        assert!(inline + std::mem::size_of::<Value>() < boxed);
+
+        assert_eq!(
+            run(&app, &mut client, &["OBJECT", "FOO", "k"]).await,
+            "-ERR unknown object subcommand 'FOO'\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn object_access() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["DEBUG", "SET-CLOCK", "1000"]).await;
+        run(&app, &mut client, &["SET", "k", "v"]).await;
+
+        let cases: &[(&[&str], &str)] = &[
+            (&["OBJECT", "IDLETIME", "missing"], "$-1\r\n"),
+            (&["OBJECT", "IDLETIME", "k"], ":0\r\n"),
+            (&["DEBUG", "SLEEP", "5"], "$2\r\nOK\r\n"),
+            // looking at the key doesn't count as an access
+            (&["TYPE", "k"], "$6\r\nstring\r\n"),
+            (&["EXISTS", "k"], ":1\r\n"),
+            (&["TTL", "k"], ":-1\r\n"),
+            (&["OBJECT", "IDLETIME", "k"], ":5\r\n"),
+            (&["TOUCH", "k", "missing"], ":1\r\n"),
+            (&["OBJECT", "IDLETIME", "k"], ":0\r\n"),
+            (&["DEBUG", "SLEEP", "2"], "$2\r\nOK\r\n"),
+            (&["SET", "k", "w"], "$2\r\nOK\r\n"),
+            (&["OBJECT", "IDLETIME", "k"], ":0\r\n"),
+            (
+                &["OBJECT", "FREQ", "k"],
+                "-ERR An LFU maxmemory policy is not selected, access frequency not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.\r\n",
+            ),
+            (&["CONFIG", "SET", "maxmemory-policy", "allkeys-lfu"], "$2\r\nOK\r\n"),
+            (
+                &["OBJECT", "IDLETIME", "k"],
+                "-ERR An LFU maxmemory policy is selected, idle time not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.\r\n",
+            ),
+            // new keys start at 5, the first accesses always count
+            (&["SET", "f", "v"], "$2\r\nOK\r\n"),
+            (&["OBJECT", "FREQ", "f"], ":5\r\n"),
+            (&["GET", "f"], "$1\r\nv\r\n"),
+            (&["OBJECT", "FREQ", "f"], ":6\r\n"),
+            // and it drops by one for every minute without one
+            (&["DEBUG", "SLEEP", "150"], "$2\r\nOK\r\n"),
+            (&["OBJECT", "FREQ", "f"], ":4\r\n"),
+            (&["TOUCH", "f"], ":1\r\n"),
+            (&["OBJECT", "FREQ", "f"], ":5\r\n"),
+            (
+                &["OBJECT", "FREQ"],
+                "-ERR wrong number of arguments for 'object|freq' command\r\n",
+            ),
+            (
+                &["TOUCH"],
+                "-ERR wrong number of arguments for 'touch' command\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+
+        // past that, accesses only count now and then
+        for _ in 0..1000 {
+            run(&app, &mut client, &["GET", "f"]).await;
+        }
+        let freq = run(&app, &mut client, &["OBJECT", "FREQ", "f"]).await;
+        let freq: u8 = freq.trim_start_matches(':').trim_end().parse().unwrap();
+        assert!((7..100).contains(&freq), "{freq}");
+    }
+
+    #[tokio::test]
+    async fn keymeta() {
+        let app = App::new();
+        let mut client = Client::default();
+        assert_eq!(
+            run(&app, &mut client, &["KEYMETA", "k"]).await,
+            "-UNKNOWN KEYMETA\r\n"
+        );
+
+        app.set_config("extension-commands".into(), "yes".into());
+        run(&app, &mut client, &["HELLO", "3"]).await;
+        assert_eq!(run(&app, &mut client, &["KEYMETA", "k"]).await, "_\r\n");
+
+        run(&app, &mut client, &["DEBUG", "SET-CLOCK", "5000"]).await;
+        run(&app, &mut client, &["SET", "k", "hello", "PX", "1000"]).await;
+        run(&app, &mut client, &["DEBUG", "SLEEP", "0.2"]).await;
+        let meta = run(&app, &mut client, &["KEYMETA", "k"]).await;
+        assert!(meta.starts_with(
+            "%5\r\n$4\r\ntype\r\n$6\r\nstring\r\n$3\r\nttl\r\n:800\r\n\
+             $8\r\nencoding\r\n$6\r\nembstr\r\n$4\r\nsize\r\n:"
+        ));
+        assert!(meta.ends_with("$11\r\nlast-access\r\n:5000\r\n"), "{meta}");
+
+        run(&app, &mut client, &["GET", "k"]).await;
+        let meta = run(&app, &mut client, &["KEYMETA", "k"]).await;
+        assert!(meta.contains("last-access\r\n:5200\r\n"), "{meta}");
+    }
+
+    #[tokio::test]
+    async fn client_compress() {
+        let app = App::new();
+        let mut client = Client::default();
+        let value = "abc".repeat(100);
+        run(&app, &mut client, &["SET", "k", &value]).await;
+        assert_eq!(
+            run(&app, &mut client, &["CLIENT", "COMPRESS", "ON"]).await,
+            "-ERR unknown client subcommand 'COMPRESS'\r\n"
+        );
+
+        app.set_config("extension-commands".into(), "yes".into());
+        run(&app, &mut client, &["CLIENT", "COMPRESS", "ON", "100"]).await;
+        for args in [&["GET", "k"][..], &["ECHO", &value]] {
+            let reply = app.dispatch_command(&mut client, cmd(args)).await;
+            let reply: Value = crate::deserializer::from_bytes(&reply).unwrap();
+            assert!(reply.get_bytes().unwrap().starts_with(compression::MARKER));
+            assert_eq!(
+                compression::decompress_value(reply).unwrap(),
+                Value::from(value.as_str())
+            );
+        }
+        assert_eq!(
+            run(&app, &mut client, &["ECHO", "abc"]).await,
+            "$3\r\nabc\r\n"
+        );
+
+        run(&app, &mut client, &["CLIENT", "COMPRESS", "OFF"]).await;
+        assert_eq!(
+            run(&app, &mut client, &["GET", "k"]).await,
+            format!("$300\r\n{value}\r\n")
+        );
+        assert_eq!(
+            run(&app, &mut client, &["CLIENT", "COMPRESS", "ON", "-1"]).await,
+            "-ERR value is not an integer or out of range\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn eviction_hooks() {
+        let app = App::new();
+        let mut client = Client::default();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        app.on_evict(move |key, reason| {
+            let tx = tx.clone();
+            async move {
+                tx.send((key, reason)).unwrap();
+            }
+        });
+
+        run(&app, &mut client, &["DEBUG", "SET-CLOCK", "1000"]).await;
+        run(&app, &mut client, &["SET", "a", "1", "PX", "10"]).await;
+        run(&app, &mut client, &["SET", "b", "1", "PX", "10"]).await;
+        run(&app, &mut client, &["SET", "c", "1"]).await;
+        run(&app, &mut client, &["DEBUG", "SLEEP", "0.02"]).await;
+
+        assert_eq!(run(&app, &mut client, &["GET", "a"]).await, "$-1\r\n");
+        assert_eq!(
+            rx.recv().await,
+            Some((Value::str("a"), EvictionReason::Expired))
+        );
+        app.prune_expired();
+        assert_eq!(
+            rx.recv().await,
+            Some((Value::str("b"), EvictionReason::Expired))
+        );
+
+        // deleted keys are reported, keys that are missing or overwritten
+        // aren't
+        run(&app, &mut client, &["SET", "d", "1"]).await;
+        run(&app, &mut client, &["SET", "e", "1"]).await;
+        run(&app, &mut client, &["SET", "c", "2"]).await;
+        assert_eq!(
+            run(&app, &mut client, &["DEL", "c", "missing"]).await,
+            ":1\r\n"
+        );
+        assert_eq!(
+            rx.recv().await,
+            Some((Value::str("c"), EvictionReason::Deleted))
+        );
+        run(&app, &mut client, &["UNLINK", "d"]).await;
+        assert_eq!(
+            rx.recv().await,
+            Some((Value::str("d"), EvictionReason::Deleted))
+        );
+        run(&app, &mut client, &["GETDEL", "e"]).await;
+        assert_eq!(
+            rx.recv().await,
+            Some((Value::str("e"), EvictionReason::Deleted))
+        );
+        tokio::task::yield_now().await;
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn watchdog_kills_slow_commands() {
+        let app = App::new();
+        let (mut client, mut other) = (Client::default(), Client::default());
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        app.on_watchdog(move |report| {
+            let tx = tx.clone();
+            async move {
+                tx.send((report.command, report.killed)).unwrap();
+            }
+        });
+        app.set_config("watchdog-period".into(), "20".into());
+        assert_eq!(
+            run(&app, &mut client, &["DEBUG", "SLEEP", "0.05"]).await,
+            "$2\r\nOK\r\n"
+        );
+        assert_eq!(rx.recv().await, Some(("DEBUG".into(), false)));
+
+        app.set_config("watchdog-kill".into(), "yes".into());
+        let reply = run(&app, &mut client, &["DEBUG", "SLEEP", "10"]).await;
+        assert!(reply.starts_with("-ERR command 'DEBUG' killed by watchdog"));
+        assert_eq!(rx.recv().await, Some(("DEBUG".into(), true)));
+        assert_eq!(run(&app, &mut client, &["PING"]).await, "$4\r\nPONG\r\n");
+        for secs in ["1e20", "-1", "inf"] {
+            assert_eq!(
+                run(&app, &mut client, &["DEBUG", "SLEEP", secs]).await,
+                "-ERR value is not a valid float\r\n",
+                "{secs}"
+            );
+        }
+
+        // blocking commands wait as long as they're told to
+        let push = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            run(&app, &mut other, &["RPUSH", "q", "x"]).await
+        };
+        let (popped, _) = tokio::join!(run(&app, &mut client, &["BLPOP", "q", "0"]), push);
+        assert_eq!(popped, "*2\r\n$1\r\nq\r\n$1\r\nx\r\n");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn inject_faults() {
+        let app = App::new();
+        let mut client = Client::default();
+        run(&app, &mut client, &["SET", "k", "v"]).await;
+
+        let ok = "$2\r\nOK\r\n";
+        let cases: &[(&[&str], &str)] = &[
+            (&["DEBUG", "INJECT", "get", "ERROR"], ok),
+            (&["GET", "k"], "-ERR injected fault\r\n"),
+            (&["get", "k"], "-ERR injected fault\r\n"),
+            (&["STRLEN", "k"], ":1\r\n"),
+            (&["DEBUG", "INJECT", "GET", "error", "try again"], ok),
+            (&["GET", "k"], "-ERR try again\r\n"),
+            (&["DEBUG", "INJECT", "get", "OFF"], ok),
+            (&["GET", "k"], "$1\r\nv\r\n"),
+            (
+                &["DEBUG", "INJECT", "debug", "ERROR"],
+                "-ERR can't inject faults into DEBUG\r\n",
+            ),
+            (
+                &["DEBUG", "INJECT", "get", "DELAY", "soon"],
+                "-ERR invalid delay, expected e.g. 50ms or 2s\r\n",
+            ),
+            (
+                &["DEBUG", "INJECT", "get", "OFF", "now"],
+                "-ERR syntax error\r\n",
+            ),
+        ];
+        for (cmd, expected) in cases {
+            assert_eq!(run(&app, &mut client, cmd).await, *expected, "{cmd:?}");
+        }
+
+        run(
+            &app,
+            &mut client,
+            &["DEBUG", "INJECT", "get", "DELAY", "50ms"],
+        )
+        .await;
+        let start = std::time::Instant::now();
+        assert_eq!(run(&app, &mut client, &["GET", "k"]).await, "$1\r\nv\r\n");
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // delays count towards the watchdog
+        app.set_config("watchdog-period".into(), "10".into());
+        app.set_config("watchdog-kill".into(), "yes".into());
+        let reply = run(&app, &mut client, &["GET", "k"]).await;
+        assert!(reply.starts_with("-ERR command 'GET' killed by watchdog"));
+
+        assert_eq!(
+            parse_delay(&Value::str("2s")).unwrap(),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            parse_delay(&Value::str("7")).unwrap(),
+            Duration::from_millis(7)
+        );
     }
 }
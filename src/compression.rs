@@ -0,0 +1,78 @@
+//! Reply compression, an extension to RESP.
+//!
+//! After `CLIENT COMPRESS ON [threshold]` the server LZ4-compresses bulk
+//! strings longer than the threshold on that connection. A compressed bulk
+//! string holds [`MARKER`] followed by an LZ4 block with its uncompressed
+//! size in front, and clients undo it with [`decompress_value`].
+//!
+//! Bulk strings that start with the marker themselves are always compressed
+//! in this mode, so anything starting with the marker is a compressed one.
+
+use crate::value::Value;
+
+/// what compressed bulk strings start with
+pub const MARKER: &[u8] = b"\0LZ4";
+
+/// the threshold used by `CLIENT COMPRESS ON` without one
+pub const DEFAULT_THRESHOLD: usize = 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("corrupt compressed bulk string: {0}")]
+    Corrupt(#[from] lz4_flex::block::DecompressError),
+}
+
+/// the marked, compressed form of `data`
+pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = MARKER.to_vec();
+    out.extend(lz4_flex::compress_prepend_size(data));
+    out
+}
+
+/// Undoes [`compress`], `None` if `data` isn't compressed.
+pub fn decompress(data: &[u8]) -> Option<Result<Vec<u8>, Error>> {
+    let block = data.strip_prefix(MARKER)?;
+    Some(lz4_flex::decompress_size_prepended(block).map_err(Error::from))
+}
+
+/// Decompresses every compressed bulk string in a reply.
+pub fn decompress_value(value: Value) -> Result<Value, Error> {
+    let all = |items: Vec<Value>| -> Result<Vec<Value>, Error> {
+        items.into_iter().map(decompress_value).collect()
+    };
+    Ok(match value {
+        Value::String(Some(s)) => match decompress(&s) {
+            Some(s) => Value::String(Some(s?)),
+            None => Value::String(Some(s)),
+        },
+        Value::Array(Some(items)) => Value::Array(Some(all(items)?)),
+        Value::Set(items) => Value::Set(all(items)?),
+        Value::Push(items) => Value::Push(all(items)?),
+        Value::Map(map) => Value::Map(
+            map.into_iter()
+                .map(|(k, v)| Ok((decompress_value(k)?, decompress_value(v)?)))
+                .collect::<Result<_, Error>>()?,
+        ),
+        value => value,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let data = "abc".repeat(1000).into_bytes();
+        let compressed = compress(&data);
+        assert!(compressed.starts_with(MARKER));
+        assert!(compressed.len() < data.len());
+
+        let reply = Value::from(vec![Value::bytes(compressed), "small".into()]);
+        assert_eq!(
+            decompress_value(reply).unwrap(),
+            Value::from(vec![Value::bytes(data), "small".into()])
+        );
+        assert!(decompress(b"\0LZ4\xff\xff\xff\xff").unwrap().is_err());
+    }
+}
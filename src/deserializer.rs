@@ -1,8 +1,15 @@
 use serde::{
-    de::{self, MapAccess, SeqAccess},
+    de::{self, value::BorrowedStrDeserializer, MapAccess, SeqAccess},
     forward_to_deserialize_any, Deserialize,
 };
 
+use atoi::FromRadix10SignedChecked;
+
+use crate::{
+    reply::{Frame, RespError},
+    value::{Double, Map, Value},
+};
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("{0}")]
@@ -23,6 +30,12 @@ pub enum Error {
     MissingValue(usize),
     #[error("expected null (_\\r\\n) at {0}")]
     ExpectedNull(usize),
+    #[error("expected error at {0}")]
+    ExpectedError(usize),
+    #[error("failed to parse double at {0}")]
+    ParseFloatError(usize),
+    #[error("expected big number at {0}")]
+    ExpectedBigNumber(usize),
 }
 
 impl de::Error for Error {
@@ -37,6 +50,8 @@ impl de::Error for Error {
 pub struct Deserializer<'de> {
     input: &'de [u8],
     orig_len: usize,
+    /// see [`Deserializer::lenient`]
+    lenient: bool,
 }
 
 impl<'de> Deserializer<'de> {
@@ -44,10 +59,21 @@ impl<'de> Deserializer<'de> {
         Deserializer {
             input,
             orig_len: input.len(),
+            lenient: false,
         }
     }
 
-    fn position(&self) -> usize {
+    /// Also accepts a lone `\n` wherever a line (or the blob of a bulk string)
+    /// ends, for frames written by hand or by tools that leave out the `\r`.
+    /// By default only `\r\n` ends a line, like the protocol says.
+    ///
+    /// Positions in errors are offsets into the input in either mode.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    pub(crate) fn position(&self) -> usize {
         self.orig_len - self.input.len()
     }
 
@@ -73,7 +99,26 @@ impl<'de> Deserializer<'de> {
         Ok(first)
     }
 
-    fn until_crlf(&mut self) -> Result<&'de [u8], Error> {
+    /// advances the input past the line break at its start and returns true if
+    /// there is one, returns false otherwise
+    #[must_use]
+    fn line_break(&mut self) -> bool {
+        self.tag(b"\r\n") || (self.lenient && self.tag(b"\n"))
+    }
+
+    /// takes the rest of the line and the line break after it
+    fn until_line_break(&mut self) -> Result<&'de [u8], Error> {
+        if self.lenient {
+            let idx = self
+                .input
+                .iter()
+                .position(|b| *b == b'\n')
+                .ok_or(Error::UnexpectedEof)?;
+            let (line, rest) = self.input.split_at(idx);
+            self.input = &rest[1..];
+            return Ok(line.strip_suffix(b"\r").unwrap_or(line));
+        }
+
         let idx = self
             .input
             .windows(2)
@@ -86,7 +131,16 @@ impl<'de> Deserializer<'de> {
     }
 
     fn parse_int(&self, buf: &[u8], position: usize) -> Result<i64, Error> {
-        atoi::atoi(buf).ok_or(Error::ParseIntError(position))
+        // atoi stops at the first non-digit, reject anything left over (or
+        // missing digits entirely)
+        let digits = buf
+            .strip_prefix(b"-")
+            .or(buf.strip_prefix(b"+"))
+            .unwrap_or(buf);
+        match i64::from_radix_10_signed_checked(buf) {
+            (Some(int), used) if used == buf.len() && !digits.is_empty() => Ok(int),
+            _ => Err(Error::ParseIntError(position)),
+        }
     }
 
     fn take(&mut self, n: usize) -> Result<&'de [u8], Error> {
@@ -99,25 +153,168 @@ impl<'de> Deserializer<'de> {
         Ok(buf)
     }
 
+    /// takes the `len` bytes of a blob and the line break after them
+    fn blob(&mut self, len: usize) -> Result<&'de [u8], Error> {
+        let buf = self.take(len)?;
+        // cut off in the line break, the blob is incomplete rather than
+        // malformed
+        if self.input.len() < 2 && b"\r\n".starts_with(self.input) {
+            return Err(Error::UnexpectedEof);
+        }
+        self.line_break()
+            .then_some(())
+            .ok_or(Error::Syntax(self.position()))?;
+        Ok(buf)
+    }
+
     fn str<V>(&mut self, len: usize, visitor: V) -> Result<V::Value, Error>
     where
         V: de::Visitor<'de>,
     {
-        let buf = self.take(len)?;
-        self.tag(b"\r\n")
-            .then_some(())
-            .ok_or(Error::Syntax(self.position()))?;
-        visitor.visit_borrowed_bytes(buf)
+        visitor.visit_borrowed_bytes(self.blob(len)?)
+    }
+
+    /// Skips the attribute map in front of the next frame, if there is one.
+    ///
+    /// Attributes only carry metadata about the reply that follows them.
+    fn skip_attributes(&mut self) -> Result<(), Error> {
+        if self.peek()? != b'|' {
+            return Ok(());
+        }
+        self.advance()?;
+        let len = self.get_length()?;
+        for _ in 0..len * 2 {
+            de::IgnoredAny::deserialize(&mut *self)?;
+        }
+        Ok(())
+    }
+
+    /// gets the length of a bulk string or array, which is -1 for RESP2 nulls
+    fn get_length_or_null(&mut self) -> Result<Option<usize>, Error> {
+        let pos = self.position();
+        let len = self.until_line_break()?;
+        match self.parse_int(len, pos)? {
+            -1 => Ok(None),
+            len => len
+                .try_into()
+                .map(Some)
+                .map_err(|_| Error::NegativeLength(pos)),
+        }
     }
 
     /// gets the length of a generic collection thing
     fn get_length(&mut self) -> Result<usize, Error> {
         let pos = self.position();
-        let len = self.until_crlf()?;
+        let len = self.until_line_break()?;
         let len = self.parse_int(len, pos)?;
         let len = len.try_into().map_err(|_| Error::NegativeLength(pos))?;
         Ok(len)
     }
+
+    /// Reads a [`Value`] without going through serde, which is what the
+    /// server does for every request. The result is the same as
+    /// deserializing a [`Value`].
+    pub(crate) fn parse_value(&mut self) -> Result<Value, Error> {
+        while self.tag(b"|") {
+            let len = self.get_length()?;
+            for _ in 0..len * 2 {
+                self.parse_value()?;
+            }
+        }
+
+        let utf8 = |buf: &[u8]| {
+            std::str::from_utf8(buf)
+                .map(str::to_owned)
+                .map_err(<Error as de::Error>::custom)
+        };
+
+        Ok(match self.advance()? {
+            b'+' => Value::SimpleString(utf8(self.until_line_break()?)?),
+            b'-' => Value::Error(RespError::parse(&utf8(self.until_line_break()?)?)),
+            b'!' => {
+                let len = self.get_length()?;
+                Value::Error(RespError::parse(&utf8(self.blob(len)?)?))
+            }
+            b'$' => match self.get_length_or_null()? {
+                Some(len) => Value::bytes(self.blob(len)?),
+                None => Value::String(None),
+            },
+            b'=' => {
+                let len = self.get_length()?;
+                let buf = self.blob(len)?;
+                let [a, b, c, b':', text @ ..] = buf else {
+                    return Err(<Error as de::Error>::invalid_value(
+                        de::Unexpected::Bytes(buf),
+                        &"a verbatim string",
+                    ));
+                };
+                Value::Verbatim {
+                    format: [*a, *b, *c],
+                    text: utf8(text)?,
+                }
+            }
+            b':' => {
+                let pos = self.position();
+                let int = self.until_line_break()?;
+                Value::Int(self.parse_int(int, pos)?)
+            }
+            b'(' => Value::BigNumber(utf8(self.until_line_break()?)?),
+            b'#' => {
+                let pos = self.position();
+                match self.until_line_break()? {
+                    [b't'] => Value::Bool(true),
+                    [b'f'] => Value::Bool(false),
+                    _ => return Err(Error::Syntax(pos)),
+                }
+            }
+            b',' => {
+                let pos = self.position();
+                let double = self.until_line_break()?;
+                let double = std::str::from_utf8(double)
+                    .ok()
+                    .and_then(|x| x.parse().ok())
+                    .ok_or(Error::ParseFloatError(pos))?;
+                Value::Double(Double(double))
+            }
+            b'_' => {
+                if !self.until_line_break()?.is_empty() {
+                    return Err(Error::Syntax(self.position()));
+                }
+                Value::Null
+            }
+            b'*' => match self.get_length_or_null()? {
+                Some(len) => Value::Array(Some(self.parse_values(len)?)),
+                None => Value::Array(None),
+            },
+            b'~' => {
+                let len = self.get_length()?;
+                Value::Set(self.parse_values(len)?)
+            }
+            b'>' => {
+                let len = self.get_length()?;
+                Value::Push(self.parse_values(len)?)
+            }
+            b'%' => {
+                let len = self.get_length()?;
+                let mut map = Map::with_capacity(len.min(4096));
+                for _ in 0..len {
+                    let key = self.parse_value()?;
+                    map.insert(key, self.parse_value()?);
+                }
+                Value::Map(map)
+            }
+            _ => return Err(Error::Syntax(self.position() - 1)),
+        })
+    }
+
+    fn parse_values(&mut self, len: usize) -> Result<Vec<Value>, Error> {
+        // the length comes from the peer, don't trust it for the allocation
+        let mut values = Vec::with_capacity(len.min(4096));
+        for _ in 0..len {
+            values.push(self.parse_value()?);
+        }
+        Ok(values)
+    }
 }
 
 pub fn from_bytes<'a, T>(s: &'a [u8]) -> Result<T, Error>
@@ -133,22 +330,23 @@ where
     }
 }
 
-impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     type Error = Error;
 
     fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
+        self.skip_attributes()?;
         match self.advance()? {
             b'+' => {
-                let buf = self.until_crlf()?;
+                let buf = self.until_line_break()?;
                 visitor.visit_borrowed_bytes(buf)
             }
-            b'$' => {
-                let len = self.get_length()?;
-                self.str(len, visitor)
-            }
+            b'$' => match self.get_length_or_null()? {
+                Some(len) => self.str(len, visitor),
+                None => visitor.visit_none(),
+            },
             b'=' => {
                 let len = self.get_length()?;
                 self.take(3)?;
@@ -159,13 +357,32 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
             }
             b':' => {
                 let pos = self.position();
-                let int = self.until_crlf()?;
+                let int = self.until_line_break()?;
                 let int = self.parse_int(int, pos)?;
                 visitor.visit_i64(int)
             }
+            b'(' => {
+                let pos = self.position();
+                let digits = self.until_line_break()?;
+                let digits = std::str::from_utf8(digits).map_err(|_| Error::ParseIntError(pos))?;
+                // visit the narrowest type, visitors for the smaller integer
+                // types don't accept 128 bit values
+                if let Ok(int) = digits.parse::<i64>() {
+                    visitor.visit_i64(int)
+                } else if let Ok(int) = digits.parse::<u64>() {
+                    visitor.visit_u64(int)
+                } else if let Ok(int) = digits.parse::<i128>() {
+                    visitor.visit_i128(int)
+                } else {
+                    let int = digits
+                        .parse::<u128>()
+                        .map_err(|_| Error::ParseIntError(pos))?;
+                    visitor.visit_u128(int)
+                }
+            }
             b'#' => {
                 let pos = self.position();
-                let b = self.until_crlf()?;
+                let b = self.until_line_break()?;
                 let b = match b {
                     [b't'] => true,
                     [b'f'] => false,
@@ -174,46 +391,63 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
 
                 visitor.visit_bool(b)
             }
-            b'*' => {
+            b'*' => match self.get_length_or_null()? {
+                Some(len) => visitor.visit_seq(Array::new(self, len)),
+                None => visitor.visit_none(),
+            },
+            b'~' | b'>' => {
                 let len = self.get_length()?;
                 visitor.visit_seq(Array::new(self, len))
             }
+            b'%' => {
+                let len = self.get_length()?;
+                visitor.visit_map(Array::new(self, len))
+            }
+            b',' => {
+                let pos = self.position();
+                let double = self.until_line_break()?;
+                let double = std::str::from_utf8(double)
+                    .ok()
+                    .and_then(|x| x.parse().ok())
+                    .ok_or(Error::ParseFloatError(pos))?;
+                visitor.visit_f64(double)
+            }
             b'_' => {
-                let rest = self.until_crlf()?;
+                let rest = self.until_line_break()?;
                 if !rest.is_empty() {
                     return Err(Error::Syntax(self.position()));
                 }
-                visitor.visit_none()
+                visitor.visit_unit()
             }
 
             _ => Err(Error::Syntax(self.position() - 1)),
         }
     }
 
-    forward_to_deserialize_any! {bool i8 i16 i32 i64 u8 u16 u32 u64 bytes str string ignored_any seq}
+    forward_to_deserialize_any! {bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 bytes str string ignored_any seq}
 
     fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        self.deserialize_any(visitor)
     }
 
     fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
-        todo!()
+        self.deserialize_any(visitor)
     }
 
-    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_char<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
         todo!()
     }
 
-    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_byte_buf<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
@@ -226,7 +460,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     {
         match self.peek()? {
             b'_' => {
-                let rest = self.until_crlf()?;
+                let rest = self.until_line_break()?;
                 if rest != [b'_'] {
                     return Err(Error::Syntax(self.position()));
                 }
@@ -235,7 +469,7 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
             b'$' | b'*' => {
                 let input = self.input;
                 self.advance()?;
-                if let Err(Error::NegativeLength(_)) = self.get_length() {
+                if self.get_length_or_null()?.is_none() {
                     return visitor.visit_none();
                 };
                 self.input = input;
@@ -249,9 +483,10 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: de::Visitor<'de>,
     {
-        self.tag(b"_\r\n")
+        let position = self.position();
+        (self.tag(b"_") && self.line_break())
             .then_some(())
-            .ok_or(Error::ExpectedNull(self.position()))?;
+            .ok_or(Error::ExpectedNull(position))?;
         visitor.visit_unit()
     }
 
@@ -268,13 +503,67 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
 
     fn deserialize_newtype_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
     {
-        visitor.visit_newtype_struct(self)
+        match Frame::from_name(name) {
+            Some(Frame::Error) => match self.advance()? {
+                b'-' => {
+                    let buf = self.until_line_break()?;
+                    visitor.visit_borrowed_bytes(buf)
+                }
+                b'!' => {
+                    let len = self.get_length()?;
+                    self.str(len, visitor)
+                }
+                _ => Err(Error::ExpectedError(self.position() - 1)),
+            },
+            Some(Frame::Verbatim) => {
+                if self.advance()? != b'=' {
+                    return Err(Error::Syntax(self.position() - 1));
+                }
+                let len = self.get_length()?;
+                self.str(len, visitor)
+            }
+            Some(Frame::BigNumber) => match self.advance()? {
+                b'(' | b':' => {
+                    let buf = self.until_line_break()?;
+                    visitor.visit_borrowed_bytes(buf)
+                }
+                _ => Err(Error::ExpectedBigNumber(self.position() - 1)),
+            },
+            Some(Frame::SimpleString) => {
+                if self.advance()? != b'+' {
+                    return Err(Error::Syntax(self.position() - 1));
+                }
+                let buf = self.until_line_break()?;
+                visitor.visit_borrowed_bytes(buf)
+            }
+            Some(Frame::Value) => {
+                self.skip_attributes()?;
+                let tag = match self.peek()? {
+                    b'+' => "SimpleString",
+                    b'*' if self.input.starts_with(b"*-1\r\n")
+                        || (self.lenient && self.input.starts_with(b"*-1\n")) =>
+                    {
+                        "NullArray"
+                    }
+                    b'-' | b'!' => "Error",
+                    b'=' => "Verbatim",
+                    b'(' => "BigNumber",
+                    b'~' => "Set",
+                    b'>' => "Push",
+                    _ => return self.deserialize_any(visitor),
+                };
+                visitor.visit_enum(Tagged { de: self, tag })
+            }
+            Some(Frame::Set | Frame::Push | Frame::Attribute) | None => {
+                visitor.visit_newtype_struct(self)
+            }
+        }
     }
 
     fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error>
@@ -323,9 +612,9 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
 
     fn deserialize_enum<V>(
         self,
-        name: &'static str,
-        variants: &'static [&'static str],
-        visitor: V,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        _visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: de::Visitor<'de>,
@@ -395,11 +684,69 @@ impl<'a, 'de: 'a> Array<'a, 'de> {
     }
 }
 
+/// A frame handed to a visitor as an enum variant named `tag`, see
+/// [`Frame::Value`]. The frame itself is the variant's content.
+struct Tagged<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+    tag: &'static str,
+}
+
+impl<'a, 'de: 'a> de::EnumAccess<'de> for Tagged<'a, 'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        let tag = seed.deserialize(BorrowedStrDeserializer::new(self.tag))?;
+        Ok((tag, self))
+    }
+}
+
+impl<'a, 'de: 'a> de::VariantAccess<'de> for Tagged<'a, 'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        de::IgnoredAny::deserialize(self.de).map(|_| ())
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        seed.deserialize(self.de)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: de::Visitor<'de>,
+    {
+        de::Deserializer::deserialize_seq(self.de, visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: de::Visitor<'de>,
+    {
+        de::Deserializer::deserialize_map(self.de, visitor)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(dead_code)]
     use super::*;
+    use crate::{
+        reply::{Push, RespError, Set, Verbatim},
+        value::{Double, Value},
+    };
     use serde::Deserialize;
+    use std::collections::BTreeSet;
 
     #[derive(Deserialize, PartialEq, Eq, Debug)]
     struct Foo(i32);
@@ -485,7 +832,8 @@ mod tests {
         ($ty:ty,$name:ident, $s:expr, ERROR) => {
             #[test]
             fn $name() {
-                let res = from_bytes::<$ty>(&($s).to_bytes());
+                let input = ($s).to_bytes();
+                let res = from_bytes::<$ty>(&input);
                 assert!(res.is_err());
             }
         };
@@ -500,12 +848,20 @@ mod tests {
 
     case!(i32, de_i32, ":1", 1);
     case!(i64, de_i64, ":15", 15);
+    case!(u64, de_big_number_u64, "(18446744073709551615", u64::MAX);
+    case!(
+        i128,
+        de_big_number,
+        "(-170141183460469231731687303715884105728",
+        i128::MIN
+    );
+    case!(i64, de_big_number_overflow, "(18446744073709551615", ERROR);
     case!(bool, de_bool, "#t", true);
     case!(&[u8], de_bytes, "+abc", "abc".as_bytes());
     case!(&str, de_str, "+abc", "abc");
     case!(&str, de_bulk_str, "$5\r\nhello", "hello");
     case!(&str, de_bulk_str_nl, "$7\r\nhel\r\nlo", "hel\r\nlo");
-    case!(Vec<i32>, int_array_empty, "*0", []);
+    case!(Vec<i32>, int_array_empty, "*0", [0i32; 0]);
     case!(
         Vec<i32>,
         int_array_some,
@@ -555,4 +911,117 @@ mod tests {
     );
     case!(Option<i32>, option_int, "_", None);
     case!(Option<Vec<i32>>, option_null_array, "*-1", None);
+
+    case!(
+        RespError,
+        simple_error,
+        "-ERR unknown command",
+        RespError::new("ERR", "unknown command")
+    );
+    case!(
+        RespError,
+        blob_error,
+        ["!21", "SYNTAX invalid syntax"],
+        RespError::new("SYNTAX", "invalid syntax")
+    );
+    case!(RespError, error_from_string, "+OK", ERROR);
+    case!(
+        Verbatim,
+        verbatim,
+        ["=15", "txt:Some string"],
+        Verbatim::txt("Some string")
+    );
+    case!(Verbatim, verbatim_too_short, ["=2", "tx"], ERROR);
+    case!(
+        BTreeSet<i32>,
+        set,
+        ["~2", ":1", ":2"],
+        BTreeSet::from([1, 2])
+    );
+    case!(
+        Push<(String, i32)>,
+        push,
+        [">2", "+message", ":1"],
+        Push(("message".into(), 1))
+    );
+    case!(
+        String,
+        attributes_are_skipped,
+        ["|1", "+ttl", "*2", ":1", ":2", "+bar"],
+        "bar"
+    );
+    case!(
+        Value,
+        value_keeps_frame_kinds,
+        ["*3", "~1", "-ERR oops", "(1", ">1", ",-0.5"],
+        Value::Array(Some(vec![
+            Value::Set(vec![Value::Error(RespError::new("ERR", "oops"))]),
+            Value::BigNumber("1".into()),
+            Value::Push(vec![Value::Double(Double(-0.5))]),
+        ]))
+    );
+    case!(Option<f64>, optional_double, ",1.5", Some(1.5));
+    case!(
+        Set<Vec<i32>>,
+        set_wrapper_from_array,
+        ["*1", ":1"],
+        Set(vec![1])
+    );
+
+    #[test]
+    fn value_binary_string() {
+        let value: Value = from_bytes(b"$3\r\n\xff\x00\xfe\r\n").unwrap();
+        assert_eq!(value, Value::bytes(*b"\xff\x00\xfe"));
+        assert_eq!(value.get_str(), None);
+    }
+
+    #[test]
+    fn lenient_line_endings() {
+        fn parse(input: &[u8], lenient: bool) -> Result<Value, Error> {
+            Value::deserialize(&mut Deserializer::from_bytes(input).lenient(lenient))
+        }
+
+        let strict = b"*5\r\n+OK\r\n$5\r\nhe\nlo\r\n:-3\r\n_\r\n*-1\r\n";
+        let expected = parse(strict, false).unwrap();
+        for input in [
+            &b"*5\n+OK\n$5\nhe\nlo\n:-3\n_\n*-1\n"[..],
+            b"*5\r\n+OK\n$5\r\nhe\nlo\n:-3\r\n_\n*-1\n",
+            strict,
+        ] {
+            assert_eq!(parse(input, true).unwrap(), expected, "{input:?}");
+        }
+        assert!(matches!(parse(b"+OK\n", false), Err(Error::UnexpectedEof)));
+        let unit = <()>::deserialize(&mut Deserializer::from_bytes(b"_\n").lenient(true));
+        assert!(unit.is_ok());
+
+        // positions are offsets into the input either way
+        assert!(matches!(
+            parse(b"*1\r\n:1x\r\n", false),
+            Err(Error::ParseIntError(5))
+        ));
+        assert!(matches!(
+            parse(b"*1\n:1x\n", true),
+            Err(Error::ParseIntError(4))
+        ));
+        assert!(matches!(parse(b"$3\nabcd\n", true), Err(Error::Syntax(6))));
+        for incomplete in [&b"$3\nab"[..], b"$3\nabc", b"$3\nabc\r", b"+OK"] {
+            assert!(
+                matches!(parse(incomplete, true), Err(Error::UnexpectedEof)),
+                "{incomplete:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_pipelined() {
+        let input = b"*1\r\n$4\r\nPING\r\n|1\r\n+a\r\n:1\r\n#t\r\n*2\r\n$3\r\nGET";
+        let (first, used) = Value::parse(input).unwrap();
+        assert_eq!(first, Value::from(vec!["PING".into()]));
+        let (second, len) = Value::parse(&input[used..]).unwrap();
+        assert_eq!(second, Value::Bool(true));
+        assert!(matches!(
+            Value::parse(&input[used + len..]),
+            Err(Error::UnexpectedEof)
+        ));
+    }
 }
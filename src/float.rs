@@ -0,0 +1,100 @@
+//! Formatting of floats in replies and stored values, the way Redis does
+//! it. Redis uses two styles: doubles sent as such (scores, RESP3 doubles)
+//! switch to exponent notation for very large and small values, while floats
+//! stored as strings (INCRBYFLOAT) are always written out in full.
+
+/// Formats a double the way Redis does: the shortest representation that
+/// round-trips, in fixed notation for exponents in `-4..17` and in `%g` style
+/// exponent notation (`1e+20`) otherwise. Integral values have no trailing
+/// `.0`, and non-finite values are `inf`, `-inf` and `nan`.
+pub(crate) fn format_double(v: f64) -> String {
+    if v.is_nan() {
+        return "nan".to_owned();
+    }
+    if v.is_infinite() {
+        return if v > 0.0 { "inf" } else { "-inf" }.to_owned();
+    }
+
+    let scientific = format!("{v:e}");
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("exponent notation always has an exponent");
+    let exponent: i32 = exponent.parse().expect("exponent is an integer");
+
+    if (-4..17).contains(&exponent) {
+        return v.to_string();
+    }
+
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{mantissa}e{sign}{:02}", exponent.abs())
+}
+
+/// Formats a float the way Redis stores the result of INCRBYFLOAT: never in
+/// exponent notation, with the shortest digits that round-trip (at most 17
+/// significant ones) and no trailing zeros. Like Redis it keeps no more than
+/// 17 decimals, `-0` becomes `0` and non-finite values are `inf`, `-inf` and
+/// `nan`.
+pub(crate) fn format_human(v: f64) -> String {
+    if v.is_nan() {
+        return "nan".to_owned();
+    }
+    if v.is_infinite() {
+        return if v > 0.0 { "inf" } else { "-inf" }.to_owned();
+    }
+
+    let mut s = v.to_string();
+    if s.split_once('.')
+        .is_some_and(|(_, decimals)| decimals.len() > 17)
+    {
+        s = format!("{v:.17}");
+        s.truncate(s.trim_end_matches('0').trim_end_matches('.').len());
+    }
+    if s == "-0" {
+        s.remove(0);
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn human() {
+        // what Redis replies with, e.g. the INCRBYFLOAT examples from its docs
+        let cases = [
+            (10.5, "10.5"),
+            (10.5 + 0.1, "10.6"),
+            (5.0e3 + 2.0e2, "5200"),
+            (3.0, "3"),
+            (-0.0, "0"),
+            (1e20, "100000000000000000000"),
+            (0.0001, "0.0001"),
+            (1.5e-7, "0.00000015"),
+            (1e-20, "0"),
+            (-1.23456789012345e-10, "-0.00000000012345679"),
+            (f64::INFINITY, "inf"),
+            (f64::NEG_INFINITY, "-inf"),
+        ];
+        for (v, expected) in cases {
+            assert_eq!(format_human(v), expected, "{v:e}");
+        }
+    }
+
+    #[test]
+    fn double() {
+        let cases = [
+            (1.5, "1.5"),
+            (3.0, "3"),
+            (-0.0, "-0"),
+            (0.00001, "1e-05"),
+            (1e16, "10000000000000000"),
+            (1e17, "1e+17"),
+            (1.5e300, "1.5e+300"),
+            (f64::NAN, "nan"),
+        ];
+        for (v, expected) in cases {
+            assert_eq!(format_double(v), expected, "{v:e}");
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Glob-style patterns as Redis uses them for KEYS, SCAN's `MATCH` and
+//! PSUBSCRIBE:
+//!
+//! - `*` matches any run of bytes, `?` any single byte
+//! - `[abc]` matches one of the bytes listed, `[a-z]` one in the range and
+//!   `[^...]` one that isn't
+//! - `\x` matches `x` itself, also inside brackets
+//!
+//! Patterns are bytes, like keys, and matching is case sensitive. Malformed
+//! patterns don't fail: an unclosed `[` runs to the end of the pattern and a
+//! trailing `\` matches itself.
+
+/// whether all of `string` matches `pattern`
+pub(crate) fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // Where to go on after the last `*` when the rest doesn't match, it then
+    // swallows one more byte. Only the last one matters, earlier ones could
+    // only swallow less.
+    let mut star = None;
+    while s < string.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, s));
+            continue;
+        }
+        if let Some(len) = match_one(&pattern[p..], string[s]) {
+            p += len;
+            s += 1;
+            continue;
+        }
+        let Some((after_star, swallowed)) = &mut star else {
+            return false;
+        };
+        *swallowed += 1;
+        (p, s) = (*after_star, *swallowed);
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Matches `c` against the element at the start of `pattern`, which isn't a
+/// `*`. Returns how long the element is if it matches.
+fn match_one(pattern: &[u8], c: u8) -> Option<usize> {
+    let (matched, len) = match pattern {
+        [] => return None,
+        [b'?', ..] => (true, 1),
+        [b'\\', escaped, ..] => (*escaped == c, 2),
+        [b'[', class @ ..] => {
+            let (matched, len) = match_class(class, c);
+            (matched, len + 1)
+        }
+        [literal, ..] => (*literal == c, 1),
+    };
+    matched.then_some(len)
+}
+
+/// Matches `c` against the bracket expression `class` starts with, just
+/// after the `[`. Returns whether it matches and how long the expression is.
+fn match_class(class: &[u8], c: u8) -> (bool, usize) {
+    let (negated, mut i) = match class.first() {
+        Some(b'^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut matched = false;
+    while i < class.len() {
+        match class[i..] {
+            [b']', ..] => return (matched != negated, i + 1),
+            [b'\\', escaped, ..] => {
+                matched |= escaped == c;
+                i += 2;
+            }
+            [start, b'-', end, ..] => {
+                let (low, high) = (start.min(end), start.max(end));
+                matched |= (low..=high).contains(&c);
+                i += 3;
+            }
+            [byte, ..] => {
+                matched |= byte == c;
+                i += 1;
+            }
+            [] => unreachable!("i is in bounds"),
+        }
+    }
+    (matched != negated, class.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("*", "", true),
+            ("*", "anything", true),
+            ("", "", true),
+            ("", "a", false),
+            ("hello", "hello", true),
+            ("hello", "hell", false),
+            ("h?llo", "hallo", true),
+            ("h?llo", "hllo", false),
+            ("h*llo", "hllo", true),
+            ("h*llo", "heeeello", true),
+            ("h*llo", "hello!", false),
+            ("*llo*", "hello world", true),
+            ("a*b*c", "aXbXbXc", true),
+            ("a*b*c", "aXbXbX", false),
+            ("**a", "ba", true),
+            ("h[ae]llo", "hello", true),
+            ("h[ae]llo", "hillo", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-b]llo", "hbllo", true),
+            ("h[b-a]llo", "hallo", true),
+            ("h[a-b]llo", "hcllo", false),
+            ("[]a", "a", false),
+            ("[^]a", "ba", true),
+            ("h\\*llo", "h*llo", true),
+            ("h\\*llo", "hello", false),
+            ("[\\]]", "]", true),
+            ("[\\-]", "-", true),
+            ("a\\", "a\\", true),
+            // an unclosed bracket runs to the end
+            ("[abc", "b", true),
+            ("[abc", "d", false),
+            ("user:*:name", "user:1000:name", true),
+            ("user:*:name", "user:1000:email", false),
+            ("*a*a*a*a*a*a*a*a*b", &"a".repeat(64), false),
+        ];
+        for (pattern, string, expected) in cases {
+            assert_eq!(
+                matches(pattern.as_bytes(), string.as_bytes()),
+                *expected,
+                "{pattern:?} {string:?}"
+            );
+        }
+        assert!(matches(b"\xff?", b"\xff\x00"));
+    }
+}
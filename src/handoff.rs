@@ -0,0 +1,216 @@
+//! The file a stopping server leaves for the one replacing it.
+//!
+//! With `--handoff <path>` the server writes its keyspace and the counters
+//! INFO reports to `path` when it's asked to stop, and a server started with
+//! the same option takes them over and removes the file. A binary upgrade then
+//! loses neither keys nor stats.
+//!
+//! This is experimental and the format is our own, it only has to be read by
+//! the next build: a header, the counters, every key with its expiry and its
+//! value as a DUMP payload (see [`rdb::dump`]), and a CRC64 of all of it so a
+//! file cut short isn't mistaken for a smaller keyspace.
+
+use std::{io, time::Duration};
+
+use crate::{object::Object, rdb, reply_pool::PoolStats, store::ExpireStats, value::Value};
+
+/// the start of every handoff file, the last byte is the version
+const MAGIC: &[u8] = b"redis-handoff\x01";
+
+/// The counters INFO reports that are carried over, the rest start afresh.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Counters {
+    pub expire: ExpireStats,
+    pub replies: PoolStats,
+}
+
+/// A key as it's carried over.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Key {
+    pub key: Value,
+    pub value: Object,
+    /// unix time in ms
+    pub expiry: Option<u128>,
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "corrupt handoff file")
+}
+
+/// Builds a handoff file in memory, to be written out in one go.
+pub(crate) struct Encoder {
+    out: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new(counters: &Counters) -> Self {
+        let mut out = MAGIC.to_vec();
+        let expire = &counters.expire;
+        for n in [
+            expire.expired_keys,
+            expire.cycles,
+            expire.time_cap_reached,
+            expire.cycle_time.as_nanos() as u64,
+            expire.last_sampled as u64,
+            expire.last_expired as u64,
+            expire.last_duration.as_nanos() as u64,
+            counters.replies.hits,
+            counters.replies.misses,
+            counters.replies.peak as u64,
+        ] {
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        Encoder { out }
+    }
+
+    pub fn key(&mut self, key: &Value, value: &Object, expiry: Option<u128>) -> io::Result<()> {
+        let key = key
+            .get_bytes()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "keys must be strings"))?;
+        let payload = rdb::dump(value)?;
+        self.out.push(1);
+        self.blob(key);
+        match expiry {
+            Some(expiry) => {
+                self.out.push(1);
+                self.out
+                    .extend_from_slice(&u64::try_from(expiry).unwrap_or(u64::MAX).to_le_bytes());
+            }
+            None => self.out.push(0),
+        }
+        self.blob(&payload);
+        Ok(())
+    }
+
+    fn blob(&mut self, bytes: &[u8]) {
+        self.out
+            .extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        self.out.extend_from_slice(bytes);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.out.push(0);
+        let crc = rdb::crc64(&self.out);
+        self.out.extend_from_slice(&crc.to_le_bytes());
+        self.out
+    }
+}
+
+fn take<'a>(input: &mut &'a [u8], n: usize) -> io::Result<&'a [u8]> {
+    if input.len() < n {
+        return Err(corrupt());
+    }
+    let (out, rest) = input.split_at(n);
+    *input = rest;
+    Ok(out)
+}
+
+fn take_u64(input: &mut &[u8]) -> io::Result<u64> {
+    let bytes = take(input, 8)?;
+    Ok(u64::from_le_bytes(bytes.try_into().expect("took 8 bytes")))
+}
+
+fn take_blob<'a>(input: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    let len = usize::try_from(take_u64(input)?).map_err(|_| corrupt())?;
+    take(input, len)
+}
+
+/// reads a file written with [`Encoder`]
+pub(crate) fn decode(input: &[u8]) -> io::Result<(Counters, Vec<Key>)> {
+    let (body, crc) = input.split_last_chunk::<8>().ok_or_else(corrupt)?;
+    if u64::from_le_bytes(*crc) != rdb::crc64(body) {
+        return Err(corrupt());
+    }
+    let mut input = body
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a handoff file"))?;
+
+    let mut n = || take_u64(&mut input);
+    let counters = Counters {
+        expire: ExpireStats {
+            expired_keys: n()?,
+            cycles: n()?,
+            time_cap_reached: n()?,
+            cycle_time: Duration::from_nanos(n()?),
+            last_sampled: n()? as usize,
+            last_expired: n()? as usize,
+            last_duration: Duration::from_nanos(n()?),
+        },
+        replies: PoolStats {
+            hits: n()?,
+            misses: n()?,
+            peak: n()? as usize,
+        },
+    };
+
+    let mut keys = vec![];
+    loop {
+        match take(&mut input, 1)? {
+            [0] => break,
+            [1] => {}
+            _ => return Err(corrupt()),
+        }
+        let key = Value::bytes(take_blob(&mut input)?);
+        let expiry = match take(&mut input, 1)? {
+            [0] => None,
+            [1] => Some(take_u64(&mut input)? as u128),
+            _ => return Err(corrupt()),
+        };
+        let value = rdb::restore(take_blob(&mut input)?).map_err(|_| corrupt())?;
+        keys.push(Key { key, value, expiry });
+    }
+    if !input.is_empty() {
+        return Err(corrupt());
+    }
+    Ok((counters, keys))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let counters = Counters {
+            expire: ExpireStats {
+                expired_keys: 3,
+                cycles: 10,
+                cycle_time: Duration::from_millis(7),
+                ..Default::default()
+            },
+            replies: PoolStats {
+                hits: 5,
+                misses: 1,
+                peak: 100,
+            },
+        };
+        let keys = [
+            Key {
+                key: Value::str("s"),
+                value: Value::str("hello").into(),
+                expiry: None,
+            },
+            Key {
+                key: Value::str("l"),
+                value: Value::Array(Some(vec![Value::str("a"), Value::str("b")])).into(),
+                expiry: Some(1_700_000_000_000),
+            },
+        ];
+        let mut encoder = Encoder::new(&counters);
+        for key in &keys {
+            encoder.key(&key.key, &key.value, key.expiry).unwrap();
+        }
+        let file = encoder.finish();
+        assert_eq!(decode(&file).unwrap(), (counters.clone(), keys.to_vec()));
+
+        let empty = Encoder::new(&Counters::default()).finish();
+        assert_eq!(decode(&empty).unwrap(), (Counters::default(), vec![]));
+
+        // a file cut short or changed is rejected rather than read partly
+        assert!(decode(&file[..file.len() - 20]).is_err());
+        let mut changed = file.clone();
+        changed[MAGIC.len() + 3] ^= 1;
+        assert!(decode(&changed).is_err());
+        assert!(decode(b"").is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Hashes as the store keeps them, fields that can expire on their own
+//! included.
+//!
+//! Like in Redis 7.4, each field of a hash may have a time to live of its own
+//! (HEXPIRE and friends). Expired fields are removed when the hash is looked
+//! up (see [`Store::expire_fields`]) and by the expiry cycle, which samples
+//! the hashes that have fields with a ttl the way it samples keys.
+//!
+//! DUMP and the handoff file keep the ttls, using the hash-with-metadata
+//! type Redis added to RDB version 12.
+//!
+//! [`Store::expire_fields`]: crate::store::Store::expire_fields
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Deref,
+};
+
+use indexmap::IndexMap;
+
+use crate::value::Value;
+
+/// The fields of a hash in an `IndexMap`, so they can be picked at random and
+/// scanned, and when those with a ttl expire.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Hash {
+    fields: IndexMap<Value, Value>,
+    /// unix time in ms each field with a ttl expires at
+    expiries: HashMap<Value, u128>,
+    /// the same by deadline, so finding the fields that expired doesn't
+    /// have to look at the others
+    deadlines: BTreeSet<(u128, Value)>,
+}
+
+/// Reading a hash reads its fields. Changes go through the methods of
+/// [`Hash`], which keep the ttls in step.
+impl Deref for Hash {
+    type Target = IndexMap<Value, Value>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.fields
+    }
+}
+
+impl FromIterator<(Value, Value)> for Hash {
+    fn from_iter<I: IntoIterator<Item = (Value, Value)>>(iter: I) -> Self {
+        Hash {
+            fields: iter.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Hash {
+    /// Sets `field` to `value`, dropping any ttl it had like HSET does.
+    /// Returns the old value.
+    pub fn insert(&mut self, field: Value, value: Value) -> Option<Value> {
+        self.drop_expiry(&field);
+        self.fields.insert(field, value)
+    }
+
+    /// Like [`Hash::insert`], but a ttl `field` has is kept, for HINCRBY and
+    /// friends.
+    pub fn insert_keep_ttl(&mut self, field: Value, value: Value) -> Option<Value> {
+        self.fields.insert(field, value)
+    }
+
+    /// Removes `field` with its ttl. The last field takes the place of the
+    /// removed one.
+    pub fn remove(&mut self, field: &Value) -> Option<Value> {
+        self.drop_expiry(field);
+        self.fields.swap_remove(field)
+    }
+
+    fn drop_expiry(&mut self, field: &Value) {
+        if let Some(expiry) = self.expiries.remove(field) {
+            self.deadlines.remove(&(expiry, field.clone()));
+        }
+    }
+
+    /// when `field` expires, as a unix time in ms
+    pub fn expiry(&self, field: &Value) -> Option<u128> {
+        self.expiries.get(field).copied()
+    }
+
+    /// Changes when `field` expires, returns false if there is no such
+    /// field.
+    pub fn set_expiry(&mut self, field: &Value, expiry: Option<u128>) -> bool {
+        if !self.fields.contains_key(field) {
+            return false;
+        }
+        self.drop_expiry(field);
+        if let Some(expiry) = expiry {
+            self.expiries.insert(field.clone(), expiry);
+            self.deadlines.insert((expiry, field.clone()));
+        }
+        true
+    }
+
+    /// the fields that have a ttl, with when they expire
+    pub fn expiries(&self) -> &HashMap<Value, u128> {
+        &self.expiries
+    }
+
+    /// the fields that have a ttl by deadline
+    pub fn deadlines(&self) -> &BTreeSet<(u128, Value)> {
+        &self.deadlines
+    }
+
+    /// whether any field has a ttl
+    pub fn has_expiries(&self) -> bool {
+        !self.expiries.is_empty()
+    }
+
+    /// whether any field expired by `now`
+    pub fn has_expired(&self, now: u128) -> bool {
+        self.deadlines
+            .first()
+            .is_some_and(|&(expiry, _)| expiry <= now)
+    }
+
+    /// whether `field` is there and hasn't expired by `now`
+    pub fn is_live(&self, field: &Value, now: u128) -> bool {
+        self.fields.contains_key(field) && self.expiry(field).is_none_or(|expiry| expiry > now)
+    }
+
+    /// removes the fields that expired by `now`, returns how many there were
+    pub fn remove_expired(&mut self, now: u128) -> usize {
+        let mut removed = 0;
+        while self.has_expired(now) {
+            let (_, field) = self.deadlines.pop_first().expect("one has expired");
+            self.expiries.remove(&field);
+            self.fields.swap_remove(&field);
+            removed += 1;
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_ttls() {
+        let mut hash: Hash = [("a", "1"), ("b", "2"), ("c", "3")]
+            .into_iter()
+            .map(|(f, v)| (Value::str(f), Value::str(v)))
+            .collect();
+        let (a, b, c) = (Value::str("a"), Value::str("b"), Value::str("c"));
+        assert!(!hash.has_expiries());
+        assert!(hash.set_expiry(&a, Some(100)));
+        assert!(hash.set_expiry(&b, Some(200)));
+        assert!(!hash.set_expiry(&Value::str("x"), Some(100)));
+        assert_eq!(hash.expiry(&a), Some(100));
+
+        // HINCRBY keeps the ttl, HSET drops it
+        hash.insert_keep_ttl(a.clone(), Value::str("2"));
+        assert_eq!(hash.expiry(&a), Some(100));
+        hash.insert(b.clone(), Value::str("3"));
+        assert_eq!(hash.expiry(&b), None);
+
+        assert!(hash.is_live(&a, 99));
+        assert!(!hash.is_live(&a, 100));
+        assert!(!hash.has_expired(99));
+        assert!(hash.has_expired(100));
+        assert_eq!(hash.remove_expired(100), 1);
+        assert_eq!(hash.keys().collect::<Vec<_>>(), [&c, &b]);
+        assert!(!hash.has_expiries());
+
+        hash.set_expiry(&c, Some(5));
+        hash.remove(&c);
+        assert!(!hash.has_expiries());
+        assert_eq!(hash.len(), 1);
+    }
+}
@@ -0,0 +1,120 @@
+//! The longest common subsequence of two strings, for the LCS command.
+//!
+//! This is the textbook dynamic programming solution, quadratic in time and
+//! memory, with the table walked back the same way Redis does it so the
+//! matching ranges come out the same: from the end of the strings to their
+//! start.
+
+/// A run of bytes that is part of the subsequence and contiguous in both
+/// strings, as inclusive ranges of indices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Match {
+    pub a: (usize, usize),
+    pub b: (usize, usize),
+}
+
+impl Match {
+    pub fn len(&self) -> usize {
+        self.a.1 - self.a.0 + 1
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Lcs {
+    pub subsequence: Vec<u8>,
+    /// the runs the subsequence is made of, last one first
+    pub matches: Vec<Match>,
+}
+
+/// how many cells the table for `a` and `b` has
+pub(crate) fn table_size(a: &[u8], b: &[u8]) -> Option<usize> {
+    (a.len() + 1).checked_mul(b.len() + 1)
+}
+
+pub(crate) fn lcs(a: &[u8], b: &[u8]) -> Lcs {
+    // table[i * width + j] is the length of the LCS of a[..i] and b[..j]
+    let width = b.len() + 1;
+    let mut table = vec![0u32; (a.len() + 1) * width];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            table[i * width + j] = if a[i - 1] == b[j - 1] {
+                table[(i - 1) * width + j - 1] + 1
+            } else {
+                table[(i - 1) * width + j].max(table[i * width + j - 1])
+            };
+        }
+    }
+
+    let mut subsequence = vec![0; table[table.len() - 1] as usize];
+    let mut matches = vec![];
+    let mut current: Option<Match> = None;
+    let (mut i, mut j, mut k) = (a.len(), b.len(), subsequence.len());
+    while i > 0 && j > 0 {
+        if a[i - 1] == b[j - 1] {
+            k -= 1;
+            subsequence[k] = a[i - 1];
+            i -= 1;
+            j -= 1;
+            // walking back along a diagonal extends the run backwards
+            let run = current.get_or_insert(Match {
+                a: (i, i),
+                b: (j, j),
+            });
+            (run.a.0, run.b.0) = (i, j);
+            if i == 0 || j == 0 {
+                matches.extend(current.take());
+            }
+        } else {
+            if table[(i - 1) * width + j] > table[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            matches.extend(current.take());
+        }
+    }
+    Lcs {
+        subsequence,
+        matches,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subsequences() {
+        // the example from the Redis docs
+        let out = lcs(b"ohmytext", b"mynewtext");
+        assert_eq!(out.subsequence, b"mytext");
+        assert_eq!(
+            out.matches,
+            [
+                Match {
+                    a: (4, 7),
+                    b: (5, 8)
+                },
+                Match {
+                    a: (2, 3),
+                    b: (0, 1)
+                },
+            ]
+        );
+        assert_eq!(out.matches[0].len(), 4);
+
+        assert_eq!(lcs(b"", b"abc").subsequence, b"");
+        assert!(lcs(b"abc", b"def").matches.is_empty());
+        let out = lcs(b"abc", b"abc");
+        assert_eq!(out.subsequence, b"abc");
+        assert_eq!(
+            out.matches,
+            [Match {
+                a: (0, 2),
+                b: (0, 2)
+            }]
+        );
+        assert_eq!(lcs(b"AGGTAB", b"GXTXAYB").subsequence, b"GTAB");
+        assert_eq!(table_size(b"ab", b"c"), Some(6));
+    }
+}
@@ -2,8 +2,22 @@ pub mod serializer;
 pub mod deserializer;
 pub mod value;
 pub mod commands;
+pub mod reply;
+pub mod compression;
 mod case_insensitive;
+mod float;
+mod glob;
+mod handoff;
+mod hash;
+mod lcs;
+mod mem_size;
+mod object;
+#[allow(dead_code)]
 mod rdb;
+mod reply_pool;
+mod sample;
+mod sparse;
+mod store;
 
 pub fn add(x: i32, y: i32) -> i32 {
     x + y
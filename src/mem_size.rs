@@ -0,0 +1,125 @@
+use std::{collections::VecDeque, mem::size_of, sync::Arc};
+
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{
+    hash::Hash,
+    object::Object,
+    sparse::Sparse,
+    store::Entry,
+    value::{Map, Value},
+};
+
+/// Estimates how much memory a stored object uses.
+///
+/// This is the one place sizes are computed, so MEMORY USAGE and any memory
+/// accounting built later agree with each other. Only the size of the
+/// allocation an object owns is counted, not allocator overhead.
+pub(crate) trait MemSize {
+    /// bytes owned on the heap
+    fn heap_size(&self) -> usize;
+
+    /// total bytes, the value itself plus what it owns
+    fn mem_size(&self) -> usize
+    where
+        Self: Sized,
+    {
+        size_of::<Self>() + self.heap_size()
+    }
+}
+
+impl MemSize for Value {
+    fn heap_size(&self) -> usize {
+        match self {
+            Value::Int(_) | Value::Bool(_) | Value::Double(_) | Value::Null => 0,
+            Value::String(s) => s.as_ref().map_or(0, Vec::capacity),
+            Value::BigNumber(s) | Value::SimpleString(s) | Value::Verbatim { text: s, .. } => {
+                s.capacity()
+            }
+            Value::Error(e) => e.code.capacity() + e.message.capacity(),
+            Value::Array(a) => a.as_ref().map_or(0, Vec::heap_size),
+            Value::Set(a) | Value::Push(a) => a.heap_size(),
+            Value::Map(m) => m.heap_size(),
+        }
+    }
+}
+
+impl MemSize for Vec<Value> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<Value>() + self.iter().map(Value::heap_size).sum::<usize>()
+    }
+}
+
+impl MemSize for Map {
+    fn heap_size(&self) -> usize {
+        // entries are stored as pairs in one allocation
+        self.iter().map(|(k, v)| k.mem_size() + v.mem_size()).sum()
+    }
+}
+
+impl MemSize for VecDeque<Value> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<Value>() + self.iter().map(Value::heap_size).sum::<usize>()
+    }
+}
+
+impl MemSize for IndexMap<Value, Value> {
+    fn heap_size(&self) -> usize {
+        // the entries with their hashes, and a table of indices into them
+        let entries = self.capacity() * (size_of::<(Value, Value)>() + size_of::<u64>());
+        let table = self.capacity() * size_of::<usize>();
+        entries
+            + table
+            + self
+                .iter()
+                .map(|(k, v)| k.heap_size() + v.heap_size())
+                .sum::<usize>()
+    }
+}
+
+impl MemSize for IndexSet<Value> {
+    fn heap_size(&self) -> usize {
+        // the members with their hashes, and a table of indices into them
+        let entries = self.capacity() * (size_of::<Value>() + size_of::<u64>());
+        let table = self.capacity() * size_of::<usize>();
+        entries + table + self.iter().map(Value::heap_size).sum::<usize>()
+    }
+}
+
+impl MemSize for Hash {
+    fn heap_size(&self) -> usize {
+        // the fields with a ttl are kept twice, by field and by deadline
+        let ttls = self.expiries().capacity() * size_of::<(Value, u128)>()
+            + self.deadlines().len() * size_of::<(u128, Value)>()
+            + 2 * self.expiries().keys().map(Value::heap_size).sum::<usize>();
+        // the fields, through `Deref`
+        MemSize::heap_size(&**self) + ttls
+    }
+}
+
+impl<T: MemSize> MemSize for Arc<T> {
+    fn heap_size(&self) -> usize {
+        // the reference counts are allocated together with the value
+        2 * size_of::<usize>() + (**self).mem_size()
+    }
+}
+
+impl MemSize for Object {
+    fn heap_size(&self) -> usize {
+        match self {
+            // shared integers belong to no key in particular
+            Object::Int(_) | Object::Inline { .. } => 0,
+            Object::Value(value) | Object::Raw(value) => value.heap_size(),
+            Object::Sparse(s) => size_of::<Sparse>() + s.heap_size(),
+            Object::List(list) => list.heap_size(),
+            Object::Hash(hash) => hash.heap_size(),
+            Object::Set(set) => set.heap_size(),
+        }
+    }
+}
+
+impl MemSize for Entry {
+    fn heap_size(&self) -> usize {
+        self.value.heap_size()
+    }
+}
@@ -0,0 +1,466 @@
+use std::{
+    collections::VecDeque,
+    ops::Range,
+    sync::{Arc, LazyLock},
+};
+
+use indexmap::IndexSet;
+
+use crate::{
+    hash::Hash,
+    rdb,
+    sparse::Sparse,
+    store::Kind,
+    value::{Map, Value},
+};
+
+/// integers from 0 up to this are shared between all keys holding them, like
+/// `OBJ_SHARED_INTEGERS` in Redis
+const SHARED_INTEGERS: i64 = 10_000;
+
+/// the refcount reported for shared integers, which are never freed
+const SHARED_REFCOUNT: usize = i32::MAX as usize;
+
+/// strings up to this long are stored inline, this keeps [`Object`] as small
+/// as the `i64` and `Arc` it holds otherwise
+const INLINE_LEN: usize = 22;
+
+static SHARED: LazyLock<Vec<Arc<Value>>> = LazyLock::new(|| {
+    (0..SHARED_INTEGERS)
+        .map(|i| Arc::new(Value::bytes(i.to_string())))
+        .collect()
+});
+
+fn shared(i: i64) -> Option<&'static Arc<Value>> {
+    SHARED.get(usize::try_from(i).ok()?)
+}
+
+/// A value as the store keeps it.
+///
+/// Most keys hold short strings, often numbers, and a `Value` behind an `Arc`
+/// costs two allocations for those. Strings are stored without any
+/// allocation when they can be: integers (that print back the same) as an
+/// `i64`, short strings inline. Reading them builds a new `Value`, except for
+/// small integers, which all share one.
+///
+/// The representation follows the encodings in Redis, which OBJECT ENCODING
+/// reports: `int`, `embstr` for strings of up to 44 bytes and `raw` for longer
+/// ones. Strings changed in place (APPEND, SETRANGE) become `raw` whatever
+/// their length, and stay that way until they're overwritten. Strings
+/// SETRANGE wrote far past their end are `raw` too as far as OBJECT ENCODING
+/// is concerned, but keep only what was written.
+///
+/// Lists are kept in a `VecDeque`, so they grow and shrink cheaply at both
+/// ends. Hashes are kept in a [`Hash`], a [`Map`] looks its keys up one by
+/// one, and sets in an `IndexSet` for the same reason.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Object {
+    /// a bulk string holding an integer
+    Int(i64),
+    /// a bulk string of at most [`INLINE_LEN`] bytes
+    Inline { len: u8, buf: [u8; INLINE_LEN] },
+    /// Everything else. Shared with the replies that are still being written,
+    /// so reading it doesn't copy it. Changing it in place should go through
+    /// [`Arc::make_mut`], which only copies if the value is still shared.
+    Value(Arc<Value>),
+    /// a string that was changed in place, changing it again doesn't copy it
+    Raw(Arc<Value>),
+    /// a string that is mostly zeros nobody wrote
+    Sparse(Box<Sparse>),
+    /// a list of bulk strings, shared like [`Object::Value`]
+    List(Arc<VecDeque<Value>>),
+    /// the fields of a hash and their values, shared like [`Object::Value`]
+    Hash(Arc<Hash>),
+    /// the members of a set, shared like [`Object::Value`]
+    Set(Arc<IndexSet<Value>>),
+}
+
+impl From<Value> for Object {
+    fn from(value: Value) -> Self {
+        let s = match value {
+            Value::Array(Some(items)) => return Object::List(Arc::new(items.into())),
+            Value::Map(map) => return Object::Hash(Arc::new(map.into_iter().collect())),
+            Value::Set(members) => return Object::Set(Arc::new(members.into_iter().collect())),
+            Value::String(Some(ref s)) => s,
+            value => return Object::Value(Arc::new(value)),
+        };
+        if let Some(i) = rdb::as_int(s) {
+            return Object::Int(i);
+        }
+        inline(s).unwrap_or_else(|| Object::Value(Arc::new(value)))
+    }
+}
+
+/// `s` stored inline, if it's short enough
+fn inline(s: &[u8]) -> Option<Object> {
+    let mut buf = [0; INLINE_LEN];
+    buf.get_mut(..s.len())?.copy_from_slice(s);
+    Some(Object::Inline {
+        len: s.len() as u8,
+        buf,
+    })
+}
+
+impl Object {
+    /// Like [`Object::from`], but strings are never stored as integers. Redis
+    /// doesn't try to encode strings it makes up itself, like the results of
+    /// INCRBYFLOAT.
+    pub fn embstr(value: Value) -> Self {
+        let Value::String(Some(s)) = &value else {
+            return Object::from(value);
+        };
+        inline(s).unwrap_or_else(|| Object::Value(Arc::new(value)))
+    }
+
+    pub fn to_value(&self) -> Arc<Value> {
+        match self {
+            Object::Int(i) => match shared(*i) {
+                Some(value) => value.clone(),
+                None => Arc::new(Value::bytes(i.to_string())),
+            },
+            Object::Inline { len, buf } => Arc::new(Value::bytes(&buf[..*len as usize])),
+            Object::Value(value) | Object::Raw(value) => value.clone(),
+            Object::Sparse(s) => Arc::new(Value::bytes(s.to_bytes())),
+            Object::List(list) => Arc::new(Value::Array(Some(list.iter().cloned().collect()))),
+            Object::Hash(hash) => Arc::new(Value::Map(Map::from_distinct(
+                hash.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            ))),
+            Object::Set(set) => Arc::new(Value::Set(set.iter().cloned().collect())),
+        }
+    }
+
+    /// the elements of a list
+    pub fn as_list(&self) -> Option<&VecDeque<Value>> {
+        match self {
+            Object::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    /// Like [`Object::as_list`], for changing the list. It's only copied if a
+    /// reply still holds on to it.
+    pub fn list_mut(&mut self) -> Option<&mut VecDeque<Value>> {
+        match self {
+            Object::List(list) => Some(Arc::make_mut(list)),
+            _ => None,
+        }
+    }
+
+    /// the fields of a hash
+    pub fn as_hash(&self) -> Option<&Hash> {
+        match self {
+            Object::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    /// Like [`Object::as_hash`], for changing the hash. It's only copied if a
+    /// reply still holds on to it.
+    pub fn hash_mut(&mut self) -> Option<&mut Hash> {
+        match self {
+            Object::Hash(hash) => Some(Arc::make_mut(hash)),
+            _ => None,
+        }
+    }
+
+    /// the members of a set
+    pub fn as_set(&self) -> Option<&IndexSet<Value>> {
+        match self {
+            Object::Set(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    /// Like [`Object::as_set`], for changing the set. It's only copied if a
+    /// reply still holds on to it.
+    pub fn set_mut(&mut self) -> Option<&mut IndexSet<Value>> {
+        match self {
+            Object::Set(set) => Some(Arc::make_mut(set)),
+            _ => None,
+        }
+    }
+
+    /// the bytes of a string in `range`, which has to be within it
+    pub fn range(&self, range: Range<usize>) -> Vec<u8> {
+        match self {
+            Object::Sparse(s) => s.read(range),
+            _ => self.to_value().get_bytes().unwrap_or_default()[range].to_vec(),
+        }
+    }
+
+    /// the integer a string holds, if it prints back the same
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Object::Int(i) => Some(*i),
+            // raw strings and INCRBYFLOAT results may hold one too
+            Object::Inline { len, buf } => rdb::as_int(&buf[..*len as usize]),
+            Object::Value(value) | Object::Raw(value) => rdb::as_int(value.get_bytes()?),
+            // far too long for one
+            Object::Sparse(_) => None,
+            Object::List(_) | Object::Hash(_) | Object::Set(_) => None,
+        }
+    }
+
+    /// the length of a string in bytes
+    pub fn strlen(&self) -> usize {
+        match self {
+            Object::Int(i) => i.to_string().len(),
+            Object::Inline { len, .. } => *len as usize,
+            Object::Value(value) | Object::Raw(value) => value.get_bytes().map_or(0, <[u8]>::len),
+            Object::Sparse(s) => s.len(),
+            Object::List(_) | Object::Hash(_) | Object::Set(_) => 0,
+        }
+    }
+
+    /// Changes a string with `f` and returns its new length. It's [`raw`]
+    /// afterwards. Strings that already live in a `Value` are changed in
+    /// place, unless a reply still holds on to them.
+    ///
+    /// [`raw`]: Object::Raw
+    fn edit(&mut self, f: impl FnOnce(&mut Vec<u8>)) -> usize {
+        let mut value = match std::mem::replace(self, Object::Int(0)) {
+            Object::Value(value) | Object::Raw(value) => value,
+            object => object.to_value(),
+        };
+        if !matches!(*value, Value::String(Some(_))) {
+            value = Arc::new(Value::bytes(value.get_bytes().unwrap_or_default()));
+        }
+        let Value::String(Some(s)) = Arc::make_mut(&mut value) else {
+            unreachable!("made a bulk string above");
+        };
+        f(s);
+        let len = s.len();
+        *self = Object::Raw(value);
+        len
+    }
+
+    /// appends `tail` to a string and returns its new length
+    pub fn append(&mut self, tail: &[u8]) -> usize {
+        if let Object::Sparse(s) = self {
+            s.write(s.len(), tail);
+            return self.settle();
+        }
+        self.edit(|s| s.extend_from_slice(tail))
+    }
+
+    /// Overwrites a string with `bytes` from `offset` on, padding it with
+    /// zeros if it's too short, and returns its new length. Padding that's
+    /// much longer than the rest of the string isn't allocated, the string
+    /// becomes [sparse] instead.
+    ///
+    /// [sparse]: Object::Sparse
+    pub fn set_range(&mut self, offset: usize, bytes: &[u8]) -> usize {
+        if let Object::Sparse(s) = self {
+            s.write(offset, bytes);
+            return self.settle();
+        }
+        let len = self.strlen();
+        let end = offset + bytes.len();
+        if offset > len && Sparse::worth_it(end, len + bytes.len()) {
+            let mut s = Sparse::new(self.range(0..len));
+            s.write(offset, bytes);
+            *self = Object::Sparse(Box::new(s));
+            return end;
+        }
+        self.edit(|s| {
+            let end = offset + bytes.len();
+            if s.len() < end {
+                s.resize(end, 0);
+            }
+            s[offset..end].copy_from_slice(bytes);
+        })
+    }
+
+    /// Turns a sparse string that was mostly written into a raw one, and
+    /// returns its length.
+    fn settle(&mut self) -> usize {
+        if let Object::Sparse(s) = self {
+            if !Sparse::worth_it(s.len(), s.written()) {
+                *self = Object::Raw(Arc::new(Value::bytes(s.to_bytes())));
+            }
+        }
+        self.strlen()
+    }
+
+    /// roughly how much work freeing the value is, the number of elements
+    /// for aggregates
+    pub fn free_effort(&self) -> usize {
+        let value = match self {
+            Object::Value(value) | Object::Raw(value) => value,
+            Object::Sparse(s) => return s.chunks(),
+            Object::List(list) if Arc::strong_count(list) == 1 => return list.len(),
+            Object::Hash(hash) if Arc::strong_count(hash) == 1 => return hash.len(),
+            Object::Set(set) if Arc::strong_count(set) == 1 => return set.len(),
+            _ => return 1,
+        };
+        // someone else frees it
+        if Arc::strong_count(value) > 1 {
+            return 1;
+        }
+        match &**value {
+            Value::Array(Some(items)) | Value::Set(items) | Value::Push(items) => items.len(),
+            Value::Map(map) => map.len(),
+            _ => 1,
+        }
+    }
+
+    pub fn kind(&self) -> Kind {
+        match self {
+            Object::Int(_) | Object::Inline { .. } | Object::Sparse(_) => Kind::String,
+            Object::Value(value) | Object::Raw(value) => Kind::of(value),
+            Object::List(_) => Kind::List,
+            Object::Hash(_) => Kind::Hash,
+            Object::Set(_) => Kind::Set,
+        }
+    }
+
+    /// the name Redis uses for the representation, as reported by OBJECT
+    /// ENCODING
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Object::Int(_) => "int",
+            Object::Inline { .. } => "embstr",
+            Object::Value(value) => encoding(value),
+            Object::Raw(_) | Object::Sparse(_) => "raw",
+            Object::List(_) => "quicklist",
+            Object::Hash(_) | Object::Set(_) => "hashtable",
+        }
+    }
+
+    /// how many references to the value there are, counting the store's own
+    pub fn refcount(&self) -> usize {
+        match self {
+            Object::Int(i) => shared(*i).map_or(1, |_| SHARED_REFCOUNT),
+            Object::Inline { .. } | Object::Sparse(_) => 1,
+            Object::Value(value) | Object::Raw(value) => Arc::strong_count(value),
+            Object::List(list) => Arc::strong_count(list),
+            Object::Hash(hash) => Arc::strong_count(hash),
+            Object::Set(set) => Arc::strong_count(set),
+        }
+    }
+
+    /// where the value lives, for DEBUG OBJECT
+    pub fn as_ptr(&self) -> *const () {
+        match self {
+            Object::Int(i) => match shared(*i) {
+                Some(value) => Arc::as_ptr(value).cast(),
+                None => (self as *const Self).cast(),
+            },
+            Object::Inline { .. } => (self as *const Self).cast(),
+            Object::Value(value) | Object::Raw(value) => Arc::as_ptr(value).cast(),
+            Object::Sparse(s) => (&**s as *const Sparse).cast(),
+            Object::List(list) => Arc::as_ptr(list).cast(),
+            Object::Hash(hash) => Arc::as_ptr(hash).cast(),
+            Object::Set(set) => Arc::as_ptr(set).cast(),
+        }
+    }
+}
+
+/// the name Redis would report for how `value` is stored
+fn encoding(value: &Value) -> &'static str {
+    let string = |s: &[u8]| {
+        if rdb::as_int(s).is_some() {
+            "int"
+        } else if s.len() <= 44 {
+            // strings this short are allocated together with their object
+            "embstr"
+        } else {
+            "raw"
+        }
+    };
+    match value {
+        Value::Int(_) | Value::Bool(_) => "int",
+        Value::String(Some(s)) => string(s),
+        Value::BigNumber(s) | Value::SimpleString(s) | Value::Verbatim { text: s, .. } => {
+            string(s.as_bytes())
+        }
+        Value::Double(_) => "embstr",
+        Value::String(None) | Value::Error(_) | Value::Null => "raw",
+        Value::Array(_) | Value::Push(_) => "quicklist",
+        Value::Set(_) | Value::Map(_) => "hashtable",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compact() {
+        assert_eq!(std::mem::size_of::<Object>(), 24);
+
+        let cases = [
+            ("1".to_owned(), "int"),
+            ("-9223372036854775808".to_owned(), "int"),
+            ("01".to_owned(), "embstr"),
+            ("-0".to_owned(), "embstr"),
+            ("".to_owned(), "embstr"),
+            ("a".repeat(INLINE_LEN), "embstr"),
+            ("a".repeat(INLINE_LEN + 1), "embstr"),
+            ("a".repeat(45), "raw"),
+        ];
+        for (s, encoding) in cases {
+            let object = Object::from(Value::str(&s));
+            assert_eq!(object.encoding(), encoding, "{s:?}");
+            assert_eq!(*object.to_value(), Value::str(&s));
+        }
+
+        let a = Object::from(Value::str("42")).to_value();
+        let b = Object::from(Value::str("42")).to_value();
+        assert!(Arc::ptr_eq(&a, &b));
+    }
+
+    #[test]
+    fn transitions() {
+        // editing copies shared integers instead of changing them
+        let mut object = Object::from(Value::str("12"));
+        assert_eq!(object.append(b"3"), 3);
+        assert_eq!(object.encoding(), "raw");
+        assert_eq!(*object.to_value(), Value::str("123"));
+        assert_eq!(*Object::from(Value::str("12")).to_value(), Value::str("12"));
+
+        let mut object = Object::from(Value::str("hello"));
+        assert_eq!(object.set_range(7, b"!"), 8);
+        assert_eq!(object.encoding(), "raw");
+        assert_eq!(*object.to_value(), Value::bytes(*b"hello\0\0!"));
+
+        // raw strings are changed in place, unless they're still being read
+        let before = object.as_ptr();
+        object.append(b"?");
+        assert_eq!(object.as_ptr(), before);
+        let reading = object.to_value();
+        object.append(b"?");
+        assert_ne!(object.as_ptr(), before);
+        assert_eq!(*reading, Value::bytes(*b"hello\0\0!?"));
+
+        // writing far past the end only keeps what was written, until most of
+        // it was
+        let mut object = Object::from(Value::str("hello"));
+        assert_eq!(object.set_range(10_000, b"!"), 10_001);
+        assert!(matches!(object, Object::Sparse(_)));
+        assert_eq!(object.encoding(), "raw");
+        assert_eq!(object.strlen(), 10_001);
+        assert_eq!(object.range(3..7), b"lo\0\0");
+        let value = object.to_value();
+        let bytes = value.get_bytes().unwrap();
+        assert_eq!(
+            (&bytes[..5], bytes[9_999], bytes[10_000]),
+            (&b"hello"[..], 0, b'!')
+        );
+        assert_eq!(object.append(b"?"), 10_002);
+        assert!(matches!(object, Object::Sparse(_)));
+        assert_eq!(object.set_range(100, &[b'x'; 5000]), 10_002);
+        assert!(matches!(object, Object::Raw(_)));
+        assert_eq!(object.range(10_000..10_002), b"!?");
+        // strings too short to bother stay raw
+        let mut object = Object::from(Value::str("hello"));
+        object.set_range(100, b"!");
+        assert!(matches!(object, Object::Raw(_)));
+
+        assert_eq!(Object::embstr(Value::str("12")).encoding(), "embstr");
+        assert_eq!(
+            Object::embstr(Value::str(&"1".repeat(45))).encoding(),
+            "raw"
+        );
+    }
+}
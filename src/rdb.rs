@@ -1,8 +1,21 @@
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+    sync::Arc,
+};
+
 use nom::{
     bytes::complete::{tag, take},
     IResult,
 };
 
+use crate::{
+    float::format_double,
+    hash::Hash,
+    object::Object,
+    value::{Map, Value},
+};
+
 pub struct Rdb {}
 
 fn header(s: &[u8]) -> IResult<&[u8], ()> {
@@ -10,7 +23,6 @@ fn header(s: &[u8]) -> IResult<&[u8], ()> {
     Ok((s, ()))
 }
 
-
 fn version(s: &[u8]) -> IResult<&[u8], u32> {
     let (s, _) = take(4u32)(s)?;
     // let vers = atoi::atoi(vers).ok_or()?;
@@ -18,7 +30,579 @@ fn version(s: &[u8]) -> IResult<&[u8], u32> {
 }
 
 impl Rdb {
-    pub fn from_file(reader: &[u8]) -> Self {
+    pub fn from_file(_reader: &[u8]) -> Self {
         todo!();
     }
 }
+
+pub(crate) const TYPE_STRING: u8 = 0;
+pub(crate) const TYPE_LIST: u8 = 1;
+pub(crate) const TYPE_SET: u8 = 2;
+pub(crate) const TYPE_HASH: u8 = 4;
+/// a hash whose fields may have a ttl, since rdb 12
+pub(crate) const TYPE_HASH_METADATA: u8 = 24;
+
+const ENC_INT8: u8 = 0xc0;
+const ENC_INT16: u8 = 0xc1;
+const ENC_INT32: u8 = 0xc2;
+const ENC_LZF: u8 = 0xc3;
+
+/// the rdb version written into DUMP payloads
+const DUMP_VERSION: u16 = 11;
+/// the rdb version payloads of hashes with field ttls are written with,
+/// RESTORE takes anything up to it
+const HASH_METADATA_VERSION: u16 = 12;
+
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{what} can't be stored in an rdb file"),
+    )
+}
+
+/// the object type byte written in front of `value` in a dump
+pub(crate) fn value_type(value: &Value) -> io::Result<u8> {
+    match value {
+        Value::Int(_)
+        | Value::Bool(_)
+        | Value::Double(_)
+        | Value::BigNumber(_)
+        | Value::SimpleString(_)
+        | Value::String(Some(_))
+        | Value::Verbatim { .. } => Ok(TYPE_STRING),
+        Value::Array(Some(_)) => Ok(TYPE_LIST),
+        Value::Set(_) => Ok(TYPE_SET),
+        Value::Map(_) => Ok(TYPE_HASH),
+        Value::Error(_) => Err(unsupported("errors")),
+        Value::Push(_) => Err(unsupported("push messages")),
+        Value::String(None) | Value::Array(None) | Value::Null => Err(unsupported("nil")),
+    }
+}
+
+/// Writes the rdb encoding of `value`, without its type byte.
+///
+/// Lists, sets and hashes use the plain (pre-listpack) encodings, which every rdb
+/// version can load. Their elements have to be strings, rdb has no nesting.
+pub(crate) fn write_value<W: Write>(w: &mut W, value: &Value) -> io::Result<()> {
+    match value {
+        Value::Array(Some(items)) | Value::Set(items) => {
+            write_length(w, items.len() as u64)?;
+            for item in items {
+                write_element(w, item)?;
+            }
+            Ok(())
+        }
+        Value::Map(map) => {
+            write_length(w, map.len() as u64)?;
+            for (k, v) in map {
+                write_element(w, k)?;
+                write_element(w, v)?;
+            }
+            Ok(())
+        }
+        _ => {
+            value_type(value)?;
+            write_element(w, value)
+        }
+    }
+}
+
+/// how many bytes [`write_value`] writes for `value`
+pub(crate) fn serialized_length(value: &Value) -> io::Result<usize> {
+    let mut counter = Counter(0);
+    write_value(&mut counter, value)?;
+    Ok(counter.0)
+}
+
+fn write_element<W: Write>(w: &mut W, value: &Value) -> io::Result<()> {
+    match value {
+        Value::Int(i) => write_int(w, *i),
+        Value::Bool(b) => write_int(w, (*b).into()),
+        Value::Double(d) => write_str(w, format_double(d.0).as_bytes()),
+        Value::String(Some(s)) => write_str(w, s),
+        Value::BigNumber(s) | Value::SimpleString(s) | Value::Verbatim { text: s, .. } => {
+            write_str(w, s.as_bytes())
+        }
+        Value::Array(Some(_)) | Value::Set(_) | Value::Map(_) => {
+            Err(unsupported("nested collections"))
+        }
+        Value::Error(_) => Err(unsupported("errors")),
+        Value::Push(_) => Err(unsupported("push messages")),
+        Value::String(None) | Value::Array(None) | Value::Null => Err(unsupported("nil")),
+    }
+}
+
+/// the integer `s` holds, if it prints back the same, only those strings can
+/// be stored as integers
+pub(crate) fn as_int(s: &[u8]) -> Option<i64> {
+    let i = std::str::from_utf8(s).ok()?.parse::<i64>().ok()?;
+    (i.to_string().as_bytes() == s).then_some(i)
+}
+
+fn write_str<W: Write>(w: &mut W, s: &[u8]) -> io::Result<()> {
+    match as_int(s) {
+        Some(i) => write_int(w, i),
+        None => write_string(w, s),
+    }
+}
+
+fn write_int<W: Write>(w: &mut W, i: i64) -> io::Result<()> {
+    if let Ok(i) = i8::try_from(i) {
+        w.write_all(&[ENC_INT8])?;
+        w.write_all(&i.to_le_bytes())
+    } else if let Ok(i) = i16::try_from(i) {
+        w.write_all(&[ENC_INT16])?;
+        w.write_all(&i.to_le_bytes())
+    } else if let Ok(i) = i32::try_from(i) {
+        w.write_all(&[ENC_INT32])?;
+        w.write_all(&i.to_le_bytes())
+    } else {
+        write_string(w, i.to_string().as_bytes())
+    }
+}
+
+fn write_string<W: Write>(w: &mut W, s: &[u8]) -> io::Result<()> {
+    write_length(w, s.len() as u64)?;
+    w.write_all(s)
+}
+
+fn write_length<W: Write>(w: &mut W, len: u64) -> io::Result<()> {
+    if len < 1 << 6 {
+        w.write_all(&[len as u8])
+    } else if len < 1 << 14 {
+        w.write_all(&(0x4000 | len as u16).to_be_bytes())
+    } else if let Ok(len) = u32::try_from(len) {
+        w.write_all(&[0x80])?;
+        w.write_all(&len.to_be_bytes())
+    } else {
+        w.write_all(&[0x81])?;
+        w.write_all(&len.to_be_bytes())
+    }
+}
+
+/// Serializes `object` for DUMP, like Redis does: its type byte and
+/// encoding, then the rdb version and a CRC64 of everything before it, both
+/// little endian.
+pub(crate) fn dump(object: &Object) -> io::Result<Vec<u8>> {
+    let (mut out, version) = match object.as_hash().filter(|hash| hash.has_expiries()) {
+        Some(hash) => {
+            let mut out = vec![TYPE_HASH_METADATA];
+            write_hash_metadata(&mut out, hash)?;
+            (out, HASH_METADATA_VERSION)
+        }
+        None => {
+            let value = object.to_value();
+            let mut out = vec![value_type(&value)?];
+            write_value(&mut out, &value)?;
+            (out, DUMP_VERSION)
+        }
+    };
+    out.extend_from_slice(&version.to_le_bytes());
+    let crc = crc64(&out);
+    out.extend_from_slice(&crc.to_le_bytes());
+    Ok(out)
+}
+
+/// Writes a hash with field ttls like Redis writes `RDB_TYPE_HASH_METADATA`:
+/// the earliest expiry as a unix time in ms, the number of fields, then each
+/// field's ttl, the field and its value. A ttl is written as its expiry minus
+/// the earliest one plus one, 0 means the field has none.
+fn write_hash_metadata<W: Write>(w: &mut W, hash: &Hash) -> io::Result<()> {
+    let min = hash.deadlines().first().map_or(0, |&(expiry, _)| expiry);
+    w.write_all(&u64::try_from(min).unwrap_or(u64::MAX).to_le_bytes())?;
+    write_length(w, hash.len() as u64)?;
+    for (field, value) in hash.iter() {
+        let ttl = hash.expiry(field).map_or(0, |expiry| expiry - min + 1);
+        write_length(w, u64::try_from(ttl).unwrap_or(u64::MAX))?;
+        write_element(w, field)?;
+        write_element(w, value)?;
+    }
+    Ok(())
+}
+
+/// Why RESTORE rejected a payload, with the messages Redis uses.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum PayloadError {
+    #[error("DUMP payload version or checksum are wrong")]
+    Checksum,
+    #[error("Bad data format")]
+    Format,
+}
+
+/// Reads a payload written by [`dump`], or by Redis as long as it sticks to
+/// the encodings known here: strings, LZF compressed or not, the plain
+/// encodings of lists, sets and hashes, and hashes with field ttls.
+///
+/// The lengths in the payload aren't trusted for allocations, a payload
+/// claiming a billion elements fails when it runs out of bytes.
+pub(crate) fn restore(payload: &[u8]) -> Result<Object, PayloadError> {
+    let (body, crc) = payload
+        .split_last_chunk::<8>()
+        .ok_or(PayloadError::Checksum)?;
+    let (mut input, version) = body.split_last_chunk::<2>().ok_or(PayloadError::Checksum)?;
+    if u16::from_le_bytes(*version) > HASH_METADATA_VERSION
+        || u64::from_le_bytes(*crc) != crc64(body)
+    {
+        return Err(PayloadError::Checksum);
+    }
+
+    let object = match input.split_first() {
+        Some((&TYPE_HASH_METADATA, rest)) => {
+            input = rest;
+            read_hash_metadata(&mut input).map(|hash| Object::Hash(Arc::new(hash)))
+        }
+        _ => read_value(&mut input).map(Object::from),
+    };
+    let object = object.ok_or(PayloadError::Format)?;
+    if !input.is_empty() {
+        return Err(PayloadError::Format);
+    }
+    Ok(object)
+}
+
+/// reads what [`write_hash_metadata`] writes
+fn read_hash_metadata(input: &mut &[u8]) -> Option<Hash> {
+    let min = u128::from(u64::from_le_bytes(take_array(input)?));
+    let len = read_collection_length(input)?;
+    let mut hash = Hash::default();
+    for _ in 0..len {
+        let ttl = read_plain_length(input)?;
+        let field = Value::bytes(read_string(input)?);
+        let value = Value::bytes(read_string(input)?);
+        if hash.insert(field.clone(), value).is_some() {
+            return None;
+        }
+        if ttl > 0 {
+            hash.set_expiry(&field, Some(min + u128::from(ttl) - 1));
+        }
+    }
+    Some(hash)
+}
+
+/// Reads a value and its type byte from the start of `input`.
+///
+/// Like Redis, this rejects empty collections, which the keyspace never
+/// holds, and sets and hashes that repeat a member or field.
+fn read_value(input: &mut &[u8]) -> Option<Value> {
+    let [kind] = take_array(input)?;
+    let value = match kind {
+        TYPE_STRING => Value::bytes(read_string(input)?),
+        TYPE_LIST | TYPE_SET => {
+            let len = read_collection_length(input)?;
+            let mut items = Vec::with_capacity(len.min(4096) as usize);
+            for _ in 0..len {
+                items.push(Value::bytes(read_string(input)?));
+            }
+            if kind == TYPE_LIST {
+                Value::Array(Some(items))
+            } else if all_distinct(items.iter()) {
+                Value::Set(items)
+            } else {
+                return None;
+            }
+        }
+        TYPE_HASH => {
+            let len = read_collection_length(input)?;
+            let mut entries = Vec::with_capacity(len.min(4096) as usize);
+            for _ in 0..len {
+                let field = Value::bytes(read_string(input)?);
+                entries.push((field, Value::bytes(read_string(input)?)));
+            }
+            if !all_distinct(entries.iter().map(|(field, _)| field)) {
+                return None;
+            }
+            Value::Map(Map::from_distinct(entries))
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// the number of elements of a list, set or hash, which can't be 0
+fn read_collection_length(input: &mut &[u8]) -> Option<u64> {
+    read_plain_length(input).filter(|len| *len > 0)
+}
+
+fn all_distinct<'a>(items: impl ExactSizeIterator<Item = &'a Value>) -> bool {
+    let len = items.len();
+    items.collect::<HashSet<_>>().len() == len
+}
+
+/// A length as [`write_length`] writes it. Strings may have the byte of a
+/// special encoding in its place instead.
+enum Length {
+    Plain(u64),
+    Encoded(u8),
+}
+
+fn read_length(input: &mut &[u8]) -> Option<Length> {
+    let [first] = take_array(input)?;
+    let len = match first >> 6 {
+        0 => u64::from(first),
+        1 => u16::from_be_bytes([first & 0x3f, take_array::<1>(input)?[0]]).into(),
+        2 if first == 0x80 => u32::from_be_bytes(take_array(input)?).into(),
+        2 if first == 0x81 => u64::from_be_bytes(take_array(input)?),
+        3 => return Some(Length::Encoded(first)),
+        _ => return None,
+    };
+    Some(Length::Plain(len))
+}
+
+fn read_plain_length(input: &mut &[u8]) -> Option<u64> {
+    match read_length(input)? {
+        Length::Plain(len) => Some(len),
+        Length::Encoded(_) => None,
+    }
+}
+
+fn read_string(input: &mut &[u8]) -> Option<Vec<u8>> {
+    let s = match read_length(input)? {
+        Length::Plain(len) => take_bytes(input, usize::try_from(len).ok()?)?.to_vec(),
+        Length::Encoded(ENC_INT8) => i8::from_le_bytes(take_array(input)?).to_string().into(),
+        Length::Encoded(ENC_INT16) => i16::from_le_bytes(take_array(input)?).to_string().into(),
+        Length::Encoded(ENC_INT32) => i32::from_le_bytes(take_array(input)?).to_string().into(),
+        Length::Encoded(ENC_LZF) => {
+            let compressed = usize::try_from(read_plain_length(input)?).ok()?;
+            let len = usize::try_from(read_plain_length(input)?).ok()?;
+            lzf_decompress(take_bytes(input, compressed)?, len)?
+        }
+        Length::Encoded(_) => return None,
+    };
+    Some(s)
+}
+
+/// Undoes the LZF compression Redis uses for longer strings, `len` is the
+/// length of the result.
+fn lzf_decompress(mut input: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    while let Some((&control, rest)) = input.split_first() {
+        input = rest;
+        if control < 1 << 5 {
+            // a run of literal bytes
+            out.extend_from_slice(take_bytes(&mut input, usize::from(control) + 1)?);
+        } else {
+            // a copy of earlier output, which may overlap what it writes
+            let mut run = usize::from(control >> 5);
+            if run == 7 {
+                run += usize::from(take_array::<1>(&mut input)?[0]);
+            }
+            let back =
+                (usize::from(control & 0x1f) << 8) + usize::from(take_array::<1>(&mut input)?[0]);
+            let start = out.len().checked_sub(back + 1)?;
+            for i in start..start + run + 2 {
+                out.push(out[i]);
+            }
+        }
+        if out.len() > len {
+            return None;
+        }
+    }
+    (out.len() == len).then_some(out)
+}
+
+fn take_bytes<'a>(input: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if input.len() < n {
+        return None;
+    }
+    let (head, rest) = input.split_at(n);
+    *input = rest;
+    Some(head)
+}
+
+fn take_array<const N: usize>(input: &mut &[u8]) -> Option<[u8; N]> {
+    take_bytes(input, N)?.try_into().ok()
+}
+
+/// the Jones polynomial Redis uses for CRC64, reflected
+const CRC64_POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+
+const CRC64_TABLE: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC64_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// the CRC64 Redis checks DUMP payloads and rdb files with
+pub(crate) fn crc64(data: &[u8]) -> u64 {
+    data.iter().fold(0, |crc, byte| {
+        CRC64_TABLE[((crc ^ u64::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// a writer that only counts the bytes written to it
+struct Counter(usize);
+
+impl Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(value: &Value) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_value(&mut out, value).expect("failed to encode");
+        assert_eq!(serialized_length(value).unwrap(), out.len());
+        out
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(encode(&Value::str("bar")), b"\x03bar");
+        assert_eq!(encode(&Value::str("")), b"\x00");
+        let long = "a".repeat(100);
+        assert_eq!(&encode(&Value::str(&long))[..2], [0x40, 100]);
+        let longer = "a".repeat(20000);
+        assert_eq!(&encode(&Value::str(&longer))[..5], [0x80, 0, 0, 0x4e, 0x20]);
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(encode(&Value::Int(12)), [0xc0, 12]);
+        assert_eq!(encode(&Value::str("-2")), [0xc0, 0xfe]);
+        assert_eq!(encode(&Value::Int(1000)), [0xc1, 0xe8, 0x03]);
+        assert_eq!(encode(&Value::Int(100_000)), [0xc2, 0xa0, 0x86, 0x01, 0x00]);
+        assert_eq!(encode(&Value::Int(1 << 40)), b"\x0d1099511627776");
+        assert_eq!(encode(&Value::Bool(true)), [0xc0, 1]);
+        // not the canonical representation, has to stay a string
+        assert_eq!(encode(&Value::str("012")), b"\x03012");
+    }
+
+    #[test]
+    fn collections() {
+        let list = Value::Array(Some(vec![Value::str("a"), Value::Int(1)]));
+        assert_eq!(value_type(&list).unwrap(), TYPE_LIST);
+        assert_eq!(encode(&list), b"\x02\x01a\xc0\x01");
+
+        let hash = Value::Map(Map::from([(Value::str("k"), Value::str("v"))]));
+        assert_eq!(value_type(&hash).unwrap(), TYPE_HASH);
+        assert_eq!(encode(&hash), b"\x01\x01k\x01v");
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc64(b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+    }
+
+    #[test]
+    fn dump_restore() {
+        let values = [
+            Value::str("bar"),
+            Value::str("-12345678"),
+            Value::str(&"a".repeat(20000)),
+            Value::Array(Some(vec![Value::str("a"), Value::str("1")])),
+            Value::Set(vec![Value::str("x")]),
+            Value::Map(Map::from([(Value::str("k"), Value::str("v"))])),
+        ];
+        for value in values {
+            let payload = dump(&value.clone().into()).unwrap();
+            assert_eq!(*restore(&payload).unwrap().to_value(), value);
+        }
+
+        // SET mykey 10, DUMP mykey, from the Redis docs
+        let redis = b"\x00\xc0\n\t\x00\xbem\x06\x89Z(\x00\n";
+        assert_eq!(*restore(redis).unwrap().to_value(), Value::str("10"));
+
+        let mut payload = dump(&Value::str("bar").into()).unwrap();
+        let last = payload.len() - 1;
+        payload[last] ^= 1;
+        assert!(matches!(restore(&payload), Err(PayloadError::Checksum)));
+        assert!(matches!(restore(b"\x00"), Err(PayloadError::Checksum)));
+
+        let newer = |body: &[u8]| {
+            let mut payload = body.to_vec();
+            payload.extend_from_slice(&(HASH_METADATA_VERSION + 1).to_le_bytes());
+            let crc = crc64(&payload);
+            payload.extend_from_slice(&crc.to_le_bytes());
+            payload
+        };
+        assert!(matches!(
+            restore(&newer(b"\x00\x03bar")),
+            Err(PayloadError::Checksum)
+        ));
+
+        let sealed = |body: &[u8]| {
+            let mut payload = body.to_vec();
+            payload.extend_from_slice(&DUMP_VERSION.to_le_bytes());
+            let crc = crc64(&payload);
+            payload.extend_from_slice(&crc.to_le_bytes());
+            payload
+        };
+        for body in [
+            &b"\x00\x03ba"[..],
+            b"\x00\x03barr",
+            b"\x05\x03bar",
+            // a list of 2^32 - 1 elements, with one there
+            b"\x01\x80\xff\xff\xff\xff\x01a",
+            // empty collections
+            b"\x01\x00",
+            b"\x02\x00",
+            b"\x04\x00",
+            // a set with a member twice, a hash with a field twice
+            b"\x02\x02\x01a\x01a",
+            b"\x04\x02\x01f\x01a\x01f\x01b",
+        ] {
+            assert!(
+                matches!(restore(&sealed(body)), Err(PayloadError::Format)),
+                "{body:?}"
+            );
+        }
+
+        // "a" and a run of 9 more copied from it
+        let lzf = sealed(b"\x00\xc3\x05\x0a\x00a\xe0\x00\x00");
+        assert_eq!(*restore(&lzf).unwrap().to_value(), Value::str("aaaaaaaaaa"));
+        let too_short = sealed(b"\x00\xc3\x05\x0b\x00a\xe0\x00\x00");
+        assert!(matches!(restore(&too_short), Err(PayloadError::Format)));
+    }
+
+    #[test]
+    fn field_ttls() {
+        let mut hash: Hash = [("a", "1"), ("b", "2"), ("c", "3")]
+            .into_iter()
+            .map(|(f, v)| (Value::str(f), Value::str(v)))
+            .collect();
+        hash.set_expiry(&Value::str("a"), Some(1_700_000_000_500));
+        hash.set_expiry(&Value::str("c"), Some(1_700_000_000_000));
+        let object = Object::Hash(Arc::new(hash));
+        let payload = dump(&object).unwrap();
+        assert_eq!(payload[0], TYPE_HASH_METADATA);
+        let restored = restore(&payload).unwrap();
+        assert_eq!(restored, object);
+        let hash = restored.as_hash().unwrap();
+        assert_eq!(hash.expiry(&Value::str("a")), Some(1_700_000_000_500));
+        assert_eq!(hash.expiry(&Value::str("b")), None);
+
+        // without field ttls a hash is written like any other
+        let plain = Object::from(Value::Map(Map::from([(Value::str("k"), Value::str("v"))])));
+        assert_eq!(dump(&plain).unwrap()[0], TYPE_HASH);
+    }
+
+    #[test]
+    fn unencodable() {
+        assert!(write_value(&mut Vec::new(), &Value::Null).is_err());
+        let nested = Value::Array(Some(vec![Value::Array(Some(vec![]))]));
+        assert!(serialized_length(&nested).is_err());
+    }
+}
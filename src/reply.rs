@@ -0,0 +1,451 @@
+//! Reply types for RESP frames that have no counterpart in serde's data model.
+//!
+//! Each type serializes as a newtype struct with a private name that the
+//! [`Serializer`](crate::serializer::Serializer) and
+//! [`Deserializer`](crate::deserializer::Deserializer) recognize; other serde
+//! formats just see the inner value.
+
+use std::{collections::BTreeMap, fmt};
+
+use serde::{de, ser::SerializeTupleStruct, Deserialize, Serialize};
+
+use crate::value::Value;
+
+pub(crate) const ERROR_TOKEN: &str = "$redis::private::Error";
+pub(crate) const VERBATIM_TOKEN: &str = "$redis::private::Verbatim";
+pub(crate) const SET_TOKEN: &str = "$redis::private::Set";
+pub(crate) const PUSH_TOKEN: &str = "$redis::private::Push";
+pub(crate) const ATTRIBUTE_TOKEN: &str = "$redis::private::Attribute";
+pub(crate) const BIG_NUMBER_TOKEN: &str = "$redis::private::BigNumber";
+pub(crate) const SIMPLE_STRING_TOKEN: &str = "$redis::private::SimpleString";
+pub(crate) const NULL_ARRAY_TOKEN: &str = "$redis::private::NullArray";
+/// asks the deserializer to tag frames [`Value`] needs to tell apart, see
+/// [`Frame::Value`]
+pub(crate) const VALUE_TOKEN: &str = "$redis::private::Value";
+
+/// The special frames the serializer knows how to write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Frame {
+    Error,
+    Verbatim,
+    Set,
+    Push,
+    Attribute,
+    BigNumber,
+    SimpleString,
+    /// Not a frame of its own: frames that serde has no type for (errors,
+    /// simple and verbatim strings, big numbers, sets, pushes and null
+    /// arrays) are handed to the
+    /// visitor as an enum variant named after the frame, so a [`Value`] can
+    /// be read back without losing its kind.
+    Value,
+}
+
+impl Frame {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            ERROR_TOKEN => Some(Self::Error),
+            VERBATIM_TOKEN => Some(Self::Verbatim),
+            SET_TOKEN => Some(Self::Set),
+            PUSH_TOKEN => Some(Self::Push),
+            ATTRIBUTE_TOKEN => Some(Self::Attribute),
+            BIG_NUMBER_TOKEN => Some(Self::BigNumber),
+            SIMPLE_STRING_TOKEN => Some(Self::SimpleString),
+            VALUE_TOKEN => Some(Self::Value),
+            _ => None,
+        }
+    }
+}
+
+/// An error reply, sent as `-CODE message\r\n`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RespError {
+    pub code: String,
+    pub message: String,
+}
+
+impl RespError {
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            code: code.into(),
+            message: message.into(),
+        }
+    }
+
+    /// splits a raw error line into its code (the first word) and message
+    pub fn parse(line: &str) -> Self {
+        let (code, message) = line.split_once(' ').unwrap_or((line, ""));
+        Self::new(code, message)
+    }
+}
+
+impl fmt::Display for RespError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "{}", self.code)
+        } else {
+            write!(f, "{} {}", self.code, self.message)
+        }
+    }
+}
+
+impl std::error::Error for RespError {}
+
+impl Serialize for RespError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_newtype_struct(ERROR_TOKEN, &self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for RespError {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct ErrorVisitor;
+
+        impl<'de> de::Visitor<'de> for ErrorVisitor {
+            type Value = RespError;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an error reply")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(RespError::parse(v))
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                let v = std::str::from_utf8(v).map_err(E::custom)?;
+                self.visit_str(v)
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                deserializer.deserialize_str(self)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(ERROR_TOKEN, ErrorVisitor)
+    }
+}
+
+/// A verbatim string, sent as `=len\r\nfmt:text\r\n` on RESP3 connections
+/// and as a plain bulk string of `text` on RESP2 ones.
+///
+/// `format` is a three byte hint for how to display the text, e.g. `txt` or
+/// `mkd`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Verbatim<'a> {
+    pub format: [u8; 3],
+    pub text: &'a str,
+}
+
+impl<'a> Verbatim<'a> {
+    pub fn new(format: [u8; 3], text: &'a str) -> Self {
+        Self { format, text }
+    }
+
+    pub fn txt(text: &'a str) -> Self {
+        Self::new(*b"txt", text)
+    }
+}
+
+impl Serialize for Verbatim<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut payload = Vec::with_capacity(self.text.len() + 4);
+        payload.extend_from_slice(&self.format);
+        payload.push(b':');
+        payload.extend_from_slice(self.text.as_bytes());
+        serializer.serialize_newtype_struct(VERBATIM_TOKEN, &Payload(&payload))
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Verbatim<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct VerbatimVisitor;
+
+        impl<'de> de::Visitor<'de> for VerbatimVisitor {
+            type Value = Verbatim<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a verbatim string")
+            }
+
+            fn visit_borrowed_bytes<E>(self, v: &'de [u8]) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                let [a, b, c, b':', text @ ..] = v else {
+                    return Err(E::invalid_value(de::Unexpected::Bytes(v), &self));
+                };
+                let text = std::str::from_utf8(text).map_err(E::custom)?;
+                Ok(Verbatim::new([*a, *b, *c], text))
+            }
+
+            fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                self.visit_borrowed_bytes(v.as_bytes())
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                deserializer.deserialize_bytes(self)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(VERBATIM_TOKEN, VerbatimVisitor)
+    }
+}
+
+/// A collection sent as a set (`~N\r\n...`) on RESP3 connections and as a
+/// plain array on RESP2 ones, e.g. `Set(&members)` for a `HashSet`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Set<T>(pub T);
+
+impl<T> Serialize for Set<T>
+where
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_newtype_struct(SET_TOKEN, &self.0)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Set<T>
+where
+    T: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(Set)
+    }
+}
+
+/// An out-of-band message (pub/sub, client tracking), sent as a push frame
+/// (`>N\r\n...`) on RESP3 connections and as a plain array on RESP2 ones.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Push<T>(pub T);
+
+impl<T> Serialize for Push<T>
+where
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_newtype_struct(PUSH_TOKEN, &self.0)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Push<T>
+where
+    T: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(Push)
+    }
+}
+
+/// A status reply, sent as `+text\r\n`.
+///
+/// Simple strings can't contain line breaks, they are replaced with spaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SimpleString<'a>(pub &'a str);
+
+impl Serialize for SimpleString<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_newtype_struct(SIMPLE_STRING_TOKEN, &Payload(self.0.as_bytes()))
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for SimpleString<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct SimpleStringVisitor;
+
+        impl<'de> de::Visitor<'de> for SimpleStringVisitor {
+            type Value = SimpleString<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a simple string")
+            }
+
+            fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(SimpleString(v))
+            }
+
+            fn visit_borrowed_bytes<E>(self, v: &'de [u8]) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                let v = std::str::from_utf8(v).map_err(E::custom)?;
+                self.visit_borrowed_str(v)
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                deserializer.deserialize_str(self)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(SIMPLE_STRING_TOKEN, SimpleStringVisitor)
+    }
+}
+
+/// A null array, sent as `*-1\r\n`. It's how RESP2 tells a missing array
+/// apart from a missing string (`$-1\r\n`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct NullArray;
+
+impl Serialize for NullArray {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_unit_struct(NULL_ARRAY_TOKEN)
+    }
+}
+
+/// An integer of arbitrary size, sent as `(digits\r\n` on RESP3 connections
+/// and as a bulk string on RESP2 ones.
+///
+/// The digits aren't checked, they have to be an optional `-` followed by
+/// decimal digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BigNumber<'a>(pub &'a str);
+
+impl Serialize for BigNumber<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_newtype_struct(BIG_NUMBER_TOKEN, &Payload(self.0.as_bytes()))
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for BigNumber<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct BigNumberVisitor;
+
+        impl<'de> de::Visitor<'de> for BigNumberVisitor {
+            type Value = BigNumber<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a big number")
+            }
+
+            fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(BigNumber(v))
+            }
+
+            fn visit_borrowed_bytes<E>(self, v: &'de [u8]) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                let v = std::str::from_utf8(v).map_err(E::custom)?;
+                self.visit_borrowed_str(v)
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                deserializer.deserialize_str(self)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(BIG_NUMBER_TOKEN, BigNumberVisitor)
+    }
+}
+
+/// A reply decorated with out-of-band metadata (e.g. key popularity or ttl
+/// hints), sent as an attribute map (`|N\r\n...`) followed by `value` on
+/// RESP3 connections. RESP2 has no attributes, there only `value` is sent.
+///
+/// Unlike the other types here this serializes as a tuple struct of the
+/// attributes and the value.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct WithAttributes<T> {
+    pub attrs: BTreeMap<Value, Value>,
+    pub value: T,
+}
+
+impl<T> WithAttributes<T> {
+    pub fn new(attrs: BTreeMap<Value, Value>, value: T) -> Self {
+        Self { attrs, value }
+    }
+}
+
+impl<T> Serialize for WithAttributes<T>
+where
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut tuple = serializer.serialize_tuple_struct(ATTRIBUTE_TOKEN, 2)?;
+        tuple.serialize_field(&self.attrs)?;
+        tuple.serialize_field(&self.value)?;
+        tuple.end()
+    }
+}
+
+/// serializes a byte slice with `serialize_bytes` rather than as a sequence
+struct Payload<'a>(&'a [u8]);
+
+impl Serialize for Payload<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
@@ -0,0 +1,126 @@
+//! Reply buffers a connection reuses.
+//!
+//! Every reply is serialized into a `Vec<u8>`, and a busy connection would
+//! allocate and free one per command, a large one for every large reply.
+//! Instead the connection hands each buffer back once the reply is written
+//! and the next reply is serialized into it. Buffers that grew past
+//! [`KEEP_CAPACITY`] are shrunk back to it first, so one huge reply doesn't
+//! pin its memory for the life of the connection.
+
+/// the most a buffer keeps allocated while it waits in the pool
+pub(crate) const KEEP_CAPACITY: usize = 64 * 1024;
+
+/// how many buffers wait in the pool at most, replies are written one at a
+/// time so there is rarely more than one
+const MAX_FREE: usize = 4;
+
+/// How well the pool works, over the buffers handed out and back since the
+/// stats were last taken.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PoolStats {
+    /// replies that got a buffer from the pool
+    pub hits: u64,
+    /// replies that needed a new buffer
+    pub misses: u64,
+    /// the largest reply handed back, in bytes
+    pub peak: usize,
+}
+
+impl PoolStats {
+    pub fn add(&mut self, other: PoolStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.peak = self.peak.max(other.peak);
+    }
+
+    /// the share of replies that reused a buffer, 0 before the first one
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ReplyPool {
+    free: Vec<Vec<u8>>,
+    stats: PoolStats,
+}
+
+impl ReplyPool {
+    /// an empty buffer for the next reply
+    pub fn take(&mut self) -> Vec<u8> {
+        match self.free.pop() {
+            Some(buf) => {
+                self.stats.hits += 1;
+                buf
+            }
+            None => {
+                self.stats.misses += 1;
+                Vec::new()
+            }
+        }
+    }
+
+    /// hands back a buffer once the reply in it has been written
+    pub fn recycle(&mut self, mut buf: Vec<u8>) {
+        self.stats.peak = self.stats.peak.max(buf.len());
+        if self.free.len() >= MAX_FREE || buf.capacity() == 0 {
+            return;
+        }
+        buf.clear();
+        buf.shrink_to(KEEP_CAPACITY);
+        self.free.push(buf);
+    }
+
+    /// the stats since they were last taken
+    pub fn take_stats(&mut self) -> PoolStats {
+        std::mem::take(&mut self.stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuse() {
+        let mut pool = ReplyPool::default();
+        let mut buf = pool.take();
+        buf.extend_from_slice(b"+OK\r\n");
+        let ptr = buf.as_ptr();
+        pool.recycle(buf);
+
+        let buf = pool.take();
+        assert!(buf.is_empty());
+        assert_eq!(buf.as_ptr(), ptr);
+        pool.recycle(buf);
+
+        // huge buffers are shrunk before they're kept
+        let mut huge = pool.take();
+        huge.resize(KEEP_CAPACITY * 4, 0);
+        pool.recycle(huge);
+        let buf = pool.take();
+        assert!(buf.capacity() <= KEEP_CAPACITY);
+
+        let stats = pool.take_stats();
+        assert_eq!(
+            stats,
+            PoolStats {
+                hits: 3,
+                misses: 1,
+                peak: KEEP_CAPACITY * 4
+            }
+        );
+        assert_eq!(stats.hit_rate(), 0.75);
+        assert_eq!(pool.take_stats(), PoolStats::default());
+
+        // buffers that were never allocated aren't worth keeping
+        pool.recycle(buf);
+        pool.recycle(Vec::new());
+        pool.take();
+        pool.take();
+        assert_eq!(pool.take_stats().misses, 1);
+    }
+}
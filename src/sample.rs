@@ -0,0 +1,74 @@
+//! Random picks for HRANDFIELD and the other commands that reply with random
+//! members of a collection.
+//!
+//! They all share the meaning of the count: a positive one asks for that many
+//! distinct members, at most all of them, a negative one for that many picks
+//! that may repeat. The commands cap negative counts, see
+//! `random-reply-limit`, since nothing else bounds the reply to those.
+
+use std::collections::HashSet;
+
+/// Indices into a collection of `len` members for a pick of `count`, in
+/// random order, except that a pick of all of them comes in the order of the
+/// collection like it does in Redis.
+///
+/// Distinct picks shuffle the indices when they want most of them, and
+/// otherwise draw until they have enough, so a small pick from a large
+/// collection doesn't allocate an index for every member.
+pub(crate) fn sample(len: usize, count: i64) -> Vec<usize> {
+    if len == 0 {
+        return vec![];
+    }
+    let Ok(count) = usize::try_from(count) else {
+        let count = count.unsigned_abs() as usize;
+        return (0..count).map(|_| fastrand::usize(..len)).collect();
+    };
+    if count >= len {
+        return (0..len).collect();
+    }
+    if count.saturating_mul(3) > len {
+        // the first `count` steps of a Fisher-Yates shuffle
+        let mut indices: Vec<usize> = (0..len).collect();
+        for i in 0..count {
+            indices.swap(i, fastrand::usize(i..len));
+        }
+        indices.truncate(count);
+        return indices;
+    }
+    let mut seen = HashSet::with_capacity(count);
+    let mut indices = Vec::with_capacity(count);
+    while indices.len() < count {
+        let i = fastrand::usize(..len);
+        if seen.insert(i) {
+            indices.push(i);
+        }
+    }
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts() {
+        for (len, count) in [(10, 3), (10, 5), (10, 9), (1000, 7)] {
+            let picked = sample(len, count);
+            assert_eq!(picked.len(), count as usize);
+            let distinct: HashSet<_> = picked.iter().collect();
+            assert_eq!(distinct.len(), picked.len(), "{len} {count}");
+            assert!(picked.iter().all(|&i| i < len));
+        }
+        assert_eq!(sample(3, 10), [0, 1, 2]);
+        assert_eq!(sample(3, 3), [0, 1, 2]);
+        assert!(sample(3, 0).is_empty());
+        assert!(sample(0, 5).is_empty());
+        assert!(sample(0, -5).is_empty());
+
+        // negative counts repeat members to get as many as asked for
+        let picked = sample(2, -50);
+        assert_eq!(picked.len(), 50);
+        assert!(picked.iter().all(|&i| i < 2));
+        assert_eq!(sample(1, -3), [0, 0, 0]);
+    }
+}
@@ -2,18 +2,106 @@ use std::io::Write;
 
 use serde::{ser, Serialize};
 
+use crate::{
+    compression,
+    float::format_double,
+    reply::{Frame, NULL_ARRAY_TOKEN},
+    value::Value,
+};
+
+/// The RESP version a peer speaks.
+///
+/// RESP3 added frame types (booleans, doubles, maps, nulls, ...) that RESP2
+/// clients cannot parse, so in RESP2 mode those are downgraded to their
+/// closest RESP2 encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
 #[derive(Debug)]
 pub struct Serializer {
     output: Vec<u8>,
+    protocol: Protocol,
+    /// set while serializing the contents of a [`crate::reply`] type
+    frame: Option<Frame>,
+    /// bulk strings longer than this are compressed, see [`compression`]
+    compress_above: Option<usize>,
+}
+
+impl Serializer {
+    pub fn new(protocol: Protocol) -> Self {
+        Self::with_output(Vec::new(), protocol)
+    }
+
+    /// a serializer that appends to `output`
+    pub(crate) fn with_output(output: Vec<u8>, protocol: Protocol) -> Self {
+        Self {
+            output,
+            protocol,
+            frame: None,
+            compress_above: None,
+        }
+    }
+
+    /// compresses bulk strings longer than `threshold` bytes, see
+    /// [`compression`]
+    pub fn with_compression(mut self, threshold: usize) -> Self {
+        self.compress_above = Some(threshold);
+        self
+    }
+
+    pub fn resp2() -> Self {
+        Self::new(Protocol::Resp2)
+    }
+
+    pub fn resp3() -> Self {
+        Self::new(Protocol::Resp3)
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.output
+    }
 }
 
 pub fn to_bytes<T>(value: &T) -> Result<Vec<u8>, Error>
 where
     T: Serialize,
 {
-    let mut serializer = Serializer { output: Vec::new() };
+    to_bytes_with_protocol(value, Protocol::Resp3)
+}
+
+pub fn to_bytes_with_protocol<T>(value: &T, protocol: Protocol) -> Result<Vec<u8>, Error>
+where
+    T: Serialize,
+{
+    let mut serializer = Serializer::new(protocol);
     value.serialize(&mut serializer)?;
-    Ok(serializer.output)
+    Ok(serializer.into_inner())
+}
+
+/// Encodes a request the way clients send them: an array of bulk strings
+/// holding the command name followed by its arguments.
+///
+/// ```
+/// let req = redis::serializer::encode_command("SET", ["key", "value"]);
+/// assert_eq!(req, b"*3\r\n$3\r\nSET\r\n$3\r\nkey\r\n$5\r\nvalue\r\n");
+/// ```
+pub fn encode_command<I>(name: &str, args: I) -> Vec<u8>
+where
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+{
+    let args: Vec<_> = args.into_iter().collect();
+    let mut output = format!("*{}\r\n", args.len() + 1).into_bytes();
+    for arg in std::iter::once(name.as_bytes()).chain(args.iter().map(AsRef::as_ref)) {
+        output.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        output.extend_from_slice(arg);
+        output.extend_from_slice(b"\r\n");
+    }
+    output
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -22,10 +110,6 @@ pub enum Error {
     Custom(String),
     #[error("error writing: {0}")]
     IoError(std::io::Error),
-    #[error("integer was too large")]
-    IntOverflow(u64),
-    #[error("tried to serialize unsized sequence")]
-    LengthRequired,
 }
 
 impl ser::Error for Error {
@@ -37,18 +121,219 @@ impl ser::Error for Error {
     }
 }
 
+impl Serializer {
+    /// picks the header for an array-like frame, taking a pending
+    /// [`crate::reply`] frame into account
+    fn seq_kind(&mut self) -> char {
+        match self.frame.take() {
+            Some(Frame::Set) if self.protocol == Protocol::Resp3 => '~',
+            Some(Frame::Push) if self.protocol == Protocol::Resp3 => '>',
+            _ => '*',
+        }
+    }
+
+    /// picks the header for a map frame and how many elements each entry
+    /// accounts for
+    fn map_kind(&mut self) -> (char, usize) {
+        if self.frame.take() == Some(Frame::Attribute) {
+            return ('|', 1);
+        }
+        match self.protocol {
+            // RESP2 has no maps, send the pairs as a flat array instead
+            Protocol::Resp2 => ('*', 2),
+            Protocol::Resp3 => ('%', 1),
+        }
+    }
+
+    /// writes a frame that ends at the first line break, so `line` can't
+    /// contain any
+    fn write_line(&mut self, kind: u8, line: &[u8]) -> Result<(), Error> {
+        self.output.push(kind);
+        self.output.extend(
+            line.iter()
+                .map(|&b| if b == b'\r' || b == b'\n' { b' ' } else { b }),
+        );
+        write!(self.output, "\r\n").map_err(Error::IoError)
+    }
+
+    fn write_header(&mut self, kind: char, len: usize) -> Result<(), Error> {
+        write!(self.output, "{kind}{len}\r\n").map_err(Error::IoError)
+    }
+
+    fn write_blob(&mut self, kind: char, blob: &[u8]) -> Result<(), Error> {
+        self.write_header(kind, blob.len())?;
+        self.output.write_all(blob).map_err(Error::IoError)?;
+        write!(self.output, "\r\n").map_err(Error::IoError)
+    }
+
+    /// writes a bulk string, compressed if that's enabled and pays off
+    fn write_bulk(&mut self, s: &[u8]) -> Result<(), Error> {
+        let Some(threshold) = self.compress_above else {
+            return self.write_blob('$', s);
+        };
+        // strings that look compressed have to be marked either way
+        let marked = s.starts_with(compression::MARKER);
+        if s.len() > threshold || marked {
+            let compressed = compression::compress(s);
+            if compressed.len() < s.len() || marked {
+                return self.write_blob('$', &compressed);
+            }
+        }
+        self.write_blob('$', s)
+    }
+
+    /// Writes a [`Value`] without going through serde, which is what the
+    /// server does for replies. The output is the same as serializing the
+    /// [`Value`].
+    pub(crate) fn write_value(&mut self, value: &Value) -> Result<(), Error> {
+        let resp3 = self.protocol == Protocol::Resp3;
+        match value {
+            Value::Int(i) => write!(self.output, ":{i}\r\n").map_err(Error::IoError),
+            Value::Bool(b) if resp3 => {
+                let b = if *b { "t" } else { "f" };
+                write!(self.output, "#{b}\r\n").map_err(Error::IoError)
+            }
+            Value::Bool(b) => write!(self.output, ":{}\r\n", *b as i64).map_err(Error::IoError),
+            Value::Double(d) if resp3 => {
+                write!(self.output, ",{}\r\n", format_double(d.0)).map_err(Error::IoError)
+            }
+            Value::Double(d) => self.write_bulk(format_double(d.0).as_bytes()),
+            Value::BigNumber(n) if resp3 => self.write_line(b'(', n.as_bytes()),
+            Value::BigNumber(n) => self.write_bulk(n.as_bytes()),
+            Value::String(Some(s)) => self.write_bulk(s),
+            Value::String(None) => write!(self.output, "$-1\r\n").map_err(Error::IoError),
+            Value::SimpleString(s) => self.write_line(b'+', s.as_bytes()),
+            Value::Verbatim { format, text } if resp3 => {
+                write!(self.output, "={}\r\n", text.len() + 4).map_err(Error::IoError)?;
+                self.output.extend_from_slice(format);
+                self.output.push(b':');
+                self.output.extend_from_slice(text.as_bytes());
+                write!(self.output, "\r\n").map_err(Error::IoError)
+            }
+            Value::Verbatim { text, .. } => self.write_bulk(text.as_bytes()),
+            Value::Error(e) => self.write_line(b'-', e.to_string().as_bytes()),
+            Value::Array(None) => write!(self.output, "*-1\r\n").map_err(Error::IoError),
+            Value::Array(Some(items)) => self.write_values('*', items),
+            Value::Set(items) => self.write_values(if resp3 { '~' } else { '*' }, items),
+            Value::Push(items) => self.write_values(if resp3 { '>' } else { '*' }, items),
+            Value::Map(map) => {
+                match self.protocol {
+                    // RESP2 has no maps, send the pairs as a flat array instead
+                    Protocol::Resp2 => self.write_header('*', map.len() * 2)?,
+                    Protocol::Resp3 => self.write_header('%', map.len())?,
+                }
+                for (k, v) in map {
+                    self.write_value(k)?;
+                    self.write_value(v)?;
+                }
+                Ok(())
+            }
+            Value::Null if resp3 => write!(self.output, "_\r\n").map_err(Error::IoError),
+            Value::Null => write!(self.output, "$-1\r\n").map_err(Error::IoError),
+        }
+    }
+
+    fn write_values(&mut self, kind: char, items: &[Value]) -> Result<(), Error> {
+        self.write_header(kind, items.len())?;
+        items.iter().try_for_each(|item| self.write_value(item))
+    }
+}
+
+/// Serializes sequences and maps.
+///
+/// When the length isn't known up front the header can't be written yet, so
+/// the elements are counted and the header is inserted in front of them once
+/// the collection ends.
+pub struct Compound<'a> {
+    ser: &'a mut Serializer,
+    pending: Option<PendingHeader>,
+    /// where the output should be cut off again once the collection ends,
+    /// for frames the protocol can't express
+    discard_from: Option<usize>,
+}
+
+struct PendingHeader {
+    position: usize,
+    kind: char,
+    /// how many frames each counted entry consists of
+    frames_per_entry: usize,
+    entries: usize,
+}
+
+impl<'a> Compound<'a> {
+    fn sized(ser: &'a mut Serializer) -> Self {
+        Self {
+            ser,
+            pending: None,
+            discard_from: None,
+        }
+    }
+
+    fn discarded(ser: &'a mut Serializer) -> Self {
+        let position = ser.output.len();
+        Self {
+            ser,
+            pending: None,
+            discard_from: Some(position),
+        }
+    }
+
+    fn counted(ser: &'a mut Serializer, kind: char, frames_per_entry: usize) -> Self {
+        let position = ser.output.len();
+        Self {
+            ser,
+            pending: Some(PendingHeader {
+                position,
+                kind,
+                frames_per_entry,
+                entries: 0,
+            }),
+            discard_from: None,
+        }
+    }
+
+    fn count_entry(&mut self) {
+        if let Some(pending) = &mut self.pending {
+            pending.entries += 1;
+        }
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        if let Some(position) = self.discard_from {
+            self.ser.output.truncate(position);
+            return Ok(());
+        }
+        let Some(pending) = self.pending else {
+            return Ok(());
+        };
+
+        let header = format!(
+            "{}{}\r\n",
+            pending.kind,
+            pending.entries * pending.frames_per_entry
+        );
+        self.ser
+            .output
+            .splice(pending.position..pending.position, header.into_bytes());
+        Ok(())
+    }
+}
+
 impl<'a> ser::Serializer for &'a mut Serializer {
     type Ok = ();
     type Error = Error;
-    type SerializeSeq = Self;
+    type SerializeSeq = Compound<'a>;
     type SerializeTuple = Self;
     type SerializeTupleStruct = Self;
     type SerializeTupleVariant = Self;
-    type SerializeMap = Self;
+    type SerializeMap = Compound<'a>;
     type SerializeStruct = Self;
     type SerializeStructVariant = Self;
 
     fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        if self.protocol == Protocol::Resp2 {
+            return self.serialize_i64(v.into());
+        }
         let s = if v { "t" } else { "f" };
         write!(self.output, "#{s}\r\n").map_err(Error::IoError)
     }
@@ -82,8 +367,28 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-        let v: i64 = v.try_into().map_err(|_| Error::IntOverflow(v))?;
-        self.serialize_i64(v)
+        self.serialize_u128(v.into())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok, Self::Error> {
+        if let Ok(v) = i64::try_from(v) {
+            return self.serialize_i64(v);
+        }
+        // too large for an integer frame
+        match self.protocol {
+            Protocol::Resp2 => self.serialize_str(&v.to_string()),
+            Protocol::Resp3 => write!(self.output, "({v}\r\n").map_err(Error::IoError),
+        }
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok, Self::Error> {
+        if let Ok(v) = i128::try_from(v) {
+            return self.serialize_i128(v);
+        }
+        match self.protocol {
+            Protocol::Resp2 => self.serialize_str(&v.to_string()),
+            Protocol::Resp3 => write!(self.output, "({v}\r\n").map_err(Error::IoError),
+        }
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
@@ -91,8 +396,9 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
-        if v.is_nan() {
-            return write!(self.output, ",nan\r\n").map_err(Error::IoError);
+        let v = format_double(v);
+        if self.protocol == Protocol::Resp2 {
+            return self.serialize_str(&v);
         }
         write!(self.output, ",{v}\r\n").map_err(Error::IoError)
     }
@@ -103,78 +409,115 @@ impl<'a> ser::Serializer for &'a mut Serializer {
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
         // if v.contains("\r\n") {
-        return self.serialize_bytes(v.as_bytes());
+        self.serialize_bytes(v.as_bytes())
         // }
         // write!(self.output, "+{v}\r\n").map_err(Error::IoError)
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
-        write!(self.output, "${}\r\n", v.len()).map_err(Error::IoError)?;
-        self.output.write_all(v).map_err(Error::IoError)?;
-        write!(self.output, "\r\n").map_err(Error::IoError)?;
-        Ok(())
+        match self.frame.take() {
+            Some(Frame::Error) => return self.write_line(b'-', v),
+            Some(Frame::SimpleString) => return self.write_line(b'+', v),
+            Some(Frame::Verbatim) if self.protocol == Protocol::Resp2 => {
+                // drop the `fmt:` prefix and send the text as a bulk string
+                return self.serialize_bytes(v.get(4..).unwrap_or_default());
+            }
+            Some(Frame::Verbatim) => {
+                write!(self.output, "={}\r\n", v.len()).map_err(Error::IoError)?;
+                self.output.write_all(v).map_err(Error::IoError)?;
+                return write!(self.output, "\r\n").map_err(Error::IoError);
+            }
+            Some(Frame::BigNumber) if self.protocol == Protocol::Resp3 => {
+                self.output.push(b'(');
+                self.output.write_all(v).map_err(Error::IoError)?;
+                return write!(self.output, "\r\n").map_err(Error::IoError);
+            }
+            _ => {}
+        }
+
+        self.write_bulk(v)
     }
 
     fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
         write!(self.output, "$-1\r\n").map_err(Error::IoError)
     }
 
-    fn serialize_some<T: ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error>
+    fn serialize_some<T>(self, value: &T) -> Result<Self::Ok, Self::Error>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
         value.serialize(self)
     }
 
     fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        if self.protocol == Protocol::Resp2 {
+            return self.serialize_none();
+        }
         write!(self.output, "_\r\n").map_err(Error::IoError)
     }
 
     fn serialize_unit_struct(self, name: &'static str) -> Result<Self::Ok, Self::Error> {
-        todo!()
+        if name == NULL_ARRAY_TOKEN {
+            return write!(self.output, "*-1\r\n").map_err(Error::IoError);
+        }
+        self.serialize_unit()
     }
 
     fn serialize_unit_variant(
         self,
-        name: &'static str,
-        variant_index: u32,
-        variant: &'static str,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
     ) -> Result<Self::Ok, Self::Error> {
         todo!()
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(
+    fn serialize_newtype_struct<T>(
         self,
         name: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
-        todo!()
+        let Some(frame) = Frame::from_name(name) else {
+            return value.serialize(self);
+        };
+
+        self.frame = Some(frame);
+        let res = value.serialize(&mut *self);
+        self.frame = None;
+        res
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(
+    fn serialize_newtype_variant<T>(
         self,
-        name: &'static str,
-        variant_index: u32,
-        variant: &'static str,
-        value: &T,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
         todo!()
     }
 
     fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
-        let len = len.ok_or(Error::LengthRequired)?;
-        write!(self.output, "*{len}\r\n").map_err(Error::IoError)?;
-        Ok(self)
+        let kind = self.seq_kind();
+        match len {
+            Some(len) => {
+                self.write_header(kind, len)?;
+                Ok(Compound::sized(self))
+            }
+            None => Ok(Compound::counted(self, kind, 1)),
+        }
     }
 
     fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
-        self.serialize_seq(Some(len))
+        let kind = self.seq_kind();
+        self.write_header(kind, len)?;
+        Ok(self)
     }
 
     fn serialize_tuple_struct(
@@ -182,23 +525,39 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         name: &'static str,
         len: usize,
     ) -> Result<Self::SerializeTupleStruct, Self::Error> {
-        todo!()
+        if Frame::from_name(name) == Some(Frame::Attribute) {
+            // the fields are the attribute map and the reply itself, which
+            // write their own headers
+            self.frame = Some(Frame::Attribute);
+            return Ok(self);
+        }
+        self.serialize_tuple(len)
     }
 
     fn serialize_tuple_variant(
         self,
-        name: &'static str,
-        variant_index: u32,
-        variant: &'static str,
-        len: usize,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
     ) -> Result<Self::SerializeTupleVariant, Self::Error> {
         todo!()
     }
 
     fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
-        let len = len.ok_or(Error::LengthRequired)?;
-        write!(self.output, "%{len}\r\n").map_err(Error::IoError)?;
-        Ok(self)
+        if self.frame == Some(Frame::Attribute) && self.protocol == Protocol::Resp2 {
+            // RESP2 has no attributes, drop them
+            self.frame = None;
+            return Ok(Compound::discarded(self));
+        }
+        let (kind, frames_per_entry) = self.map_kind();
+        match len {
+            Some(len) => {
+                self.write_header(kind, len * frames_per_entry)?;
+                Ok(Compound::sized(self))
+            }
+            None => Ok(Compound::counted(self, kind, frames_per_entry)),
+        }
     }
 
     fn serialize_struct(
@@ -206,124 +565,124 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         _name: &'static str,
         len: usize,
     ) -> Result<Self::SerializeStruct, Self::Error> {
-        self.serialize_map(Some(len))
+        let (kind, frames_per_entry) = self.map_kind();
+        self.write_header(kind, len * frames_per_entry)?;
+        Ok(self)
     }
 
     fn serialize_struct_variant(
         self,
-        name: &'static str,
-        variant_index: u32,
-        variant: &'static str,
-        len: usize,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
     ) -> Result<Self::SerializeStructVariant, Self::Error> {
         todo!()
     }
 }
 
-impl<'a> ser::SerializeSeq for &'a mut Serializer {
+impl ser::SerializeSeq for Compound<'_> {
     type Ok = ();
 
     type Error = Error;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
-        value.serialize(&mut **self)
+        self.count_entry();
+        value.serialize(&mut *self.ser)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+        self.finish()
     }
 }
 
-impl<'a> ser::SerializeTuple for &'a mut Serializer {
+impl ser::SerializeTuple for &mut Serializer {
     type Ok = ();
 
     type Error = Error;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
-        <Self as ser::SerializeSeq>::serialize_element(self, value)
+        value.serialize(&mut **self)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        <Self as ser::SerializeSeq>::end(self)
+        Ok(())
     }
 }
 
-impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
+impl ser::SerializeTupleStruct for &mut Serializer {
     type Ok = ();
 
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
-        <Self as ser::SerializeSeq>::serialize_element(self, value)
+        <Self as ser::SerializeTuple>::serialize_element(self, value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        <Self as ser::SerializeSeq>::end(self)
+        <Self as ser::SerializeTuple>::end(self)
     }
 }
 
-impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
+impl ser::SerializeTupleVariant for &mut Serializer {
     type Ok = ();
 
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
-        <Self as ser::SerializeSeq>::serialize_element(self, value)
+        <Self as ser::SerializeTuple>::serialize_element(self, value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        <Self as ser::SerializeSeq>::end(self)
+        <Self as ser::SerializeTuple>::end(self)
     }
 }
 
-impl<'a> ser::SerializeMap for &'a mut Serializer {
+impl ser::SerializeMap for Compound<'_> {
     type Ok = ();
 
     type Error = Error;
 
-    fn serialize_key<T: ?Sized>(&mut self, key: &T) -> Result<(), Self::Error>
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Self::Error>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
-        key.serialize(&mut **self)
+        self.count_entry();
+        key.serialize(&mut *self.ser)
     }
 
-    fn serialize_value<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
-        value.serialize(&mut **self)
+        value.serialize(&mut *self.ser)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+        self.finish()
     }
 }
 
-impl<'a> ser::SerializeStruct for &'a mut Serializer {
+impl ser::SerializeStruct for &mut Serializer {
     type Ok = ();
 
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(
-        &mut self,
-        key: &'static str,
-        value: &T,
-    ) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
         key.serialize(&mut **self)?;
         value.serialize(&mut **self)?;
@@ -335,18 +694,14 @@ impl<'a> ser::SerializeStruct for &'a mut Serializer {
     }
 }
 
-impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
+impl ser::SerializeStructVariant for &mut Serializer {
     type Ok = ();
 
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(
-        &mut self,
-        key: &'static str,
-        value: &T,
-    ) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, _key: &'static str, _value: &T) -> Result<(), Self::Error>
     where
-        T: serde::Serialize,
+        T: ?Sized + serde::Serialize,
     {
         todo!()
     }
@@ -355,3 +710,228 @@ impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
         todo!()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use crate::value::Value;
+
+    #[derive(Serialize)]
+    struct Unit;
+
+    #[derive(Serialize)]
+    struct Flattened {
+        a: i32,
+        #[serde(flatten)]
+        rest: BTreeMap<&'static str, i32>,
+    }
+
+    /// serializes the inner vec without telling the serializer its length
+    struct Unsized<T>(Vec<T>);
+
+    impl<T: Serialize> Serialize for Unsized<T> {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.0.iter().filter(|_| true))
+        }
+    }
+
+    macro_rules! case {
+        (RESP2, $name:ident, $v:expr, $expected:expr) => {
+            #[test]
+            fn $name() {
+                let out =
+                    to_bytes_with_protocol(&$v, Protocol::Resp2).expect("failed to serialize");
+                assert_eq!(String::from_utf8_lossy(&out), $expected);
+            }
+        };
+
+        ($name:ident, $v:expr, $expected:expr) => {
+            #[test]
+            fn $name() {
+                let out = to_bytes(&$v).expect("failed to serialize");
+                assert_eq!(String::from_utf8_lossy(&out), $expected);
+            }
+        };
+    }
+
+    #[test]
+    fn encode_command_bulk_strings() {
+        assert_eq!(encode_command("PING", [""; 0]), b"*1\r\n$4\r\nPING\r\n");
+        assert_eq!(
+            encode_command("SET", [b"k".as_slice(), b"\r\n\0"]),
+            b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$3\r\n\r\n\0\r\n"
+        );
+        assert_eq!(
+            encode_command("GET", vec![String::from("k")]),
+            b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n"
+        );
+    }
+
+    case!(ser_int, 17, ":17\r\n");
+    case!(ser_bool, true, "#t\r\n");
+    case!(ser_str, "hello", "$5\r\nhello\r\n");
+    case!(ser_none, None::<i32>, "$-1\r\n");
+    case!(ser_seq, vec![1, 2], "*2\r\n:1\r\n:2\r\n");
+    case!(ser_unit, (), "_\r\n");
+    case!(ser_unit_struct, Unit, "_\r\n");
+    case!(ser_map, BTreeMap::from([(1, true)]), "%1\r\n:1\r\n#t\r\n");
+    case!(ser_double, 1.5, ",1.5\r\n");
+    case!(ser_double_integral, 3.0, ",3\r\n");
+    case!(ser_double_inf, f64::INFINITY, ",inf\r\n");
+    case!(ser_double_neg_inf, f64::NEG_INFINITY, ",-inf\r\n");
+    case!(ser_double_nan, f64::NAN, ",nan\r\n");
+    case!(ser_double_neg_zero, -0.0, ",-0\r\n");
+    case!(ser_double_small, 0.0001, ",0.0001\r\n");
+    case!(ser_double_tiny, 0.00001, ",1e-05\r\n");
+    case!(ser_double_large, 1e16, ",10000000000000000\r\n");
+    case!(ser_double_huge, 1.5e300, ",1.5e+300\r\n");
+    case!(ser_double_f32, 0.5f32, ",0.5\r\n");
+    case!(ser_u64_small, 7u64, ":7\r\n");
+    case!(ser_u64_big, u64::MAX, "(18446744073709551615\r\n");
+    case!(
+        ser_i128,
+        i128::MIN,
+        "(-170141183460469231731687303715884105728\r\n"
+    );
+    case!(
+        ser_u128,
+        u128::MAX,
+        "(340282366920938463463374607431768211455\r\n"
+    );
+    case!(
+        ser_error,
+        crate::reply::RespError::new("ERR", "bad\r\nthing"),
+        "-ERR bad  thing\r\n"
+    );
+    case!(
+        ser_verbatim,
+        crate::reply::Verbatim::txt("Some string"),
+        "=15\r\ntxt:Some string\r\n"
+    );
+    case!(
+        RESP2,
+        resp2_verbatim,
+        crate::reply::Verbatim::new(*b"mkd", "# hi"),
+        "$4\r\n# hi\r\n"
+    );
+    case!(
+        ser_set,
+        crate::reply::Set(BTreeSet::from([1, 2])),
+        "~2\r\n:1\r\n:2\r\n"
+    );
+    case!(ser_plain_set, BTreeSet::from([1]), "*1\r\n:1\r\n");
+    case!(ser_unsized_seq, Unsized(vec![1, 2]), "*2\r\n:1\r\n:2\r\n");
+    case!(ser_unsized_empty, Unsized(Vec::<i32>::new()), "*0\r\n");
+    case!(
+        ser_unsized_nested,
+        Unsized(vec![Unsized(vec![1]), Unsized(vec![])]),
+        "*2\r\n*1\r\n:1\r\n*0\r\n"
+    );
+    case!(
+        ser_unsized_set,
+        crate::reply::Set(Unsized(vec![1])),
+        "~1\r\n:1\r\n"
+    );
+    case!(
+        ser_flatten,
+        Flattened {
+            a: 1,
+            rest: BTreeMap::from([("b", 2)])
+        },
+        "%2\r\n$1\r\na\r\n:1\r\n$1\r\nb\r\n:2\r\n"
+    );
+    case!(
+        ser_push,
+        crate::reply::Push(("message", "chan", 1)),
+        ">3\r\n$7\r\nmessage\r\n$4\r\nchan\r\n:1\r\n"
+    );
+    case!(
+        ser_attributes,
+        crate::reply::WithAttributes::new(
+            BTreeMap::from([(Value::str("ttl"), Value::Int(10))]),
+            "bar"
+        ),
+        "|1\r\n$3\r\nttl\r\n:10\r\n$3\r\nbar\r\n"
+    );
+    case!(
+        RESP2,
+        resp2_set,
+        crate::reply::Set(BTreeSet::from([1])),
+        "*1\r\n:1\r\n"
+    );
+    case!(
+        RESP2,
+        resp2_flatten,
+        Flattened {
+            a: 1,
+            rest: BTreeMap::new()
+        },
+        "*2\r\n$1\r\na\r\n:1\r\n"
+    );
+    case!(
+        RESP2,
+        resp2_push,
+        crate::reply::Push(vec![1]),
+        "*1\r\n:1\r\n"
+    );
+    case!(
+        RESP2,
+        resp2_attributes,
+        crate::reply::WithAttributes::new(
+            BTreeMap::from([(Value::str("ttl"), Value::Int(10))]),
+            (1, 2)
+        ),
+        "*2\r\n:1\r\n:2\r\n"
+    );
+    case!(
+        RESP2,
+        resp2_value_frames,
+        Value::Array(Some(vec![
+            Value::BigNumber("12345678901234567890123".into()),
+            Value::Set(vec![Value::Double(crate::value::Double(0.5))]),
+            Value::Verbatim {
+                format: *b"txt",
+                text: "hi".into()
+            },
+        ])),
+        "*3\r\n$23\r\n12345678901234567890123\r\n*1\r\n$3\r\n0.5\r\n$2\r\nhi\r\n"
+    );
+    case!(
+        RESP2,
+        resp2_value_nulls,
+        Value::Array(Some(vec![
+            Value::Null,
+            Value::String(None),
+            Value::Array(None),
+            Value::SimpleString("OK\r\n".into()),
+        ])),
+        "*4\r\n$-1\r\n$-1\r\n*-1\r\n+OK  \r\n"
+    );
+    case!(
+        RESP2,
+        resp2_error,
+        crate::reply::RespError::new("WRONGTYPE", "nope"),
+        "-WRONGTYPE nope\r\n"
+    );
+
+    case!(RESP2, resp2_bool, false, ":0\r\n");
+    case!(RESP2, resp2_double, 1.5, "$3\r\n1.5\r\n");
+    case!(RESP2, resp2_double_inf, f64::INFINITY, "$3\r\ninf\r\n");
+    case!(RESP2, resp2_unit, (), "$-1\r\n");
+    case!(
+        RESP2,
+        resp2_big_number,
+        u64::MAX,
+        "$20\r\n18446744073709551615\r\n"
+    );
+    case!(RESP2, resp2_unit_struct, Unit, "$-1\r\n");
+    case!(
+        RESP2,
+        resp2_map,
+        BTreeMap::from([(1, true)]),
+        "*2\r\n:1\r\n:1\r\n"
+    );
+}
@@ -1,7 +1,11 @@
-use std::sync::OnceLock;
+use std::{path::PathBuf, sync::OnceLock};
 
 use clap::Parser;
-use redis::{commands::App, deserializer::from_bytes, value::Value};
+use redis::{
+    commands::{App, Client, EXPIRE_CYCLE_INTERVAL},
+    deserializer,
+    value::Value,
+};
 use tokio::{
     io::AsyncWriteExt,
     net::{TcpListener, TcpStream},
@@ -9,48 +13,164 @@ use tokio::{
 
 static APP: OnceLock<App> = OnceLock::new();
 
-async fn handle_connection(mut socket: TcpStream) -> anyhow::Result<()> {
-    let app = APP.get().unwrap();
+/// Reads what the client sent next onto `input`, returns how much that is, 0
+/// once it hung up.
+///
+/// A command that takes more than one read is parsed again after each, so
+/// reads get larger along with `input` to keep that from adding up.
+async fn read(socket: &TcpStream, input: &mut Vec<u8>) -> std::io::Result<usize> {
     loop {
         socket.readable().await?;
 
-        let mut buf = [0; 4096];
-
-        match socket.try_read(&mut buf) {
-            Ok(0) => break,
-            Ok(n) => {
-                let v: Value = from_bytes(&buf[..n])?;
-                // println!("{v:?}");
-                let response = app.dispatch_command(v).await;
-                // println!("{response:?}");
-                // use std::io::Write;
-                // std::io::stderr().write_all(&ser)?;
-                socket.write_all(&response).await?;
-            }
+        input.reserve(input.len().max(4096));
+
+        match socket.try_read_buf(input) {
+            Ok(n) => return Ok(n),
             Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
                 continue;
             }
             Err(e) => {
-                return Err(e.into());
+                return Err(e);
             }
         }
     }
+}
+
+async fn handle_connection(mut socket: TcpStream) -> anyhow::Result<()> {
+    let app = APP.get().unwrap();
+    let mut client = Client::default();
+    client.addr = socket.peer_addr().ok();
+    // what the client sent, of which the first `parsed` bytes were run
+    let (mut pending, mut parsed) = (vec![], 0);
+    loop {
+        // pipelined commands can arrive in the same read, and a long one
+        // over several
+        let (v, used) = match Value::parse(&pending[parsed..]) {
+            Ok(next) => next,
+            Err(deserializer::Error::UnexpectedEof) => {
+                pending.drain(..parsed);
+                parsed = 0;
+                if read(&socket, &mut pending).await? == 0 {
+                    break;
+                }
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        parsed += used;
+        // Keep reading while the command runs, a blocked command waits for as
+        // long as it takes. One whose client hangs up is dropped, so it
+        // doesn't take what it waited for.
+        let hung_up = async {
+            while read(&socket, &mut pending).await? > 0 {}
+            std::io::Result::Ok(())
+        };
+        let response = tokio::select! {
+            biased;
+            response = app.dispatch_command(&mut client, v) => response,
+            res = hung_up => return Ok(res?),
+        };
+        socket.write_all(&response).await?;
+        client.recycle(response);
+    }
 
     Ok(())
 }
 
+/// Takes over the listening socket passed in by systemd socket activation
+/// (`LISTEN_FDS`/`LISTEN_PID`), if there is one. Since systemd keeps the socket
+/// open across restarts, no connections are refused while the server restarts.
+///
+/// Only the first socket is used. The variables are cleared either way so they
+/// don't leak into child processes.
+#[cfg(unix)]
+fn activated_listener() -> anyhow::Result<Option<std::net::TcpListener>> {
+    use std::os::fd::{FromRawFd, RawFd};
+
+    /// the first descriptor passed by systemd, see sd_listen_fds(3)
+    const LISTEN_FDS_START: RawFd = 3;
+
+    let pid = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|x| x.parse::<u32>().ok());
+    let fds = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|x| x.parse::<u32>().ok())
+        .unwrap_or(0);
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+
+    // the variables are meant for another process
+    if pid != Some(std::process::id()) || fds == 0 {
+        return Ok(None);
+    }
+
+    // SAFETY: with LISTEN_PID set to our pid, systemd passed ownership of the
+    // descriptors starting at LISTEN_FDS_START to us, and nothing else uses
+    // them
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    listener.set_nonblocking(true)?;
+    Ok(Some(listener))
+}
+
+#[cfg(not(unix))]
+fn activated_listener() -> anyhow::Result<Option<std::net::TcpListener>> {
+    Ok(None)
+}
+
 #[derive(clap::Parser)]
 struct Cli {
+    /// the port to listen on, unless systemd passes a socket
+    #[clap(long, default_value_t = 6379)]
+    port: u16,
     #[clap(long)]
     dir: Option<String>,
     #[clap(long)]
     dbfilename: Option<String>,
+    /// Experimental: when stopped, leave the keyspace in this file for the
+    /// next server, and on start take over what the last one left there.
+    #[clap(long)]
+    handoff: Option<PathBuf>,
+}
+
+/// resolves once the server is asked to stop, by SIGTERM or ctrl-c
+async fn stop_requested() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            _ = terminate.recv() => Ok(()),
+            res = tokio::signal::ctrl_c() => res,
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
-    let mut app = App::new();
+    let app = App::new();
+    app.on_watchdog(|report| async move {
+        let (command, elapsed) = (report.command, report.elapsed);
+        if report.killed {
+            eprintln!("watchdog: killed '{command}' after {elapsed:?}");
+        } else {
+            eprintln!("watchdog: '{command}' has been running for more than {elapsed:?}");
+        }
+    });
+    app.on_keys_warning(|warning| async move {
+        let client = warning
+            .client
+            .map_or_else(|| "a client".to_owned(), |addr| addr.to_string());
+        eprintln!(
+            "KEYS {:?} from {client} ran over {} keys, it should use SCAN",
+            String::from_utf8_lossy(warning.pattern.get_bytes().unwrap_or_default()),
+            warning.keys,
+        );
+    });
     if let Some(dir) = cli.dir {
         app.set_config("dir".into(), dir);
     }
@@ -58,14 +178,47 @@ async fn main() -> anyhow::Result<()> {
         app.set_config("dbfilename".into(), dbfilename);
     }
 
+    if let Some(path) = cli.handoff.as_ref().filter(|path| path.exists()) {
+        let keys = app.read_handoff(path)?;
+        eprintln!("took over {keys} keys from {}", path.display());
+    }
+
     APP.set(app).unwrap();
-    let listener = TcpListener::bind("0.0.0.0:6379").await?;
+    if let Some(path) = cli.handoff {
+        tokio::spawn(async move {
+            if let Err(e) = stop_requested().await {
+                eprintln!("can't wait for signals, there will be no handoff: {e}");
+                return;
+            }
+            match APP.get().unwrap().write_handoff(&path) {
+                Ok(()) => std::process::exit(0),
+                Err(e) => {
+                    eprintln!("failed to write the handoff file: {e}");
+                    std::process::exit(1);
+                }
+            }
+        });
+    }
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(EXPIRE_CYCLE_INTERVAL);
+        loop {
+            interval.tick().await;
+            APP.get().unwrap().active_expire();
+        }
+    });
+    let listener = match activated_listener()? {
+        Some(listener) => TcpListener::from_std(listener)?,
+        None => TcpListener::bind(("0.0.0.0", cli.port)).await?,
+    };
     dbg!(redis::add(1, 2));
     loop {
         let (socket, _) = listener.accept().await?;
-        match handle_connection(socket).await {
-            Ok(_) => {}
-            Err(e) => eprintln!("Error {e}"),
-        }
+        // a task per connection, clients blocked by BLPOP and friends mustn't
+        // hold up the others
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(socket).await {
+                eprintln!("Error {e}");
+            }
+        });
     }
 }
@@ -0,0 +1,171 @@
+//! Strings that were written far past their end.
+//!
+//! `SETRANGE key 4000000 x` makes a string of four million zeros and an `x`.
+//! Instead of allocating all of those, the string keeps only the regions
+//! that were written and its length, everything in between reads as zeros.
+//! Reads that need all of it, like GET, build the whole string for the
+//! reply, but nothing that big stays around in the store.
+//!
+//! A string only stays sparse while that saves a good amount of memory, see
+//! [`Sparse::worth_it`], otherwise it's turned into a plain string.
+
+use std::{collections::BTreeMap, mem::size_of, ops::Range};
+
+/// strings need at least this many unwritten bytes to be kept sparse
+const MIN_GAP: usize = 4096;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Sparse {
+    len: usize,
+    /// the written regions by where they start, they neither overlap nor
+    /// touch, those are merged
+    chunks: BTreeMap<usize, Vec<u8>>,
+}
+
+impl Sparse {
+    /// a sparse string holding `bytes`
+    pub fn new(bytes: Vec<u8>) -> Self {
+        let mut out = Sparse::default();
+        if !bytes.is_empty() {
+            out.len = bytes.len();
+            out.chunks.insert(0, bytes);
+        }
+        out
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// how many bytes are actually stored
+    pub fn written(&self) -> usize {
+        self.chunks.values().map(Vec::len).sum()
+    }
+
+    /// whether a string of `len` bytes with `written` of them written is
+    /// better kept sparse
+    pub fn worth_it(len: usize, written: usize) -> bool {
+        let gap = len - written;
+        gap >= MIN_GAP && gap > written
+    }
+
+    /// Overwrites the string with `bytes` from `offset` on, growing it if
+    /// needed.
+    pub fn write(&mut self, offset: usize, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let end = offset + bytes.len();
+        self.len = self.len.max(end);
+
+        // the chunks touching the new one, they all become one
+        let first = self
+            .chunks
+            .range(..=offset)
+            .next_back()
+            .filter(|(start, chunk)| **start + chunk.len() >= offset)
+            .map_or(offset, |(start, _)| *start);
+        let touching: Vec<usize> = self.chunks.range(first..=end).map(|(s, _)| *s).collect();
+
+        let mut merged = Vec::new();
+        for start in touching {
+            let chunk = self.chunks.remove(&start).expect("collected above");
+            let at = start - first;
+            if merged.len() < at + chunk.len() {
+                merged.resize(at + chunk.len(), 0);
+            }
+            merged[at..at + chunk.len()].copy_from_slice(&chunk);
+        }
+        let at = offset - first;
+        if merged.len() < at + bytes.len() {
+            merged.resize(at + bytes.len(), 0);
+        }
+        merged[at..at + bytes.len()].copy_from_slice(bytes);
+        self.chunks.insert(first, merged);
+    }
+
+    /// the bytes in `range`, which has to be within the string
+    pub fn read(&self, range: Range<usize>) -> Vec<u8> {
+        let mut out = vec![0; range.len()];
+        // the chunk `range` starts in, if any, and the ones after it
+        let from = self
+            .chunks
+            .range(..=range.start)
+            .next_back()
+            .map_or(range.start, |(start, _)| *start);
+        for (start, chunk) in self.chunks.range(from..range.end) {
+            let (lo, hi) = (range.start.max(*start), range.end.min(start + chunk.len()));
+            if lo < hi {
+                out[lo - range.start..hi - range.start]
+                    .copy_from_slice(&chunk[lo - start..hi - start]);
+            }
+        }
+        out
+    }
+
+    /// the whole string
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.read(0..self.len)
+    }
+
+    pub fn heap_size(&self) -> usize {
+        // the map's nodes are left out, there are few of them
+        self.chunks
+            .values()
+            .map(|chunk| size_of::<(usize, Vec<u8>)>() + chunk.capacity())
+            .sum()
+    }
+
+    /// how many separately written regions there are
+    pub fn chunks(&self) -> usize {
+        self.chunks.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes() {
+        let mut s = Sparse::new(b"hello".to_vec());
+        s.write(10, b"world");
+        assert_eq!(s.len(), 15);
+        assert_eq!(s.written(), 10);
+        assert_eq!(s.to_bytes(), b"hello\0\0\0\0\0world");
+        assert_eq!(s.read(3..12), b"lo\0\0\0\0\0wo");
+        assert_eq!(s.read(6..8), b"\0\0");
+
+        // touching and overlapping writes merge
+        s.write(5, b"!");
+        s.write(8, b"??xx");
+        assert_eq!(s.chunks(), 2);
+        assert_eq!(s.to_bytes(), b"hello!\0\0??xxrld");
+        s.write(4, b"OOO");
+        assert_eq!(s.chunks(), 2);
+        s.write(7, b"_");
+        assert_eq!(s.chunks(), 1);
+        assert_eq!(s.to_bytes(), b"hellOOO_??xxrld");
+        assert_eq!(s.written(), s.len());
+
+        let mut s = Sparse::default();
+        s.write(4_000_000, b"x");
+        assert_eq!(s.len(), 4_000_001);
+        assert!(s.heap_size() < 100);
+        assert_eq!(s.read(3_999_999..4_000_001), b"\0x");
+        s.write(0, b"a");
+        s.write(2_000_000, b"b");
+        s.write(3_999_990, &[b'c'; 20]);
+        assert_eq!(s.chunks(), 3);
+        assert_eq!(s.len(), 4_000_010);
+        assert_eq!(s.read(3_999_989..3_999_992), b"\0cc");
+    }
+
+    #[test]
+    fn worth_it() {
+        assert!(Sparse::worth_it(5000, 10));
+        assert!(!Sparse::worth_it(1000, 10));
+        assert!(!Sparse::worth_it(10000, 5000));
+        assert!(Sparse::worth_it(10000, 4999));
+    }
+}
@@ -0,0 +1,580 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{object::Object, value::Value};
+
+/// the current unix time in ms
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time went backwards")
+        .as_millis()
+}
+
+/// Where the store takes the current time from when checking expiries.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Clock {
+    #[default]
+    System,
+    /// Stopped at a unix time in ms, it only moves when advanced explicitly.
+    /// Keys then expire at exactly the instant the clock is moved past their
+    /// deadline, which makes tests of TTL dependent behaviour reproducible.
+    Frozen(u128),
+}
+
+impl Clock {
+    pub fn now_ms(self) -> u128 {
+        match self {
+            Clock::System => now_ms(),
+            Clock::Frozen(now) => now,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) struct Entry {
+    pub value: Object,
+    pub expiry: Option<u128>,
+    /// when the value was last written or read by a command, as a unix time
+    /// in ms
+    pub last_access: u128,
+    /// the access frequency counter, see [`Entry::freq`]
+    freq: u8,
+}
+
+/// the counter new keys start at, so they get a chance to be used before
+/// they look rarely used, `LFU_INIT_VAL` in Redis
+const LFU_INIT: u8 = 5;
+
+/// how much harder each increment of the counter gets, the default
+/// `lfu-log-factor` of Redis
+const LFU_LOG_FACTOR: f64 = 10.0;
+
+/// the counter drops by one for every minute the key isn't accessed, the
+/// default `lfu-decay-time` of Redis
+const LFU_DECAY_MS: u128 = 60_000;
+
+impl Entry {
+    pub fn is_expired(&self, now: u128) -> bool {
+        let Some(expiry) = self.expiry else {
+            return false;
+        };
+
+        now > expiry
+    }
+
+    pub fn new(value: impl Into<Object>) -> Self {
+        Self {
+            value: value.into(),
+            expiry: None,
+            last_access: 0,
+            freq: LFU_INIT,
+        }
+    }
+
+    /// How often the key is accessed, as OBJECT FREQ reports it. Like in
+    /// Redis the counter is logarithmic, 255 stands for about a million
+    /// accesses, and decays while the key isn't accessed.
+    pub fn freq(&self, now: u128) -> u8 {
+        let decay = now.saturating_sub(self.last_access) / LFU_DECAY_MS;
+        self.freq
+            .saturating_sub(u8::try_from(decay).unwrap_or(u8::MAX))
+    }
+
+    /// counts an access at `now`
+    fn touch(&mut self, now: u128) {
+        let mut freq = self.freq(now);
+        let increment = 1.0 / (f64::from(freq.saturating_sub(LFU_INIT)) * LFU_LOG_FACTOR + 1.0);
+        if freq < u8::MAX && fastrand::f64() < increment {
+            freq += 1;
+        }
+        self.freq = freq;
+        self.last_access = now;
+    }
+}
+
+/// The data type of a stored value, as reported by TYPE and INFO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum Kind {
+    String,
+    List,
+    Set,
+    Hash,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 4] = [Kind::String, Kind::List, Kind::Set, Kind::Hash];
+
+    pub fn of(value: &Value) -> Self {
+        match value {
+            Value::Array(_) | Value::Push(_) => Kind::List,
+            Value::Set(_) => Kind::Set,
+            Value::Map(_) => Kind::Hash,
+            _ => Kind::String,
+        }
+    }
+
+    /// the kind called `name`, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Kind::ALL
+            .into_iter()
+            .find(|kind| kind.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::String => "string",
+            Kind::List => "list",
+            Kind::Set => "set",
+            Kind::Hash => "hash",
+        }
+    }
+}
+
+/// Counters over the keyspace, kept up to date on every write so INFO never
+/// has to scan the keys.
+///
+/// Like in Redis, keys that expired but haven't been pruned yet are counted.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Stats {
+    pub keys: usize,
+    pub expires: usize,
+    /// sum of all deadlines, for the average ttl
+    expiry_sum: u128,
+    by_kind: [usize; Kind::ALL.len()],
+}
+
+impl Stats {
+    fn add(&mut self, entry: &Entry) {
+        self.keys += 1;
+        self.by_kind[entry.value.kind() as usize] += 1;
+        self.add_expiry(entry.expiry);
+    }
+
+    fn remove(&mut self, entry: &Entry) {
+        self.keys -= 1;
+        self.by_kind[entry.value.kind() as usize] -= 1;
+        self.remove_expiry(entry.expiry);
+    }
+
+    fn add_expiry(&mut self, expiry: Option<u128>) {
+        if let Some(expiry) = expiry {
+            self.expires += 1;
+            self.expiry_sum += expiry;
+        }
+    }
+
+    fn remove_expiry(&mut self, expiry: Option<u128>) {
+        if let Some(expiry) = expiry {
+            self.expires -= 1;
+            self.expiry_sum -= expiry;
+        }
+    }
+
+    /// number of keys holding a value of type `kind`
+    pub fn count(&self, kind: Kind) -> usize {
+        self.by_kind[kind as usize]
+    }
+
+    /// average time to live of the keys with an expiry in ms, 0 if there are
+    /// none
+    pub fn avg_ttl(&self, now: u128) -> u128 {
+        if self.expires == 0 {
+            return 0;
+        }
+        (self.expiry_sum / self.expires as u128).saturating_sub(now)
+    }
+}
+
+/// What expiry has been up to, see [`Store::expire_cycle`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct ExpireStats {
+    /// keys removed because they expired, by lookups and cycles alike
+    pub expired_keys: u64,
+    pub cycles: u64,
+    /// cycles that stopped because they ran out of time
+    pub time_cap_reached: u64,
+    pub cycle_time: Duration,
+    /// keys with an expiry the last cycle looked at
+    pub last_sampled: usize,
+    /// how many of those had expired
+    pub last_expired: usize,
+    pub last_duration: Duration,
+}
+
+impl ExpireStats {
+    /// percentage of the keys sampled by the last cycle that had expired
+    pub fn stale_percent(&self) -> f64 {
+        if self.last_sampled == 0 {
+            return 0.0;
+        }
+        self.last_expired as f64 * 100.0 / self.last_sampled as f64
+    }
+}
+
+/// keys looked at per round of [`Store::expire_cycle`]
+const EXPIRE_SAMPLE: usize = 20;
+
+/// One step of a scan over `items`: the `count` items before `cursor` and
+/// the cursor to go on from, 0 once all have been visited. See
+/// [`Store::scan`] for how the cursor works, HSCAN and SSCAN walk the
+/// fields and members of a collection the same way.
+///
+/// Only the items returned are looked at, as long as skipping over the
+/// others is cheap, which it is for iterators over an `IndexMap` or
+/// `IndexSet`.
+pub(crate) fn scan_step<I: ExactSizeIterator>(
+    items: I,
+    cursor: u64,
+    count: usize,
+) -> (u64, Vec<I::Item>) {
+    let end = match usize::try_from(cursor) {
+        Ok(0) | Err(_) => items.len(),
+        Ok(end) => end.min(items.len()),
+    };
+    let start = end.saturating_sub(count.max(1));
+    (start as u64, items.skip(start).take(end - start).collect())
+}
+
+/// The keyspace.
+///
+/// Keys live in a hash map, so point lookups (GET/SET and friends) are O(1).
+/// Like the `expires` dict in Redis, the keys with an expiry are also kept in
+/// a set of their own, in which the expiry cycle can pick random keys without
+/// wading through the ones that never expire. Commands that visit every key
+/// (KEYS, SCAN) walk the map by position instead of every write paying for an
+/// ordered index, see [`Store::keys_before`].
+///
+/// Expired keys are removed when they're looked up, by
+/// [`Store::expire_cycle`] and by [`Store::prune_expired`]. Hashes whose
+/// fields have a ttl get a set of their own too, for the expiry cycle to
+/// sample.
+#[derive(Debug, Default)]
+pub(crate) struct Store {
+    map: IndexMap<Value, Entry>,
+    /// the keys in `map` that have an expiry
+    volatile: IndexSet<Value>,
+    /// The keys of hashes with fields that have a ttl. Keys that don't hold
+    /// one anymore are dropped once the expiry cycle comes across them.
+    volatile_fields: IndexSet<Value>,
+    stats: Stats,
+    expire_stats: ExpireStats,
+    clock: Clock,
+}
+
+impl Store {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn now_ms(&self) -> u128 {
+        self.clock.now_ms()
+    }
+
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    /// moves a frozen clock forward by `ms`, returns false if the clock isn't
+    /// frozen
+    pub fn advance_clock(&mut self, ms: u128) -> bool {
+        let Clock::Frozen(now) = &mut self.clock else {
+            return false;
+        };
+        *now += ms;
+        true
+    }
+
+    /// returns the entry stored at `key`, treating expired entries as missing
+    pub fn get(&self, key: &Value) -> Option<&Entry> {
+        let now = self.now_ms();
+        self.map.get(key).filter(|e| !e.is_expired(now))
+    }
+
+    /// all keys that haven't expired and their entries, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&Value, &Entry)> {
+        let now = self.now_ms();
+        self.map.iter().filter(move |(_, e)| !e.is_expired(now))
+    }
+
+    /// Walks the keyspace from its end a chunk at a time, for commands that
+    /// visit every key but shouldn't hold the lock all along. Returns the keys
+    /// that haven't expired among the `count` before `end` and where the next
+    /// chunk ends, 0 once all keys have been visited. The first chunk ends at
+    /// `usize::MAX`.
+    ///
+    /// Removing a key moves the last one into its place. Walking from the
+    /// end, that one has been visited already, so every key that is there
+    /// from the first chunk to the last is visited, though some may be visited
+    /// twice. Keys added in between may be missed.
+    pub fn keys_before(&self, end: usize, count: usize) -> (usize, Vec<&Value>) {
+        let now = self.now_ms();
+        let end = end.min(self.map.len());
+        let start = end.saturating_sub(count);
+        let keys = (start..end)
+            .filter_map(|i| self.map.get_index(i))
+            .filter(|(_, e)| !e.is_expired(now))
+            .map(|(k, _)| k)
+            .collect();
+        (start, keys)
+    }
+
+    /// a random key, which may have expired, `None` if there are no keys
+    pub fn random_key(&self) -> Option<&Value> {
+        if self.map.is_empty() {
+            return None;
+        }
+        let (key, _) = self.map.get_index(fastrand::usize(..self.map.len()))?;
+        Some(key)
+    }
+
+    /// Visits some of the keys, for SCAN. Returns those that haven't expired
+    /// among the next `count` and the cursor to go on from, 0 once all keys
+    /// have been visited.
+    ///
+    /// Like [`Store::keys_before`], a scan walks the map from its end and the
+    /// cursor is the position the next step ends at, 0 for the first one. So
+    /// a step only costs as much as the keys it visits, and every key that is
+    /// there from the start of a scan to its end is visited.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&Value, &Entry)>) {
+        let now = self.now_ms();
+        let (next, mut batch) = scan_step(self.map.iter(), cursor, count);
+        batch.retain(|(_, e)| !e.is_expired(now));
+        (next, batch)
+    }
+
+    /// Sets `key` to `entry`. Overwriting a key counts as an access to it,
+    /// the new value takes over its access frequency.
+    pub fn insert(&mut self, key: Value, mut entry: Entry) -> Option<Entry> {
+        let now = self.now_ms();
+        match self.map.get(&key).filter(|e| !e.is_expired(now)) {
+            Some(old) => {
+                (entry.freq, entry.last_access) = (old.freq, old.last_access);
+                entry.touch(now);
+            }
+            None => entry.last_access = now,
+        }
+        self.stats.add(&entry);
+        if entry.expiry.is_some() {
+            self.volatile.insert(key.clone());
+        } else {
+            self.volatile.swap_remove(&key);
+        }
+        if entry
+            .value
+            .as_hash()
+            .is_some_and(|hash| hash.has_expiries())
+        {
+            self.volatile_fields.insert(key.clone());
+        }
+        let old = self.map.insert(key, entry);
+        if let Some(old) = &old {
+            self.stats.remove(old);
+        }
+        old
+    }
+
+    /// like [`Store::get`], but counts as an access to the key
+    pub fn access(&mut self, key: &Value) -> Option<&Entry> {
+        self.access_mut(key).map(|e| &*e)
+    }
+
+    /// Like [`Store::access`], for changing the value in place. It has to stay
+    /// of the same kind and keep its expiry, since [`Stats`] counts those,
+    /// changes to them go through [`Store::insert`] and
+    /// [`Store::set_expiry`].
+    pub fn access_mut(&mut self, key: &Value) -> Option<&mut Entry> {
+        let now = self.now_ms();
+        let entry = self.map.get_mut(key).filter(|e| !e.is_expired(now))?;
+        entry.touch(now);
+        Some(entry)
+    }
+
+    /// Sets when `key` was last accessed and its access frequency counter, as
+    /// RESTORE brings them along. Unlike [`Store::access_mut`] this doesn't
+    /// count as an access.
+    pub fn set_access(&mut self, key: &Value, last_access: Option<u128>, freq: Option<u8>) {
+        let Some(entry) = self.map.get_mut(key) else {
+            return;
+        };
+        if let Some(last_access) = last_access {
+            entry.last_access = last_access;
+        }
+        if let Some(freq) = freq {
+            entry.freq = freq;
+        }
+    }
+
+    /// changes the expiry of `key`, returns false if there is no such key
+    pub fn set_expiry(&mut self, key: &Value, expiry: Option<u128>) -> bool {
+        let now = self.now_ms();
+        let Some(entry) = self.map.get_mut(key).filter(|e| !e.is_expired(now)) else {
+            return false;
+        };
+        self.stats.remove_expiry(entry.expiry);
+        self.stats.add_expiry(expiry);
+        entry.expiry = expiry;
+        if expiry.is_some() {
+            self.volatile.insert(key.clone());
+        } else {
+            self.volatile.swap_remove(key);
+        }
+        true
+    }
+
+    /// To be called after changing the fields of the hash at `key`. Removes
+    /// the hash if that left it empty, and has the expiry cycle look after
+    /// its fields if they have a ttl.
+    pub fn hash_changed(&mut self, key: &Value) {
+        let Some(hash) = self.map.get(key).and_then(|entry| entry.value.as_hash()) else {
+            return;
+        };
+        if hash.is_empty() {
+            self.remove(key);
+        } else if hash.has_expiries() {
+            self.volatile_fields.insert(key.clone());
+        }
+    }
+
+    /// Removes the fields of the hash at `key` that expired, and the hash
+    /// with them if that leaves it empty. Returns how many fields were
+    /// removed.
+    pub fn expire_fields(&mut self, key: &Value) -> usize {
+        let now = self.now_ms();
+        let Some(entry) = self.map.get_mut(key) else {
+            return 0;
+        };
+        if !entry
+            .value
+            .as_hash()
+            .is_some_and(|hash| hash.has_expired(now))
+        {
+            return 0;
+        }
+        let hash = entry.value.hash_mut().expect("checked above");
+        let removed = hash.remove_expired(now);
+        if hash.is_empty() {
+            self.remove(key);
+        }
+        removed
+    }
+
+    /// removes `key`, whether it expired or not
+    pub fn remove(&mut self, key: &Value) -> Option<Entry> {
+        let entry = self.map.swap_remove(key)?;
+        self.volatile.swap_remove(key);
+        self.volatile_fields.swap_remove(key);
+        self.stats.remove(&entry);
+        Some(entry)
+    }
+
+    /// removes `key`, which has expired
+    fn remove_expired_key(&mut self, key: &Value) -> Option<(Value, Entry)> {
+        let (key, entry) = self.map.swap_remove_entry(key)?;
+        self.volatile.swap_remove(&key);
+        self.volatile_fields.swap_remove(&key);
+        self.stats.remove(&entry);
+        self.expire_stats.expired_keys += 1;
+        Some((key, entry))
+    }
+
+    /// removes `key` if it has expired and returns its entry
+    pub fn remove_expired(&mut self, key: &Value) -> Option<Entry> {
+        let now = self.now_ms();
+        if !self.map.get(key)?.is_expired(now) {
+            return None;
+        }
+        self.remove_expired_key(key).map(|(_, entry)| entry)
+    }
+
+    /// removes all expired keys and returns them
+    pub fn prune_expired(&mut self) -> Vec<(Value, Entry)> {
+        let now = self.now_ms();
+        let keys: Vec<_> = self
+            .volatile
+            .iter()
+            .filter(|key| self.map[*key].is_expired(now))
+            .cloned()
+            .collect();
+        keys.iter()
+            .filter_map(|key| self.remove_expired_key(key))
+            .collect()
+    }
+
+    /// Removes expired keys by sampling, like the active expiry in Redis.
+    ///
+    /// Each round looks at [`EXPIRE_SAMPLE`] random keys with an expiry. While
+    /// more than a quarter of them turn out to be expired there are likely
+    /// more, so the cycle goes on with another round until `budget` is used
+    /// up.
+    pub fn expire_cycle(&mut self, budget: Duration) -> Vec<(Value, Entry)> {
+        let start = Instant::now();
+        let now = self.now_ms();
+        let mut expired = vec![];
+        let mut sampled = 0;
+        while !self.volatile.is_empty() {
+            let (mut round_sampled, mut round_expired) = (0, 0);
+            for _ in 0..EXPIRE_SAMPLE.min(self.volatile.len()) {
+                let index = fastrand::usize(..self.volatile.len());
+                let key = &self.volatile[index];
+                round_sampled += 1;
+                if self.map[key].is_expired(now) {
+                    let key = key.clone();
+                    expired.extend(self.remove_expired_key(&key));
+                    round_expired += 1;
+                }
+            }
+            sampled += round_sampled;
+
+            if round_expired * 4 <= round_sampled {
+                break;
+            }
+            if start.elapsed() >= budget {
+                self.expire_stats.time_cap_reached += 1;
+                break;
+            }
+        }
+
+        // a round over the hashes with fields that expire, which removes all
+        // of their fields that have
+        for _ in 0..EXPIRE_SAMPLE.min(self.volatile_fields.len()) {
+            let index = fastrand::usize(..self.volatile_fields.len());
+            let key = self.volatile_fields[index].clone();
+            self.expire_fields(&key);
+            let tracked = self
+                .map
+                .get(&key)
+                .and_then(|entry| entry.value.as_hash())
+                .is_some_and(|hash| hash.has_expiries());
+            if !tracked {
+                self.volatile_fields.swap_remove(&key);
+            }
+        }
+
+        let stats = &mut self.expire_stats;
+        stats.cycles += 1;
+        stats.last_sampled = sampled;
+        stats.last_expired = expired.len();
+        stats.last_duration = start.elapsed();
+        stats.cycle_time += stats.last_duration;
+        expired
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub fn expire_stats(&self) -> &ExpireStats {
+        &self.expire_stats
+    }
+
+    /// carries over the stats of another server, see [`crate::handoff`]
+    pub fn set_expire_stats(&mut self, stats: ExpireStats) {
+        self.expire_stats = stats;
+    }
+
+    pub fn clock(&self) -> Clock {
+        self.clock
+    }
+}
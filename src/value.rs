@@ -1,22 +1,284 @@
-use std::collections::BTreeMap;
+use std::{cmp::Ordering, collections::BTreeMap, fmt, hash::Hash};
 
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Serialize};
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Default)]
-#[serde(untagged)]
+use crate::{
+    deserializer::{self, Deserializer},
+    float::format_double,
+    reply::{self, RespError, VALUE_TOKEN},
+    serializer::{Protocol, Serializer},
+};
+
+#[cfg(any(test, feature = "testing"))]
+pub mod arbitrary;
+mod compare;
+#[cfg(feature = "json")]
+mod json;
+mod map;
+
+pub use map::{Iter as MapIter, Map};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
 pub enum Value {
     Int(i64),
     Bool(bool),
-    String(Option<String>),
+    Double(Double),
+    /// the digits of an integer too large for `Int`
+    BigNumber(String),
+    /// a bulk string, any bytes are allowed
+    String(Option<Vec<u8>>),
+    /// a status reply like `OK`, it can't contain line breaks
+    SimpleString(String),
+    Verbatim {
+        format: [u8; 3],
+        text: String,
+    },
+    Error(RespError),
     Array(Option<Vec<Value>>),
-    Map(BTreeMap<Value, Value>),
+    Set(Vec<Value>),
+    Push(Vec<Value>),
+    Map(Map),
     #[default]
     Null,
 }
 
+/// An `f64` that can be used as a map key.
+///
+/// Doubles are compared with [`f64::total_cmp`], so `NaN` is equal to itself
+/// and `0.0` and `-0.0` are different values.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Double(pub f64);
+
+impl PartialEq for Double {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Double {}
+
+impl PartialOrd for Double {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Double {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl Hash for Double {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Value::Int(i) => serializer.serialize_i64(*i),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Double(d) => serializer.serialize_f64(d.0),
+            Value::BigNumber(n) => reply::BigNumber(n).serialize(serializer),
+            Value::String(Some(s)) => serializer.serialize_bytes(s),
+            Value::String(None) => serializer.serialize_none(),
+            Value::SimpleString(s) => reply::SimpleString(s).serialize(serializer),
+            Value::Verbatim { format, text } => {
+                reply::Verbatim::new(*format, text).serialize(serializer)
+            }
+            Value::Error(e) => e.serialize(serializer),
+            Value::Array(Some(a)) => a.serialize(serializer),
+            Value::Array(None) => reply::NullArray.serialize(serializer),
+            Value::Set(s) => reply::Set(s).serialize(serializer),
+            Value::Push(p) => reply::Push(p).serialize(serializer),
+            Value::Map(m) => m.serialize(serializer),
+            Value::Null => serializer.serialize_unit(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_newtype_struct(VALUE_TOKEN, ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> de::Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a RESP value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(Value::Int(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(i64::try_from(v).map_or_else(|_| Value::BigNumber(v.to_string()), Value::Int))
+    }
+
+    fn visit_i128<E>(self, v: i128) -> Result<Self::Value, E> {
+        Ok(i64::try_from(v).map_or_else(|_| Value::BigNumber(v.to_string()), Value::Int))
+    }
+
+    fn visit_u128<E>(self, v: u128) -> Result<Self::Value, E> {
+        Ok(i64::try_from(v).map_or_else(|_| Value::BigNumber(v.to_string()), Value::Int))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(Value::Double(Double(v)))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(Value::str(v))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Self::Value, E> {
+        Ok(Value::String(Some(v.into_bytes())))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(Value::bytes(v))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(Value::String(Some(v)))
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E> {
+        Ok(Value::String(None))
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Value::Array(Some(items)))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::MapAccess<'de>,
+    {
+        let mut entries = Map::with_capacity(map.size_hint().unwrap_or(0).min(4096));
+        while let Some((k, v)) = map.next_entry()? {
+            entries.insert(k, v);
+        }
+        Ok(Value::Map(entries))
+    }
+
+    /// frames serde has no type for, tagged by the
+    /// [`Deserializer`](crate::deserializer::Deserializer)
+    fn visit_enum<A>(self, data: A) -> Result<Self::Value, A::Error>
+    where
+        A: de::EnumAccess<'de>,
+    {
+        use de::VariantAccess;
+
+        const TAGS: &[&str] = &[
+            "SimpleString",
+            "NullArray",
+            "Error",
+            "Verbatim",
+            "BigNumber",
+            "Set",
+            "Push",
+        ];
+
+        let (tag, frame): (&str, _) = data.variant()?;
+        match tag {
+            "SimpleString" => {
+                let s: reply::SimpleString = frame.newtype_variant()?;
+                Ok(Value::SimpleString(s.0.to_owned()))
+            }
+            "NullArray" => frame.unit_variant().map(|()| Value::Array(None)),
+            "Error" => frame.newtype_variant().map(Value::Error),
+            "Verbatim" => {
+                let v: reply::Verbatim = frame.newtype_variant()?;
+                Ok(Value::Verbatim {
+                    format: v.format,
+                    text: v.text.to_owned(),
+                })
+            }
+            "BigNumber" => {
+                let n: reply::BigNumber = frame.newtype_variant()?;
+                Ok(Value::BigNumber(n.0.to_owned()))
+            }
+            "Set" => frame.newtype_variant().map(Value::Set),
+            "Push" => frame.newtype_variant().map(Value::Push),
+            _ => Err(de::Error::unknown_variant(tag, TAGS)),
+        }
+    }
+}
+
 impl Value {
     pub fn str(s: &str) -> Self {
-        Self::String(Some(s.into()))
+        Self::bytes(s)
+    }
+
+    pub fn bytes(b: impl Into<Vec<u8>>) -> Self {
+        Self::String(Some(b.into()))
+    }
+
+    /// Parses the frame at the start of `input` and returns it together with
+    /// the number of bytes it took up, so several frames can be read from the
+    /// same buffer. Fails with [`deserializer::Error::UnexpectedEof`] if the
+    /// frame is incomplete.
+    ///
+    /// Gives the same value as [`deserializer::from_bytes`], but reads the
+    /// frame directly instead of going through serde.
+    pub fn parse(input: &[u8]) -> Result<(Value, usize), deserializer::Error> {
+        let mut deserializer = Deserializer::from_bytes(input);
+        let value = deserializer.parse_value()?;
+        Ok((value, deserializer.position()))
+    }
+
+    /// Appends the value to `out`. Writes the same bytes as
+    /// [`to_bytes_with_protocol`](crate::serializer::to_bytes_with_protocol),
+    /// but without going through serde.
+    pub fn write(&self, out: &mut Vec<u8>, protocol: Protocol) {
+        let mut serializer = Serializer::with_output(std::mem::take(out), protocol);
+        serializer
+            .write_value(self)
+            .expect("writing to a Vec can't fail");
+        *out = serializer.into_inner();
     }
 
     pub fn to_int(self) -> Option<i64> {
@@ -33,9 +295,16 @@ impl Value {
         }
     }
 
+    /// the string as text, `None` if it isn't valid utf-8
     pub fn to_str(self) -> Option<String> {
+        self.to_bytes().and_then(|b| String::from_utf8(b).ok())
+    }
+
+    /// the bytes of a bulk or simple string
+    pub fn to_bytes(self) -> Option<Vec<u8>> {
         match self {
             Self::String(i) => i,
+            Self::SimpleString(s) => Some(s.into_bytes()),
             _ => None,
         }
     }
@@ -47,13 +316,48 @@ impl Value {
         }
     }
 
-    pub fn to_map(self) -> Option<BTreeMap<Value, Value>> {
+    pub fn to_map(self) -> Option<Map> {
         match self {
             Self::Map(i) => Some(i),
             _ => None,
         }
     }
 
+    pub fn to_double(self) -> Option<f64> {
+        match self {
+            Self::Double(d) => Some(d.0),
+            _ => None,
+        }
+    }
+
+    pub fn to_big_number(self) -> Option<String> {
+        match self {
+            Self::BigNumber(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn to_error(self) -> Option<RespError> {
+        match self {
+            Self::Error(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    pub fn to_set(self) -> Option<Vec<Value>> {
+        match self {
+            Self::Set(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn to_push(self) -> Option<Vec<Value>> {
+        match self {
+            Self::Push(i) => Some(i),
+            _ => None,
+        }
+    }
+
     pub fn get_int(&self) -> Option<i64> {
         match self {
             Self::Int(i) => Some(*i),
@@ -68,9 +372,16 @@ impl Value {
         }
     }
 
-    pub fn get_str(&self) -> Option<&String> {
+    /// the string as text, `None` if it isn't valid utf-8
+    pub fn get_str(&self) -> Option<&str> {
+        self.get_bytes().and_then(|b| std::str::from_utf8(b).ok())
+    }
+
+    /// the bytes of a bulk or simple string
+    pub fn get_bytes(&self) -> Option<&[u8]> {
         match self {
-            Self::String(i) => i.as_ref(),
+            Self::String(i) => i.as_deref(),
+            Self::SimpleString(s) => Some(s.as_bytes()),
             _ => None,
         }
     }
@@ -82,10 +393,378 @@ impl Value {
         }
     }
 
-    pub fn get_map(&self) -> Option<&BTreeMap<Value, Value>> {
+    pub fn get_map(&self) -> Option<&Map> {
         match self {
             Self::Map(i) => Some(i),
             _ => None,
         }
     }
+
+    pub fn get_double(&self) -> Option<f64> {
+        match self {
+            Self::Double(d) => Some(d.0),
+            _ => None,
+        }
+    }
+
+    pub fn get_big_number(&self) -> Option<&str> {
+        match self {
+            Self::BigNumber(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// the text of a verbatim string, without its format
+    pub fn get_verbatim(&self) -> Option<&str> {
+        match self {
+            Self::Verbatim { text, .. } => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn get_error(&self) -> Option<&RespError> {
+        match self {
+            Self::Error(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    pub fn get_set(&self) -> Option<&Vec<Value>> {
+        match self {
+            Self::Set(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn get_push(&self) -> Option<&Vec<Value>> {
+        match self {
+            Self::Push(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    /// the element at `index` of an array, set or push
+    pub fn get_index(&self, index: usize) -> Option<&Value> {
+        match self {
+            Self::Array(Some(items)) | Self::Set(items) | Self::Push(items) => items.get(index),
+            _ => None,
+        }
+    }
+
+    /// The value stored under `key` in a map. Arrays are read as a flat list
+    /// of key value pairs, which is how RESP2 sends maps.
+    pub fn get_key(&self, key: &Value) -> Option<&Value> {
+        match self {
+            Self::Map(map) => map.get(key),
+            Self::Array(Some(items)) => items
+                .chunks_exact(2)
+                .find(|pair| pair[0] == *key)
+                .map(|pair| &pair[1]),
+            _ => None,
+        }
+    }
+
+    /// Looks up a nested value by a `/` separated path like `config/dir` or
+    /// `slots/0/2`. Each segment is a map key (a bulk or simple string) or an
+    /// index, see [`Value::get_key`] and [`Value::get_index`]. An empty path
+    /// is the value itself.
+    pub fn pointer(&self, path: &str) -> Option<&Value> {
+        if path.is_empty() {
+            return Some(self);
+        }
+        path.split('/').try_fold(self, |value, segment| {
+            let index = segment.parse().ok().and_then(|i| value.get_index(i));
+            index
+                .or_else(|| value.get_key(&Value::str(segment)))
+                .or_else(|| value.get_key(&Value::SimpleString(segment.to_owned())))
+        })
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value::Int(v)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Self {
+        Value::Bool(v)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Value::Double(Double(v))
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Self {
+        Value::str(v)
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Self {
+        Value::bytes(v)
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(v: &[u8]) -> Self {
+        Value::bytes(v)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(v: Vec<Value>) -> Self {
+        Value::Array(Some(v))
+    }
+}
+
+impl From<Map> for Value {
+    fn from(v: Map) -> Self {
+        Value::Map(v)
+    }
+}
+
+/// the entries end up sorted by key
+impl From<BTreeMap<Value, Value>> for Value {
+    fn from(v: BTreeMap<Value, Value>) -> Self {
+        Value::Map(v.into())
+    }
+}
+
+impl From<RespError> for Value {
+    fn from(v: RespError) -> Self {
+        Value::Error(v)
+    }
+}
+
+/// `None` becomes a null bulk string, like a missing key in a GET reply
+impl<T> From<Option<T>> for Value
+where
+    T: Into<Value>,
+{
+    fn from(v: Option<T>) -> Self {
+        v.map_or(Value::String(None), Into::into)
+    }
+}
+
+/// The error returned when converting a [`Value`] of the wrong kind, it hands
+/// the value (or the part of it that couldn't be converted) back.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("expected {expected}, got {value}")]
+pub struct FromValueError {
+    pub value: Value,
+    expected: &'static str,
+}
+
+macro_rules! try_from_value {
+    ($ty:ty, $expected:literal, $($pat:pat => $v:expr),+ $(,)?) => {
+        impl TryFrom<Value> for $ty {
+            type Error = FromValueError;
+
+            fn try_from(value: Value) -> Result<Self, Self::Error> {
+                match value {
+                    $($pat => Ok($v),)+
+                    value => Err(FromValueError {
+                        value,
+                        expected: $expected,
+                    }),
+                }
+            }
+        }
+    };
+}
+
+try_from_value!(i64, "an integer", Value::Int(i) => i);
+try_from_value!(bool, "a boolean", Value::Bool(b) => b);
+try_from_value!(f64, "a double", Value::Double(d) => d.0);
+try_from_value!(Vec<u8>, "a string", Value::String(Some(s)) => s);
+try_from_value!(Vec<Value>, "an array", Value::Array(Some(a)) => a);
+try_from_value!(Map, "a map", Value::Map(m) => m);
+try_from_value!(BTreeMap<Value, Value>, "a map", Value::Map(m) => m.into());
+
+impl TryFrom<Value> for String {
+    type Error = FromValueError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let err = |value| FromValueError {
+            value,
+            expected: "a utf-8 string",
+        };
+        match value {
+            Value::String(Some(s)) => {
+                String::from_utf8(s).map_err(|e| err(Value::bytes(e.into_bytes())))
+            }
+            Value::SimpleString(text) | Value::Verbatim { text, .. } => Ok(text),
+            value => Err(err(value)),
+        }
+    }
+}
+
+/// Formats the value the way `redis-cli` prints replies, e.g.
+///
+/// ```text
+/// 1) "key"
+/// 2) (integer) 1
+/// ```
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = String::new();
+        self.format_tty(&mut out, "")?;
+        f.write_str(out.strip_suffix('\n').unwrap_or(&out))
+    }
+}
+
+impl Value {
+    /// appends the `redis-cli` formatting of `self` to `out`, every line ends
+    /// in a newline and lines after the first are indented by `prefix`
+    fn format_tty(&self, out: &mut String, prefix: &str) -> fmt::Result {
+        use fmt::Write;
+
+        let (items, kind): (Vec<(&Value, Option<&Value>)>, char) = match self {
+            Value::Array(Some(a)) | Value::Push(a) if !a.is_empty() => {
+                (a.iter().map(|v| (v, None)).collect(), ')')
+            }
+            Value::Set(a) if !a.is_empty() => (a.iter().map(|v| (v, None)).collect(), '~'),
+            Value::Map(m) if !m.is_empty() => (m.iter().map(|(k, v)| (k, Some(v))).collect(), '#'),
+            _ => {
+                self.format_scalar(out)?;
+                return writeln!(out);
+            }
+        };
+
+        let width = items.len().to_string().len();
+        let nested = format!("{prefix}{}", " ".repeat(width + 2));
+        for (i, (item, value)) in items.into_iter().enumerate() {
+            if i > 0 {
+                out.push_str(prefix);
+            }
+            write!(out, "{:>width$}{kind} ", i + 1)?;
+            item.format_tty(out, &nested)?;
+            if let Some(value) = value {
+                out.pop();
+                out.push_str(" => ");
+                value.format_tty(out, &nested)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// formats anything but a non-empty collection, without a newline
+    fn format_scalar(&self, out: &mut String) -> fmt::Result {
+        use fmt::Write;
+
+        match self {
+            Value::Int(i) => write!(out, "(integer) {i}"),
+            Value::Bool(b) => write!(out, "({b})"),
+            Value::Double(d) => write!(out, "(double) {}", format_double(d.0)),
+            Value::BigNumber(n) => write!(out, "(big number) {n}"),
+            Value::String(Some(s)) => quote(out, s),
+            Value::SimpleString(text) | Value::Verbatim { text, .. } => write!(out, "{text}"),
+            Value::Error(e) => write!(out, "(error) {e}"),
+            Value::String(None) | Value::Array(None) | Value::Null => write!(out, "(nil)"),
+            Value::Array(Some(_)) | Value::Push(_) => write!(out, "(empty array)"),
+            Value::Set(_) => write!(out, "(empty set)"),
+            Value::Map(_) => write!(out, "(empty hash)"),
+        }
+    }
+}
+
+/// writes `s` in double quotes, escaping it like `redis-cli` does
+fn quote(out: &mut String, s: &[u8]) -> fmt::Result {
+    use fmt::Write;
+
+    out.push('"');
+    for &b in s {
+        match b {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x07 => out.push_str("\\a"),
+            0x08 => out.push_str("\\b"),
+            b if b.is_ascii_graphic() || b == b' ' => out.push(b as char),
+            b => write!(out, "\\x{b:02x}")?,
+        }
+    }
+    out.push('"');
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_scalars() {
+        assert_eq!(Value::from(1).to_string(), "(integer) 1");
+        assert_eq!(Value::from("a\"b\n\x01").to_string(), r#""a\"b\n\x01""#);
+        assert_eq!(Value::String(None).to_string(), "(nil)");
+        assert_eq!(Value::from(1.5).to_string(), "(double) 1.5");
+        assert_eq!(Value::from(true).to_string(), "(true)");
+        assert_eq!(
+            Value::from(RespError::new("ERR", "oops")).to_string(),
+            "(error) ERR oops"
+        );
+        assert_eq!(Value::from(vec![]).to_string(), "(empty array)");
+    }
+
+    #[test]
+    fn display_nested() {
+        let items = (1..=10).map(Value::from).collect::<Vec<_>>();
+        let value = Value::from(vec![
+            "a".into(),
+            Value::from(vec!["b".into(), Value::Set(vec!["c".into()])]),
+            Value::from(BTreeMap::from([("k".into(), items.into())])),
+        ]);
+        let expected = [
+            r#"1) "a""#,
+            r#"2) 1) "b""#,
+            r#"   2) 1~ "c""#,
+            r#"3) 1# "k" =>  1) (integer) 1"#,
+            r#"       2) (integer) 2"#,
+        ];
+        let out = value.to_string();
+        let lines: Vec<_> = out.lines().take(5).collect();
+        assert_eq!(lines, expected);
+        assert!(out.ends_with("\n      10) (integer) 10"), "{out}");
+    }
+
+    #[test]
+    fn try_from() {
+        assert_eq!(i64::try_from(Value::from(3)), Ok(3));
+        assert_eq!(String::try_from(Value::from("x")), Ok("x".to_owned()));
+
+        let err = String::try_from(Value::bytes(*b"\xff")).unwrap_err();
+        assert_eq!(err.value, Value::bytes(*b"\xff"));
+        assert_eq!(
+            i64::try_from(Value::from("1")).unwrap_err().to_string(),
+            r#"expected an integer, got "1""#
+        );
+    }
+
+    #[test]
+    fn pointer() {
+        let config = Value::from(vec!["dir".into(), "/tmp".into()]);
+        let slots = Value::from(vec![Value::from(vec![0.into(), 5460.into()])]);
+        let reply = Value::from(BTreeMap::from([
+            (Value::SimpleString("config".into()), config),
+            ("slots".into(), slots),
+        ]));
+
+        assert_eq!(reply.pointer("config/dir"), Some(&Value::from("/tmp")));
+        assert_eq!(reply.pointer("slots/0/1"), Some(&Value::from(5460)));
+        assert_eq!(reply.pointer(""), Some(&reply));
+        assert_eq!(reply.pointer("slots/1"), None);
+        assert_eq!(reply.pointer("config/dir/x"), None);
+        assert_eq!(
+            reply.get_key(&"slots".into()).and_then(|s| s.get_index(0)),
+            reply.pointer("slots/0")
+        );
+    }
 }
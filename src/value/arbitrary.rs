@@ -0,0 +1,120 @@
+//! Random [`Value`]s for property tests, built on [`proptest`].
+//!
+//! Every generated value survives a round trip through RESP3, i.e. writing it
+//! and parsing it back gives the same value: simple strings and errors have
+//! no line breaks, error codes are a single word, big numbers are digits and
+//! the only NaN is [`f64::NAN`]. RESP2 can't tell all kinds apart, so there a
+//! value only reads back as something.
+//!
+//! ```ignore
+//! use proptest::prelude::*;
+//! use redis::value::{arbitrary::Params, Value};
+//!
+//! proptest! {
+//!     #[test]
+//!     fn shallow(value in any_with::<Value>(Params { depth: 2, ..Params::default() })) {
+//!         // ...
+//!     }
+//! }
+//! ```
+
+use proptest::{
+    collection::vec,
+    num::f64::{INFINITE, NEGATIVE, NORMAL, POSITIVE, SUBNORMAL, ZERO},
+    prelude::*,
+};
+
+use super::{Double, Map, Value};
+use crate::reply::RespError;
+
+/// How big the generated values get.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Params {
+    /// how many levels of aggregates may be nested
+    pub depth: u32,
+    /// the number of frames a value should have, roughly
+    pub size: u32,
+    /// the most elements (or entries, for maps) a single aggregate has
+    pub len: usize,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            depth: 4,
+            size: 64,
+            len: 8,
+        }
+    }
+}
+
+/// values without anything nested in them
+fn scalar() -> impl Strategy<Value = Value> {
+    let double = prop_oneof![
+        (POSITIVE | NEGATIVE | NORMAL | SUBNORMAL | ZERO | INFINITE).prop_map(Double),
+        Just(Double(f64::NAN)),
+    ];
+    prop_oneof![
+        any::<i64>().prop_map(Value::Int),
+        any::<bool>().prop_map(Value::Bool),
+        double.prop_map(Value::Double),
+        "-?[1-9][0-9]{18,40}".prop_map(Value::BigNumber),
+        vec(any::<u8>(), 0..64).prop_map(Value::bytes),
+        Just(Value::String(None)),
+        "[^\r\n]{0,32}".prop_map(Value::SimpleString),
+        ("[a-z]{3}", any::<String>()).prop_map(|(format, text)| Value::Verbatim {
+            format: format
+                .as_bytes()
+                .try_into()
+                .expect("format is three letters"),
+            text,
+        }),
+        ("[A-Z]{1,10}", "[^\r\n]{0,32}")
+            .prop_map(|(code, message)| Value::Error(RespError::new(code, message))),
+        Just(Value::Array(None)),
+        Just(Value::Null),
+    ]
+}
+
+impl Arbitrary for Value {
+    type Parameters = Params;
+    type Strategy = BoxedStrategy<Value>;
+
+    fn arbitrary_with(params: Params) -> Self::Strategy {
+        let len = 0..=params.len;
+        scalar()
+            .prop_recursive(params.depth, params.size, params.len as u32, move |inner| {
+                prop_oneof![
+                    vec(inner.clone(), len.clone()).prop_map(|v| Value::Array(Some(v))),
+                    vec(inner.clone(), len.clone()).prop_map(Value::Set),
+                    vec(inner.clone(), len.clone()).prop_map(Value::Push),
+                    // duplicate keys collapse, so maps may come out shorter
+                    vec((inner.clone(), inner), len.clone())
+                        .prop_map(|entries| Value::Map(entries.into_iter().collect::<Map>())),
+                ]
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serializer::{to_bytes_with_protocol, Protocol};
+
+    proptest! {
+        #[test]
+        fn round_trip(value in any::<Value>()) {
+            let mut out = vec![];
+            value.write(&mut out, Protocol::Resp3);
+            prop_assert_eq!(Value::parse(&out).unwrap(), (value.clone(), out.len()));
+
+            for protocol in [Protocol::Resp2, Protocol::Resp3] {
+                let mut direct = vec![];
+                value.write(&mut direct, protocol);
+                prop_assert_eq!(&to_bytes_with_protocol(&value, protocol).unwrap(), &direct);
+                prop_assert_eq!(Value::parse(&direct).unwrap().1, direct.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Comparing replies that should mean the same but may not be sent the same,
+//! like the replies to one command over RESP2 and RESP3, or from two servers
+//! that order their maps differently.
+//!
+//! A [`Value`] owns everything nested in it, so it can't contain itself and
+//! everything here terminates without having to look out for cycles.
+
+use crate::{float::format_double, mem_size::MemSize};
+
+use super::{Map, Value};
+
+impl Value {
+    /// How many bytes the value takes up, itself and everything nested in
+    /// it. This is the estimate MEMORY USAGE uses.
+    pub fn deep_size(&self) -> usize {
+        self.mem_size()
+    }
+
+    /// Like `==`, except that maps, also nested ones, are equal if they have
+    /// the same entries in any order.
+    pub fn deep_eq_ignore_order_for_maps(&self, other: &Value) -> bool {
+        let all = |a: &[Value], b: &[Value]| {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|(a, b)| a.deep_eq_ignore_order_for_maps(b))
+        };
+        match (self, other) {
+            (Value::Array(Some(a)), Value::Array(Some(b)))
+            | (Value::Set(a), Value::Set(b))
+            | (Value::Push(a), Value::Push(b)) => all(a, b),
+            // the keys of a map are unique, so a match for each entry will do
+            (Value::Map(a), Value::Map(b)) => {
+                a.len() == b.len()
+                    && a.iter().all(|(k, v)| {
+                        b.iter().any(|(k2, v2)| {
+                            k.deep_eq_ignore_order_for_maps(k2)
+                                && v.deep_eq_ignore_order_for_maps(v2)
+                        })
+                    })
+            }
+            _ => self == other,
+        }
+    }
+
+    /// The value with the differences between RESP2 and RESP3 taken out:
+    /// everything the two send differently gets the shape RESP2 sends it in,
+    /// all kinds of null become [`Value::Null`], and the entries of maps are
+    /// sorted by key.
+    ///
+    /// RESP2 sends maps as flat arrays of their keys and values, which can't
+    /// be told apart from other arrays. Maps are kept as maps here, to compare
+    /// one with a RESP2 reply build a map from its pairs first.
+    pub fn normalize(&self) -> Value {
+        match self {
+            Value::Null | Value::String(None) | Value::Array(None) => Value::Null,
+            Value::Bool(b) => Value::Int(*b as i64),
+            Value::Double(d) => Value::bytes(format_double(d.0)),
+            Value::BigNumber(n) => Value::bytes(n.as_str()),
+            Value::Verbatim { text, .. } => Value::bytes(text.as_str()),
+            Value::Array(Some(items)) | Value::Set(items) | Value::Push(items) => {
+                Value::Array(Some(items.iter().map(Value::normalize).collect()))
+            }
+            Value::Map(map) => {
+                let mut entries: Vec<_> = map
+                    .iter()
+                    .map(|(k, v)| (k.normalize(), v.normalize()))
+                    .collect();
+                entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+                Value::Map(Map::from(entries))
+            }
+            Value::Int(_) | Value::String(Some(_)) | Value::SimpleString(_) | Value::Error(_) => {
+                self.clone()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::{serializer::Protocol, value::Double};
+
+    use super::*;
+
+    fn map(entries: &[(&str, Value)]) -> Value {
+        Value::Map(
+            entries
+                .iter()
+                .map(|(k, v)| (Value::str(k), v.clone()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn deep_eq() {
+        let a = Value::from(vec![
+            map(&[
+                ("a", 1.into()),
+                ("b", map(&[("x", 1.into()), ("y", 2.into())])),
+            ]),
+            Value::Set(vec!["s".into()]),
+        ]);
+        let b = Value::from(vec![
+            map(&[
+                ("b", map(&[("y", 2.into()), ("x", 1.into())])),
+                ("a", 1.into()),
+            ]),
+            Value::Set(vec!["s".into()]),
+        ]);
+        assert_ne!(a, b);
+        assert!(a.deep_eq_ignore_order_for_maps(&b));
+        assert!(b.deep_eq_ignore_order_for_maps(&a));
+
+        // the order of everything else still counts
+        let c = Value::from(vec![
+            Value::Set(vec!["s".into()]),
+            a.get_index(0).unwrap().clone(),
+        ]);
+        assert!(!a.deep_eq_ignore_order_for_maps(&c));
+        assert!(!map(&[("a", 1.into())]).deep_eq_ignore_order_for_maps(&map(&[("a", 2.into())])));
+        assert!(!map(&[("a", 1.into())])
+            .deep_eq_ignore_order_for_maps(&map(&[("a", 1.into()), ("b", 1.into())])));
+        assert!(!Value::from(1).deep_eq_ignore_order_for_maps(&Value::from("1")));
+    }
+
+    #[test]
+    fn normalize() {
+        let reply = Value::from(vec![
+            Value::Bool(true),
+            Value::Double(Double(1.5)),
+            Value::BigNumber("123456789012345678901234567890".into()),
+            Value::Verbatim {
+                format: *b"txt",
+                text: "hi".into(),
+            },
+            Value::Null,
+            Value::Set(vec!["m".into()]),
+            Value::SimpleString("OK".into()),
+        ]);
+        // the same reply read back after going over either protocol
+        let over = |protocol| {
+            let mut out = vec![];
+            reply.write(&mut out, protocol);
+            Value::parse(&out).unwrap().0
+        };
+        let (resp2, resp3) = (over(Protocol::Resp2), over(Protocol::Resp3));
+        assert_ne!(resp2, resp3);
+        assert_eq!(resp2.normalize(), resp3.normalize());
+        assert_eq!(resp3.normalize(), reply.normalize());
+
+        let sorted = map(&[("b", 1.into()), ("a", Value::Array(None))]).normalize();
+        assert_eq!(
+            sorted,
+            Value::from(BTreeMap::from([
+                ("a".into(), Value::Null),
+                ("b".into(), 1.into())
+            ]))
+        );
+        assert_eq!(Value::String(None).normalize(), Value::Null);
+    }
+
+    #[test]
+    fn deep_size() {
+        let small = Value::from("x");
+        let nested = Value::from(vec![small.clone(), Value::from(vec![small.clone()])]);
+        assert!(nested.deep_size() > 3 * small.deep_size());
+        assert_eq!(Value::Null.deep_size(), std::mem::size_of::<Value>());
+    }
+}
@@ -0,0 +1,124 @@
+//! Conversions between [`Value`] and [`serde_json::Value`], for bridging RESP
+//! data to JSON tooling and fixtures.
+
+use serde_json::{Number, Value as Json};
+
+use super::{FromValueError, Map, Value};
+
+/// Numbers become integers where they fit and doubles otherwise, object keys
+/// become bulk strings.
+impl From<Json> for Value {
+    fn from(json: Json) -> Self {
+        match json {
+            Json::Null => Value::Null,
+            Json::Bool(b) => Value::Bool(b),
+            Json::Number(n) => match (n.as_i64(), n.as_u64(), n.as_f64()) {
+                (Some(i), _, _) => Value::Int(i),
+                (None, Some(u), _) => Value::BigNumber(u.to_string()),
+                (None, None, f) => f.map_or(Value::Null, Value::from),
+            },
+            Json::String(s) => s.into(),
+            Json::Array(a) => a.into_iter().map(Value::from).collect::<Vec<_>>().into(),
+            Json::Object(o) => o
+                .into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect::<Map>()
+                .into(),
+        }
+    }
+}
+
+/// All nulls become `null` and sets and pushes become arrays. Big numbers that
+/// don't fit a JSON integer are kept as strings of digits.
+///
+/// Fails on error replies, strings that aren't utf-8, doubles JSON can't
+/// represent (`nan`, `inf`) and map keys that aren't strings or integers. The
+/// error hands back the part of the value that couldn't be converted.
+impl TryFrom<Value> for Json {
+    type Error = FromValueError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let err = |value, expected| FromValueError { value, expected };
+        Ok(match value {
+            Value::Int(i) => i.into(),
+            Value::Bool(b) => b.into(),
+            Value::Double(d) => match Number::from_f64(d.0) {
+                Some(n) => Json::Number(n),
+                None => return Err(err(Value::Double(d), "a finite double")),
+            },
+            Value::BigNumber(n) => match n.parse::<i64>() {
+                Ok(i) => i.into(),
+                Err(_) => n.parse::<u64>().map_or(Json::String(n), Json::from),
+            },
+            Value::String(Some(s)) => match String::from_utf8(s) {
+                Ok(s) => s.into(),
+                Err(e) => return Err(err(Value::bytes(e.into_bytes()), "a utf-8 string")),
+            },
+            Value::SimpleString(s) | Value::Verbatim { text: s, .. } => s.into(),
+            Value::String(None) | Value::Array(None) | Value::Null => Json::Null,
+            Value::Array(Some(a)) | Value::Set(a) | Value::Push(a) => a
+                .into_iter()
+                .map(Json::try_from)
+                .collect::<Result<_, _>>()?,
+            Value::Map(m) => m
+                .into_iter()
+                .map(|(k, v)| {
+                    let k = match k {
+                        Value::Int(i) => i.to_string(),
+                        k => String::try_from(k)
+                            .map_err(|e| err(e.value, "a string or integer key"))?,
+                    };
+                    Ok((k, Json::try_from(v)?))
+                })
+                .collect::<Result<serde_json::Map<_, _>, _>>()?
+                .into(),
+            e @ Value::Error(_) => return Err(err(e, "a value that isn't an error")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::reply::RespError;
+
+    #[test]
+    fn from_json() {
+        let value = Value::from(json!({"a": [1, 1.5, null, true], "b": u64::MAX}));
+        let expected = Value::from(Map::from([
+            (
+                "a".into(),
+                Value::from(vec![1.into(), 1.5.into(), Value::Null, true.into()]),
+            ),
+            ("b".into(), Value::BigNumber(u64::MAX.to_string())),
+        ]));
+        assert_eq!(value, expected);
+    }
+
+    #[test]
+    fn to_json() {
+        let value = Value::from(Map::from([
+            (Value::Int(1), Value::Set(vec!["x".into()])),
+            (
+                Value::SimpleString("n".into()),
+                Value::BigNumber("123456789012345678901234567890".into()),
+            ),
+        ]));
+        assert_eq!(
+            Json::try_from(value),
+            Ok(json!({"1": ["x"], "n": "123456789012345678901234567890"}))
+        );
+    }
+
+    #[test]
+    fn to_json_fails() {
+        let error = Value::from(RespError::new("ERR", "oops"));
+        let nested = Value::from(vec![1.into(), error.clone()]);
+        assert_eq!(Json::try_from(nested).unwrap_err().value, error);
+
+        let nan = Value::from(f64::NAN);
+        assert!(Json::try_from(nan).is_err());
+    }
+}
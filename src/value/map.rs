@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+
+use serde::{ser::SerializeMap, Serialize};
+
+use super::Value;
+
+/// The entries of a RESP map, in the order they were sent or inserted.
+///
+/// Replies like CONFIG GET and XINFO are meant to be read in wire order, so
+/// unlike a `BTreeMap` this doesn't sort the keys. Lookups are linear, which
+/// is fine for the handful of entries replies carry.
+///
+/// The order is part of the value: two maps with the same entries in a
+/// different order are not equal.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Map(Vec<(Value, Value)>);
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self(Vec::with_capacity(capacity))
+    }
+
+    /// Builds a map from entries whose keys are known to be distinct, without
+    /// looking for duplicates.
+    pub(crate) fn from_distinct(entries: Vec<(Value, Value)>) -> Self {
+        Self(entries)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub fn get_mut(&mut self, key: &Value) -> Option<&mut Value> {
+        self.0.iter_mut().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub fn contains_key(&self, key: &Value) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Adds an entry at the end. If `key` is already there its value is
+    /// replaced in place and the old one returned.
+    pub fn insert(&mut self, key: Value, value: Value) -> Option<Value> {
+        if let Some(old) = self.get_mut(&key) {
+            return Some(std::mem::replace(old, value));
+        }
+        self.0.push((key, value));
+        None
+    }
+
+    /// removes the entry for `key`, keeping the order of the others
+    pub fn remove(&mut self, key: &Value) -> Option<Value> {
+        let index = self.0.iter().position(|(k, _)| k == key)?;
+        Some(self.0.remove(index).1)
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter(self.0.iter())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Value> {
+        self.0.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.0.iter().map(|(_, v)| v)
+    }
+}
+
+/// The entries of a [`Map`] in order, see [`Map::iter`].
+#[derive(Debug, Clone)]
+pub struct Iter<'a>(std::slice::Iter<'a, (Value, Value)>);
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Value, &'a Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(k, v)| (k, v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a> IntoIterator for &'a Map {
+    type Item = (&'a Value, &'a Value);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for Map {
+    type Item = (Value, Value);
+    type IntoIter = std::vec::IntoIter<(Value, Value)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+/// later entries replace the values of earlier ones with the same key
+impl FromIterator<(Value, Value)> for Map {
+    fn from_iter<I: IntoIterator<Item = (Value, Value)>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+        let mut map = Map::with_capacity(iter.size_hint().0);
+        for (k, v) in iter {
+            map.insert(k, v);
+        }
+        map
+    }
+}
+
+impl Extend<(Value, Value)> for Map {
+    fn extend<I: IntoIterator<Item = (Value, Value)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<const N: usize> From<[(Value, Value); N]> for Map {
+    fn from(entries: [(Value, Value); N]) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+impl From<Vec<(Value, Value)>> for Map {
+    fn from(entries: Vec<(Value, Value)>) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+impl From<BTreeMap<Value, Value>> for Map {
+    fn from(map: BTreeMap<Value, Value>) -> Self {
+        Self(map.into_iter().collect())
+    }
+}
+
+impl From<Map> for BTreeMap<Value, Value> {
+    fn from(map: Map) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl Serialize for Map {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (k, v) in self.iter() {
+            map.serialize_entry(k, v)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_order() {
+        let mut map = Map::from([("b".into(), 1.into()), ("a".into(), 2.into())]);
+        assert_eq!(map.insert("b".into(), 3.into()), Some(1.into()));
+        map.insert("c".into(), 4.into());
+
+        let keys: Vec<_> = map.keys().filter_map(Value::get_str).collect();
+        assert_eq!(keys, ["b", "a", "c"]);
+        assert_eq!(map.get(&"b".into()), Some(&3.into()));
+
+        assert_eq!(map.remove(&"a".into()), Some(2.into()));
+        assert_ne!(
+            map,
+            Map::from([("c".into(), 4.into()), ("b".into(), 3.into())])
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! Mainstream client libraries against the server.
+//!
+//! The unit tests check replies byte for byte, which doesn't say whether
+//! clients read them the way they're meant to. Here the server binary is
+//! driven by the scripts in `tests/client_compat`, one per client library,
+//! across the commands the server has: strings, keys, expiry and pipelines,
+//! in RESP2 and, where the client speaks it, RESP3. Hashes, pub/sub and
+//! transactions join the matrix once the server supports them.
+//!
+//! The clients have to be installed, so this only runs with
+//! `REDIS_CLIENT_COMPAT` set to the ones to run, comma separated:
+//!
+//! - `redis-py`: `pip install redis`
+//! - `node-redis`: `npm install --no-save redis` in the crate
+//!
+//! ```sh
+//! REDIS_CLIENT_COMPAT=redis-py,node-redis cargo test --test client_compat
+//! ```
+//!
+//! How the server reads requests off the socket is checked here as well,
+//! with a plain `TcpStream`, so that always runs.
+
+use std::{
+    net::{TcpListener, TcpStream},
+    path::Path,
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// the clients, and the command running the script for each, which gets the
+/// port as its last argument
+const CLIENTS: &[(&str, &[&str])] = &[
+    ("redis-py", &["python3", "redis_py.py"]),
+    ("node-redis", &["node", "node_redis.js"]),
+];
+
+/// a server on a port of its own, killed when dropped
+struct Server {
+    child: Child,
+    port: u16,
+}
+
+impl Server {
+    fn start() -> Self {
+        // the port is free now, and most likely still is when the server
+        // binds it
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("failed to find a free port")
+            .port();
+        let child = Command::new(env!("CARGO_BIN_EXE_redis-server"))
+            .args(["--port", &port.to_string()])
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("failed to start the server");
+        let server = Server { child, port };
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(Instant::now() < deadline, "the server didn't come up");
+            thread::sleep(Duration::from_millis(20));
+        }
+        server
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn clients() {
+    let Ok(wanted) = std::env::var("REDIS_CLIENT_COMPAT") else {
+        eprintln!("skipped, set REDIS_CLIENT_COMPAT to run it");
+        return;
+    };
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/client_compat");
+
+    let mut failures = vec![];
+    for name in wanted.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        let Some((_, command)) = CLIENTS.iter().find(|(n, _)| *n == name) else {
+            let known: Vec<_> = CLIENTS.iter().map(|(n, _)| *n).collect();
+            panic!("unknown client {name:?}, there are {known:?}");
+        };
+
+        let server = Server::start();
+        let output = Command::new(command[0])
+            .args(&command[1..])
+            .arg(server.port.to_string())
+            .current_dir(&dir)
+            .output()
+            .unwrap_or_else(|e| panic!("failed to run {}: {e}", command[0]));
+        if !output.status.success() {
+            failures.push(format!(
+                "{name}: {}\n{}{}",
+                output.status,
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+/// Commands don't have to arrive in one read: long ones take several, and
+/// clients may write them piecemeal.
+#[test]
+fn split_commands() {
+    use std::io::{Read, Write};
+
+    let server = Server::start();
+    let mut socket = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let request = |socket: &mut TcpStream, parts: &[&[u8]], reply: &[u8]| {
+        for part in parts {
+            socket.write_all(part).unwrap();
+            thread::sleep(Duration::from_millis(50));
+        }
+        let mut buf = vec![0; reply.len()];
+        socket.read_exact(&mut buf).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buf),
+            String::from_utf8_lossy(reply)
+        );
+    };
+
+    let value = "x".repeat(6000);
+    let set = format!("*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$6000\r\n{value}\r\n");
+    request(&mut socket, &[set.as_bytes()], b"$2\r\nOK\r\n");
+    let get = b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n";
+    let reply = format!("$6000\r\n{value}\r\n");
+    request(&mut socket, &[&get[..9], &get[9..]], reply.as_bytes());
+    // the second command of a pipeline cut short
+    let ping = b"*1\r\n$4\r\nPING\r\n";
+    let pipeline = [&get[..], &ping[..10]].concat();
+    let reply = format!("$6000\r\n{value}\r\n$4\r\nPONG\r\n");
+    request(&mut socket, &[&pipeline, &ping[10..]], reply.as_bytes());
+}
@@ -0,0 +1,104 @@
+// Runs the client compatibility matrix with node-redis, see client_compat.rs.
+//
+// Usage: node node_redis.js <port>
+
+const assert = require("node:assert/strict");
+const { createClient } = require("redis");
+
+async function strings(r, p) {
+  assert.equal(await r.set(p + "s", "hello"), "OK");
+  assert.equal(await r.get(p + "s"), "hello");
+  assert.equal(await r.get(p + "missing"), null);
+  assert.equal(await r.append(p + "s", " world"), 11);
+  assert.equal(await r.strLen(p + "s"), 11);
+  assert.equal(await r.getRange(p + "s", 0, 4), "hello");
+  assert.equal(await r.setRange(p + "s", 6, "there"), 11);
+  assert.equal(await r.set(p + "s", "x", { NX: true }), null);
+  assert.equal(await r.set(p + "s", "x", { XX: true, GET: true }), "hello there");
+  assert.equal(await r.setNX(p + "s", "y"), false);
+  assert.equal(await r.getSet(p + "s", "z"), "x");
+  assert.equal(await r.getDel(p + "s"), "z");
+  assert.equal(await r.exists(p + "s"), 0);
+
+  assert.equal(await r.incr(p + "n"), 1);
+  assert.equal(await r.incrBy(p + "n", 10), 11);
+  assert.equal(await r.decr(p + "n"), 10);
+  assert.equal(await r.decrBy(p + "n", 5), 5);
+  assert.equal(Number(await r.incrByFloat(p + "n", 0.5)), 5.5);
+
+  assert.equal(await r.mSet([p + "a", "1", p + "b", "2"]), "OK");
+  assert.deepEqual(await r.mGet([p + "a", p + "missing", p + "b"]), ["1", null, "2"]);
+  assert.equal(await r.mSetNX([p + "a", "3", p + "c", "4"]), false);
+
+  await r.set(p + "word", "abc");
+  await assert.rejects(r.incr(p + "word"), /not an integer/);
+}
+
+async function keys(r, p) {
+  await r.mSet([p + "k1", "1", p + "k2", "2", p + "k3", "3"]);
+  assert.equal(await r.exists([p + "k1", p + "k2", p + "missing"]), 2);
+  assert.equal(await r.type(p + "k1"), "string");
+  assert.equal(await r.type(p + "missing"), "none");
+
+  assert.equal(await r.setEx(p + "k1", 100, "1"), "OK");
+  assert.equal(await r.expire(p + "k2", 100), true);
+  assert.equal(await r.expire(p + "missing", 100), false);
+  const ttl = await r.ttl(p + "k2");
+  assert.ok(ttl > 0 && ttl <= 100, `ttl ${ttl}`);
+  const pttl = await r.pTTL(p + "k2");
+  assert.ok(pttl > 0 && pttl <= 100000, `pttl ${pttl}`);
+  const expireTime = await r.sendCommand(["EXPIRETIME", p + "k2"]);
+  assert.ok(expireTime >= Math.floor(Date.now() / 1000), `expiretime ${expireTime}`);
+  assert.equal(await r.persist(p + "k2"), true);
+  assert.equal(await r.ttl(p + "k2"), -1);
+  assert.equal(await r.ttl(p + "missing"), -2);
+
+  assert.equal(await r.rename(p + "k3", p + "k4"), "OK");
+  assert.equal(await r.renameNX(p + "k4", p + "k1"), false);
+  assert.equal(await r.copy(p + "k4", p + "k5"), true);
+  const expected = ["k1", "k2", "k4", "k5"].map((k) => p + k).sort();
+  assert.deepEqual((await r.keys(p + "k*")).sort(), expected);
+  const scanned = [];
+  for await (const batch of r.scanIterator({ MATCH: p + "k*", COUNT: 2 })) {
+    // node-redis 4 yields keys, 5 yields batches of them
+    scanned.push(...[].concat(batch));
+  }
+  assert.deepEqual(scanned.sort(), expected);
+  assert.notEqual(await r.randomKey(), null);
+  assert.equal(await r.del([p + "k1", p + "missing"]), 1);
+  assert.equal(await r.unlink([p + "k2", p + "k4"]), 2);
+}
+
+async function pipelines(r, p) {
+  const replies = await r
+    .multi()
+    .set(p + "pipe", "1")
+    .incr(p + "pipe")
+    .get(p + "pipe")
+    .echo("hi")
+    .execAsPipeline();
+  assert.deepEqual(replies, ["OK", 2, "2", "hi"]);
+}
+
+async function main() {
+  const r = createClient({ socket: { port: Number(process.argv[2]) } });
+  await r.connect();
+  assert.equal(await r.ping(), "PONG");
+  // there is no FLUSHDB, this keeps the keys apart from other runs
+  const p = "compat:node:";
+  for (const check of [strings, keys, pipelines]) {
+    try {
+      await check(r, p);
+    } catch (e) {
+      console.error(`${check.name} failed`);
+      throw e;
+    }
+  }
+  // there is no QUIT either
+  await r.disconnect();
+}
+
+main().catch((e) => {
+  console.error(e);
+  process.exit(1);
+});
@@ -0,0 +1,95 @@
+"""Runs the client compatibility matrix with redis-py, see client_compat.rs.
+
+Usage: python3 redis_py.py <port>
+"""
+
+import sys
+import time
+
+import redis
+
+
+def strings(r, p):
+    assert r.set(p + "s", "hello") is True
+    assert r.get(p + "s") == b"hello"
+    assert r.get(p + "missing") is None
+    assert r.append(p + "s", " world") == 11
+    assert r.strlen(p + "s") == 11
+    assert r.getrange(p + "s", 0, 4) == b"hello"
+    assert r.setrange(p + "s", 6, "there") == 11
+    assert r.set(p + "s", "x", nx=True) is None
+    assert r.set(p + "s", "x", xx=True, get=True) == b"hello there"
+    assert r.setnx(p + "s", "y") is False
+    assert r.getset(p + "s", "z") == b"x"
+    assert r.getdel(p + "s") == b"z"
+    assert r.exists(p + "s") == 0
+
+    assert r.incr(p + "n") == 1
+    assert r.incrby(p + "n", 10) == 11
+    assert r.decr(p + "n") == 10
+    assert r.decrby(p + "n", 5) == 5
+    assert r.incrbyfloat(p + "n", 0.5) == 5.5
+
+    assert r.mset({p + "a": "1", p + "b": "2"}) is True
+    assert r.mget([p + "a", p + "missing", p + "b"]) == [b"1", None, b"2"]
+    assert r.msetnx({p + "a": "3", p + "c": "4"}) is False
+
+    r.set(p + "word", "abc")
+    try:
+        r.incr(p + "word")
+    except redis.ResponseError as e:
+        assert "not an integer" in str(e), e
+    else:
+        raise AssertionError("INCR on a word succeeded")
+
+
+def keys(r, p):
+    r.mset({p + "k1": "1", p + "k2": "2", p + "k3": "3"})
+    assert r.exists(p + "k1", p + "k2", p + "missing") == 2
+    assert r.type(p + "k1") == b"string"
+    assert r.type(p + "missing") == b"none"
+
+    assert r.setex(p + "k1", 100, "1") is True
+    assert r.expire(p + "k2", 100) is True
+    assert r.expire(p + "missing", 100) is False
+    assert 0 < r.ttl(p + "k2") <= 100
+    assert 0 < r.pttl(p + "k2") <= 100_000
+    assert r.execute_command("EXPIRETIME", p + "k2") >= int(time.time())
+    assert r.persist(p + "k2") is True
+    assert r.ttl(p + "k2") == -1
+    assert r.ttl(p + "missing") == -2
+
+    assert r.rename(p + "k3", p + "k4") is True
+    assert r.renamenx(p + "k4", p + "k1") is False
+    assert r.copy(p + "k4", p + "k5") is True
+    expected = sorted((p + k).encode() for k in ["k1", "k2", "k4", "k5"])
+    assert sorted(r.keys(p + "k*")) == expected
+    assert sorted(r.scan_iter(match=p + "k*", count=2)) == expected
+    assert r.randomkey() is not None
+    assert r.delete(p + "k1", p + "missing") == 1
+    assert r.unlink(p + "k2", p + "k4") == 2
+
+
+def pipelines(r, p):
+    pipe = r.pipeline(transaction=False)
+    pipe.set(p + "pipe", "1").incr(p + "pipe").get(p + "pipe").echo("hi")
+    assert pipe.execute() == [True, 2, b"2", b"hi"]
+
+
+def main():
+    port = int(sys.argv[1])
+    for protocol in [2, 3]:
+        r = redis.Redis(port=port, protocol=protocol)
+        assert r.ping() is True
+        # keep the runs apart, there is no FLUSHDB
+        p = f"compat:{protocol}:"
+        for check in [strings, keys, pipelines]:
+            try:
+                check(r, p)
+            except Exception:
+                print(f"{check.__name__} failed with RESP{protocol}", file=sys.stderr)
+                raise
+        r.close()
+
+
+main()
@@ -0,0 +1,76 @@
+//! GET must hand stored strings to the reply without copying them.
+//!
+//! A counting allocator tracks what the current thread allocates while a
+//! GET of a large string runs through `App::dispatch_command`. The reply
+//! buffer comes from the connection's pool and the value is shared with the
+//! store, so the only copy of it is the one serialized into that buffer and
+//! GET allocates nothing at all.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+use redis::{
+    commands::{App, Client},
+    value::Value,
+};
+
+struct Counting;
+
+thread_local! {
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.with(|n| n.set(n.get() + layout.size()));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.with(|n| n.set(n.get() + new_size.saturating_sub(layout.size())));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn allocated() -> usize {
+    ALLOCATED.with(Cell::get)
+}
+
+fn command(args: &[&str]) -> Value {
+    Value::Array(Some(args.iter().map(|a| Value::str(a)).collect()))
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn get_does_not_copy_the_value() {
+    const LEN: usize = 16 * 1024;
+    let app = App::new();
+    let mut client = Client::default();
+    let value = "x".repeat(LEN);
+    app.dispatch_command(&mut client, command(&["SET", "big", &value]))
+        .await;
+
+    // the first reply sizes the pooled buffer
+    let get = command(&["GET", "big"]);
+    let reply = app.dispatch_command(&mut client, get.clone()).await;
+    assert!(reply.ends_with(format!("{value}\r\n").as_bytes()));
+    client.recycle(reply);
+
+    for _ in 0..3 {
+        let get = get.clone();
+        let before = allocated();
+        let reply = app.dispatch_command(&mut client, get).await;
+        let during = allocated() - before;
+        assert_eq!(reply.len(), LEN + "$16384\r\n\r\n".len());
+        assert_eq!(during, 0, "GET allocated {during} bytes");
+        client.recycle(reply);
+    }
+}
@@ -0,0 +1,129 @@
+//! RESP conformance corpus.
+//!
+//! Every `*.resp` file under `tests/resp_corpus/valid` must parse into a
+//! [`Value`] and re-serialize (RESP3) to the bytes in the matching
+//! `*.expected` file. Every `*.resp` file under `tests/resp_corpus/invalid`
+//! must fail to parse with the error message in the matching `*.err` file.
+//!
+//! [`Value::parse`] and [`Value::write`] skip serde, they must agree with it
+//! on every case.
+//!
+//! To add a case drop a new pair of files into the right directory.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use redis::{
+    deserializer::from_bytes,
+    serializer::{to_bytes, to_bytes_with_protocol, Protocol},
+    value::Value,
+};
+
+fn cases(dir: &str) -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/resp_corpus")
+        .join(dir);
+    let mut cases: Vec<_> = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("failed to read {}: {e}", dir.display()))
+        .map(|entry| entry.expect("failed to read corpus entry").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "resp"))
+        .collect();
+    cases.sort();
+    assert!(!cases.is_empty(), "no cases in {}", dir.display());
+    cases
+}
+
+fn read(path: &Path) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| panic!("failed to read {}: {e}", path.display()))
+}
+
+#[test]
+fn valid_frames() {
+    let mut failures = vec![];
+    for case in cases("valid") {
+        let input = read(&case);
+        let expected = read(&case.with_extension("expected"));
+
+        match from_bytes::<Value>(&input) {
+            Ok(value) => {
+                let out = to_bytes(&value).expect("failed to serialize");
+                if out != expected {
+                    failures.push(format!(
+                        "{}: expected {:?}, got {:?}",
+                        case.display(),
+                        String::from_utf8_lossy(&expected),
+                        String::from_utf8_lossy(&out),
+                    ));
+                }
+                if let Err(e) = check_direct(&input, &value) {
+                    failures.push(format!("{}: {e}", case.display()));
+                }
+            }
+            Err(e) => failures.push(format!("{}: failed to parse: {e}", case.display())),
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+/// checks that [`Value::parse`] and [`Value::write`] agree with serde
+fn check_direct(input: &[u8], value: &Value) -> Result<(), String> {
+    match Value::parse(input) {
+        Ok((parsed, used)) if parsed == *value && used == input.len() => {}
+        Ok((parsed, used)) => {
+            return Err(format!(
+                "Value::parse read {used} bytes as {parsed:?}, serde read {value:?}"
+            ))
+        }
+        Err(e) => return Err(format!("Value::parse failed: {e}")),
+    }
+
+    for protocol in [Protocol::Resp2, Protocol::Resp3] {
+        let serde = to_bytes_with_protocol(value, protocol).expect("failed to serialize");
+        let mut direct = Vec::new();
+        value.write(&mut direct, protocol);
+        if direct != serde {
+            return Err(format!(
+                "Value::write wrote {:?} in {protocol:?}, serde wrote {:?}",
+                String::from_utf8_lossy(&direct),
+                String::from_utf8_lossy(&serde),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn invalid_frames() {
+    let mut failures = vec![];
+    for case in cases("invalid") {
+        let input = read(&case);
+        let expected =
+            String::from_utf8(read(&case.with_extension("err"))).expect("error file is not utf-8");
+
+        match from_bytes::<Value>(&input) {
+            Ok(value) => failures.push(format!("{}: parsed as {value:?}", case.display())),
+            Err(e) if e.to_string() != expected.trim_end() => failures.push(format!(
+                "{}: expected error {expected:?}, got {:?}",
+                case.display(),
+                e.to_string(),
+            )),
+            Err(_) => {}
+        }
+
+        match Value::parse(&input) {
+            Ok((value, used)) if used == input.len() => failures.push(format!(
+                "{}: Value::parse parsed it as {value:?}",
+                case.display()
+            )),
+            Err(e) if e.to_string() != expected.trim_end() => failures.push(format!(
+                "{}: expected error {expected:?} from Value::parse, got {:?}",
+                case.display(),
+                e.to_string(),
+            )),
+            _ => {}
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}